@@ -0,0 +1,92 @@
+//! Packs the `initramfs` directory into a newc cpio archive, which the kernel embeds and
+//! unpacks at boot. Set `BLOG_OS_INITRAMFS` to the path of a cpio or ustar archive to embed
+//! that instead.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+const MODE_DIRECTORY: u32 = 0o040_755;
+const MODE_FILE: u32 = 0o100_644;
+
+fn main() -> io::Result<()> {
+    let out = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set")).join("initramfs");
+    println!("cargo:rerun-if-env-changed=BLOG_OS_INITRAMFS");
+    if let Some(archive) = env::var_os("BLOG_OS_INITRAMFS") {
+        println!("cargo:rerun-if-changed={}", Path::new(&archive).display());
+        fs::copy(&archive, &out)?;
+        return Ok(());
+    }
+
+    println!("cargo:rerun-if-changed=initramfs");
+    let mut archive = Vec::new();
+    let mut inode = 1;
+    add_dir(Path::new("initramfs"), "", &mut archive, &mut inode)?;
+    add_entry(&mut archive, "TRAILER!!!", 0, 0, &[]);
+    fs::write(out, archive)
+}
+
+/// Adds the contents of a directory, sorted by name so that the archive is reproducible.
+fn add_dir(dir: &Path, prefix: &str, archive: &mut Vec<u8>, inode: &mut u32) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        println!("cargo:rerun-if-changed={}", entry.path().display());
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("non-UTF-8 file name {:?}", name),
+            )
+        })?;
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let file_type = entry.file_type()?;
+        *inode += 1;
+        if file_type.is_dir() {
+            add_entry(archive, &path, MODE_DIRECTORY, *inode, &[]);
+            add_dir(&entry.path(), &path, archive, inode)?;
+        } else if file_type.is_file() {
+            let data = fs::read(entry.path())?;
+            add_entry(archive, &path, MODE_FILE, *inode, &data);
+        }
+    }
+    Ok(())
+}
+
+/// Appends a newc header, the name and the data, each padded to 4 bytes.
+fn add_entry(archive: &mut Vec<u8>, name: &str, mode: u32, inode: u32, data: &[u8]) {
+    let fields = [
+        inode,
+        mode,
+        0, // uid
+        0, // gid
+        1, // nlink
+        0, // mtime
+        data.len() as u32,
+        0, // devmajor
+        0, // devminor
+        0, // rdevmajor
+        0, // rdevminor
+        name.len() as u32 + 1,
+        0, // check
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields.iter() {
+        archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    pad(archive);
+    archive.extend_from_slice(data);
+    pad(archive);
+}
+
+fn pad(archive: &mut Vec<u8>) {
+    while archive.len() % 4 != 0 {
+        archive.push(0);
+    }
+}
@@ -0,0 +1,21 @@
+#!/bin/sh
+# Runs a kernel executable with `bootimage runner`, which cargo uses for `cargo run` and
+# `cargo test`.
+#
+# Creates the empty disk images the tests attach as scratch drives first, see `test-args`
+# in `Cargo.toml`. QEMU opens them with `snapshot=on` and resolves them relative to the
+# working directory, so the tests' writes are discarded and the images stay empty.
+set -e
+
+create_image() {
+    if [ ! -e "$1" ]; then
+        mkdir -p "$(dirname "$1")"
+        truncate -s "$2" "$1"
+    fi
+}
+
+create_image target/ata-scratch.img 4M
+create_image target/ahci-scratch.img 16M
+create_image target/virtio-scratch.img 8M
+
+exec bootimage runner "$@"
@@ -0,0 +1,150 @@
+//! Lookup of ACPI tables through the RSDP that the BIOS places in low memory.
+//!
+//! Only finding tables and validating their checksums is implemented; the tables are
+//! interpreted by their users, e.g. the PCI driver reads the MCFG table.
+
+use crate::memory;
+use core::slice;
+use x86_64::PhysAddr;
+
+/// The segment of the extended BIOS data area is stored at this address.
+const EBDA_SEGMENT_POINTER: u64 = 0x40e;
+/// The main BIOS area, which is searched for the RSDP after the first KiB of the EBDA.
+const BIOS_AREA: (u64, u64) = (0xe_0000, 0x10_0000);
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const RSDP_V1_LENGTH: usize = 20;
+const RSDP_V2_LENGTH: usize = 36;
+
+/// The length of the header shared by all tables.
+pub const HEADER_LENGTH: usize = 36;
+
+/// Returns the physical memory at `address` as a byte slice.
+///
+/// The complete physical memory is mapped, so this is sound as long as the memory
+/// isn't changed while the slice exists, which holds for the firmware tables.
+fn physical_bytes(address: u64, len: usize) -> &'static [u8] {
+    let virt = memory::physical_memory_offset() + address;
+    unsafe { slice::from_raw_parts(virt.as_ptr(), len) }
+}
+
+/// Whether the bytes sum to 0 modulo 256, as ACPI checksums require.
+pub fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+/// The root table: the XSDT with 64-bit entries if the firmware provides one, otherwise
+/// the RSDT with 32-bit entries.
+#[derive(Debug, Clone, Copy)]
+struct RootTable {
+    address: u64,
+    entry_size: usize,
+}
+
+fn find_rsdp_in(start: u64, end: u64) -> Option<RootTable> {
+    (start..end).step_by(16).find_map(|address| {
+        let bytes = physical_bytes(address, RSDP_V1_LENGTH);
+        if &bytes[..8] != RSDP_SIGNATURE || !checksum_ok(bytes) {
+            return None;
+        }
+        let revision = bytes[15];
+        if revision >= 2 {
+            let bytes = physical_bytes(address, RSDP_V2_LENGTH);
+            let xsdt = read_u64(bytes, 24);
+            if checksum_ok(bytes) && xsdt != 0 {
+                return Some(RootTable {
+                    address: xsdt,
+                    entry_size: 8,
+                });
+            }
+        }
+        Some(RootTable {
+            address: read_u32(bytes, 16) as u64,
+            entry_size: 4,
+        })
+    })
+}
+
+fn find_root_table() -> Option<RootTable> {
+    let pointer = physical_bytes(EBDA_SEGMENT_POINTER, 2);
+    let segment = u16::from_le_bytes([pointer[0], pointer[1]]);
+    let ebda = (segment as u64) << 4;
+    let in_ebda = if ebda != 0 {
+        find_rsdp_in(ebda, ebda + 1024)
+    } else {
+        None
+    };
+    in_ebda.or_else(|| find_rsdp_in(BIOS_AREA.0, BIOS_AREA.1))
+}
+
+/// An ACPI table with a valid checksum.
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    pub address: PhysAddr,
+    /// The complete table, including the header.
+    pub data: &'static [u8],
+}
+
+impl Table {
+    /// Returns the table at `address` if its checksum is valid.
+    fn at(address: u64) -> Option<Table> {
+        let header = physical_bytes(address, HEADER_LENGTH);
+        let length = read_u32(header, 4) as usize;
+        if length < HEADER_LENGTH {
+            return None;
+        }
+        let data = physical_bytes(address, length);
+        if !checksum_ok(data) {
+            return None;
+        }
+        Some(Table {
+            address: PhysAddr::new(address),
+            data,
+        })
+    }
+
+    pub fn signature(&self) -> &'static [u8] {
+        &self.data[..4]
+    }
+
+    /// Returns the table contents after the header.
+    pub fn body(&self) -> &'static [u8] {
+        &self.data[HEADER_LENGTH..]
+    }
+}
+
+/// Returns the table with the given signature, e.g. `b"MCFG"`.
+///
+/// Requires `memory::init_kernel_memory` to have been called.
+pub fn find_table(signature: &[u8; 4]) -> Option<Table> {
+    let root_table = find_root_table()?;
+    let root = Table::at(root_table.address)?;
+    root.body()
+        .chunks_exact(root_table.entry_size)
+        .map(|entry| match root_table.entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        })
+        .filter(|&address| address != 0)
+        .filter_map(Table::at)
+        .find(|table| table.signature() == signature)
+}
+
+#[test_case]
+fn test_checksum() {
+    assert!(checksum_ok(&[0x10, 0xf0]));
+    assert!(checksum_ok(&[]));
+    assert!(!checksum_ok(&[1, 2, 3]));
+}
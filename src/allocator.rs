@@ -1,3 +1,7 @@
+use crate::{
+    config,
+    memory::vma::{self, Area, AreaError, AreaKind, Backing},
+};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
 use fixed_size_block::FixedSizeBlockAllocator;
@@ -13,18 +17,32 @@ pub mod fixed_size_block;
 pub mod linked_list;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// The initial heap size unless `heap_size` is set on the command line.
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// The size the heap can grow to once `enable_heap_growth` was called.
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
 
+/// The initial heap size: `heap_size` from the command line, or `HEAP_SIZE`.
+///
+/// Rounded up to whole pages and limited to `HEAP_MAX_SIZE`.
+pub fn heap_size() -> usize {
+    const PAGE_SIZE: usize = 4096;
+
+    let size = config::get_size("heap_size", HEAP_SIZE).clamp(PAGE_SIZE, HEAP_MAX_SIZE);
+    (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
+    let heap_size = heap_size();
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_end = heap_start + heap_size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -39,9 +57,42 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(HEAP_START, heap_size);
+    }
+
+    Ok(())
+}
+
+/// Lets the heap grow beyond its initial size up to `HEAP_MAX_SIZE`.
+///
+/// The additional heap memory is a lazily backed area, so its pages are only mapped by the
+/// page fault handler when the allocator first touches them. Requires
+/// `memory::init_kernel_memory` to have been called.
+pub fn enable_heap_growth() -> Result<(), AreaError> {
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let heap_size = heap_size();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    vma::register_mapped(Area {
+        name: "heap",
+        start: heap_start,
+        end: heap_start + heap_size,
+        kind: AreaKind::Heap,
+        backing: Backing::Eager,
+        flags,
+    })?;
+    if heap_size < HEAP_MAX_SIZE {
+        vma::register(Area {
+            name: "heap growth",
+            start: heap_start + heap_size,
+            end: heap_start + HEAP_MAX_SIZE,
+            kind: AreaKind::Heap,
+            backing: Backing::Lazy,
+            flags,
+        })?;
     }
 
+    ALLOCATOR.lock().set_max_size(HEAP_MAX_SIZE);
     Ok(())
 }
 
@@ -1,4 +1,4 @@
-use super::Locked;
+use super::{align_up, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{
     mem,
@@ -11,6 +11,9 @@ use core::{
 /// the block alignment (alignments must be always powers of 2).
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
+/// The minimum amount by which the heap is extended when it runs out of memory.
+const GROWTH_STEP: usize = 64 * 1024;
+
 /// Choose an appropriate block size for the given layout.
 ///
 /// Returns an index into the `BLOCK_SIZES` array.
@@ -26,6 +29,7 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    max_size: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -35,6 +39,7 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            max_size: 0,
         }
     }
 
@@ -45,15 +50,36 @@ impl FixedSizeBlockAllocator {
     /// called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.fallback_allocator.init(heap_start, heap_size);
+        self.max_size = heap_size;
+    }
+
+    /// Allows the heap to grow up to `max_size` bytes.
+    ///
+    /// The caller must ensure that the memory above the current heap end is reserved for
+    /// the heap and mapped on demand.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
     }
 
     /// Allocates using the fallback allocator.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
+            Err(_) if self.grow(layout.size() + layout.align()) => self.fallback_alloc(layout),
             Err(_) => ptr::null_mut(),
         }
     }
+
+    /// Extends the heap by at least `min_size` bytes, if it may still grow.
+    fn grow(&mut self, min_size: usize) -> bool {
+        let size = self.fallback_allocator.size();
+        let by = align_up(min_size.max(GROWTH_STEP), GROWTH_STEP);
+        if size.saturating_add(by) > self.max_size {
+            return false;
+        }
+        unsafe { self.fallback_allocator.extend(by) };
+        true
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
@@ -0,0 +1,110 @@
+//! Block devices: storage that is read and written in fixed-size sectors.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{fmt, future::Future, pin::Pin};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub mod partition;
+
+/// The sector size of all current block devices.
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The access reaches beyond the last sector.
+    OutOfRange,
+    /// The buffer length is not a multiple of the sector size.
+    InvalidBuffer,
+    /// The device reported an error, with the device-specific error code.
+    Device(u8),
+    /// The device didn't respond in time.
+    Timeout,
+    /// The device doesn't support writing.
+    ReadOnly,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::OutOfRange => write!(f, "access beyond the end of the device"),
+            BlockError::InvalidBuffer => write!(f, "buffer is not a multiple of the sector size"),
+            BlockError::Device(code) => write!(f, "device error {:#04x}", code),
+            BlockError::Timeout => write!(f, "device timed out"),
+            BlockError::ReadOnly => write!(f, "device is read-only"),
+        }
+    }
+}
+
+/// The future returned by the methods of `BlockDevice`.
+pub type BlockFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BlockError>> + 'a>>;
+
+/// A device that stores data in sectors of `sector_size` bytes.
+///
+/// The methods take `&self`, so that a device can be shared between tasks; devices
+/// serialize concurrent requests themselves.
+pub trait BlockDevice {
+    /// A short name like `ata0`, used to refer to the device in the shell.
+    fn name(&self) -> &str;
+
+    /// A description of the device, e.g. the model name.
+    fn model(&self) -> &str {
+        ""
+    }
+
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64;
+
+    /// The capacity of the device in bytes.
+    fn capacity(&self) -> u64 {
+        self.sector_count() * self.sector_size() as u64
+    }
+
+    /// Reads `buf.len() / sector_size` sectors starting at `sector` into `buf`.
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()>;
+
+    /// Writes `buf.len() / sector_size` sectors starting at `sector`.
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()>;
+
+    /// Waits until all written data is stored persistently.
+    fn flush(&self) -> BlockFuture<'_, ()>;
+}
+
+/// Checks that `len` bytes starting at `sector` are whole sectors within the device and
+/// returns the number of sectors.
+pub fn check_range(device: &dyn BlockDevice, sector: u64, len: usize) -> Result<u64, BlockError> {
+    if len % device.sector_size() != 0 {
+        return Err(BlockError::InvalidBuffer);
+    }
+    let count = (len / device.sector_size()) as u64;
+    match sector.checked_add(count) {
+        Some(end) if end <= device.sector_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice + Send + Sync>>> = Mutex::new(Vec::new());
+
+/// Makes a device available by name, e.g. to the shell.
+pub fn register(device: Arc<dyn BlockDevice + Send + Sync>) {
+    interrupts::without_interrupts(|| DEVICES.lock().push(device));
+}
+
+/// Returns the device with the given name.
+pub fn device(name: &str) -> Option<Arc<dyn BlockDevice + Send + Sync>> {
+    interrupts::without_interrupts(|| {
+        DEVICES
+            .lock()
+            .iter()
+            .find(|device| device.name() == name)
+            .cloned()
+    })
+}
+
+/// Returns all registered devices in registration order.
+pub fn devices() -> Vec<Arc<dyn BlockDevice + Send + Sync>> {
+    interrupts::without_interrupts(|| DEVICES.lock().clone())
+}
@@ -0,0 +1,621 @@
+//! Partition tables: MBR, with extended partitions, and GPT.
+//!
+//! `scan` reads the table of a block device and exposes each partition as a block device
+//! of its own, named after the parent like Linux does: `vda1` for `vda`, `sata0p1` for
+//! `sata0`. Partitions are numbered from 1 in table order; logical partitions in an MBR
+//! extended partition are numbered from 5.
+
+use super::{check_range, BlockDevice, BlockError, BlockFuture};
+use crate::{info, warn};
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// The MBR system ID of the protective partition covering a GPT disk.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+/// The MBR system IDs of extended partitions.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// The number of the first logical partition.
+const FIRST_LOGICAL: usize = 5;
+/// Bounds the walk of the extended partition chain in case it is cyclic.
+const MAX_LOGICAL: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_HEADER_MAX_SIZE: usize = 512;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// The most partition entries read, four times the usual table size.
+const GPT_MAX_ENTRIES: usize = 512;
+/// The largest partition entry array read, four times the usual size.
+const GPT_MAX_ENTRIES_LEN: usize = GPT_MAX_ENTRIES * GPT_MIN_ENTRY_SIZE;
+const GPT_LABEL_UNITS: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError {
+    Block(BlockError),
+    /// The device has no MBR signature.
+    NoTable,
+    /// The GPT header or partition entries are corrupt.
+    InvalidGpt,
+    /// The chain of extended boot records is too long or leaves the extended partition.
+    InvalidExtended,
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(err: BlockError) -> Self {
+        PartitionError::Block(err)
+    }
+}
+
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionError::Block(err) => write!(f, "{}", err),
+            PartitionError::NoTable => write!(f, "no partition table"),
+            PartitionError::InvalidGpt => write!(f, "corrupt GPT"),
+            PartitionError::InvalidExtended => write!(f, "invalid extended partition chain"),
+        }
+    }
+}
+
+/// Computes the CRC-32 used by GPT, the one of Ethernet and zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+/// A GUID as stored on disk, with the first three fields little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+
+    /// Parses the text form, e.g. `c12a7328-f81f-11d2-ba4b-00a0c93ec93b`.
+    pub fn parse(s: &str) -> Option<Guid> {
+        // the order in which the bytes appear in the text form
+        const TEXT_ORDER: [usize; 16] = [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15];
+
+        let s = s.as_bytes();
+        if s.len() != 36 || [8, 13, 18, 23].iter().any(|&dash| s[dash] != b'-') {
+            return None;
+        }
+        let mut digits = s.iter().filter(|&&c| c != b'-');
+        let mut guid = [0; 16];
+        for &index in TEXT_ORDER.iter() {
+            let high = (*digits.next()? as char).to_digit(16)?;
+            let low = (*digits.next()? as char).to_digit(16)?;
+            guid[index] = (high << 4 | low) as u8;
+        }
+        Some(Guid(guid))
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-",
+            read_u32(g, 0),
+            read_u16(g, 4),
+            read_u16(g, 6)
+        )?;
+        write!(f, "{:02x}{:02x}-", g[8], g[9])?;
+        g[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// A primary entry of an MBR or extended boot record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbrEntry {
+    pub bootable: bool,
+    pub system_id: u8,
+    /// The first sector, relative to the start of the table's scope.
+    pub start: u64,
+    pub sectors: u64,
+}
+
+impl MbrEntry {
+    fn is_extended(&self) -> bool {
+        MBR_TYPES_EXTENDED.contains(&self.system_id)
+    }
+}
+
+/// Parses the four entries of an MBR or extended boot record, with `None` for empty ones.
+pub fn parse_mbr(sector: &[u8]) -> Result<[Option<MbrEntry>; 4], PartitionError> {
+    if sector.len() < 512 || sector[510..512] != MBR_SIGNATURE {
+        return Err(PartitionError::NoTable);
+    }
+    let mut entries = [None; 4];
+    for (index, slot) in entries.iter_mut().enumerate() {
+        let entry = &sector[MBR_ENTRIES_OFFSET + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let system_id = entry[4];
+        let sectors = read_u32(entry, 12) as u64;
+        if system_id != 0 && sectors != 0 {
+            *slot = Some(MbrEntry {
+                bootable: entry[0] & 0x80 != 0,
+                system_id,
+                start: read_u32(entry, 8) as u64,
+                sectors,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The fields of a GPT header that are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GptHeader {
+    pub alternate_lba: u64,
+    pub first_usable: u64,
+    pub last_usable: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub entry_count: usize,
+    pub entry_size: usize,
+    pub entries_crc: u32,
+}
+
+impl GptHeader {
+    /// The size in bytes of the partition entry array.
+    pub fn entries_len(&self) -> usize {
+        self.entry_count * self.entry_size
+    }
+}
+
+/// Parses and validates the GPT header in `sector`, which was read from `lba`.
+///
+/// Entries must fit in a sector and the entry array in `GPT_MAX_ENTRIES_LEN` bytes, so that
+/// a corrupt header can't make the array huge.
+pub fn parse_gpt_header(sector: &[u8], lba: u64) -> Result<GptHeader, PartitionError> {
+    if sector.len() < GPT_HEADER_MIN_SIZE || &sector[..8] != GPT_SIGNATURE {
+        return Err(PartitionError::InvalidGpt);
+    }
+    let size = read_u32(sector, 12) as usize;
+    if size < GPT_HEADER_MIN_SIZE || size > GPT_HEADER_MAX_SIZE || size > sector.len() {
+        return Err(PartitionError::InvalidGpt);
+    }
+    // the checksum is computed with its own field zeroed
+    let mut header = [0; GPT_HEADER_MAX_SIZE];
+    header[..size].copy_from_slice(&sector[..size]);
+    header[16..20].copy_from_slice(&[0; 4]);
+    if crc32(&header[..size]) != read_u32(sector, 16) || read_u64(sector, 24) != lba {
+        return Err(PartitionError::InvalidGpt);
+    }
+
+    let mut disk_guid = [0; 16];
+    disk_guid.copy_from_slice(&sector[56..72]);
+    let entry_size = read_u32(sector, 84) as usize;
+    let entry_count = read_u32(sector, 80) as usize;
+    if entry_size < GPT_MIN_ENTRY_SIZE
+        || entry_size > sector.len()
+        || entry_size % 8 != 0
+        || entry_count > GPT_MAX_ENTRIES
+        || entry_count * entry_size > GPT_MAX_ENTRIES_LEN
+    {
+        return Err(PartitionError::InvalidGpt);
+    }
+    Ok(GptHeader {
+        alternate_lba: read_u64(sector, 32),
+        first_usable: read_u64(sector, 40),
+        last_usable: read_u64(sector, 48),
+        disk_guid: Guid(disk_guid),
+        entries_lba: read_u64(sector, 72),
+        entry_count,
+        entry_size,
+        entries_crc: read_u32(sector, 88),
+    })
+}
+
+/// What a partition table says about a partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    Mbr {
+        system_id: u8,
+        bootable: bool,
+    },
+    Gpt {
+        type_guid: Guid,
+        guid: Guid,
+        label: String,
+    },
+}
+
+/// A partition found in a partition table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub number: usize,
+    /// The first sector on the parent device.
+    pub start: u64,
+    pub sectors: u64,
+    pub kind: PartitionKind,
+}
+
+/// Parses the used entries of a GPT partition entry array.
+fn parse_gpt_entries(header: &GptHeader, entries: &[u8]) -> Vec<PartitionInfo> {
+    entries
+        .chunks_exact(header.entry_size)
+        .take(header.entry_count)
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let mut type_guid = [0; 16];
+            type_guid.copy_from_slice(&entry[..16]);
+            if Guid(type_guid) == Guid::ZERO {
+                return None;
+            }
+            let mut guid = [0; 16];
+            guid.copy_from_slice(&entry[16..32]);
+            let first = read_u64(entry, 32);
+            let last = read_u64(entry, 40);
+            if last < first {
+                return None;
+            }
+            let units = (0..GPT_LABEL_UNITS)
+                .map(|unit| read_u16(entry, 56 + 2 * unit))
+                .take_while(|&unit| unit != 0);
+            let label = core::char::decode_utf16(units)
+                .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+                .collect();
+            Some(PartitionInfo {
+                number: index + 1,
+                start: first,
+                sectors: last - first + 1,
+                kind: PartitionKind::Gpt {
+                    type_guid: Guid(type_guid),
+                    guid: Guid(guid),
+                    label,
+                },
+            })
+        })
+        .collect()
+}
+
+type Device = Arc<dyn BlockDevice + Send + Sync>;
+
+async fn read_sectors(device: &Device, lba: u64, len: usize) -> Result<Vec<u8>, BlockError> {
+    let sector_size = device.sector_size();
+    let mut buf = vec![0; (len + sector_size - 1) / sector_size * sector_size];
+    device.read(lba, &mut buf).await?;
+    Ok(buf)
+}
+
+/// Reads and validates the GPT whose header is at `lba`.
+async fn read_gpt(device: &Device, lba: u64) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let sector = read_sectors(device, lba, device.sector_size()).await?;
+    let header = parse_gpt_header(&sector, lba)?;
+    let entries = read_sectors(device, header.entries_lba, header.entries_len()).await?;
+    if crc32(&entries[..header.entries_len()]) != header.entries_crc {
+        return Err(PartitionError::InvalidGpt);
+    }
+    Ok(parse_gpt_entries(&header, &entries))
+}
+
+/// Reads the GPT, falling back to the backup in the last sector if the primary is corrupt.
+async fn scan_gpt(device: &Device) -> Result<Vec<PartitionInfo>, PartitionError> {
+    match read_gpt(device, 1).await {
+        Ok(partitions) => Ok(partitions),
+        Err(err) => {
+            // a device that reports no sectors has no last sector to hold the backup
+            let backup = device.sector_count().checked_sub(1).ok_or(err)?;
+            warn!(
+                "{}: primary GPT unusable: {}, using the backup",
+                device.name(),
+                err
+            );
+            read_gpt(device, backup).await
+        }
+    }
+}
+
+/// Reads the MBR entries and the logical partitions in an extended partition.
+async fn scan_mbr(
+    device: &Device,
+    entries: [Option<MbrEntry>; 4],
+) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let to_info = |number, start, entry: MbrEntry| PartitionInfo {
+        number,
+        start,
+        sectors: entry.sectors,
+        kind: PartitionKind::Mbr {
+            system_id: entry.system_id,
+            bootable: entry.bootable,
+        },
+    };
+
+    let mut partitions = Vec::new();
+    let mut extended = None;
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some(entry) = *entry {
+            partitions.push(to_info(index + 1, entry.start, entry));
+            if entry.is_extended() && extended.is_none() {
+                extended = Some(entry);
+            }
+        }
+    }
+
+    // each extended boot record describes a logical partition relative to itself, and
+    // links to the next record relative to the start of the extended partition
+    if let Some(extended) = extended {
+        let mut record = extended.start;
+        for number in FIRST_LOGICAL..FIRST_LOGICAL + MAX_LOGICAL {
+            let sector = read_sectors(device, record, device.sector_size()).await?;
+            let entries = parse_mbr(&sector).map_err(|_| PartitionError::InvalidExtended)?;
+            if let Some(logical) = entries[0] {
+                partitions.push(to_info(number, record + logical.start, logical));
+            }
+            match entries[1] {
+                Some(next) if next.is_extended() => {
+                    if next.start == 0 || next.start >= extended.sectors {
+                        return Err(PartitionError::InvalidExtended);
+                    }
+                    record = extended.start + next.start;
+                }
+                _ => return Ok(partitions),
+            }
+        }
+        return Err(PartitionError::InvalidExtended);
+    }
+    Ok(partitions)
+}
+
+/// Reads the partition table of a device.
+pub async fn read_table(device: &Device) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let sector = read_sectors(device, 0, device.sector_size()).await?;
+    let entries = parse_mbr(&sector)?;
+    let protective = entries
+        .iter()
+        .flatten()
+        .any(|entry| entry.system_id == MBR_TYPE_GPT_PROTECTIVE);
+    if protective {
+        scan_gpt(device).await
+    } else {
+        scan_mbr(device, entries).await
+    }
+}
+
+/// A partition exposed as a block device.
+pub struct Partition {
+    parent: Device,
+    name: String,
+    model: String,
+    info: PartitionInfo,
+}
+
+impl Partition {
+    fn new(parent: Device, info: PartitionInfo) -> Self {
+        let parent_name = parent.name();
+        let separator = match parent_name.bytes().last() {
+            Some(last) if last.is_ascii_digit() => "p",
+            _ => "",
+        };
+        let name = format!("{}{}{}", parent_name, separator, info.number);
+        let model = match &info.kind {
+            PartitionKind::Mbr { system_id, .. } => format!("MBR type {:#04x}", system_id),
+            PartitionKind::Gpt { label, .. } => label.clone(),
+        };
+        Partition {
+            parent,
+            name,
+            model,
+            info,
+        }
+    }
+
+    pub fn parent(&self) -> &Device {
+        &self.parent
+    }
+
+    pub fn info(&self) -> &PartitionInfo {
+        &self.info
+    }
+
+    /// The unique GUID of a GPT partition.
+    pub fn guid(&self) -> Option<Guid> {
+        match self.info.kind {
+            PartitionKind::Gpt { guid, .. } => Some(guid),
+            PartitionKind::Mbr { .. } => None,
+        }
+    }
+
+    /// The label of a GPT partition.
+    pub fn label(&self) -> Option<&str> {
+        match &self.info.kind {
+            PartitionKind::Gpt { label, .. } => Some(label),
+            PartitionKind::Mbr { .. } => None,
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        match self.info.kind {
+            PartitionKind::Mbr { system_id, .. } => MBR_TYPES_EXTENDED.contains(&system_id),
+            PartitionKind::Gpt { .. } => false,
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn sector_size(&self) -> usize {
+        self.parent.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.info.sectors
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            self.parent.read(self.info.start + sector, buf).await
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            self.parent.write(self.info.start + sector, buf).await
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        self.parent.flush()
+    }
+}
+
+static PARTITIONS: Mutex<Vec<Arc<Partition>>> = Mutex::new(Vec::new());
+
+/// Reads the partition table of a device and registers its partitions as block devices.
+///
+/// Partitions reaching beyond the end of the device, and MBR extended partitions, which
+/// only contain the logical partitions, are skipped. Returns the partitions registered.
+pub async fn scan(device: Device) -> Result<Vec<Arc<Partition>>, PartitionError> {
+    let table = read_table(&device).await?;
+    let mut partitions = Vec::new();
+    for info in table {
+        let partition = Arc::new(Partition::new(device.clone(), info));
+        let end = partition.info.start.checked_add(partition.info.sectors);
+        if end.map_or(true, |end| end > device.sector_count()) {
+            warn!("{}: partition beyond the end of the device", partition.name);
+            continue;
+        }
+        if partition.is_extended() {
+            continue;
+        }
+        super::register(partition.clone());
+        interrupts::without_interrupts(|| PARTITIONS.lock().push(partition.clone()));
+        partitions.push(partition);
+    }
+    Ok(partitions)
+}
+
+/// Scans the registered block devices that aren't partitions and have not been scanned.
+///
+/// Returns the number of partitions found. Devices without a partition table are skipped
+/// silently.
+pub async fn scan_devices() -> usize {
+    let mut found = 0;
+    for device in super::devices() {
+        // skip partitions and devices scanned before
+        let known = partitions().iter().any(|partition| {
+            partition.name() == device.name() || partition.parent().name() == device.name()
+        });
+        if known {
+            continue;
+        }
+        match scan(device.clone()).await {
+            Ok(partitions) => {
+                if !partitions.is_empty() {
+                    info!("{}: {} partitions", device.name(), partitions.len());
+                }
+                found += partitions.len();
+            }
+            Err(PartitionError::NoTable) => {}
+            Err(err) => warn!("{}: {}", device.name(), err),
+        }
+    }
+    found
+}
+
+/// Returns all registered partitions.
+pub fn partitions() -> Vec<Arc<Partition>> {
+    interrupts::without_interrupts(|| PARTITIONS.lock().clone())
+}
+
+/// Returns the GPT partition with the given unique GUID.
+pub fn find_by_guid(guid: Guid) -> Option<Arc<Partition>> {
+    partitions()
+        .into_iter()
+        .find(|partition| partition.guid() == Some(guid))
+}
+
+/// Returns the first GPT partition with the given label.
+pub fn find_by_label(label: &str) -> Option<Arc<Partition>> {
+    partitions()
+        .into_iter()
+        .find(|partition| partition.label() == Some(label))
+}
+
+#[test_case]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(&[]), 0);
+}
+
+#[test_case]
+fn test_guid_parse() {
+    let guid = Guid::parse("c12a7328-f81f-11d2-ba4b-00a0c93ec93b").unwrap();
+    assert_eq!(
+        guid.0,
+        [
+            0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e,
+            0xc9, 0x3b
+        ]
+    );
+    assert_eq!(Guid::parse("c12a7328f81f11d2ba4b00a0c93ec93b"), None);
+    assert_eq!(Guid::parse("g12a7328-f81f-11d2-ba4b-00a0c93ec93b"), None);
+}
+
+#[test_case]
+fn test_parse_mbr() {
+    let mut sector = [0; 512];
+    assert_eq!(parse_mbr(&sector), Err(PartitionError::NoTable));
+
+    sector[510..].copy_from_slice(&MBR_SIGNATURE);
+    let entry = &mut sector[MBR_ENTRIES_OFFSET + MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+    entry[0] = 0x80;
+    entry[4] = 0x83;
+    entry[8..12].copy_from_slice(&2048u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&4096u32.to_le_bytes());
+    assert_eq!(
+        parse_mbr(&sector),
+        Ok([
+            None,
+            Some(MbrEntry {
+                bootable: true,
+                system_id: 0x83,
+                start: 2048,
+                sectors: 4096,
+            }),
+            None,
+            None
+        ])
+    );
+}
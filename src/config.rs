@@ -0,0 +1,227 @@
+//! Boot-time configuration from the kernel command line.
+//!
+//! The command line consists of whitespace separated `key=value` pairs; values containing
+//! whitespace are put in double quotes, and a key without a value is a flag. The bootloader
+//! doesn't pass a command line, so it's embedded at build time from the `BLOG_OS_CMDLINE`
+//! environment variable unless `init` is called with another one, e.g. by a test.
+//!
+//! Recognized keys:
+//!
+//! - `heap_size`: the initial heap size, e.g. `256K`
+//! - `log`: log filters, e.g. `info,blog_os::task=debug`
+//! - `layout`: the keyboard layout, e.g. `de`
+//! - `console`: the serial port of the serial shell, or `none`
+//! - `log_port`: the serial port kernel logs are written to
+//! - `com1` to `com4`: serial line settings, e.g. `115200,8n1`
+//! - `shell`: whether the keyboard shell runs commands
+//! - `font`: a PSF font file for the framebuffer console, e.g. `/etc/console.psf`
+//! - `init`: a command to run at boot, e.g. `init="translate 0xb8000"`
+//! - `executor_queue`, `spawn_queue`, `scancode_queue`: queue capacities
+//! - `scrollback`: the number of lines each console keeps for Shift+PageUp
+
+use crate::{
+    drivers::uart::LineConfig,
+    input::{self, Layout},
+    log,
+    serial::{self, ComPort},
+    warn,
+};
+use conquer_once::spin::OnceCell;
+
+/// The command line embedded at build time.
+pub const BUILTIN_CMDLINE: &str = match option_env!("BLOG_OS_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+static CMDLINE: OnceCell<&'static str> = OnceCell::uninit();
+
+/// Sets the command line instead of the built-in one.
+///
+/// Must be called before the configuration is first read, i.e. before `blog_os::init`.
+pub fn init(cmdline: &'static str) {
+    CMDLINE
+        .try_init_once(|| cmdline)
+        .expect("config::init should only be called once, before the config is read");
+}
+
+/// Returns the kernel command line.
+pub fn command_line() -> CommandLine<'static> {
+    CommandLine::new(CMDLINE.get_or_init(|| BUILTIN_CMDLINE))
+}
+
+/// A parsed view of a command line.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandLine<'a> {
+    line: &'a str,
+}
+
+impl<'a> CommandLine<'a> {
+    pub const fn new(line: &'a str) -> Self {
+        CommandLine { line }
+    }
+
+    /// Returns the key-value pairs in order; flags have an empty value.
+    pub fn params(&self) -> Params<'a> {
+        Params { rest: self.line }
+    }
+
+    /// Returns the value of the key; if it's given more than once, the last one wins.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.params()
+            .filter(|&(name, _)| name == key)
+            .last()
+            .map(|(_, value)| value)
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.line
+    }
+}
+
+/// An iterator over the parameters of a command line.
+pub struct Params<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Params<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+
+        let mut in_quotes = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .map_or(rest.len(), |(index, _)| index);
+        let (param, rest) = rest.split_at(end);
+        self.rest = rest;
+
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        Some((key, value))
+    }
+}
+
+/// Parses `1`, `true`, `yes` and `on`, or `0`, `false`, `no` and `off`. An empty value,
+/// i.e. a flag, is `true`.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "" | "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a size in bytes with an optional `K`, `M` or `G` suffix.
+pub fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&value[..value.len() - 1], 10),
+        b'M' => (&value[..value.len() - 1], 20),
+        b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    let number: usize = digits.parse().ok()?;
+    number.checked_mul(1 << shift)
+}
+
+/// Returns the value of the key, or `None` if it isn't given.
+pub fn get(key: &str) -> Option<&'static str> {
+    command_line().get(key)
+}
+
+/// Returns the value of the key parsed with `parse`, or `default` if the key isn't given.
+///
+/// Invalid values are logged and replaced by the default, so a typo on the command line
+/// doesn't prevent booting.
+pub fn get_parsed<T>(key: &str, default: T, parse: impl FnOnce(&str) -> Option<T>) -> T {
+    match get(key) {
+        Some(value) => parse(value).unwrap_or_else(|| {
+            warn!("invalid value '{}' for '{}' on the command line", value, key);
+            default
+        }),
+        None => default,
+    }
+}
+
+pub fn get_bool(key: &str, default: bool) -> bool {
+    get_parsed(key, default, parse_bool)
+}
+
+pub fn get_usize(key: &str, default: usize) -> usize {
+    get_parsed(key, default, |value| value.parse().ok())
+}
+
+/// Like `get_usize`, but rejects 0, which isn't a valid queue capacity.
+pub fn get_capacity(key: &str, default: usize) -> usize {
+    get_parsed(key, default, |value| value.parse().ok().filter(|&n| n > 0))
+}
+
+/// Like `get_usize`, but accepts `K`, `M` and `G` suffixes.
+pub fn get_size(key: &str, default: usize) -> usize {
+    get_parsed(key, default, parse_size)
+}
+
+/// Applies the settings that take effect during `blog_os::init`: serial line settings, the
+/// log port, log filters and the keyboard layout.
+pub(crate) fn apply() {
+    let keys = ["com1", "com2", "com3", "com4"];
+    for (&port, &key) in ComPort::ALL.iter().zip(keys.iter()) {
+        if let Some(config) = get_parsed(key, None, |value| LineConfig::parse(value).map(Some)) {
+            if let Err(err) = serial::configure(port, &config) {
+                warn!("can't configure {}: {}", port, err);
+            }
+        }
+    }
+    if let Some(port) = get_parsed("log_port", None, |value| ComPort::from_name(value).map(Some)) {
+        if let Err(err) = serial::set_log_port(port) {
+            warn!("can't log to {}: {}", port, err);
+        }
+    }
+    if let Some(spec) = get("log") {
+        if let Err(err) = log::apply_filters(spec) {
+            warn!("invalid log filters '{}': {}", spec, err);
+        }
+    }
+    let layout = get_parsed("layout", input::layout(), Layout::from_name);
+    input::set_layout(layout);
+}
+
+#[test_case]
+fn test_params() {
+    let cmdline = CommandLine::new("  quiet log=debug init=\"translate 0xb8000\" log=warn ");
+    let mut params = cmdline.params();
+    assert_eq!(params.next(), Some(("quiet", "")));
+    assert_eq!(params.next(), Some(("log", "debug")));
+    assert_eq!(params.next(), Some(("init", "translate 0xb8000")));
+    assert_eq!(params.next(), Some(("log", "warn")));
+    assert_eq!(params.next(), None);
+    assert_eq!(cmdline.get("log"), Some("warn"));
+    assert_eq!(cmdline.get("quiet"), Some(""));
+    assert_eq!(cmdline.get("layout"), None);
+}
+
+#[test_case]
+fn test_parse_values() {
+    assert_eq!(parse_size("512K"), Some(512 * 1024));
+    assert_eq!(parse_size("4m"), Some(4 * 1024 * 1024));
+    assert_eq!(parse_size("4096"), Some(4096));
+    assert_eq!(parse_size("K"), None);
+    assert_eq!(parse_size(""), None);
+    assert_eq!(parse_bool(""), Some(true));
+    assert_eq!(parse_bool("off"), Some(false));
+    assert_eq!(parse_bool("maybe"), None);
+}
@@ -0,0 +1,9 @@
+//! Drivers for the devices of the machine.
+
+pub mod ahci;
+pub mod ata;
+pub mod bga;
+pub mod pci;
+pub mod ps2;
+pub mod uart;
+pub mod virtio;
@@ -0,0 +1,623 @@
+//! SATA disks on an AHCI controller, accessed with DMA transfers.
+//!
+//! The first controller on the PCI bus is used. Each port executes one command at a time
+//! from command slot 0, without native command queuing. Data is transferred through bounce
+//! frames, so the buffers passed in don't need to be physically contiguous. With QEMU, a
+//! disk is attached with `-device ich9-ahci,id=ahci -drive id=disk,file=disk.img,format=raw,
+//! if=none -device ide-hd,drive=disk,bus=ahci.0`.
+
+use crate::{
+    block::{self, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE},
+    drivers::{
+        ata,
+        pci::{self, DeviceMatch, PciAddress, PciDriver},
+    },
+    info,
+    interrupts::irq::{self, IrqReturn},
+    memory::dma::{self, FRAME_SIZE},
+    task::busy_lock::{BusyGuard, BusyLock},
+    warn,
+};
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::atomic::{fence, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use x86_64::{structures::paging::PhysFrame, VirtAddr};
+
+/// The PCI class code, subclass and programming interface of AHCI controllers.
+const PCI_CLASS: (u8, u8, u8) = (0x01, 0x06, 0x01);
+/// The BAR with the controller registers, called ABAR.
+const PCI_BAR: u8 = 5;
+
+const HBA_GLOBAL_CONTROL: usize = 0x04;
+const HBA_INTERRUPT_STATUS: usize = 0x08;
+const HBA_PORTS_IMPLEMENTED: usize = 0x0c;
+
+const GLOBAL_CONTROL_RESET: u32 = 1 << 0;
+const GLOBAL_CONTROL_INTERRUPTS: u32 = 1 << 1;
+const GLOBAL_CONTROL_AHCI_ENABLE: u32 = 1 << 31;
+
+const PORT_REGISTERS: usize = 0x100;
+const PORT_REGISTERS_SIZE: usize = 0x80;
+
+const PORT_COMMAND_LIST: usize = 0x00;
+const PORT_COMMAND_LIST_UPPER: usize = 0x04;
+const PORT_FIS: usize = 0x08;
+const PORT_FIS_UPPER: usize = 0x0c;
+const PORT_INTERRUPT_STATUS: usize = 0x10;
+const PORT_INTERRUPT_ENABLE: usize = 0x14;
+const PORT_COMMAND: usize = 0x18;
+const PORT_TASK_FILE: usize = 0x20;
+const PORT_SIGNATURE: usize = 0x24;
+const PORT_SATA_STATUS: usize = 0x28;
+const PORT_SATA_ERROR: usize = 0x30;
+const PORT_COMMAND_ISSUE: usize = 0x38;
+
+const COMMAND_START: u32 = 1 << 0;
+const COMMAND_SPIN_UP: u32 = 1 << 1;
+const COMMAND_POWER_ON: u32 = 1 << 2;
+const COMMAND_FIS_RECEIVE_ENABLE: u32 = 1 << 4;
+const COMMAND_FIS_RECEIVE_RUNNING: u32 = 1 << 14;
+const COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+const INTERRUPT_DEVICE_TO_HOST: u32 = 1 << 0;
+const INTERRUPT_TASK_FILE_ERROR: u32 = 1 << 30;
+
+const TASK_FILE_ERROR: u32 = 1 << 0;
+const TASK_FILE_DATA_REQUEST: u32 = 1 << 3;
+const TASK_FILE_BUSY: u32 = 1 << 7;
+
+/// The device detection field of the SATA status when a device is present and
+/// communication is established.
+const SATA_STATUS_PRESENT: u32 = 3;
+/// The signature of a SATA disk; ATAPI devices, port multipliers etc. are not supported.
+const SIGNATURE_ATA: u32 = 0x0000_0101;
+
+const FIS_TYPE_REGISTER_HOST_TO_DEVICE: u8 = 0x27;
+/// Marks a register FIS as a command rather than a control update.
+const FIS_COMMAND: u8 = 0x80;
+const DEVICE_LBA: u8 = 1 << 6;
+
+const COMMAND_READ_DMA_EXT: u8 = 0x25;
+const COMMAND_WRITE_DMA_EXT: u8 = 0x35;
+const COMMAND_FLUSH_EXT: u8 = 0xea;
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+/// The length of a register FIS in dwords, as stored in the command header.
+const COMMAND_FIS_LENGTH: u32 = 5;
+const COMMAND_HEADER_WRITE: u32 = 1 << 6;
+
+/// The layout of the control frame of a port: the command list with 32 headers of 32
+/// bytes, of which only slot 0 is used, the received FIS area and the command table of
+/// slot 0, whose physical region descriptor table (PRDT) starts at offset 0x80.
+const COMMAND_LIST_OFFSET: usize = 0;
+const RECEIVED_FIS_OFFSET: usize = 1024;
+const COMMAND_TABLE_OFFSET: usize = 2048;
+const PRDT_OFFSET: usize = COMMAND_TABLE_OFFSET + 0x80;
+const PRDT_ENTRY_SIZE: usize = 16;
+
+/// The number of bounce frames per port, each described by one PRDT entry.
+const BOUNCE_FRAMES: usize = 16;
+const MAX_SECTORS_PER_COMMAND: usize = BOUNCE_FRAMES * FRAME_SIZE / SECTOR_SIZE;
+
+const MAX_PORTS: usize = 32;
+
+/// How often a register is polled before the controller is considered unresponsive.
+const POLL_LIMIT: usize = 1_000_000;
+
+/// A set of memory-mapped 32-bit registers.
+#[derive(Debug, Clone, Copy)]
+struct Registers {
+    base: VirtAddr,
+}
+
+impl Registers {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + offset as u64).as_ptr()) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + offset as u64).as_mut_ptr(), value) }
+    }
+
+    /// Returns the registers of a port of the controller.
+    fn port(&self, port: usize) -> Registers {
+        Registers {
+            base: self.base + (PORT_REGISTERS + port * PORT_REGISTERS_SIZE) as u64,
+        }
+    }
+
+    /// Polls until the bits of `mask` are clear in the register at `offset`.
+    fn wait_clear(&self, offset: usize, mask: u32) -> Result<(), BlockError> {
+        (0..POLL_LIMIT)
+            .map(|_| self.read(offset))
+            .find(|value| value & mask == 0)
+            .map(|_| ())
+            .ok_or(BlockError::Timeout)
+    }
+}
+
+/// The state of a port shared with the interrupt handler.
+struct PortState {
+    /// Held while a task is using the port; requests are serialized per port.
+    busy: BusyLock,
+    /// The interrupt status bits collected by the interrupt handler since the last command
+    /// was issued.
+    interrupts: AtomicU32,
+    waker: AtomicWaker,
+}
+
+impl PortState {
+    const fn new() -> Self {
+        PortState {
+            busy: BusyLock::new(),
+            interrupts: AtomicU32::new(0),
+            waker: AtomicWaker::new(),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IDLE_PORT: PortState = PortState::new();
+static PORTS: [PortState; MAX_PORTS] = [IDLE_PORT; MAX_PORTS];
+
+/// The virtual address of the controller registers, or 0 before `init`.
+static HBA_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// The interrupt handler of the controller.
+fn interrupt_handler(_irq: u8) -> IrqReturn {
+    let base = HBA_BASE.load(Ordering::Acquire);
+    if base == 0 {
+        return IrqReturn::NotMine;
+    }
+    let hba = Registers {
+        base: VirtAddr::new(base),
+    };
+    let pending = hba.read(HBA_INTERRUPT_STATUS);
+    if pending == 0 {
+        return IrqReturn::NotMine;
+    }
+    for (port, state) in PORTS.iter().enumerate() {
+        if pending & (1 << port) == 0 {
+            continue;
+        }
+        let registers = hba.port(port);
+        // the status bits are cleared by writing ones
+        let status = registers.read(PORT_INTERRUPT_STATUS);
+        registers.write(PORT_INTERRUPT_STATUS, status);
+        state.interrupts.fetch_or(status, Ordering::AcqRel);
+        state.waker.wake();
+    }
+    hba.write(HBA_INTERRUPT_STATUS, pending);
+    IrqReturn::Handled
+}
+
+/// A SATA disk on an AHCI port.
+pub struct AhciDrive {
+    port: usize,
+    registers: Registers,
+    /// Holds the command list, the received FIS area and the command table.
+    control: PhysFrame,
+    bounce: [PhysFrame; BOUNCE_FRAMES],
+    name: String,
+    model: String,
+    sectors: u64,
+}
+
+impl AhciDrive {
+    /// The number of the port on the controller.
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    fn state(&self) -> &'static PortState {
+        &PORTS[self.port]
+    }
+
+    fn write_control(&self, offset: usize, value: u32) {
+        let control = dma::frame_ptr(self.control);
+        unsafe { ptr::write_volatile(control.add(offset) as *mut u32, value) }
+    }
+
+    /// Waits until no other task uses the port.
+    async fn lock(&self) -> PortGuard {
+        PortGuard {
+            _busy: self.state().busy.lock().await,
+            registers: self.registers,
+        }
+    }
+
+    /// Sets up command slot 0 for a command that transfers `bytes` bytes through the bounce
+    /// frames and issues it.
+    ///
+    /// Must be called with the port locked.
+    fn issue(&self, command: u8, sector: u64, bytes: usize, write: bool) {
+        let regions = (bytes + FRAME_SIZE - 1) / FRAME_SIZE;
+        let table = self.control.start_address() + COMMAND_TABLE_OFFSET as u64;
+        let mut header = COMMAND_FIS_LENGTH | (regions as u32) << 16;
+        if write {
+            header |= COMMAND_HEADER_WRITE;
+        }
+        self.write_control(COMMAND_LIST_OFFSET, header);
+        // the byte count transferred, updated by the controller
+        self.write_control(COMMAND_LIST_OFFSET + 4, 0);
+        self.write_control(COMMAND_LIST_OFFSET + 8, table.as_u64() as u32);
+        self.write_control(COMMAND_LIST_OFFSET + 12, (table.as_u64() >> 32) as u32);
+
+        let count = bytes / SECTOR_SIZE;
+        let fis = [
+            FIS_TYPE_REGISTER_HOST_TO_DEVICE,
+            FIS_COMMAND,
+            command,
+            0,
+            sector as u8,
+            (sector >> 8) as u8,
+            (sector >> 16) as u8,
+            DEVICE_LBA,
+            (sector >> 24) as u8,
+            (sector >> 32) as u8,
+            (sector >> 40) as u8,
+            0,
+            count as u8,
+            (count >> 8) as u8,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        for (index, dword) in fis.chunks_exact(4).enumerate() {
+            let value = u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]);
+            self.write_control(COMMAND_TABLE_OFFSET + index * 4, value);
+        }
+
+        for (index, frame) in self.bounce[..regions].iter().enumerate() {
+            let entry = PRDT_OFFSET + index * PRDT_ENTRY_SIZE;
+            let address = frame.start_address().as_u64();
+            let length = (bytes - index * FRAME_SIZE).min(FRAME_SIZE);
+            self.write_control(entry, address as u32);
+            self.write_control(entry + 4, (address >> 32) as u32);
+            self.write_control(entry + 8, 0);
+            // the byte count is stored minus one
+            self.write_control(entry + 12, length as u32 - 1);
+        }
+
+        self.state().interrupts.store(0, Ordering::Release);
+        // the command must be in memory before the controller fetches it
+        fence(Ordering::SeqCst);
+        self.registers.write(PORT_COMMAND_ISSUE, 1);
+    }
+
+    /// Returns the result of the issued command, or `None` if it's still running.
+    fn completion(&self) -> Option<Result<(), BlockError>> {
+        let interrupts = self.state().interrupts.load(Ordering::Acquire)
+            | self.registers.read(PORT_INTERRUPT_STATUS);
+        let task_file = self.registers.read(PORT_TASK_FILE);
+        if interrupts & INTERRUPT_TASK_FILE_ERROR != 0 || task_file & TASK_FILE_ERROR != 0 {
+            // the error register is in the second byte of the task file data
+            Some(Err(BlockError::Device((task_file >> 8) as u8)))
+        } else if self.registers.read(PORT_COMMAND_ISSUE) & 1 == 0 {
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    /// Polls until the issued command completes; used before interrupts are enabled.
+    fn wait_polling(&self) -> Result<(), BlockError> {
+        (0..POLL_LIMIT)
+            .find_map(|_| self.completion())
+            .unwrap_or(Err(BlockError::Timeout))
+    }
+
+    /// Issues a command and waits for the interrupt signaling its completion.
+    ///
+    /// Must be called with the port locked.
+    async fn execute(
+        &self,
+        command: u8,
+        sector: u64,
+        bytes: usize,
+        write: bool,
+    ) -> Result<(), BlockError> {
+        self.issue(command, sector, bytes, write);
+        let result = Completion { drive: self }.await;
+        if result.is_err() {
+            // a failed command stops the port
+            if let Err(err) = restart_port(self.registers) {
+                warn!("{}: can't restart port after error: {}", self.name, err);
+            }
+        }
+        result
+    }
+
+    async fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        block::check_range(self, sector, buf.len())?;
+
+        let _guard = self.lock().await;
+        let chunks = buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_COMMAND) as u64;
+            self.execute(COMMAND_READ_DMA_EXT, start, chunk.len(), false)
+                .await?;
+            dma::copy_from_frames(&self.bounce, chunk);
+        }
+        Ok(())
+    }
+
+    async fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<(), BlockError> {
+        block::check_range(self, sector, buf.len())?;
+
+        let _guard = self.lock().await;
+        let chunks = buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_COMMAND) as u64;
+            dma::copy_to_frames(&self.bounce, chunk);
+            self.execute(COMMAND_WRITE_DMA_EXT, start, chunk.len(), true)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn flush_cache(&self) -> Result<(), BlockError> {
+        let _guard = self.lock().await;
+        self.execute(COMMAND_FLUSH_EXT, 0, 0, false).await
+    }
+}
+
+impl BlockDevice for AhciDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read_sectors(sector, buf))
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write_sectors(sector, buf))
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+struct PortGuard {
+    _busy: BusyGuard<'static>,
+    registers: Registers,
+}
+
+impl Drop for PortGuard {
+    fn drop(&mut self) {
+        // a request dropped while its command runs would leave the controller transferring
+        // data to or from the bounce frames of the next request
+        if self.registers.wait_clear(PORT_COMMAND_ISSUE, 1).is_err() {
+            // stopping the port aborts the command
+            if let Err(err) = restart_port(self.registers) {
+                warn!("AHCI: can't restart port after a dropped request: {}", err);
+            }
+        }
+    }
+}
+
+struct Completion<'a> {
+    drive: &'a AhciDrive,
+}
+
+impl Future for Completion<'_> {
+    type Output = Result<(), BlockError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let drive = self.drive;
+        // fast path
+        if let Some(result) = drive.completion() {
+            return Poll::Ready(result);
+        }
+
+        drive.state().waker.register(&cx.waker());
+        match drive.completion() {
+            Some(result) => {
+                drive.state().waker.take();
+                Poll::Ready(result)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Stops command processing and FIS reception of a port.
+fn stop_port(registers: Registers) -> Result<(), BlockError> {
+    let command = registers.read(PORT_COMMAND);
+    registers.write(PORT_COMMAND, command & !COMMAND_START);
+    registers.wait_clear(PORT_COMMAND, COMMAND_LIST_RUNNING)?;
+    let command = registers.read(PORT_COMMAND);
+    registers.write(PORT_COMMAND, command & !COMMAND_FIS_RECEIVE_ENABLE);
+    registers.wait_clear(PORT_COMMAND, COMMAND_FIS_RECEIVE_RUNNING)
+}
+
+/// Starts FIS reception and command processing of a port whose memory is set up.
+fn start_port(registers: Registers) -> Result<(), BlockError> {
+    registers.wait_clear(PORT_TASK_FILE, TASK_FILE_BUSY | TASK_FILE_DATA_REQUEST)?;
+    let command = registers.read(PORT_COMMAND) | COMMAND_SPIN_UP | COMMAND_POWER_ON;
+    registers.write(PORT_COMMAND, command | COMMAND_FIS_RECEIVE_ENABLE);
+    registers.write(
+        PORT_COMMAND,
+        command | COMMAND_FIS_RECEIVE_ENABLE | COMMAND_START,
+    );
+    Ok(())
+}
+
+/// Restarts a port after an error, clearing the error state.
+fn restart_port(registers: Registers) -> Result<(), BlockError> {
+    stop_port(registers)?;
+    registers.write(PORT_SATA_ERROR, !0);
+    registers.write(PORT_INTERRUPT_STATUS, !0);
+    start_port(registers)
+}
+
+/// Sets up a port with a SATA disk and identifies the disk.
+///
+/// Returns `Ok(None)` if no disk is attached to the port.
+fn probe_port(hba: Registers, port: usize, index: usize) -> Result<Option<AhciDrive>, BlockError> {
+    let registers = hba.port(port);
+    if registers.read(PORT_SATA_STATUS) & 0xf != SATA_STATUS_PRESENT
+        || registers.read(PORT_SIGNATURE) != SIGNATURE_ATA
+    {
+        return Ok(None);
+    }
+    stop_port(registers)?;
+    let (control, bounce) = match dma::allocate_frames::<BOUNCE_FRAMES>() {
+        Some(frames) => frames,
+        None => {
+            warn!("ahci: out of memory for port {}", port);
+            return Ok(None);
+        }
+    };
+
+    let control_address = control.start_address();
+    let fis_address = control_address + RECEIVED_FIS_OFFSET as u64;
+    registers.write(PORT_COMMAND_LIST, control_address.as_u64() as u32);
+    registers.write(
+        PORT_COMMAND_LIST_UPPER,
+        (control_address.as_u64() >> 32) as u32,
+    );
+    registers.write(PORT_FIS, fis_address.as_u64() as u32);
+    registers.write(PORT_FIS_UPPER, (fis_address.as_u64() >> 32) as u32);
+    registers.write(PORT_INTERRUPT_ENABLE, 0);
+    registers.write(PORT_SATA_ERROR, !0);
+    registers.write(PORT_INTERRUPT_STATUS, !0);
+    // on errors from here on, the frames are leaked, since the port may still access them
+    start_port(registers)?;
+
+    let mut drive = AhciDrive {
+        port,
+        registers,
+        control,
+        bounce,
+        name: format!("sata{}", index),
+        model: String::new(),
+        sectors: 0,
+    };
+    drive.issue(COMMAND_IDENTIFY, 0, SECTOR_SIZE, false);
+    drive.wait_polling()?;
+    let mut data = [0u8; SECTOR_SIZE];
+    dma::copy_from_frames(&drive.bounce, &mut data);
+    let mut identify = [0u16; 256];
+    for (word, bytes) in identify.iter_mut().zip(data.chunks_exact(2)) {
+        *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+    // SATA disks always support 48-bit addressing
+    drive.sectors = ata::sector_count(&identify).0;
+    drive.model = ata::model(&identify);
+
+    registers.write(PORT_INTERRUPT_STATUS, !0);
+    registers.write(
+        PORT_INTERRUPT_ENABLE,
+        INTERRUPT_DEVICE_TO_HOST | INTERRUPT_TASK_FILE_ERROR,
+    );
+    Ok(Some(drive))
+}
+
+/// The driver binding AHCI controllers.
+pub static DRIVER: PciDriver = PciDriver {
+    name: "ahci",
+    matches: &[DeviceMatch::Class(
+        PCI_CLASS.0,
+        PCI_CLASS.1,
+        Some(PCI_CLASS.2),
+    )],
+    probe,
+};
+
+/// The number of disks found.
+static DISKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Resets the controller, registers its disks as block devices named `sata0`, `sata1` etc.
+/// and enables its interrupt. Only the first controller is used.
+fn probe(device: PciAddress) -> bool {
+    if HBA_BASE.load(Ordering::Acquire) != 0 {
+        return false;
+    }
+    device.enable_bus_master();
+    let base = match device.map_bar(PCI_BAR, "ahci") {
+        Ok(base) => base,
+        Err(err) => {
+            warn!("ahci {}: can't map registers: {}", device, err);
+            return false;
+        }
+    };
+    let hba = Registers { base };
+
+    hba.write(HBA_GLOBAL_CONTROL, GLOBAL_CONTROL_AHCI_ENABLE);
+    hba.write(
+        HBA_GLOBAL_CONTROL,
+        GLOBAL_CONTROL_AHCI_ENABLE | GLOBAL_CONTROL_RESET,
+    );
+    if hba
+        .wait_clear(HBA_GLOBAL_CONTROL, GLOBAL_CONTROL_RESET)
+        .is_err()
+    {
+        warn!("ahci {}: controller reset timed out", device);
+        return false;
+    }
+    // the reset clears the AHCI enable bit
+    hba.write(HBA_GLOBAL_CONTROL, GLOBAL_CONTROL_AHCI_ENABLE);
+    HBA_BASE.store(base.as_u64(), Ordering::Release);
+
+    let implemented = hba.read(HBA_PORTS_IMPLEMENTED);
+    let mut found = 0;
+    for port in (0..MAX_PORTS).filter(|port| implemented & (1 << port) != 0) {
+        match probe_port(hba, port, found) {
+            Ok(Some(drive)) => {
+                info!(
+                    "{}: {}, {} MiB",
+                    drive.name,
+                    drive.model,
+                    drive.capacity() / (1024 * 1024)
+                );
+                block::register(Arc::new(drive));
+                found += 1;
+            }
+            Ok(None) => {}
+            Err(err) => warn!("ahci port {}: {}", port, err),
+        }
+    }
+
+    if found > 0 {
+        hba.write(HBA_INTERRUPT_STATUS, !0);
+        hba.write(
+            HBA_GLOBAL_CONTROL,
+            GLOBAL_CONTROL_AHCI_ENABLE | GLOBAL_CONTROL_INTERRUPTS,
+        );
+        if let Err(err) = irq::register(device.interrupt_line(), "AHCI", interrupt_handler) {
+            warn!("ahci {}: interrupt unusable: {}", device, err);
+        }
+    }
+    DISKS.store(found, Ordering::Relaxed);
+    true
+}
+
+/// Registers the AHCI driver and binds it to the first controller.
+///
+/// Returns the number of disks found. Requires `memory::init_kernel_memory` to have been
+/// called and the heap to be initialized.
+pub fn init() -> usize {
+    pci::register_driver(&DRIVER);
+    pci::probe_drivers();
+    DISKS.load(Ordering::Relaxed)
+}
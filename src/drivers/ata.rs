@@ -0,0 +1,540 @@
+//! ATA disks on the two legacy IDE channels, accessed with PIO transfers.
+//!
+//! Transfers wait for the interrupt the drive raises when it is ready for the next sector
+//! instead of polling the status register. With QEMU, a disk image is attached with
+//! `-drive file=disk.img,format=raw,if=ide,index=N`, where index 0 is the boot disk, 1 the
+//! primary slave and 2 and 3 the drives of the secondary channel.
+
+use crate::{
+    block::{self, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE},
+    info,
+    interrupts::{
+        self,
+        irq::{self, IrqReturn},
+        InterruptIndex,
+    },
+    task::busy_lock::{BusyGuard, BusyLock},
+    warn,
+};
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use x86_64::instructions::port::Port;
+
+const REGISTER_DATA: u16 = 0;
+const REGISTER_ERROR: u16 = 1;
+const REGISTER_SECTOR_COUNT: u16 = 2;
+const REGISTER_LBA_LOW: u16 = 3;
+const REGISTER_LBA_MID: u16 = 4;
+const REGISTER_LBA_HIGH: u16 = 5;
+const REGISTER_DRIVE: u16 = 6;
+/// The status register when read, the command register when written.
+const REGISTER_STATUS: u16 = 7;
+const REGISTER_COMMAND: u16 = 7;
+
+const CONTROL_NO_INTERRUPTS: u8 = 1 << 1;
+const CONTROL_SOFTWARE_RESET: u8 = 1 << 2;
+
+const STATUS_ERROR: u8 = 1 << 0;
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+const STATUS_FAULT: u8 = 1 << 5;
+const STATUS_BUSY: u8 = 1 << 7;
+
+/// Selects LBA addressing; bits 5 and 7 are obsolete and always set.
+const DRIVE_LBA: u8 = 0xe0;
+const DRIVE_SLAVE: u8 = 1 << 4;
+
+const COMMAND_READ: u8 = 0x20;
+const COMMAND_READ_EXT: u8 = 0x24;
+const COMMAND_WRITE: u8 = 0x30;
+const COMMAND_WRITE_EXT: u8 = 0x34;
+const COMMAND_FLUSH: u8 = 0xe7;
+const COMMAND_FLUSH_EXT: u8 = 0xea;
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+/// The number of sectors transferred by one command; a sector count of 0 means 256.
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+
+/// How often the status register is polled before a drive is considered unresponsive.
+const POLL_LIMIT: usize = 1_000_000;
+
+/// How long a command may take before its channel is reset; flushing the cache of a real
+/// disk can take several seconds.
+const INTERRUPT_TIMEOUT_MS: u64 = 10_000;
+
+/// An IDE channel with up to two drives.
+struct Channel {
+    name: &'static str,
+    base: u16,
+    control: u16,
+    irq: u8,
+    /// Held while a task is using the channel; requests are serialized per channel.
+    busy: BusyLock,
+    /// Set by the interrupt handler, cleared when the interrupt is waited for.
+    interrupted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+static CHANNELS: [Channel; 2] = [
+    Channel::new("ATA primary", 0x1f0, 0x3f6, 14),
+    Channel::new("ATA secondary", 0x170, 0x376, 15),
+];
+
+impl Channel {
+    const fn new(name: &'static str, base: u16, control: u16, irq: u8) -> Self {
+        Channel {
+            name,
+            base,
+            control,
+            irq,
+            busy: BusyLock::new(),
+            interrupted: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write_register(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// Reads the status without acknowledging an interrupt.
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::new(self.control).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::new(self.control).write(value) }
+    }
+
+    /// Selects a drive and waits the 400 ns it needs to put its status on the bus.
+    fn select(&self, drive: u8) {
+        self.write_register(REGISTER_DRIVE, drive);
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    /// Aborts the commands of both drives on the channel.
+    fn reset(&self) {
+        self.set_control(CONTROL_SOFTWARE_RESET);
+        // the reset bit has to stay set for 5 µs
+        for _ in 0..50 {
+            self.alternate_status();
+        }
+        self.set_control(0);
+        let _ = self.wait_not_busy();
+        self.interrupted.store(false, Ordering::Release);
+    }
+
+    /// Polls until the selected drive is no longer busy and returns its status.
+    fn wait_not_busy(&self) -> Result<u8, BlockError> {
+        (0..POLL_LIMIT)
+            .map(|_| self.alternate_status())
+            .find(|status| status & STATUS_BUSY == 0)
+            .ok_or(BlockError::Timeout)
+    }
+
+    fn read_data(&self, buf: &mut [u8]) {
+        let mut port: Port<u16> = Port::new(self.base + REGISTER_DATA);
+        for word in buf.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { port.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_data(&self, buf: &[u8]) {
+        let mut port: Port<u16> = Port::new(self.base + REGISTER_DATA);
+        for word in buf.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+
+    /// Waits until no other task uses the channel.
+    async fn lock(&self) -> ChannelGuard<'_> {
+        ChannelGuard {
+            _busy: self.busy.lock().await,
+            channel: self,
+        }
+    }
+
+    /// Returns a future that resolves to the drive status after the next interrupt, or
+    /// resets the channel and fails if the interrupt does not arrive in time.
+    fn interrupt(&self) -> Interrupt<'_> {
+        Interrupt {
+            channel: self,
+            deadline: interrupts::uptime_ms() + INTERRUPT_TIMEOUT_MS,
+        }
+    }
+}
+
+struct ChannelGuard<'a> {
+    _busy: BusyGuard<'a>,
+    channel: &'a Channel,
+}
+
+impl Drop for ChannelGuard<'_> {
+    fn drop(&mut self) {
+        // a request dropped in the middle of a transfer leaves the drive busy or waiting
+        // for data, which would confuse the next command
+        let status = self.channel.alternate_status();
+        if status & (STATUS_BUSY | STATUS_DATA_REQUEST) != 0 {
+            self.channel.reset();
+        }
+    }
+}
+
+struct Interrupt<'a> {
+    channel: &'a Channel,
+    /// The uptime in milliseconds after which the drive is considered hung.
+    deadline: u64,
+}
+
+impl Future for Interrupt<'_> {
+    type Output = Result<u8, BlockError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let channel = self.channel;
+        // fast path
+        if channel.interrupted.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(Ok(channel.alternate_status()));
+        }
+
+        channel.waker.register(&cx.waker());
+        if channel.interrupted.swap(false, Ordering::AcqRel) {
+            channel.waker.take();
+            Poll::Ready(Ok(channel.alternate_status()))
+        } else if interrupts::uptime_ms() >= self.deadline {
+            channel.waker.take();
+            warn!("{}: command timed out, resetting the channel", channel.name);
+            channel.reset();
+            Poll::Ready(Err(BlockError::Timeout))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The interrupt handler for IRQs 14 and 15.
+fn interrupt_handler(irq: u8) -> IrqReturn {
+    let channel = match CHANNELS.iter().find(|channel| channel.irq == irq) {
+        Some(channel) => channel,
+        None => return IrqReturn::NotMine,
+    };
+    // reading the status register acknowledges the interrupt
+    channel.read_register(REGISTER_STATUS);
+    channel.interrupted.store(true, Ordering::Release);
+    channel.waker.wake();
+    IrqReturn::Handled
+}
+
+/// Shares the timer interrupt to repoll the tasks waiting for a drive, so that they notice
+/// when their deadline has passed.
+fn timer_interrupt_handler(_irq: u8) -> IrqReturn {
+    for channel in CHANNELS.iter() {
+        channel.waker.wake();
+    }
+    IrqReturn::Handled
+}
+
+/// An ATA disk.
+pub struct AtaDrive {
+    channel: &'static Channel,
+    slave: bool,
+    name: String,
+    model: String,
+    sectors: u64,
+    lba48: bool,
+}
+
+impl AtaDrive {
+    /// Whether the drive supports 48-bit addressing, which is used if it does.
+    pub fn lba48(&self) -> bool {
+        self.lba48
+    }
+
+    fn drive_select(&self) -> u8 {
+        if self.slave {
+            DRIVE_LBA | DRIVE_SLAVE
+        } else {
+            DRIVE_LBA
+        }
+    }
+
+    /// Sends a command for `count` sectors starting at `sector` to the drive.
+    ///
+    /// Must be called with the channel locked.
+    fn issue(&self, command: u8, sector: u64, count: usize) -> Result<(), BlockError> {
+        let channel = self.channel;
+        if self.lba48 {
+            channel.select(self.drive_select());
+            channel.wait_not_busy()?;
+            // the high bytes are written first; the registers keep the two last values
+            channel.write_register(REGISTER_SECTOR_COUNT, (count >> 8) as u8);
+            channel.write_register(REGISTER_LBA_LOW, (sector >> 24) as u8);
+            channel.write_register(REGISTER_LBA_MID, (sector >> 32) as u8);
+            channel.write_register(REGISTER_LBA_HIGH, (sector >> 40) as u8);
+        } else {
+            channel.select(self.drive_select() | (sector >> 24) as u8 & 0x0f);
+            channel.wait_not_busy()?;
+        }
+        channel.write_register(REGISTER_SECTOR_COUNT, count as u8);
+        channel.write_register(REGISTER_LBA_LOW, sector as u8);
+        channel.write_register(REGISTER_LBA_MID, (sector >> 8) as u8);
+        channel.write_register(REGISTER_LBA_HIGH, (sector >> 16) as u8);
+        channel.interrupted.store(false, Ordering::Release);
+        channel.write_register(REGISTER_COMMAND, command);
+        Ok(())
+    }
+
+    fn check_status(&self, status: u8, data_expected: bool) -> Result<(), BlockError> {
+        if status & (STATUS_ERROR | STATUS_FAULT) != 0 {
+            Err(BlockError::Device(
+                self.channel.read_register(REGISTER_ERROR),
+            ))
+        } else if data_expected && status & STATUS_DATA_REQUEST == 0 {
+            Err(BlockError::Device(0))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        block::check_range(self, sector, buf.len())?;
+        let command = if self.lba48 {
+            COMMAND_READ_EXT
+        } else {
+            COMMAND_READ
+        };
+
+        let _guard = self.channel.lock().await;
+        let chunks = buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_COMMAND) as u64;
+            self.issue(command, start, chunk.len() / SECTOR_SIZE)?;
+            // the drive interrupts whenever the next sector can be read
+            for data in chunk.chunks_mut(SECTOR_SIZE) {
+                let status = self.channel.interrupt().await?;
+                self.check_status(status, true)?;
+                self.channel.read_data(data);
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<(), BlockError> {
+        block::check_range(self, sector, buf.len())?;
+        let command = if self.lba48 {
+            COMMAND_WRITE_EXT
+        } else {
+            COMMAND_WRITE
+        };
+
+        let _guard = self.channel.lock().await;
+        let chunks = buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_COMMAND) as u64;
+            self.issue(command, start, chunk.len() / SECTOR_SIZE)?;
+            for (chunk_sector, data) in chunk.chunks(SECTOR_SIZE).enumerate() {
+                // the drive only interrupts after a sector was written
+                let status = if chunk_sector == 0 {
+                    self.channel.wait_not_busy()?
+                } else {
+                    self.channel.interrupt().await?
+                };
+                self.check_status(status, true)?;
+                self.channel.write_data(data);
+            }
+            let status = self.channel.interrupt().await?;
+            self.check_status(status, false)?;
+        }
+        Ok(())
+    }
+
+    async fn flush_cache(&self) -> Result<(), BlockError> {
+        let command = if self.lba48 {
+            COMMAND_FLUSH_EXT
+        } else {
+            COMMAND_FLUSH
+        };
+
+        let _guard = self.channel.lock().await;
+        self.issue(command, 0, 0)?;
+        let status = self.channel.interrupt().await?;
+        self.check_status(status, false)
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read_sectors(sector, buf))
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write_sectors(sector, buf))
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+/// Sends IDENTIFY to a drive and returns its identification data, or `None` if there is
+/// no ATA drive. ATAPI drives, e.g. CD-ROMs, are not supported.
+///
+/// Polls, so the channel's interrupts should be disabled.
+fn identify(channel: &Channel, drive: u8) -> Option<[u16; 256]> {
+    channel.select(drive);
+    channel.write_register(REGISTER_SECTOR_COUNT, 0);
+    channel.write_register(REGISTER_LBA_LOW, 0);
+    channel.write_register(REGISTER_LBA_MID, 0);
+    channel.write_register(REGISTER_LBA_HIGH, 0);
+    channel.write_register(REGISTER_COMMAND, COMMAND_IDENTIFY);
+    if channel.alternate_status() == 0 {
+        return None;
+    }
+    channel.wait_not_busy().ok()?;
+    // ATAPI and SATA devices put a signature into the LBA registers
+    if channel.read_register(REGISTER_LBA_MID) != 0 || channel.read_register(REGISTER_LBA_HIGH) != 0
+    {
+        return None;
+    }
+    let status = (0..POLL_LIMIT)
+        .map(|_| channel.alternate_status())
+        .find(|status| status & (STATUS_DATA_REQUEST | STATUS_ERROR) != 0)?;
+    if status & STATUS_ERROR != 0 {
+        return None;
+    }
+
+    let mut data = [0u8; SECTOR_SIZE];
+    channel.read_data(&mut data);
+    let mut words = [0u16; 256];
+    for (word, bytes) in words.iter_mut().zip(data.chunks_exact(2)) {
+        *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+    Some(words)
+}
+
+/// Returns the number of sectors and whether 48-bit addressing is supported.
+pub(super) fn sector_count(identify: &[u16; 256]) -> (u64, bool) {
+    let lba48 = identify[83] & (1 << 10) != 0;
+    let words = if lba48 {
+        &identify[100..104]
+    } else {
+        &identify[60..62]
+    };
+    let sectors = words
+        .iter()
+        .rev()
+        .fold(0, |sectors, &word| sectors << 16 | word as u64);
+    (sectors, lba48)
+}
+
+/// The model name is stored in words 27 to 46, with the first character in the high byte.
+pub(super) fn model(identify: &[u16; 256]) -> String {
+    let model: String = identify[27..47]
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .map(char::from)
+        .collect();
+    String::from(model.trim())
+}
+
+/// Detects the drives on both channels, registers them as block devices named `ata0` to
+/// `ata3` and enables the interrupts of the channels with drives.
+///
+/// Returns the number of drives found. Requires the heap to be initialized.
+pub fn init() -> usize {
+    let found = init_channels();
+    if found > 0 {
+        if let Err(err) = irq::register(
+            InterruptIndex::Timer.as_irq(),
+            "ATA timeout",
+            timer_interrupt_handler,
+        ) {
+            warn!("ATA commands cannot time out: {}", err);
+        }
+    }
+    found
+}
+
+fn init_channels() -> usize {
+    let mut found = 0;
+    for (channel_index, channel) in CHANNELS.iter().enumerate() {
+        // without a controller, the bus floats high
+        if channel.alternate_status() == 0xff {
+            continue;
+        }
+        channel.set_control(CONTROL_NO_INTERRUPTS);
+        let mut drives = 0;
+        for &slave in [false, true].iter() {
+            let drive_select = if slave {
+                DRIVE_LBA | DRIVE_SLAVE
+            } else {
+                DRIVE_LBA
+            };
+            let identify = match identify(channel, drive_select) {
+                Some(identify) => identify,
+                None => continue,
+            };
+            let (sectors, lba48) = sector_count(&identify);
+            let drive = AtaDrive {
+                channel,
+                slave,
+                name: format!("ata{}", channel_index * 2 + slave as usize),
+                model: model(&identify),
+                sectors,
+                lba48,
+            };
+            info!(
+                "{}: {}, {} MiB",
+                drive.name,
+                drive.model,
+                drive.capacity() / (1024 * 1024)
+            );
+            block::register(Arc::new(drive));
+            drives += 1;
+        }
+        channel.set_control(0);
+
+        if drives > 0 {
+            if let Err(err) = irq::register(channel.irq, channel.name, interrupt_handler) {
+                warn!("{} channel unusable: {}", channel.name, err);
+            }
+        }
+        found += drives;
+    }
+    found
+}
+
+#[test_case]
+fn test_identify_parsing() {
+    let mut identify = [0u16; 256];
+    identify[60] = 0x0000;
+    identify[61] = 0x0002;
+    assert_eq!(sector_count(&identify), (0x2_0000, false));
+
+    identify[83] = 1 << 10;
+    identify[100] = 0x1234;
+    identify[102] = 0x0001;
+    assert_eq!(sector_count(&identify), (0x0001_0000_1234, true));
+}
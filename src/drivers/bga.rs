@@ -0,0 +1,136 @@
+//! The Bochs Graphics Adapter, the display device of Bochs and of QEMU's `-vga std`.
+
+use super::pci;
+use crate::{
+    graphics::Framebuffer,
+    memory::{mmio, vma::AreaError},
+};
+use core::fmt;
+use x86_64::{instructions::port::Port, PhysAddr};
+
+const INDEX_PORT: u16 = 0x01ce;
+const DATA_PORT: u16 = 0x01cf;
+
+const REGISTER_ID: u16 = 0;
+const REGISTER_X_RESOLUTION: u16 = 1;
+const REGISTER_Y_RESOLUTION: u16 = 2;
+const REGISTER_BPP: u16 = 3;
+const REGISTER_ENABLE: u16 = 4;
+const REGISTER_VIRTUAL_WIDTH: u16 = 6;
+const REGISTER_X_OFFSET: u16 = 8;
+const REGISTER_Y_OFFSET: u16 = 9;
+
+const ENABLED: u16 = 0x01;
+const LINEAR_FRAMEBUFFER_ENABLED: u16 = 0x40;
+/// Keeps the video memory, which holds the text buffer and font of the VGA text mode.
+const NO_CLEAR_MEMORY: u16 = 0x80;
+
+/// The lowest interface version supporting 32 bits per pixel and a linear framebuffer.
+const MIN_VERSION: u16 = 0xb0c4;
+const MAX_VERSION: u16 = 0xb0c5;
+
+const PCI_VENDOR_ID: u16 = 0x1234;
+const PCI_DEVICE_ID: u16 = 0x1111;
+/// The framebuffer address used by Bochs, which has no PCI device for the adapter.
+const DEFAULT_FRAMEBUFFER: u64 = 0xe000_0000;
+
+/// The maximum resolution supported by the adapter.
+pub const MAX_WIDTH: u16 = 1600;
+pub const MAX_HEIGHT: u16 = 1200;
+
+#[derive(Debug)]
+pub enum BgaError {
+    NotPresent,
+    UnsupportedMode { width: u16, height: u16 },
+    Map(AreaError),
+}
+
+impl fmt::Display for BgaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BgaError::NotPresent => write!(f, "no Bochs graphics adapter found"),
+            BgaError::UnsupportedMode { width, height } => {
+                write!(f, "unsupported mode {}x{}", width, height)
+            }
+            BgaError::Map(err) => write!(f, "failed to map the framebuffer: {}", err),
+        }
+    }
+}
+
+fn read_register(index: u16) -> u16 {
+    unsafe {
+        Port::new(INDEX_PORT).write(index);
+        Port::new(DATA_PORT).read()
+    }
+}
+
+fn write_register(index: u16, value: u16) {
+    unsafe {
+        Port::new(INDEX_PORT).write(index);
+        Port::new(DATA_PORT).write(value);
+    }
+}
+
+/// Returns whether a BGA with linear framebuffer support is present.
+pub fn is_present() -> bool {
+    let version = read_register(REGISTER_ID);
+    (MIN_VERSION..=MAX_VERSION).contains(&version)
+}
+
+/// Returns whether a graphics mode set by `set_mode` is shown.
+pub fn is_enabled() -> bool {
+    is_present() && read_register(REGISTER_ENABLE) & ENABLED != 0
+}
+
+/// Switches to a graphics mode with 32 bits per pixel and returns its framebuffer.
+///
+/// The VGA text buffer is no longer shown afterwards. The framebuffer initially contains
+/// whatever the video memory held before. Requires `memory::init_kernel_memory` to have
+/// been called.
+pub fn set_mode(width: u16, height: u16) -> Result<Framebuffer, BgaError> {
+    if !is_present() {
+        return Err(BgaError::NotPresent);
+    }
+    if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+        return Err(BgaError::UnsupportedMode { width, height });
+    }
+
+    let framebuffer_address = pci::find(PCI_VENDOR_ID, PCI_DEVICE_ID)
+        .and_then(|device| device.memory_bar(0))
+        .unwrap_or(DEFAULT_FRAMEBUFFER);
+
+    write_register(REGISTER_ENABLE, 0);
+    write_register(REGISTER_X_RESOLUTION, width);
+    write_register(REGISTER_Y_RESOLUTION, height);
+    write_register(REGISTER_BPP, 32);
+    write_register(
+        REGISTER_ENABLE,
+        ENABLED | LINEAR_FRAMEBUFFER_ENABLED | NO_CLEAR_MEMORY,
+    );
+
+    if read_register(REGISTER_X_RESOLUTION) != width
+        || read_register(REGISTER_Y_RESOLUTION) != height
+    {
+        write_register(REGISTER_ENABLE, 0);
+        return Err(BgaError::UnsupportedMode { width, height });
+    }
+    write_register(REGISTER_X_OFFSET, 0);
+    write_register(REGISTER_Y_OFFSET, 0);
+    let stride = read_register(REGISTER_VIRTUAL_WIDTH) as usize;
+
+    let size = (stride * height as usize * 4) as u64;
+    let base = mmio::map("bga framebuffer", PhysAddr::new(framebuffer_address), size)
+        .map_err(BgaError::Map)?;
+    Ok(unsafe { Framebuffer::new(base.as_mut_ptr(), width as usize, height as usize, stride) })
+}
+
+/// Switches the adapter off, leaving the display to the VGA registers.
+///
+/// Setting a mode reprograms the VGA registers, so `vga_buffer::enable_text_mode` has to
+/// be called to show the text buffer again. The text mode font is kept unless the first
+/// 256 KiB of the framebuffer were drawn to.
+pub fn disable() {
+    if is_present() {
+        write_register(REGISTER_ENABLE, 0);
+    }
+}
@@ -0,0 +1,778 @@
+//! PCI devices: configuration space access, bus enumeration, BAR decoding and the binding
+//! of drivers to functions.
+//!
+//! The configuration space is accessed through the legacy I/O ports until `init` finds an
+//! enhanced configuration access mechanism (ECAM) region in the ACPI MCFG table, e.g. on
+//! QEMU's `q35` machine; ECAM maps each bus's configuration space into memory.
+
+use crate::{
+    acpi, info,
+    memory::{mmio, vma::AreaError},
+};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Serializes the two-step accesses through the address and data ports.
+static CONFIG_PORTS: Mutex<()> = Mutex::new(());
+
+const HEADER_TYPE_ENDPOINT: u8 = 0x00;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+
+const CAPABILITY_MSI: u8 = 0x05;
+const CAPABILITY_MSIX: u8 = 0x11;
+
+/// The configuration space of a bus in an ECAM region: 32 devices with 8 functions of
+/// 4 KiB each.
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNMAPPED: AtomicU64 = AtomicU64::new(0);
+
+/// An ECAM region of PCI segment 0, with the buses mapped so far.
+struct Ecam {
+    base: PhysAddr,
+    start_bus: u8,
+    end_bus: u8,
+    /// The virtual address of each bus's configuration space, 0 until it is mapped.
+    mapped: [AtomicU64; 256],
+}
+
+static ECAM: OnceCell<Ecam> = OnceCell::uninit();
+/// Serializes the mapping of buses.
+static ECAM_MAP: Mutex<()> = Mutex::new(());
+
+/// An entry of the ACPI MCFG table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Parses the body of an MCFG table, which starts with 8 reserved bytes.
+pub fn parse_mcfg(body: &[u8]) -> impl Iterator<Item = McfgEntry> + '_ {
+    body.get(8..)
+        .unwrap_or(&[])
+        .chunks_exact(16)
+        .map(|entry| McfgEntry {
+            base: u64::from_le_bytes([
+                entry[0], entry[1], entry[2], entry[3], entry[4], entry[5], entry[6], entry[7],
+            ]),
+            segment: u16::from_le_bytes([entry[8], entry[9]]),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+}
+
+/// Returns the virtual address of a function's configuration space in the ECAM region,
+/// mapping its bus on first use, or `None` if ECAM isn't used for the bus.
+fn ecam_address(address: &PciAddress) -> Option<VirtAddr> {
+    let ecam = ECAM.get()?;
+    if address.bus < ecam.start_bus || address.bus > ecam.end_bus {
+        return None;
+    }
+    let slot = &ecam.mapped[address.bus as usize];
+    let mut bus_base = slot.load(Ordering::Acquire);
+    if bus_base == 0 {
+        let _map = ECAM_MAP.lock();
+        bus_base = slot.load(Ordering::Acquire);
+        if bus_base == 0 {
+            let phys = ecam.base + (address.bus - ecam.start_bus) as u64 * ECAM_BUS_SIZE;
+            bus_base = mmio::map("pci ecam", phys, ECAM_BUS_SIZE).ok()?.as_u64();
+            slot.store(bus_base, Ordering::Release);
+        }
+    }
+    let offset = (address.device as u64) << 15 | (address.function as u64) << 12;
+    Some(VirtAddr::new(bus_base + offset))
+}
+
+/// Whether the configuration space of `bus` is accessed through ECAM, which maps it on
+/// first use.
+pub fn is_ecam_mapped(bus: u8) -> bool {
+    ECAM.get().map_or(false, |ecam| {
+        ecam.mapped[bus as usize].load(Ordering::Acquire) != 0
+    })
+}
+
+/// The location of a PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        1 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset as u32 & 0xfc)
+    }
+
+    /// Reads the 32-bit register at `offset` (rounded down to a multiple of 4).
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        use x86_64::instructions::interrupts;
+
+        if let Some(base) = ecam_address(self) {
+            let register = base + (offset & 0xfc) as u64;
+            return unsafe { core::ptr::read_volatile(register.as_ptr()) };
+        }
+        interrupts::without_interrupts(|| {
+            let _ports = CONFIG_PORTS.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+                Port::new(CONFIG_DATA).read()
+            }
+        })
+    }
+
+    /// Writes the 32-bit register at `offset` (rounded down to a multiple of 4).
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        use x86_64::instructions::interrupts;
+
+        if let Some(base) = ecam_address(self) {
+            let register = base + (offset & 0xfc) as u64;
+            return unsafe { core::ptr::write_volatile(register.as_mut_ptr(), value) };
+        }
+        interrupts::without_interrupts(|| {
+            let _ports = CONFIG_PORTS.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+                Port::new(CONFIG_DATA).write(value);
+            }
+        })
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read_u16(0x00)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read_u16(0x02)
+    }
+
+    /// Returns the class code, subclass and programming interface.
+    pub fn class(&self) -> (u8, u8, u8) {
+        let register = self.read_u32(0x08);
+        (
+            (register >> 24) as u8,
+            (register >> 16) as u8,
+            (register >> 8) as u8,
+        )
+    }
+
+    /// Returns the legacy IRQ line the firmware routed the function's interrupt to.
+    pub fn interrupt_line(&self) -> u8 {
+        self.read_u8(0x3c)
+    }
+
+    /// Enables decoding of the I/O and memory BARs and lets the function initiate DMA.
+    pub fn enable_bus_master(&self) {
+        const COMMAND_IO_SPACE: u32 = 1 << 0;
+        const COMMAND_MEMORY_SPACE: u32 = 1 << 1;
+        const COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+        // the upper half is the status register, whose bits are cleared by writing ones
+        let command = self.read_u32(0x04) & 0xffff;
+        self.write_u32(
+            0x04,
+            command | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER,
+        );
+    }
+
+    /// Returns the offsets of the capabilities in the capability list, with their IDs.
+    pub fn capabilities(&self) -> Capabilities {
+        const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+        let next = if self.read_u16(0x06) & STATUS_CAPABILITIES != 0 {
+            self.read_u8(0x34) & 0xfc
+        } else {
+            0
+        };
+        Capabilities {
+            address: *self,
+            next,
+            remaining: 48,
+        }
+    }
+
+    /// Returns whether a function exists at this address.
+    pub fn exists(&self) -> bool {
+        self.vendor_id() != 0xffff
+    }
+
+    /// Returns the raw value of the base address register `index` (0 to 5).
+    pub fn bar(&self, index: u8) -> u32 {
+        self.read_u32(0x10 + 4 * index)
+    }
+
+    /// Returns the port of I/O BAR `index`, or `None` for memory BARs.
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = self.bar(index);
+        if bar & 1 == 0 {
+            return None;
+        }
+        Some((bar & !0x3) as u16)
+    }
+
+    /// Returns the physical address of memory BAR `index`, handling 64-bit BARs.
+    ///
+    /// Returns `None` for I/O BARs.
+    pub fn memory_bar(&self, index: u8) -> Option<u64> {
+        let low = self.bar(index);
+        if low & 1 != 0 {
+            return None;
+        }
+        let mut address = (low & !0xf) as u64;
+        if (low >> 1) & 0b11 == 0b10 {
+            address |= (self.bar(index + 1) as u64) << 32;
+        }
+        Some(address)
+    }
+
+    /// Returns the header type, without the multi-function bit.
+    pub fn header_type(&self) -> u8 {
+        self.read_u8(0x0e) & 0x7f
+    }
+
+    /// Returns whether the device at this address implements more than one function.
+    pub fn is_multi_function(&self) -> bool {
+        self.read_u8(0x0e) & 0x80 != 0
+    }
+
+    /// Returns the bus behind the function if it is a PCI-to-PCI bridge.
+    pub fn secondary_bus(&self) -> Option<u8> {
+        if self.header_type() == HEADER_TYPE_BRIDGE {
+            Some(self.read_u8(0x19))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of BARs in the header: 6 for endpoints, 2 for bridges.
+    pub fn bar_count(&self) -> u8 {
+        match self.header_type() {
+            HEADER_TYPE_ENDPOINT => 6,
+            HEADER_TYPE_BRIDGE => 2,
+            _ => 0,
+        }
+    }
+
+    /// Returns whether BAR `index` holds the upper half of a 64-bit memory BAR.
+    fn is_upper_half(&self, index: u8) -> bool {
+        let mut current = 0;
+        while current < index {
+            if self.bar(current) & 0b111 == 0b100 {
+                if current + 1 == index {
+                    return true;
+                }
+                current += 2;
+            } else {
+                current += 1;
+            }
+        }
+        false
+    }
+
+    /// Decodes BAR `index` with its size, which is found by writing all ones to the
+    /// register and reading back which address bits are fixed.
+    ///
+    /// Returns `None` for unimplemented BARs and the upper halves of 64-bit BARs. Decoding
+    /// is disabled while the register is probed, so this must not race with accesses to
+    /// the function's registers.
+    pub fn decode_bar(&self, index: u8) -> Option<Bar> {
+        use x86_64::instructions::interrupts;
+
+        if index >= self.bar_count() {
+            return None;
+        }
+        if self.is_upper_half(index) {
+            return None;
+        }
+        let offset = 0x10 + 4 * index;
+        let low = self.read_u32(offset);
+        let wide = low & 0b111 == 0b100 && index + 1 < self.bar_count();
+
+        let (mask, upper_mask) = interrupts::without_interrupts(|| {
+            let command = self.read_u32(0x04) & 0xffff;
+            self.write_u32(0x04, command & !0b11);
+            let probe = |offset: u8| {
+                let value = self.read_u32(offset);
+                self.write_u32(offset, !0);
+                let mask = self.read_u32(offset);
+                self.write_u32(offset, value);
+                mask
+            };
+            let mask = probe(offset);
+            let upper_mask = if wide { probe(offset + 4) } else { 0 };
+            self.write_u32(0x04, command);
+            (mask, upper_mask)
+        });
+
+        if mask == 0 {
+            return None;
+        }
+        if low & 1 != 0 {
+            // the upper half of I/O BARs may be hardwired to 0
+            let mask = (mask | 0xffff_0000) & !0x3;
+            return Some(Bar::Io {
+                port: (low & !0x3) as u16,
+                size: (!mask).wrapping_add(1) as u16,
+            });
+        }
+        let upper_mask = if wide { upper_mask } else { 0xffff_ffff };
+        let mask = (upper_mask as u64) << 32 | (mask & !0xf) as u64;
+        Some(Bar::Memory {
+            address: self.memory_bar(index)?,
+            size: (!mask).wrapping_add(1),
+            prefetchable: low & 0b1000 != 0,
+            wide,
+        })
+    }
+
+    /// Returns the implemented BARs with their indices.
+    pub fn bars(&self) -> Vec<(u8, Bar)> {
+        (0..self.bar_count())
+            .filter_map(|index| Some((index, self.decode_bar(index)?)))
+            .collect()
+    }
+
+    /// Maps memory BAR `index` to virtual memory, under the given name.
+    pub fn map_bar(&self, index: u8, name: &'static str) -> Result<VirtAddr, PciError> {
+        match self.decode_bar(index) {
+            Some(Bar::Memory { address, size, .. }) => {
+                mmio::map(name, PhysAddr::new(address), size).map_err(PciError::Map)
+            }
+            Some(Bar::Io { .. }) => Err(PciError::NotMemory(index)),
+            None => Err(PciError::NoBar(index)),
+        }
+    }
+
+    /// Returns the offset of the first capability with the given ID.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities()
+            .find(|&(capability, _)| capability == id)
+            .map(|(_, offset)| offset)
+    }
+
+    /// Parses the MSI capability, if the function has one.
+    pub fn msi(&self) -> Option<Msi> {
+        let offset = self.find_capability(CAPABILITY_MSI)?;
+        let control = self.read_u16(offset + 2);
+        Some(Msi {
+            offset,
+            enabled: control & 1 != 0,
+            vectors: 1 << ((control >> 1) as u8 & 0b111).min(5),
+            is_64bit: control & (1 << 7) != 0,
+            per_vector_masking: control & (1 << 8) != 0,
+        })
+    }
+
+    /// Parses the MSI-X capability, if the function has one.
+    pub fn msix(&self) -> Option<MsiX> {
+        let offset = self.find_capability(CAPABILITY_MSIX)?;
+        let control = self.read_u16(offset + 2);
+        let table = self.read_u32(offset + 4);
+        let pba = self.read_u32(offset + 8);
+        Some(MsiX {
+            offset,
+            enabled: control & (1 << 15) != 0,
+            table_size: (control & 0x7ff) + 1,
+            table_bar: (table & 0b111) as u8,
+            table_offset: table & !0b111,
+            pba_bar: (pba & 0b111) as u8,
+            pba_offset: pba & !0b111,
+        })
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Whether the BAR takes two registers for a 64-bit address.
+        wide: bool,
+    },
+    Io {
+        port: u16,
+        size: u16,
+    },
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                wide,
+            } => {
+                write!(
+                    f,
+                    "memory at {:#x} ({}-bit",
+                    address,
+                    if wide { 64 } else { 32 }
+                )?;
+                if prefetchable {
+                    write!(f, ", prefetchable")?;
+                }
+                write!(f, ") [size={:#x}]", size)
+            }
+            Bar::Io { port, size } => write!(f, "I/O ports at {:#x} [size={:#x}]", port, size),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PciError {
+    NoBar(u8),
+    NotMemory(u8),
+    Map(AreaError),
+}
+
+impl fmt::Display for PciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PciError::NoBar(index) => write!(f, "BAR {} is not implemented", index),
+            PciError::NotMemory(index) => write!(f, "BAR {} is not a memory BAR", index),
+            PciError::Map(err) => write!(f, "can't map BAR: {}", err),
+        }
+    }
+}
+
+/// The MSI capability of a function.
+#[derive(Debug, Clone, Copy)]
+pub struct Msi {
+    /// The offset of the capability in the configuration space.
+    pub offset: u8,
+    pub enabled: bool,
+    /// The number of vectors the function can request.
+    pub vectors: u8,
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+}
+
+/// The MSI-X capability of a function.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiX {
+    /// The offset of the capability in the configuration space.
+    pub offset: u8,
+    pub enabled: bool,
+    /// The number of entries in the vector table.
+    pub table_size: u16,
+    /// The BAR and offset of the vector table.
+    pub table_bar: u8,
+    pub table_offset: u32,
+    /// The BAR and offset of the pending bit array.
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+/// Returns a name for a class code and subclass.
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVM controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus",
+        (0x0c, _) => "Serial bus controller",
+        (0xff, _) => "Unassigned class",
+        _ => "Unknown class",
+    }
+}
+
+/// Returns the first function with the given vendor and device ID.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciAddress> {
+    functions()
+        .into_iter()
+        .find(|address| address.vendor_id() == vendor_id && address.device_id() == device_id)
+}
+
+/// Returns the first function with the given class code, subclass and programming
+/// interface.
+pub fn find_class(class: u8, subclass: u8, prog_if: u8) -> Option<PciAddress> {
+    functions()
+        .into_iter()
+        .find(|address| address.class() == (class, subclass, prog_if))
+}
+
+/// Returns all functions reachable from the host bridges, in address order.
+///
+/// The buses are enumerated recursively: each function of a multi-function host bridge
+/// at 00:00.0 roots the bus with its function number, and PCI-to-PCI bridges lead to
+/// their secondary buses. Requires the heap to be initialized.
+pub fn functions() -> Vec<PciAddress> {
+    let mut functions = Vec::new();
+    let mut scanned = [false; 256];
+    let host_bridge = PciAddress::new(0, 0, 0);
+    if host_bridge.is_multi_function() {
+        for function in 0..8 {
+            if PciAddress::new(0, 0, function).exists() {
+                scan_bus(function, &mut scanned, &mut functions);
+            }
+        }
+    } else {
+        scan_bus(0, &mut scanned, &mut functions);
+    }
+    functions.sort_unstable();
+    functions
+}
+
+fn scan_bus(bus: u8, scanned: &mut [bool; 256], functions: &mut Vec<PciAddress>) {
+    if scanned[bus as usize] {
+        return;
+    }
+    scanned[bus as usize] = true;
+    for device in 0..32 {
+        let first = PciAddress::new(bus, device, 0);
+        if !first.exists() {
+            continue;
+        }
+        let count = if first.is_multi_function() { 8 } else { 1 };
+        for function in 0..count {
+            let address = PciAddress::new(bus, device, function);
+            if !address.exists() {
+                continue;
+            }
+            functions.push(address);
+            if let Some(secondary) = address.secondary_bus() {
+                scan_bus(secondary, scanned, functions);
+            }
+        }
+    }
+}
+
+/// An iterator over the capability list of a function, yielding `(id, offset)` pairs.
+pub struct Capabilities {
+    address: PciAddress,
+    next: u8,
+    /// Bounds the walk in case of a malformed, cyclic list.
+    remaining: u8,
+}
+
+impl Iterator for Capabilities {
+    type Item = (u8, u8);
+
+    fn next(&mut self) -> Option<(u8, u8)> {
+        if self.next == 0 || self.remaining == 0 {
+            return None;
+        }
+        let offset = self.next;
+        self.next = self.address.read_u8(offset + 1) & 0xfc;
+        self.remaining -= 1;
+        Some((self.address.read_u8(offset), offset))
+    }
+}
+
+/// Describes the functions a driver can drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceMatch {
+    /// A vendor and device ID.
+    Id(u16, u16),
+    /// Any device of a vendor.
+    Vendor(u16),
+    /// A class code and subclass, and optionally a programming interface.
+    Class(u8, u8, Option<u8>),
+}
+
+impl DeviceMatch {
+    pub fn matches(&self, vendor_id: u16, device_id: u16, class: (u8, u8, u8)) -> bool {
+        match *self {
+            DeviceMatch::Id(vendor, device) => vendor == vendor_id && device == device_id,
+            DeviceMatch::Vendor(vendor) => vendor == vendor_id,
+            DeviceMatch::Class(code, subclass, prog_if) => {
+                code == class.0 && subclass == class.1 && prog_if.map_or(true, |p| p == class.2)
+            }
+        }
+    }
+}
+
+/// A driver that binds to PCI functions.
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [DeviceMatch],
+    /// Initializes a matching function and returns whether the driver took it.
+    pub probe: fn(PciAddress) -> bool,
+}
+
+impl PciDriver {
+    fn matches(&self, address: &PciAddress) -> bool {
+        let (vendor_id, device_id) = (address.vendor_id(), address.device_id());
+        let class = address.class();
+        self.matches
+            .iter()
+            .any(|pattern| pattern.matches(vendor_id, device_id, class))
+    }
+}
+
+static DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+/// The functions with a driver, and the driver's name.
+static BOUND: Mutex<Vec<(PciAddress, &'static str)>> = Mutex::new(Vec::new());
+
+/// Adds a driver to the registry; `probe_drivers` binds it to functions.
+pub fn register_driver(driver: &'static PciDriver) {
+    let mut drivers = DRIVERS.lock();
+    if !drivers
+        .iter()
+        .any(|registered| registered.name == driver.name)
+    {
+        drivers.push(driver);
+    }
+}
+
+/// Returns the name of the driver bound to a function.
+pub fn driver_of(address: PciAddress) -> Option<&'static str> {
+    BOUND
+        .lock()
+        .iter()
+        .find(|(bound, _)| *bound == address)
+        .map(|&(_, name)| name)
+}
+
+/// Offers each function without a driver to the matching registered drivers, in
+/// registration order, until one takes it.
+///
+/// Returns the number of functions bound. Requires the heap to be initialized.
+pub fn probe_drivers() -> usize {
+    // the locks aren't held while probing, since probe functions use the registry
+    let drivers = DRIVERS.lock().clone();
+    let mut bound = 0;
+    for address in functions() {
+        if driver_of(address).is_some() {
+            continue;
+        }
+        let driver = drivers
+            .iter()
+            .find(|driver| driver.matches(&address) && (driver.probe)(address));
+        if let Some(driver) = driver {
+            BOUND.lock().push((address, driver.name));
+            bound += 1;
+        }
+    }
+    bound
+}
+
+/// Switches configuration space access to ECAM if the ACPI MCFG table describes a region
+/// for segment 0.
+///
+/// Requires `memory::init_kernel_memory` to have been called.
+pub fn init() {
+    let table = match acpi::find_table(b"MCFG") {
+        Some(table) => table,
+        None => {
+            info!("pci: no MCFG table, using configuration ports");
+            return;
+        }
+    };
+    let entry = match parse_mcfg(table.body()).find(|entry| entry.segment == 0) {
+        Some(entry) => entry,
+        None => return,
+    };
+    info!(
+        "pci: ECAM at {:#x} for buses {:02x}-{:02x}",
+        entry.base, entry.start_bus, entry.end_bus
+    );
+    let _ = ECAM.try_init_once(|| Ecam {
+        base: PhysAddr::new(entry.base),
+        start_bus: entry.start_bus,
+        end_bus: entry.end_bus,
+        mapped: [UNMAPPED; 256],
+    });
+}
+
+#[test_case]
+fn test_parse_mcfg() {
+    let mut body = [0; 8 + 2 * 16];
+    body[8..16].copy_from_slice(&0xb000_0000u64.to_le_bytes());
+    body[18] = 0;
+    body[19] = 0xff;
+    body[24..32].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+    body[32] = 1;
+    body[34] = 0x10;
+    body[35] = 0x1f;
+
+    let mut entries = parse_mcfg(&body);
+    assert_eq!(
+        entries.next(),
+        Some(McfgEntry {
+            base: 0xb000_0000,
+            segment: 0,
+            start_bus: 0,
+            end_bus: 0xff,
+        })
+    );
+    assert_eq!(
+        entries.next(),
+        Some(McfgEntry {
+            base: 0x1_0000_0000,
+            segment: 1,
+            start_bus: 0x10,
+            end_bus: 0x1f,
+        })
+    );
+    assert_eq!(entries.next(), None);
+    assert_eq!(parse_mcfg(&[0; 4]).count(), 0);
+}
+
+#[test_case]
+fn test_device_match() {
+    let ahci = (0x01, 0x06, 0x01);
+    assert!(DeviceMatch::Class(0x01, 0x06, Some(0x01)).matches(0x8086, 0x2922, ahci));
+    assert!(DeviceMatch::Class(0x01, 0x06, None).matches(0x8086, 0x2922, ahci));
+    assert!(!DeviceMatch::Class(0x01, 0x01, None).matches(0x8086, 0x2922, ahci));
+    assert!(DeviceMatch::Id(0x8086, 0x2922).matches(0x8086, 0x2922, ahci));
+    assert!(!DeviceMatch::Id(0x8086, 0x7010).matches(0x8086, 0x2922, ahci));
+    assert!(DeviceMatch::Vendor(0x1af4).matches(0x1af4, 0x1001, (0x01, 0x00, 0x00)));
+}
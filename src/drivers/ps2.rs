@@ -0,0 +1,357 @@
+//! The PS/2 controller (Intel 8042) and the keyboard and mouse connected to it.
+//!
+//! `init` configures the controller and resets the devices by polling, before the
+//! interrupts of the ports are enabled. Afterwards, all bytes from the devices are read by
+//! the keyboard and mouse interrupt handlers, so keyboard commands sent later wait for the
+//! keyboard interrupt handler to pass on the acknowledgement.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
+};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+pub mod keyboard;
+pub mod mouse;
+
+const DATA_PORT: u16 = 0x60;
+/// The status register when read, the command register when written.
+const STATUS_PORT: u16 = 0x64;
+
+/// Set while a byte from the controller or a device waits to be read.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Set while the controller hasn't read the last byte written to it.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND_PORT: u8 = 0xa7;
+const COMMAND_ENABLE_SECOND_PORT: u8 = 0xa8;
+const COMMAND_TEST_SECOND_PORT: u8 = 0xa9;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST_PORT: u8 = 0xab;
+const COMMAND_DISABLE_FIRST_PORT: u8 = 0xad;
+const COMMAND_ENABLE_FIRST_PORT: u8 = 0xae;
+/// Sends the next byte written to the data port to the device on the second port.
+const COMMAND_WRITE_SECOND_PORT: u8 = 0xd4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const CONFIG_FIRST_PORT_INTERRUPT: u8 = 1 << 0;
+const CONFIG_SECOND_PORT_INTERRUPT: u8 = 1 << 1;
+const CONFIG_SECOND_PORT_CLOCK_DISABLED: u8 = 1 << 5;
+/// Translates scancode set 2 from the keyboard to set 1, which `input::Decoder` expects.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const DEVICE_RESET: u8 = 0xff;
+const DEVICE_ACK: u8 = 0xfa;
+const DEVICE_RESEND: u8 = 0xfe;
+const DEVICE_SELF_TEST_PASSED: u8 = 0xaa;
+
+/// How often a device is asked again to take a command it requested to be resent.
+const RETRIES: usize = 3;
+
+/// The number of times the status register is polled before giving up. Devices take a
+/// while to reset, so it's generous.
+const TIMEOUT: usize = 1_000_000;
+
+static KEYBOARD_PRESENT: AtomicBool = AtomicBool::new(false);
+static MOUSE_PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Held while a keyboard command and its parameters are sent, so that the answers of
+/// concurrent commands don't get mixed up.
+static KEYBOARD_COMMAND: Mutex<()> = Mutex::new(());
+/// Set while a keyboard command waits for an answer.
+static KEYBOARD_WAITING: AtomicBool = AtomicBool::new(false);
+/// The answer to the last keyboard command, or `NO_RESPONSE`.
+static KEYBOARD_RESPONSE: AtomicU16 = AtomicU16::new(NO_RESPONSE);
+const NO_RESPONSE: u16 = u16::MAX;
+
+/// The two ports of the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    /// The keyboard port, on IRQ 1.
+    First,
+    /// The mouse port, on IRQ 12.
+    Second,
+}
+
+#[derive(Debug)]
+pub enum Ps2Error {
+    /// The controller didn't accept or return a byte in time.
+    Timeout,
+    /// The controller self test returned the given byte instead of `0x55`.
+    SelfTestFailed(u8),
+    /// The device answered a command with the given byte instead of acknowledging it.
+    NotAcknowledged(Ps2Port, u8),
+    /// The device reported a failed self test after a reset.
+    ResetFailed(Ps2Port, u8),
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ps2Error::Timeout => write!(f, "PS/2 controller timed out"),
+            Ps2Error::SelfTestFailed(response) => {
+                write!(f, "PS/2 controller self test failed ({:#x})", response)
+            }
+            Ps2Error::NotAcknowledged(port, response) => write!(
+                f,
+                "PS/2 device on {:?} port answered {:#x} to a command",
+                port, response
+            ),
+            Ps2Error::ResetFailed(port, response) => write!(
+                f,
+                "PS/2 device on {:?} port failed to reset ({:#x})",
+                port, response
+            ),
+        }
+    }
+}
+
+/// The devices found by `init`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Devices {
+    pub keyboard: bool,
+    pub mouse: bool,
+}
+
+/// Returns the devices found by `init`.
+pub fn devices() -> Devices {
+    Devices {
+        keyboard: KEYBOARD_PRESENT.load(Ordering::Relaxed),
+        mouse: MOUSE_PRESENT.load(Ordering::Relaxed),
+    }
+}
+
+fn read_status() -> u8 {
+    unsafe { Port::new(STATUS_PORT).read() }
+}
+
+/// Reads the data port without checking the status register.
+///
+/// Used by the interrupt handlers, which are only raised once a byte is available.
+pub(crate) fn read_byte() -> u8 {
+    unsafe { Port::new(DATA_PORT).read() }
+}
+
+fn wait_for(status: u8, set: bool) -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if (read_status() & status != 0) == set {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Waits for a byte from the controller or a device and returns it.
+fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(STATUS_OUTPUT_FULL, true)?;
+    Ok(read_byte())
+}
+
+fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::new(STATUS_PORT).write(command) };
+    Ok(())
+}
+
+/// Discards the bytes waiting in the output buffer.
+fn flush_output() {
+    for _ in 0..16 {
+        if read_status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        read_byte();
+    }
+}
+
+fn read_config() -> Result<u8, Ps2Error> {
+    write_command(COMMAND_READ_CONFIG)?;
+    read_data()
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Sends a byte to the device on the given port, without waiting for an answer.
+fn write_device(port: Ps2Port, byte: u8) -> Result<(), Ps2Error> {
+    if port == Ps2Port::Second {
+        write_command(COMMAND_WRITE_SECOND_PORT)?;
+    }
+    write_data(byte)
+}
+
+/// Sends a byte to the device on the given port and waits for its acknowledgement.
+///
+/// Only used by `init`: once the interrupts of the port are enabled, the answer is read
+/// by the interrupt handler.
+fn device_command(port: Ps2Port, byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..RETRIES {
+        write_device(port, byte)?;
+        match read_data()? {
+            DEVICE_ACK => return Ok(()),
+            DEVICE_RESEND => continue,
+            response => return Err(Ps2Error::NotAcknowledged(port, response)),
+        }
+    }
+    Err(Ps2Error::NotAcknowledged(port, DEVICE_RESEND))
+}
+
+/// Called by the keyboard interrupt handler with each byte from the keyboard.
+///
+/// Returns whether the byte answers a command sent by `keyboard_command`; otherwise it's a
+/// scancode. Doesn't block.
+pub(crate) fn keyboard_response(byte: u8) -> bool {
+    let is_response = byte == DEVICE_ACK || byte == DEVICE_RESEND;
+    if is_response && KEYBOARD_WAITING.load(Ordering::Acquire) {
+        KEYBOARD_RESPONSE.store(byte as u16, Ordering::Release);
+        true
+    } else {
+        false
+    }
+}
+
+fn wait_for_keyboard_response() -> Result<u8, Ps2Error> {
+    for _ in 0..TIMEOUT {
+        let response = KEYBOARD_RESPONSE.load(Ordering::Acquire);
+        if response != NO_RESPONSE {
+            return Ok(response as u8);
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Sends a command and its parameters to the keyboard, waiting for the acknowledgement of
+/// each byte.
+///
+/// Used once the keyboard interrupt is enabled. The acknowledgements are received by the
+/// keyboard interrupt handler, so this times out if interrupts are disabled.
+fn keyboard_command(bytes: &[u8]) -> Result<(), Ps2Error> {
+    let _command = KEYBOARD_COMMAND.lock();
+    for &byte in bytes {
+        let mut acknowledged = false;
+        for _ in 0..RETRIES {
+            KEYBOARD_RESPONSE.store(NO_RESPONSE, Ordering::Relaxed);
+            KEYBOARD_WAITING.store(true, Ordering::Release);
+            let response =
+                write_device(Ps2Port::First, byte).and_then(|()| wait_for_keyboard_response());
+            KEYBOARD_WAITING.store(false, Ordering::Release);
+            if response? == DEVICE_ACK {
+                acknowledged = true;
+                break;
+            }
+        }
+        if !acknowledged {
+            return Err(Ps2Error::NotAcknowledged(Ps2Port::First, DEVICE_RESEND));
+        }
+    }
+    Ok(())
+}
+
+/// Resets the device on the given port and waits for its self test to pass.
+fn reset_device(port: Ps2Port) -> Result<(), Ps2Error> {
+    device_command(port, DEVICE_RESET)?;
+    match read_data()? {
+        DEVICE_SELF_TEST_PASSED => {
+            if port == Ps2Port::Second {
+                // mice also send their device ID
+                read_data()?;
+            }
+            Ok(())
+        }
+        response => Err(Ps2Error::ResetFailed(port, response)),
+    }
+}
+
+fn test_port(port: Ps2Port) -> Result<bool, Ps2Error> {
+    write_command(match port {
+        Ps2Port::First => COMMAND_TEST_FIRST_PORT,
+        Ps2Port::Second => COMMAND_TEST_SECOND_PORT,
+    })?;
+    Ok(read_data()? == PORT_TEST_PASSED)
+}
+
+/// Initializes and self-tests the controller, and resets and configures the keyboard and
+/// the mouse.
+///
+/// Must be called while interrupts are disabled, before the keyboard and mouse interrupt
+/// handlers are registered. A missing or failing device is not an error; it's reported
+/// as absent in the returned `Devices`.
+///
+/// If the controller fails, the configuration left by the firmware is restored and the
+/// keyboard port enabled again. `devices` then reports the keyboard as present if that
+/// worked.
+pub fn init() -> Result<Devices, Ps2Error> {
+    write_command(COMMAND_DISABLE_FIRST_PORT)?;
+    write_command(COMMAND_DISABLE_SECOND_PORT)?;
+    flush_output();
+
+    let firmware_config = read_config()?;
+    configure_controller(firmware_config).map_err(|err| {
+        let restored = write_config(firmware_config)
+            .and_then(|()| write_command(COMMAND_ENABLE_FIRST_PORT))
+            .is_ok();
+        KEYBOARD_PRESENT.store(restored, Ordering::Relaxed);
+        err
+    })
+}
+
+fn configure_controller(mut config: u8) -> Result<Devices, Ps2Error> {
+    // no interrupts and no translation while the devices are set up by polling
+    config &= !(CONFIG_FIRST_PORT_INTERRUPT | CONFIG_SECOND_PORT_INTERRUPT | CONFIG_TRANSLATION);
+    write_config(config)?;
+
+    write_command(COMMAND_SELF_TEST)?;
+    match read_data()? {
+        SELF_TEST_PASSED => {}
+        response => return Err(Ps2Error::SelfTestFailed(response)),
+    }
+    // the self test may reset the controller
+    write_config(config)?;
+
+    // enabling the second port clears its clock disable bit if the controller has one
+    write_command(COMMAND_ENABLE_SECOND_PORT)?;
+    let dual_port = read_config()? & CONFIG_SECOND_PORT_CLOCK_DISABLED == 0;
+    write_command(COMMAND_DISABLE_SECOND_PORT)?;
+
+    let mut devices = Devices::default();
+    if test_port(Ps2Port::First)? {
+        write_command(COMMAND_ENABLE_FIRST_PORT)?;
+        devices.keyboard = reset_device(Ps2Port::First)
+            .and_then(|()| keyboard::configure())
+            .is_ok();
+    }
+    if dual_port && test_port(Ps2Port::Second)? {
+        write_command(COMMAND_ENABLE_SECOND_PORT)?;
+        devices.mouse = reset_device(Ps2Port::Second)
+            .and_then(|()| mouse::configure())
+            .is_ok();
+    }
+    flush_output();
+
+    config |= CONFIG_TRANSLATION;
+    if devices.keyboard {
+        config |= CONFIG_FIRST_PORT_INTERRUPT;
+    }
+    if devices.mouse {
+        config |= CONFIG_SECOND_PORT_INTERRUPT;
+    }
+    write_config(config)?;
+
+    KEYBOARD_PRESENT.store(devices.keyboard, Ordering::Relaxed);
+    MOUSE_PRESENT.store(devices.mouse, Ordering::Relaxed);
+    Ok(devices)
+}
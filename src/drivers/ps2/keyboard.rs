@@ -0,0 +1,77 @@
+//! Commands for the keyboard on the first port of the PS/2 controller.
+
+use super::{device_command, keyboard_command, Ps2Error, Ps2Port};
+use crate::input::Locks;
+
+const COMMAND_SET_LEDS: u8 = 0xed;
+const COMMAND_SCANCODE_SET: u8 = 0xf0;
+const COMMAND_SET_TYPEMATIC: u8 = 0xf3;
+
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// The scancode set the keyboard is switched to; the controller translates it to set 1.
+const SCANCODE_SET: u8 = 2;
+
+/// How long a key has to be held down before it repeats, and how fast it repeats then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typematic {
+    /// The delay in steps of 250 ms, from 0 (250 ms) to 3 (1 s).
+    pub delay: u8,
+    /// The repeat rate, from 0 (30 characters per second) to 31 (2 per second).
+    pub rate: u8,
+}
+
+impl Typematic {
+    /// 500 ms delay, about 10.9 characters per second.
+    pub const DEFAULT: Typematic = Typematic {
+        delay: 1,
+        rate: 0x0b,
+    };
+
+    fn encode(self) -> u8 {
+        (self.delay & 0x03) << 5 | (self.rate & 0x1f)
+    }
+}
+
+/// Sets up the keyboard after a reset. Called by `ps2::init` before interrupts are
+/// enabled.
+pub(super) fn configure() -> Result<(), Ps2Error> {
+    device_command(Ps2Port::First, COMMAND_SCANCODE_SET)?;
+    device_command(Ps2Port::First, SCANCODE_SET)?;
+    device_command(Ps2Port::First, COMMAND_SET_TYPEMATIC)?;
+    device_command(Ps2Port::First, Typematic::DEFAULT.encode())?;
+    device_command(Ps2Port::First, COMMAND_SET_LEDS)?;
+    device_command(Ps2Port::First, 0)
+}
+
+/// Turns the keyboard LEDs on or off to show the given lock state.
+pub fn set_leds(locks: Locks) -> Result<(), Ps2Error> {
+    let mut leds = 0;
+    if locks.scroll_lock {
+        leds |= LED_SCROLL_LOCK;
+    }
+    if locks.num_lock {
+        leds |= LED_NUM_LOCK;
+    }
+    if locks.caps_lock {
+        leds |= LED_CAPS_LOCK;
+    }
+    keyboard_command(&[COMMAND_SET_LEDS, leds])
+}
+
+/// Sets the key repeat delay and rate.
+pub fn set_typematic(typematic: Typematic) -> Result<(), Ps2Error> {
+    keyboard_command(&[COMMAND_SET_TYPEMATIC, typematic.encode()])
+}
+
+#[test_case]
+fn test_typematic_encoding() {
+    assert_eq!(Typematic::DEFAULT.encode(), 0x2b);
+    let typematic = Typematic {
+        delay: 3,
+        rate: 0x1f,
+    };
+    assert_eq!(typematic.encode(), 0x7f);
+}
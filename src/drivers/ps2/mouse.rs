@@ -0,0 +1,120 @@
+//! The mouse on the second port of the PS/2 controller.
+
+use super::{device_command, Ps2Error, Ps2Port};
+
+const COMMAND_SET_DEFAULTS: u8 = 0xf6;
+const COMMAND_ENABLE_REPORTING: u8 = 0xf4;
+
+/// Flags in the first byte of a movement packet.
+const PACKET_LEFT_BUTTON: u8 = 1 << 0;
+const PACKET_RIGHT_BUTTON: u8 = 1 << 1;
+const PACKET_MIDDLE_BUTTON: u8 = 1 << 2;
+/// Always set in the first byte, which allows finding the start of a packet.
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+const PACKET_SIZE: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// A movement of the mouse or a change of its buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// The horizontal movement, positive to the right.
+    pub dx: i16,
+    /// The vertical movement, positive upwards.
+    pub dy: i16,
+    /// The buttons held down after the event.
+    pub buttons: MouseButtons,
+}
+
+/// Assembles the bytes sent by the mouse into events.
+pub struct PacketDecoder {
+    packet: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl PacketDecoder {
+    pub const fn new() -> Self {
+        PacketDecoder {
+            packet: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Adds a byte received from the mouse, returning an event once a packet is complete.
+    ///
+    /// Bytes that can't start a packet are skipped, so the decoder gets back in sync if a
+    /// byte was lost.
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < PACKET_SIZE {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y] = self.packet;
+        let movement = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                // the movement is unreliable
+                0
+            } else if flags & sign != 0 {
+                i16::from(value) - 0x100
+            } else {
+                i16::from(value)
+            }
+        };
+        Some(MouseEvent {
+            dx: movement(x, PACKET_X_SIGN, PACKET_X_OVERFLOW),
+            dy: movement(y, PACKET_Y_SIGN, PACKET_Y_OVERFLOW),
+            buttons: MouseButtons {
+                left: flags & PACKET_LEFT_BUTTON != 0,
+                right: flags & PACKET_RIGHT_BUTTON != 0,
+                middle: flags & PACKET_MIDDLE_BUTTON != 0,
+            },
+        })
+    }
+}
+
+/// Sets up the mouse after a reset. Called by `ps2::init` before interrupts are enabled.
+pub(super) fn configure() -> Result<(), Ps2Error> {
+    device_command(Ps2Port::Second, COMMAND_SET_DEFAULTS)?;
+    device_command(Ps2Port::Second, COMMAND_ENABLE_REPORTING)
+}
+
+#[test_case]
+fn test_packet_decoding() {
+    let mut decoder = PacketDecoder::new();
+    // a byte without the always-one bit is skipped
+    assert_eq!(decoder.add_byte(0x00), None);
+    assert_eq!(decoder.add_byte(0x09), None);
+    assert_eq!(decoder.add_byte(0x05), None);
+    let event = decoder.add_byte(0x03).expect("packet not complete");
+    assert_eq!((event.dx, event.dy), (5, 3));
+    assert!(event.buttons.left && !event.buttons.right && !event.buttons.middle);
+
+    // negative movement
+    decoder.add_byte(0x3a);
+    decoder.add_byte(0xfe);
+    let event = decoder.add_byte(0xff).expect("packet not complete");
+    assert_eq!((event.dx, event.dy), (-2, -1));
+    assert!(event.buttons.right);
+
+    // overflowing movement is dropped
+    decoder.add_byte(0x48);
+    decoder.add_byte(0x10);
+    let event = decoder.add_byte(0x10).expect("packet not complete");
+    assert_eq!((event.dx, event.dy), (0, 16));
+}
@@ -0,0 +1,426 @@
+//! The 16550 UART behind each serial port.
+
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+const REGISTER_DATA: u16 = 0;
+const REGISTER_INTERRUPT_ENABLE: u16 = 1;
+/// The interrupt identification register when read, the FIFO control register when
+/// written.
+const REGISTER_INTERRUPT_ID: u16 = 2;
+const REGISTER_LINE_CONTROL: u16 = 3;
+const REGISTER_MODEM_CONTROL: u16 = 4;
+const REGISTER_LINE_STATUS: u16 = 5;
+const REGISTER_SCRATCH: u16 = 7;
+/// The divisor latch, in place of the data and interrupt enable registers while
+/// `LINE_CONTROL_DIVISOR_LATCH` is set.
+const REGISTER_DIVISOR_LOW: u16 = 0;
+const REGISTER_DIVISOR_HIGH: u16 = 1;
+
+const LINE_CONTROL_DATA_BITS_MASK: u8 = 0x03;
+const LINE_CONTROL_TWO_STOP_BITS: u8 = 1 << 2;
+const LINE_CONTROL_PARITY_MASK: u8 = 0x38;
+const LINE_CONTROL_PARITY_ODD: u8 = 0x08;
+const LINE_CONTROL_PARITY_EVEN: u8 = 0x18;
+const LINE_CONTROL_DIVISOR_LATCH: u8 = 1 << 7;
+
+/// Enables and clears both FIFOs, with an interrupt once 14 bytes were received.
+const FIFO_CONTROL_ENABLE: u8 = 0xc7;
+
+const MODEM_CONTROL_DTR: u8 = 1 << 0;
+const MODEM_CONTROL_RTS: u8 = 1 << 1;
+const MODEM_CONTROL_OUT1: u8 = 1 << 2;
+/// Connects the interrupt line of the UART to the interrupt controller.
+const MODEM_CONTROL_OUT2: u8 = 1 << 3;
+const MODEM_CONTROL_LOOPBACK: u8 = 1 << 4;
+
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Raised when received data is available.
+pub const INTERRUPT_RECEIVED: u8 = 1 << 0;
+/// Raised when the transmit holding register is empty.
+pub const INTERRUPT_TRANSMIT_EMPTY: u8 = 1 << 1;
+
+/// Bit 0 of the interrupt identification register is clear while an interrupt is pending.
+const INTERRUPT_ID_NONE_PENDING: u8 = 1 << 0;
+const INTERRUPT_ID_MASK: u8 = 0x0e;
+const INTERRUPT_ID_TRANSMIT_EMPTY: u8 = 0x02;
+const INTERRUPT_ID_RECEIVED: u8 = 0x04;
+const INTERRUPT_ID_LINE_STATUS: u8 = 0x06;
+const INTERRUPT_ID_TIMEOUT: u8 = 0x0c;
+
+/// The clock rate of the UART divided by 16, from which the baud rate is derived.
+const BASE_BAUD: u32 = 115_200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// The speed and character format of a serial line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    baud: u32,
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: u8,
+}
+
+impl LineConfig {
+    /// 38400 baud, 8 data bits, no parity and one stop bit.
+    pub const DEFAULT: LineConfig = LineConfig {
+        baud: 38_400,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+    };
+
+    /// Returns the configuration if the UART supports it: the baud rate has to divide
+    /// 115200 into a 16-bit divisor, and characters have 5 to 8 data bits and 1 or 2 stop
+    /// bits.
+    pub fn new(baud: u32, data_bits: u8, parity: Parity, stop_bits: u8) -> Option<Self> {
+        let valid = baud > 0
+            && BASE_BAUD % baud == 0
+            && BASE_BAUD / baud <= u16::MAX as u32
+            && (5..=8).contains(&data_bits)
+            && (1..=2).contains(&stop_bits);
+        if valid {
+            Some(LineConfig {
+                baud,
+                data_bits,
+                parity,
+                stop_bits,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Parses a configuration like `115200` or `9600,7e1`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ',');
+        let baud = parts.next()?.parse().ok()?;
+        let format = match parts.next() {
+            Some(format) => format.as_bytes(),
+            None => return Self::new(baud, 8, Parity::None, 1),
+        };
+        if format.len() != 3 {
+            return None;
+        }
+        let digit = |byte: u8| (byte as char).to_digit(10).map(|digit| digit as u8);
+        let parity = match format[1].to_ascii_lowercase() {
+            b'n' => Parity::None,
+            b'o' => Parity::Odd,
+            b'e' => Parity::Even,
+            _ => return None,
+        };
+        Self::new(baud, digit(format[0])?, parity, digit(format[2])?)
+    }
+
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    fn divisor(&self) -> u16 {
+        (BASE_BAUD / self.baud) as u16
+    }
+
+    fn line_control(&self) -> u8 {
+        let mut line_control = self.data_bits - 5;
+        if self.stop_bits == 2 {
+            line_control |= LINE_CONTROL_TWO_STOP_BITS;
+        }
+        match self.parity {
+            Parity::None => {}
+            Parity::Odd => line_control |= LINE_CONTROL_PARITY_ODD,
+            Parity::Even => line_control |= LINE_CONTROL_PARITY_EVEN,
+        }
+        line_control
+    }
+
+    /// The inverse of `divisor` and `line_control`.
+    fn from_registers(divisor: u16, line_control: u8) -> Option<Self> {
+        if divisor == 0 {
+            return None;
+        }
+        let parity = match line_control & LINE_CONTROL_PARITY_MASK {
+            0 => Parity::None,
+            LINE_CONTROL_PARITY_ODD => Parity::Odd,
+            LINE_CONTROL_PARITY_EVEN => Parity::Even,
+            // stick parity
+            _ => return None,
+        };
+        let stop_bits = if line_control & LINE_CONTROL_TWO_STOP_BITS != 0 {
+            2
+        } else {
+            1
+        };
+        let data_bits = (line_control & LINE_CONTROL_DATA_BITS_MASK) + 5;
+        Self::new(BASE_BAUD / divisor as u32, data_bits, parity, stop_bits)
+    }
+}
+
+/// Holds a formatted `LineConfig`, so that it can be padded as a whole.
+struct ConfigBuffer {
+    /// Long enough for `115200 8N1`.
+    bytes: [u8; 16],
+    len: usize,
+}
+
+impl ConfigBuffer {
+    fn new() -> Self {
+        ConfigBuffer {
+            bytes: [0; 16],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // only whole `str`s are ever copied in
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for ConfigBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+impl fmt::Display for LineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use core::fmt::Write;
+
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let mut buffer = ConfigBuffer::new();
+        write!(
+            buffer,
+            "{} {}{}{}",
+            self.baud, self.data_bits, parity, self.stop_bits
+        )?;
+        f.pad(buffer.as_str())
+    }
+}
+
+/// The reason the UART raised an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptCause {
+    /// Data was received; also raised when received data sat in the FIFO for a while.
+    Received,
+    TransmitEmpty,
+    /// A receive error, like a framing error; cleared by reading the line status.
+    LineStatus,
+    /// A change of the modem status lines; cleared by reading the modem status.
+    ModemStatus,
+}
+
+pub struct Uart {
+    base: u16,
+    config: LineConfig,
+}
+
+impl Uart {
+    /// Creates a driver for the UART with the given base I/O port.
+    ///
+    /// Unsafe because the port must belong to a UART, which nothing else accesses.
+    pub const unsafe fn new(base: u16) -> Self {
+        Uart {
+            base,
+            config: LineConfig::DEFAULT,
+        }
+    }
+
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// The line configuration set by `init`.
+    pub fn config(&self) -> LineConfig {
+        self.config
+    }
+
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// Returns whether a UART responds at the base port.
+    ///
+    /// Sends a byte to itself in loopback mode, so `init` has to be called afterwards.
+    pub fn probe(&mut self) -> bool {
+        const PATTERN: u8 = 0xae;
+
+        // without a device, reads return 0xff
+        self.write_register(REGISTER_SCRATCH, 0x5a);
+        if self.read_register(REGISTER_SCRATCH) != 0x5a {
+            return false;
+        }
+
+        self.write_register(REGISTER_INTERRUPT_ENABLE, 0);
+        self.write_register(
+            REGISTER_MODEM_CONTROL,
+            MODEM_CONTROL_RTS | MODEM_CONTROL_OUT1 | MODEM_CONTROL_OUT2 | MODEM_CONTROL_LOOPBACK,
+        );
+        while self.try_receive().is_some() {}
+        self.write_register(REGISTER_DATA, PATTERN);
+        let received = (0..10_000).find_map(|_| self.try_receive());
+        self.write_register(REGISTER_MODEM_CONTROL, 0);
+        received == Some(PATTERN)
+    }
+
+    /// Sets up the UART with the given line configuration and all interrupts disabled.
+    pub fn init(&mut self, config: &LineConfig) {
+        self.write_register(REGISTER_INTERRUPT_ENABLE, 0);
+        let divisor = config.divisor();
+        self.write_register(REGISTER_LINE_CONTROL, LINE_CONTROL_DIVISOR_LATCH);
+        self.write_register(REGISTER_DIVISOR_LOW, divisor as u8);
+        self.write_register(REGISTER_DIVISOR_HIGH, (divisor >> 8) as u8);
+        self.write_register(REGISTER_LINE_CONTROL, config.line_control());
+        self.write_register(REGISTER_INTERRUPT_ID, FIFO_CONTROL_ENABLE);
+        self.write_register(
+            REGISTER_MODEM_CONTROL,
+            MODEM_CONTROL_DTR | MODEM_CONTROL_RTS | MODEM_CONTROL_OUT2,
+        );
+        self.config = *config;
+    }
+
+    /// Reads the line configuration back from the registers of the UART.
+    ///
+    /// Returns `None` if the registers hold a configuration `LineConfig` can't describe.
+    pub fn read_config(&mut self) -> Option<LineConfig> {
+        let line_control = self.read_register(REGISTER_LINE_CONTROL);
+        self.write_register(
+            REGISTER_LINE_CONTROL,
+            line_control | LINE_CONTROL_DIVISOR_LATCH,
+        );
+        let low = self.read_register(REGISTER_DIVISOR_LOW);
+        let high = self.read_register(REGISTER_DIVISOR_HIGH);
+        self.write_register(REGISTER_LINE_CONTROL, line_control);
+        LineConfig::from_registers(
+            u16::from_le_bytes([low, high]),
+            line_control & !LINE_CONTROL_DIVISOR_LATCH,
+        )
+    }
+
+    /// Sets the interrupts the UART raises, a combination of the `INTERRUPT_*` flags.
+    pub fn set_interrupts(&mut self, interrupts: u8) {
+        self.write_register(REGISTER_INTERRUPT_ENABLE, interrupts);
+    }
+
+    pub fn interrupts(&self) -> u8 {
+        self.read_register(REGISTER_INTERRUPT_ENABLE)
+    }
+
+    /// Returns the highest priority pending interrupt.
+    pub fn pending_interrupt(&self) -> Option<InterruptCause> {
+        let id = self.read_register(REGISTER_INTERRUPT_ID);
+        if id & INTERRUPT_ID_NONE_PENDING != 0 {
+            return None;
+        }
+        Some(match id & INTERRUPT_ID_MASK {
+            INTERRUPT_ID_RECEIVED | INTERRUPT_ID_TIMEOUT => InterruptCause::Received,
+            INTERRUPT_ID_TRANSMIT_EMPTY => InterruptCause::TransmitEmpty,
+            INTERRUPT_ID_LINE_STATUS => {
+                self.read_register(REGISTER_LINE_STATUS);
+                InterruptCause::LineStatus
+            }
+            _ => InterruptCause::ModemStatus,
+        })
+    }
+
+    /// Routes the output of the UART back to its input, for testing.
+    pub fn set_loopback(&mut self, loopback: bool) {
+        let mut control = self.read_register(REGISTER_MODEM_CONTROL);
+        if loopback {
+            control |= MODEM_CONTROL_LOOPBACK;
+        } else {
+            control &= !MODEM_CONTROL_LOOPBACK;
+        }
+        self.write_register(REGISTER_MODEM_CONTROL, control);
+    }
+
+    /// Returns whether a byte can be written without waiting.
+    pub fn is_transmit_empty(&self) -> bool {
+        self.read_register(REGISTER_LINE_STATUS) & LINE_STATUS_TRANSMIT_EMPTY != 0
+    }
+
+    /// Writes a byte, waiting until the UART can take it.
+    pub fn send(&mut self, byte: u8) {
+        while !self.is_transmit_empty() {
+            core::hint::spin_loop();
+        }
+        self.write_register(REGISTER_DATA, byte);
+    }
+
+    /// Reads a received byte, if there is one.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.read_register(REGISTER_LINE_STATUS) & LINE_STATUS_DATA_READY != 0 {
+            Some(self.read_register(REGISTER_DATA))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_line_config() {
+    let config = LineConfig::parse("9600,7e2").expect("valid configuration rejected");
+    assert_eq!(config.divisor(), 12);
+    assert_eq!(config.line_control(), 0x02 | 0x04 | 0x18);
+    assert_eq!(LineConfig::parse("38400"), Some(LineConfig::DEFAULT));
+    assert_eq!(LineConfig::DEFAULT.line_control(), 0x03);
+    assert_eq!(LineConfig::parse("1000"), None);
+    assert_eq!(LineConfig::parse("9600,9n1"), None);
+    assert_eq!(LineConfig::parse("9600,8x1"), None);
+    // the divisor of 1 baud doesn't fit in 16 bits
+    assert_eq!(LineConfig::parse("1"), None);
+    assert_eq!(
+        LineConfig::parse("2").map(|config| config.divisor()),
+        Some(57_600)
+    );
+}
+
+#[test_case]
+fn test_line_config_registers() {
+    let config = LineConfig::parse("9600,7e2").expect("valid configuration rejected");
+    assert_eq!(
+        LineConfig::from_registers(config.divisor(), config.line_control()),
+        Some(config)
+    );
+    assert_eq!(LineConfig::from_registers(0, 0x03), None);
+    assert_eq!(LineConfig::from_registers(1, 0x28), None);
+}
+
+#[test_case]
+fn test_line_config_is_padded() {
+    use core::fmt::Write;
+
+    let mut buffer = ConfigBuffer::new();
+    write!(buffer, "[{:<12}]", LineConfig::DEFAULT).expect("formatting failed");
+    assert_eq!(buffer.as_str(), "[38400 8N1   ]");
+}
@@ -0,0 +1,657 @@
+//! VirtIO devices on the PCI bus: the transport and the drivers built on it.
+//!
+//! Both the legacy interface, with its registers in I/O BAR 0, and the modern interface
+//! of VirtIO 1.0, with register structures found through vendor-specific PCI capabilities,
+//! are supported. Transitional devices, QEMU's default, are driven through the modern
+//! interface. Devices interrupt through their legacy PCI interrupt line; MSI-X is not used.
+
+use crate::{
+    drivers::pci::{self, DeviceMatch, PciAddress, PciDriver},
+    info,
+    interrupts::irq::{self, IrqError, IrqReturn},
+    memory::{mmio, vma::AreaError},
+    task::busy_lock::{BusyGuard, BusyLock},
+    warn,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+pub mod blk;
+pub mod console;
+pub mod queue;
+pub mod rng;
+
+use queue::Virtqueue;
+
+pub const VENDOR_ID: u16 = 0x1af4;
+
+/// The kinds of VirtIO devices with a driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Block,
+    Console,
+    Entropy,
+}
+
+impl DeviceType {
+    /// Returns the type of a device from its PCI device ID, for both transitional
+    /// (0x1000 to 0x103f) and modern (0x1040 plus the VirtIO device ID) devices.
+    pub fn from_pci_id(device_id: u16) -> Option<Self> {
+        match device_id {
+            0x1001 | 0x1042 => Some(DeviceType::Block),
+            0x1003 | 0x1043 => Some(DeviceType::Console),
+            0x1005 | 0x1044 => Some(DeviceType::Entropy),
+            _ => None,
+        }
+    }
+}
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+/// Tells the device that the driver gave up on it.
+const STATUS_FAILED: u8 = 128;
+
+/// The number of polls before waiting for the device is given up.
+const POLL_LIMIT: usize = 1_000_000;
+
+/// Set by modern devices; a driver accepting it uses the VirtIO 1.0 interface.
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// The ISR status bit signaling used buffers; bit 1 signals a configuration change.
+const ISR_QUEUE: u8 = 1 << 0;
+
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_ADDRESS: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0c;
+const LEGACY_QUEUE_SELECT: u16 = 0x0e;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_DEVICE_STATUS: u16 = 0x12;
+const LEGACY_ISR_STATUS: u16 = 0x13;
+/// The start of the device-specific configuration while MSI-X is disabled.
+const LEGACY_DEVICE_CONFIG: u16 = 0x14;
+
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+const COMMON_QUEUE_DESCRIPTORS: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+/// The PCI capability ID of the vendor-specific capabilities describing the modern
+/// register structures.
+const CAPABILITY_VENDOR: u8 = 0x09;
+const CONFIG_COMMON: u8 = 1;
+const CONFIG_NOTIFY: u8 = 2;
+const CONFIG_ISR: u8 = 3;
+const CONFIG_DEVICE: u8 = 4;
+
+/// The maximum number of VirtIO devices driven at the same time.
+const MAX_DEVICES: usize = 8;
+/// The maximum number of virtqueues per device.
+pub const MAX_QUEUES: usize = 2;
+
+#[derive(Debug)]
+pub enum VirtioError {
+    /// The device has neither modern capabilities nor a legacy I/O BAR.
+    NoTransport,
+    /// The device didn't accept the negotiated features.
+    FeaturesRejected,
+    /// The device has no virtqueue with the given index.
+    QueueUnavailable(u16),
+    /// The virtqueue can't hold the descriptors of a request.
+    QueueTooSmall(u16),
+    /// All descriptors of the virtqueue are in use.
+    QueueFull,
+    OutOfMemory,
+    Map(AreaError),
+    Irq(IrqError),
+    /// `MAX_DEVICES` devices are driven already.
+    TooManyDevices,
+    /// The device reported an error for a request, with the device-specific status.
+    Device(u8),
+    /// The device didn't complete a reset or a request in time.
+    Timeout,
+}
+
+impl fmt::Display for VirtioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VirtioError::NoTransport => write!(f, "no usable register interface"),
+            VirtioError::FeaturesRejected => write!(f, "device rejected the features"),
+            VirtioError::QueueUnavailable(index) => write!(f, "virtqueue {} unavailable", index),
+            VirtioError::QueueTooSmall(index) => write!(f, "virtqueue {} is too small", index),
+            VirtioError::QueueFull => write!(f, "virtqueue is full"),
+            VirtioError::OutOfMemory => write!(f, "out of memory"),
+            VirtioError::Map(err) => write!(f, "can't map registers: {}", err),
+            VirtioError::Irq(err) => write!(f, "{}", err),
+            VirtioError::TooManyDevices => write!(f, "too many VirtIO devices"),
+            VirtioError::Device(status) => write!(f, "device error {}", status),
+            VirtioError::Timeout => write!(f, "device timed out"),
+        }
+    }
+}
+
+/// The register interface of a device.
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+    Legacy {
+        port: u16,
+    },
+    Modern {
+        common: VirtAddr,
+        notify: VirtAddr,
+        notify_multiplier: u32,
+        isr: VirtAddr,
+        /// The device-specific configuration, which some devices don't have.
+        device: Option<VirtAddr>,
+    },
+}
+
+/// Volatile accesses to the memory-mapped modern register structures.
+unsafe fn read_mmio<T>(addr: VirtAddr) -> T {
+    core::ptr::read_volatile(addr.as_ptr())
+}
+
+unsafe fn write_mmio<T>(addr: VirtAddr, value: T) {
+    core::ptr::write_volatile(addr.as_mut_ptr(), value)
+}
+
+impl Transport {
+    /// Finds the modern register structures of a device, falling back to the legacy
+    /// interface.
+    fn probe(pci: PciAddress) -> Result<Transport, VirtioError> {
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut device = None;
+        for (id, offset) in pci.capabilities() {
+            if id != CAPABILITY_VENDOR {
+                continue;
+            }
+            let config_type = pci.read_u8(offset + 3);
+            let bar = match pci.memory_bar(pci.read_u8(offset + 4)) {
+                Some(bar) => bar,
+                None => continue,
+            };
+            let phys = PhysAddr::new(bar + pci.read_u32(offset + 8) as u64);
+            let length = pci.read_u32(offset + 12) as u64;
+            let slot = match config_type {
+                CONFIG_COMMON => &mut common,
+                CONFIG_NOTIFY => &mut notify,
+                CONFIG_ISR => &mut isr,
+                CONFIG_DEVICE => &mut device,
+                _ => continue,
+            };
+            if slot.is_none() {
+                *slot = Some((phys, length, offset));
+            }
+        }
+
+        match (common, notify, isr) {
+            (Some(common), Some(notify), Some(isr)) => {
+                let map = |(phys, length, _): (PhysAddr, u64, u8)| {
+                    mmio::map("virtio", phys, length).map_err(VirtioError::Map)
+                };
+                Ok(Transport::Modern {
+                    common: map(common)?,
+                    notify: map(notify)?,
+                    notify_multiplier: pci.read_u32(notify.2 + 16),
+                    isr: map(isr)?,
+                    device: device.map(map).transpose()?,
+                })
+            }
+            _ => pci
+                .io_bar(0)
+                .map(|port| Transport::Legacy { port })
+                .ok_or(VirtioError::NoTransport),
+        }
+    }
+
+    fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { port } => unsafe { Port::new(port + LEGACY_DEVICE_STATUS).read() },
+            Transport::Modern { common, .. } => unsafe { read_mmio(common + COMMON_DEVICE_STATUS) },
+        }
+    }
+
+    fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { port } => unsafe {
+                Port::new(port + LEGACY_DEVICE_STATUS).write(status)
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_mmio(common + COMMON_DEVICE_STATUS, status)
+            },
+        }
+    }
+
+    /// Resets the device; writing 0 to the status starts the reset, which is complete
+    /// once the status reads back as 0.
+    fn reset(&self) -> Result<(), VirtioError> {
+        self.set_status(0);
+        (0..POLL_LIMIT)
+            .find(|_| self.status() == 0)
+            .map(|_| ())
+            .ok_or(VirtioError::Timeout)
+    }
+
+    fn device_features(&self) -> u64 {
+        match *self {
+            Transport::Legacy { port } => unsafe {
+                Port::<u32>::new(port + LEGACY_DEVICE_FEATURES).read() as u64
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_mmio(common + COMMON_DEVICE_FEATURE_SELECT, 0u32);
+                let low: u32 = read_mmio(common + COMMON_DEVICE_FEATURE);
+                write_mmio(common + COMMON_DEVICE_FEATURE_SELECT, 1u32);
+                let high: u32 = read_mmio(common + COMMON_DEVICE_FEATURE);
+                (high as u64) << 32 | low as u64
+            },
+        }
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        match *self {
+            Transport::Legacy { port } => unsafe {
+                Port::new(port + LEGACY_DRIVER_FEATURES).write(features as u32)
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_mmio(common + COMMON_DRIVER_FEATURE_SELECT, 0u32);
+                write_mmio(common + COMMON_DRIVER_FEATURE, features as u32);
+                write_mmio(common + COMMON_DRIVER_FEATURE_SELECT, 1u32);
+                write_mmio(common + COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+            },
+        }
+    }
+
+    /// Selects a virtqueue and returns its maximum size, 0 if it doesn't exist.
+    fn select_queue(&self, index: u16) -> u16 {
+        match *self {
+            Transport::Legacy { port } => unsafe {
+                Port::new(port + LEGACY_QUEUE_SELECT).write(index);
+                Port::new(port + LEGACY_QUEUE_SIZE).read()
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_mmio(common + COMMON_QUEUE_SELECT, index);
+                read_mmio(common + COMMON_QUEUE_SIZE)
+            },
+        }
+    }
+
+    /// Hands the memory of the selected virtqueue to the device and returns the queue's
+    /// notification offset.
+    ///
+    /// Legacy devices require the queue size they reported and the layout of
+    /// `queue::layout`.
+    fn enable_queue(&self, queue: &Virtqueue) -> u16 {
+        let (descriptors, driver, device) = queue.addresses();
+        match *self {
+            Transport::Legacy { port } => {
+                let frame_number = (descriptors.as_u64() >> 12) as u32;
+                unsafe { Port::new(port + LEGACY_QUEUE_ADDRESS).write(frame_number) };
+                0
+            }
+            Transport::Modern { common, .. } => unsafe {
+                write_mmio(common + COMMON_QUEUE_SIZE, queue.size());
+                write_mmio(common + COMMON_QUEUE_DESCRIPTORS, descriptors.as_u64());
+                write_mmio(common + COMMON_QUEUE_DRIVER, driver.as_u64());
+                write_mmio(common + COMMON_QUEUE_DEVICE, device.as_u64());
+                write_mmio(common + COMMON_QUEUE_ENABLE, 1u16);
+                read_mmio(common + COMMON_QUEUE_NOTIFY_OFF)
+            },
+        }
+    }
+
+    /// Tells the device that a virtqueue has new available buffers.
+    fn notify(&self, index: u16, notify_off: u16) {
+        match *self {
+            Transport::Legacy { port } => unsafe {
+                Port::new(port + LEGACY_QUEUE_NOTIFY).write(index)
+            },
+            Transport::Modern {
+                notify,
+                notify_multiplier,
+                ..
+            } => unsafe {
+                let offset = notify_off as u64 * notify_multiplier as u64;
+                write_mmio(notify + offset, index)
+            },
+        }
+    }
+
+    fn read_config_u32(&self, offset: u16) -> u32 {
+        match *self {
+            Transport::Legacy { port } => unsafe {
+                Port::new(port + LEGACY_DEVICE_CONFIG + offset).read()
+            },
+            Transport::Modern { device, .. } => match device {
+                Some(device) => unsafe { read_mmio(device + offset as u64) },
+                None => 0,
+            },
+        }
+    }
+}
+
+/// The state of a device shared with the interrupt handler.
+struct InterruptSlot {
+    /// Set while the slot belongs to a device.
+    used: AtomicBool,
+    irq: AtomicU8,
+    /// The legacy port or the address of the ISR status register; reading it
+    /// acknowledges the interrupt.
+    isr_port: AtomicU16,
+    isr_address: AtomicU64,
+    /// Woken on every interrupt of the device, one per virtqueue.
+    wakers: [AtomicWaker; MAX_QUEUES],
+}
+
+impl InterruptSlot {
+    const fn new() -> Self {
+        const NO_WAKER: AtomicWaker = AtomicWaker::new();
+        InterruptSlot {
+            used: AtomicBool::new(false),
+            irq: AtomicU8::new(0),
+            isr_port: AtomicU16::new(0),
+            isr_address: AtomicU64::new(0),
+            wakers: [NO_WAKER; MAX_QUEUES],
+        }
+    }
+
+    fn read_isr(&self) -> u8 {
+        match self.isr_address.load(Ordering::Acquire) {
+            0 => unsafe { Port::new(self.isr_port.load(Ordering::Acquire)).read() },
+            address => unsafe { read_mmio(VirtAddr::new(address)) },
+        }
+    }
+}
+
+const FREE_SLOT: InterruptSlot = InterruptSlot::new();
+static SLOTS: [InterruptSlot; MAX_DEVICES] = [FREE_SLOT; MAX_DEVICES];
+
+/// The IRQ lines the interrupt handler is registered on, as a bit mask.
+static REGISTERED_LINES: Mutex<u16> = Mutex::new(0);
+
+/// The interrupt handler of all VirtIO devices; wakes the tasks waiting on the queues of
+/// the devices that raised the interrupt.
+fn interrupt_handler(irq: u8) -> IrqReturn {
+    let mut result = IrqReturn::NotMine;
+    let slots = SLOTS.iter().filter(|slot| {
+        slot.used.load(Ordering::Acquire) && slot.irq.load(Ordering::Relaxed) == irq
+    });
+    for slot in slots {
+        let isr = slot.read_isr();
+        if isr & ISR_QUEUE != 0 {
+            slot.wakers.iter().for_each(AtomicWaker::wake);
+        }
+        if isr != 0 {
+            result = IrqReturn::Handled;
+        }
+    }
+    result
+}
+
+/// A VirtIO device being initialized or driven.
+pub struct VirtioDevice {
+    pci: PciAddress,
+    transport: Transport,
+    slot: usize,
+    features: u64,
+    /// Held while a task has a request in flight; requests are serialized per device.
+    busy: BusyLock,
+    /// Set once the device was reset because it didn't complete a dropped request.
+    failed: AtomicBool,
+}
+
+impl VirtioDevice {
+    /// Resets a device and acknowledges it, starting its initialization.
+    ///
+    /// A device dropped before it is driven, e.g. because its initialization failed, is
+    /// marked as failed and gives up its interrupt slot.
+    pub fn new(pci: PciAddress) -> Result<Self, VirtioError> {
+        pci.enable_bus_master();
+        let transport = Transport::probe(pci)?;
+        let slot = match SLOTS.iter().position(|slot| {
+            slot.used
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        }) {
+            Some(slot) => slot,
+            None => {
+                transport.set_status(transport.status() | STATUS_FAILED);
+                return Err(VirtioError::TooManyDevices);
+            }
+        };
+        let device = VirtioDevice {
+            pci,
+            transport,
+            slot,
+            features: 0,
+            busy: BusyLock::new(),
+            failed: AtomicBool::new(false),
+        };
+        transport.reset()?;
+        transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        Ok(device)
+    }
+
+    pub fn pci_address(&self) -> PciAddress {
+        self.pci
+    }
+
+    pub fn is_modern(&self) -> bool {
+        self.transport.is_modern()
+    }
+
+    /// Accepts the offered features among `wanted` and returns them.
+    pub fn negotiate(&mut self, wanted: u64) -> Result<u64, VirtioError> {
+        let transport = self.transport;
+        let mut wanted = wanted;
+        if transport.is_modern() {
+            wanted |= FEATURE_VERSION_1;
+        }
+        self.features = transport.device_features() & wanted;
+        transport.set_driver_features(self.features);
+        if transport.is_modern() {
+            transport.set_status(transport.status() | STATUS_FEATURES_OK);
+            if transport.status() & STATUS_FEATURES_OK == 0 {
+                return Err(VirtioError::FeaturesRejected);
+            }
+        }
+        Ok(self.features)
+    }
+
+    /// Whether the feature with the given bit was negotiated.
+    pub fn has_feature(&self, bit: u32) -> bool {
+        self.features & 1 << bit != 0
+    }
+
+    /// Sets up the virtqueue `index` with at most `max_size` entries; legacy devices
+    /// always use their own queue size.
+    pub fn queue(&self, index: u16, max_size: u16) -> Result<Mutex<Virtqueue>, VirtioError> {
+        let device_size = self.transport.select_queue(index);
+        if device_size == 0 || index as usize >= MAX_QUEUES {
+            return Err(VirtioError::QueueUnavailable(index));
+        }
+        let size = if self.transport.is_modern() {
+            device_size.min(max_size)
+        } else {
+            device_size
+        };
+        let waker = &SLOTS[self.slot].wakers[index as usize];
+        let mut queue = Virtqueue::new(index, size, waker)?;
+        let notify_off = self.transport.enable_queue(&queue);
+        queue.set_notify_off(notify_off);
+        Ok(Mutex::new(queue))
+    }
+
+    /// Enables the device's interrupt and tells it that the driver is ready.
+    pub fn finish(&self) -> Result<(), VirtioError> {
+        let irq = self.pci.interrupt_line();
+        if irq as usize >= irq::IRQ_LINES {
+            return Err(VirtioError::Irq(IrqError::InvalidLine(irq)));
+        }
+        let slot = &SLOTS[self.slot];
+        match self.transport {
+            Transport::Legacy { port } => slot
+                .isr_port
+                .store(port + LEGACY_ISR_STATUS, Ordering::Release),
+            Transport::Modern { isr, .. } => {
+                slot.isr_address.store(isr.as_u64(), Ordering::Release)
+            }
+        }
+        slot.irq.store(irq, Ordering::Relaxed);
+
+        {
+            let mut lines = REGISTERED_LINES.lock();
+            if *lines & 1 << irq == 0 {
+                irq::register(irq, "VirtIO", interrupt_handler).map_err(VirtioError::Irq)?;
+                *lines |= 1 << irq;
+            }
+        }
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_DRIVER_OK);
+        Ok(())
+    }
+
+    /// Tells the device that buffers were added to the queue.
+    pub fn notify(&self, queue: &Virtqueue) {
+        self.transport.notify(queue.index(), queue.notify_off());
+    }
+
+    /// Reads a 32-bit field of the device-specific configuration.
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        self.transport.read_config_u32(offset)
+    }
+
+    /// Reads a 64-bit field of the device-specific configuration, which may be torn if
+    /// the device changes it at the same time.
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        let low = self.read_config_u32(offset) as u64;
+        let high = self.read_config_u32(offset + 4) as u64;
+        high << 32 | low
+    }
+
+    /// Waits until no other task has a request in flight on the device, for requests on
+    /// `queue`.
+    ///
+    /// Fails if the device was reset after a dropped request it didn't complete.
+    pub async fn lock<'a>(
+        &'a self,
+        queue: &'a Mutex<Virtqueue>,
+    ) -> Result<DeviceGuard<'a>, VirtioError> {
+        let guard = DeviceGuard {
+            _busy: self.busy.lock().await,
+            device: self,
+            queue,
+        };
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(VirtioError::Timeout);
+        }
+        Ok(guard)
+    }
+}
+
+impl Drop for VirtioDevice {
+    fn drop(&mut self) {
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_FAILED);
+        let slot = &SLOTS[self.slot];
+        slot.isr_port.store(0, Ordering::Relaxed);
+        slot.isr_address.store(0, Ordering::Relaxed);
+        slot.used.store(false, Ordering::Release);
+    }
+}
+
+pub struct DeviceGuard<'a> {
+    _busy: BusyGuard<'a>,
+    device: &'a VirtioDevice,
+    queue: &'a Mutex<Virtqueue>,
+}
+
+impl Drop for DeviceGuard<'_> {
+    fn drop(&mut self) {
+        // a request dropped before the device used its buffers would have them overwritten
+        // by the next request, and its used chain taken for the next request's
+        let idle = (0..POLL_LIMIT).any(|_| {
+            let mut queue = self.queue.lock();
+            while queue.pop_used().is_some() {}
+            queue.is_idle()
+        });
+        if !idle {
+            // after a reset, the device no longer accesses the queue
+            let device = self.device;
+            warn!("virtio {}: reset after a dropped request", device.pci);
+            let _ = device.transport.reset();
+            device.transport.set_status(STATUS_FAILED);
+            device.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The driver binding VirtIO devices, which dispatches to the driver for each device type.
+pub static DRIVER: PciDriver = PciDriver {
+    name: "virtio",
+    matches: &[DeviceMatch::Vendor(VENDOR_ID)],
+    probe,
+};
+
+/// The number of devices initialized.
+static DEVICES: AtomicUsize = AtomicUsize::new(0);
+
+fn probe(pci: PciAddress) -> bool {
+    let device_type = match DeviceType::from_pci_id(pci.device_id()) {
+        Some(device_type) => device_type,
+        None => return false,
+    };
+    let result = VirtioDevice::new(pci).and_then(|device| match device_type {
+        DeviceType::Block => blk::init(device),
+        DeviceType::Console => console::init(device),
+        DeviceType::Entropy => rng::init(device),
+    });
+    match result {
+        Ok(()) => {
+            info!("virtio {}: {:?} device", pci, device_type);
+            DEVICES.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(err) => {
+            warn!("virtio {}: {:?} device unusable: {}", pci, device_type, err);
+            false
+        }
+    }
+}
+
+/// Registers the VirtIO driver and initializes the devices with a driver.
+///
+/// Returns the number of devices initialized. Requires `memory::init_kernel_memory` to
+/// have been called and the heap to be initialized.
+pub fn init() -> usize {
+    pci::register_driver(&DRIVER);
+    pci::probe_drivers();
+    DEVICES.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_device_types() {
+    assert_eq!(DeviceType::from_pci_id(0x1001), Some(DeviceType::Block));
+    assert_eq!(DeviceType::from_pci_id(0x1044), Some(DeviceType::Entropy));
+    assert_eq!(DeviceType::from_pci_id(0x1000), None);
+}
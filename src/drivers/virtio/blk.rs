@@ -0,0 +1,215 @@
+//! VirtIO block devices, registered as block devices named `vda`, `vdb` etc.
+//!
+//! With QEMU, a disk is attached with `-drive file=disk.img,format=raw,if=virtio`.
+
+use super::{
+    queue::{self, Buffer, Virtqueue},
+    VirtioDevice, VirtioError,
+};
+use crate::{
+    block::{self, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE},
+    memory::dma::{self, FRAME_SIZE},
+};
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+const FEATURE_READ_ONLY: u32 = 5;
+const FEATURE_FLUSH: u32 = 9;
+
+/// The capacity in 512-byte sectors, the first field of the configuration.
+const CONFIG_CAPACITY: u16 = 0;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+/// Written to the status byte before a request, so that a request the device didn't
+/// complete is reported as an error.
+const STATUS_PENDING: u8 = 0xff;
+
+/// The request header is at the start of the control frame, the status byte after it.
+const HEADER_SIZE: u32 = 16;
+const STATUS_OFFSET: u64 = HEADER_SIZE as u64;
+
+/// The number of bounce frames, each described by its own descriptor.
+const BOUNCE_FRAMES: usize = 16;
+const MAX_SECTORS_PER_REQUEST: usize = BOUNCE_FRAMES * FRAME_SIZE / SECTOR_SIZE;
+/// The header, the data and the status descriptors of the largest request.
+const MAX_DESCRIPTORS: u16 = BOUNCE_FRAMES as u16 + 2;
+
+/// The number of block devices found so far, used for naming.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A VirtIO block device.
+pub struct VirtioBlock {
+    device: VirtioDevice,
+    queue: Mutex<Virtqueue>,
+    /// Holds the request header and the status byte.
+    control: PhysFrame,
+    bounce: [PhysFrame; BOUNCE_FRAMES],
+    name: String,
+    sectors: u64,
+    read_only: bool,
+}
+
+impl VirtioBlock {
+    /// Sends a request for `bytes` bytes of data in the bounce frames and waits for it.
+    ///
+    /// Must be called with the device locked.
+    async fn request(&self, kind: u32, sector: u64, bytes: usize) -> Result<(), BlockError> {
+        let control = dma::frame_ptr(self.control);
+        unsafe {
+            ptr::write_volatile(control as *mut u32, kind);
+            ptr::write_volatile(control.add(4) as *mut u32, 0);
+            ptr::write_volatile(control.add(8) as *mut u64, sector);
+            ptr::write_volatile(control.add(STATUS_OFFSET as usize), STATUS_PENDING);
+        }
+
+        let control_address = self.control.start_address();
+        let mut buffers = [Buffer {
+            address: control_address,
+            len: HEADER_SIZE,
+            writable: false,
+        }; MAX_DESCRIPTORS as usize];
+        let regions = (bytes + FRAME_SIZE - 1) / FRAME_SIZE;
+        for (index, frame) in self.bounce[..regions].iter().enumerate() {
+            buffers[index + 1] = Buffer {
+                address: frame.start_address(),
+                len: (bytes - index * FRAME_SIZE).min(FRAME_SIZE) as u32,
+                writable: kind == REQUEST_IN,
+            };
+        }
+        buffers[regions + 1] = Buffer {
+            address: control_address + STATUS_OFFSET,
+            len: 1,
+            writable: true,
+        };
+
+        {
+            let mut queue = self.queue.lock();
+            queue
+                .add(&buffers[..regions + 2])
+                .map_err(|_| BlockError::Device(STATUS_PENDING))?;
+            self.device.notify(&queue);
+        }
+        queue::used(&self.queue).await;
+
+        match unsafe { ptr::read_volatile(control.add(STATUS_OFFSET as usize)) } {
+            STATUS_OK => Ok(()),
+            status => Err(BlockError::Device(status)),
+        }
+    }
+
+    async fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        block::check_range(self, sector, buf.len())?;
+
+        let _guard = self
+            .device
+            .lock(&self.queue)
+            .await
+            .map_err(|_| BlockError::Timeout)?;
+        let chunks = buf.chunks_mut(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_REQUEST) as u64;
+            self.request(REQUEST_IN, start, chunk.len()).await?;
+            dma::copy_from_frames(&self.bounce, chunk);
+        }
+        Ok(())
+    }
+
+    async fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        block::check_range(self, sector, buf.len())?;
+
+        let _guard = self
+            .device
+            .lock(&self.queue)
+            .await
+            .map_err(|_| BlockError::Timeout)?;
+        let chunks = buf.chunks(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_REQUEST) as u64;
+            dma::copy_to_frames(&self.bounce, chunk);
+            self.request(REQUEST_OUT, start, chunk.len()).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush_cache(&self) -> Result<(), BlockError> {
+        // without the flush feature, the device writes through
+        if !self.device.has_feature(FEATURE_FLUSH) {
+            return Ok(());
+        }
+        let _guard = self
+            .device
+            .lock(&self.queue)
+            .await
+            .map_err(|_| BlockError::Timeout)?;
+        self.request(REQUEST_FLUSH, 0, 0).await
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        if self.device.is_modern() {
+            "VirtIO block device"
+        } else {
+            "VirtIO block device (legacy)"
+        }
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read_sectors(sector, buf))
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write_sectors(sector, buf))
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+/// Sets up a block device and registers it.
+pub(super) fn init(mut device: VirtioDevice) -> Result<(), VirtioError> {
+    device.negotiate(1 << FEATURE_READ_ONLY | 1 << FEATURE_FLUSH)?;
+    let queue = device.queue(0, 256)?;
+    if queue.lock().size() < MAX_DESCRIPTORS {
+        return Err(VirtioError::QueueTooSmall(0));
+    }
+    let (control, bounce) =
+        dma::allocate_frames::<BOUNCE_FRAMES>().ok_or(VirtioError::OutOfMemory)?;
+    device.finish()?;
+
+    let index = COUNT.fetch_add(1, Ordering::Relaxed);
+    let name = format!("vd{}", char::from(b'a' + index as u8));
+    let sectors = device.read_config_u64(CONFIG_CAPACITY);
+    let read_only = device.has_feature(FEATURE_READ_ONLY);
+    block::register(Arc::new(VirtioBlock {
+        device,
+        queue,
+        control,
+        bounce,
+        name,
+        sectors,
+        read_only,
+    }));
+    Ok(())
+}
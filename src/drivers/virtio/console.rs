@@ -0,0 +1,265 @@
+//! The VirtIO console, a character device that can carry a shell like a serial port.
+//!
+//! Only the first port of the first console is used; the multiport feature is not
+//! negotiated. With QEMU, the console is attached with `-device virtio-serial-pci
+//! -device virtconsole,chardev=vc -chardev stdio,id=vc`, or with a socket chardev.
+
+use super::{
+    queue::{self, Buffer, Virtqueue},
+    VirtioDevice, VirtioError,
+};
+use crate::{
+    memory::dma::{self, FRAME_SIZE},
+    task::serial_console,
+    vga_buffer, warn,
+};
+use conquer_once::spin::OnceCell;
+use core::{
+    fmt,
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{future, stream::Stream, task::AtomicWaker};
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// A VirtIO console port with one buffer frame for each direction.
+pub struct VirtioConsole {
+    device: VirtioDevice,
+    receive: Mutex<Virtqueue>,
+    transmit: Mutex<Virtqueue>,
+    receive_frame: PhysFrame,
+    transmit_frame: PhysFrame,
+}
+
+static CONSOLE: OnceCell<VirtioConsole> = OnceCell::uninit();
+
+impl VirtioConsole {
+    /// Hands the receive frame to the device, to be filled with input.
+    fn post_receive_buffer(&self) -> Result<(), VirtioError> {
+        let mut queue = self.receive.lock();
+        queue.add(&[Buffer {
+            address: self.receive_frame.start_address(),
+            len: FRAME_SIZE as u32,
+            writable: true,
+        }])?;
+        self.device.notify(&queue);
+        Ok(())
+    }
+
+    /// Sends `data` to the host.
+    pub async fn write(&self, data: &[u8]) -> Result<(), VirtioError> {
+        let _guard = self.device.lock(&self.transmit).await?;
+        for chunk in data.chunks(FRAME_SIZE) {
+            let frame = dma::frame_ptr(self.transmit_frame);
+            unsafe { ptr::copy_nonoverlapping(chunk.as_ptr(), frame, chunk.len()) };
+            {
+                let mut queue = self.transmit.lock();
+                queue.add(&[Buffer {
+                    address: self.transmit_frame.start_address(),
+                    len: chunk.len() as u32,
+                    writable: false,
+                }])?;
+                self.device.notify(&queue);
+            }
+            queue::used(&self.transmit).await;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the console, if one was found.
+pub fn get() -> Option<&'static VirtioConsole> {
+    CONSOLE.get()
+}
+
+/// Sets up the console port.
+pub(super) fn init(mut device: VirtioDevice) -> Result<(), VirtioError> {
+    device.negotiate(0)?;
+    let receive = device.queue(RECEIVE_QUEUE, 64)?;
+    let transmit = device.queue(TRANSMIT_QUEUE, 64)?;
+    let (receive_frame, [transmit_frame]) =
+        dma::allocate_frames::<1>().ok_or(VirtioError::OutOfMemory)?;
+    device.finish()?;
+
+    let console = VirtioConsole {
+        device,
+        receive,
+        transmit,
+        receive_frame,
+        transmit_frame,
+    };
+    console.post_receive_buffer()?;
+    // only the first console is used
+    let _ = CONSOLE.try_init_once(|| console);
+    Ok(())
+}
+
+/// The bytes received on the console.
+pub struct ConsoleStream {
+    console: &'static VirtioConsole,
+    /// The received bytes in the receive frame, and the number already returned.
+    received: usize,
+    position: usize,
+}
+
+impl ConsoleStream {
+    /// Returns the stream of the console, if there is one.
+    ///
+    /// There must be only one stream, since it owns the receive buffer.
+    pub fn new() -> Option<Self> {
+        Some(ConsoleStream {
+            console: get()?,
+            received: 0,
+            position: 0,
+        })
+    }
+
+    /// Returns the next received byte if there is one, without waiting.
+    fn try_next(&mut self) -> Option<u8> {
+        if self.position == self.received {
+            let (_, len) = self.console.receive.lock().pop_used()?;
+            self.received = len as usize;
+            self.position = 0;
+            if self.received == 0 {
+                self.repost();
+                return None;
+            }
+        }
+        let frame = dma::frame_ptr(self.console.receive_frame);
+        let byte = unsafe { ptr::read_volatile(frame.add(self.position)) };
+        self.position += 1;
+        if self.position == self.received {
+            self.repost();
+        }
+        Some(byte)
+    }
+
+    fn repost(&mut self) {
+        if let Err(err) = self.console.post_receive_buffer() {
+            warn!("virtio console: can't receive: {}", err);
+        }
+    }
+}
+
+impl Stream for ConsoleStream {
+    type Item = u8;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // fast path
+        if let Some(byte) = self.try_next() {
+            return Poll::Ready(Some(byte));
+        }
+
+        let waker = self.console.receive.lock().waker();
+        waker.register(&cx.waker());
+        match self.try_next() {
+            Some(byte) => {
+                waker.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// The console output waiting to be sent by `run_shell`.
+static OUTPUT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static OUTPUT_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// The virtual console whose output is mirrored to the VirtIO console, or `NO_CONSOLE`.
+static MIRRORED: AtomicUsize = AtomicUsize::new(NO_CONSOLE);
+const NO_CONSOLE: usize = usize::MAX;
+
+/// Returns the virtual console mirrored to the VirtIO console.
+pub fn console() -> Option<usize> {
+    match MIRRORED.load(Ordering::Relaxed) {
+        NO_CONSOLE => None,
+        console => Some(console),
+    }
+}
+
+/// Queues console output for the VirtIO console, translating `\n` to `\r\n`.
+///
+/// Called by `vga_buffer::_print` with interrupts disabled. When the queue is full, the
+/// oldest output is dropped.
+pub(crate) fn write_console(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    struct QueueWriter(&'static ArrayQueue<u8>);
+
+    impl QueueWriter {
+        fn push(&mut self, byte: u8) {
+            let mut byte = byte;
+            while let Err(full) = self.0.push(byte) {
+                byte = full.0;
+                let _ = self.0.pop();
+            }
+        }
+    }
+
+    impl fmt::Write for QueueWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for byte in s.bytes() {
+                if byte == b'\n' {
+                    self.push(b'\r');
+                }
+                self.push(byte);
+            }
+            Ok(())
+        }
+    }
+
+    if let Ok(queue) = OUTPUT_QUEUE.try_get() {
+        let _ = QueueWriter(queue).write_fmt(args);
+        OUTPUT_WAKER.wake();
+    }
+}
+
+/// Sends the queued console output.
+async fn transmit_output(console: &'static VirtioConsole, queue: &'static ArrayQueue<u8>) {
+    let mut chunk = [0; 256];
+    loop {
+        let len = future::poll_fn(|cx| {
+            OUTPUT_WAKER.register(&cx.waker());
+            let len = chunk
+                .iter_mut()
+                .map_while(|byte| queue.pop().ok().map(|popped| *byte = popped))
+                .count();
+            if len == 0 {
+                Poll::Pending
+            } else {
+                OUTPUT_WAKER.take();
+                Poll::Ready(len)
+            }
+        })
+        .await;
+        if let Err(err) = console.write(&chunk[..len]).await {
+            warn!("virtio console: can't send: {}", err);
+        }
+    }
+}
+
+/// Runs a shell on the VirtIO console, mirroring the current console to it like
+/// `serial_console::run_shell` does for a serial port.
+///
+/// Returns at once if there is no VirtIO console. Requires the heap to be initialized.
+pub async fn run_shell() {
+    let console = match get() {
+        Some(console) => console,
+        None => return,
+    };
+    let bytes = ConsoleStream::new().expect("VirtIO console disappeared");
+    let queue = OUTPUT_QUEUE.get_or_init(|| ArrayQueue::new(4096));
+    let mirrored = vga_buffer::current_console();
+    MIRRORED.store(mirrored, Ordering::Relaxed);
+
+    let shell = serial_console::run_shell_on(bytes, mirrored);
+    future::join(transmit_output(console, queue), shell).await;
+}
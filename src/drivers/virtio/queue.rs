@@ -0,0 +1,259 @@
+//! Split virtqueues: the rings through which buffers are exchanged with a device.
+//!
+//! A queue occupies physically contiguous frames with the descriptor table, the available
+//! ring written by the driver and, starting on the next page, the used ring written by the
+//! device. This is the layout legacy devices require; modern devices are given the three
+//! addresses separately, so they work with it as well.
+
+use super::VirtioError;
+use crate::memory;
+use core::{
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::atomic::{fence, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::{structures::paging::PhysFrame, PhysAddr, VirtAddr};
+
+const DESCRIPTOR_SIZE: u64 = 16;
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Returns the offset of the used ring and the total size in bytes of a queue with `size`
+/// entries.
+pub fn layout(size: u16) -> (u64, u64) {
+    let size = size as u64;
+    let driver_area = DESCRIPTOR_SIZE * size + 6 + 2 * size;
+    let used_offset = x86_64::align_up(driver_area, PAGE_SIZE);
+    let total = x86_64::align_up(used_offset + 6 + 8 * size, PAGE_SIZE);
+    (used_offset, total)
+}
+
+/// A buffer in physical memory that is passed to a device.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub address: PhysAddr,
+    pub len: u32,
+    /// Whether the device writes to the buffer, rather than reading it.
+    pub writable: bool,
+}
+
+/// A virtqueue with its descriptors, kept in a free list threaded through their `next`
+/// fields.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    start: PhysFrame,
+    used_offset: u64,
+    free_head: u16,
+    free_count: u16,
+    /// The available ring index, i.e. the number of chains made available so far.
+    next_available: u16,
+    /// The number of used ring entries consumed so far.
+    last_used: u16,
+    notify_off: u16,
+    waker: &'static AtomicWaker,
+}
+
+impl Virtqueue {
+    /// Allocates a queue with `size` entries, which is woken through `waker`.
+    pub(super) fn new(
+        index: u16,
+        size: u16,
+        waker: &'static AtomicWaker,
+    ) -> Result<Self, VirtioError> {
+        let (used_offset, total) = layout(size);
+        let start = memory::with_kernel_memory(|memory| {
+            memory
+                .frame_allocator
+                .allocate_contiguous(total / PAGE_SIZE)
+        })
+        .ok_or(VirtioError::OutOfMemory)?;
+        let queue = Virtqueue {
+            index,
+            size,
+            start,
+            used_offset,
+            free_head: 0,
+            free_count: size,
+            next_available: 0,
+            last_used: 0,
+            notify_off: 0,
+            waker,
+        };
+        for descriptor in 0..size {
+            queue.write_descriptor_next(descriptor, descriptor.wrapping_add(1));
+        }
+        Ok(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Whether the device holds no buffers of the queue.
+    pub fn is_idle(&self) -> bool {
+        self.free_count == self.size
+    }
+
+    /// The waker woken on the device's interrupts.
+    pub fn waker(&self) -> &'static AtomicWaker {
+        self.waker
+    }
+
+    pub(super) fn notify_off(&self) -> u16 {
+        self.notify_off
+    }
+
+    pub(super) fn set_notify_off(&mut self, notify_off: u16) {
+        self.notify_off = notify_off;
+    }
+
+    /// Returns the physical addresses of the descriptor table, the available ring and the
+    /// used ring.
+    pub(super) fn addresses(&self) -> (PhysAddr, PhysAddr, PhysAddr) {
+        let start = self.start.start_address();
+        (
+            start,
+            start + DESCRIPTOR_SIZE * self.size as u64,
+            start + self.used_offset,
+        )
+    }
+
+    /// Returns the virtual address of the given offset into the queue memory.
+    fn addr(&self, offset: u64) -> VirtAddr {
+        memory::physical_memory_offset() + self.start.start_address().as_u64() + offset
+    }
+
+    fn write<T>(&self, offset: u64, value: T) {
+        unsafe { ptr::write_volatile(self.addr(offset).as_mut_ptr(), value) }
+    }
+
+    fn read<T>(&self, offset: u64) -> T {
+        unsafe { ptr::read_volatile(self.addr(offset).as_ptr()) }
+    }
+
+    fn descriptor_offset(descriptor: u16) -> u64 {
+        descriptor as u64 * DESCRIPTOR_SIZE
+    }
+
+    fn write_descriptor_next(&self, descriptor: u16, next: u16) {
+        self.write(Self::descriptor_offset(descriptor) + 14, next);
+    }
+
+    /// Adds a chain of buffers to the available ring and returns the index of its first
+    /// descriptor. The device must be notified afterwards.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() || buffers.len() > self.size as usize {
+            return Err(VirtioError::QueueTooSmall(self.index));
+        }
+        if buffers.len() > self.free_count as usize {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let head = self.free_head;
+        let mut descriptor = head;
+        for (index, buffer) in buffers.iter().enumerate() {
+            let offset = Self::descriptor_offset(descriptor);
+            let mut flags = 0;
+            if buffer.writable {
+                flags |= DESCRIPTOR_WRITE;
+            }
+            if index + 1 < buffers.len() {
+                flags |= DESCRIPTOR_NEXT;
+            }
+            self.write(offset, buffer.address.as_u64());
+            self.write(offset + 8, buffer.len);
+            self.write(offset + 12, flags);
+            // free descriptors are already linked, so the chain keeps their order
+            descriptor = self.read(offset + 14);
+        }
+        self.free_head = descriptor;
+        self.free_count -= buffers.len() as u16;
+
+        let slot = self.next_available % self.size;
+        self.write(
+            DESCRIPTOR_SIZE * self.size as u64 + 4 + 2 * slot as u64,
+            head,
+        );
+        self.next_available = self.next_available.wrapping_add(1);
+        // the ring entry must be visible before the index that publishes it
+        fence(Ordering::SeqCst);
+        self.write(DESCRIPTOR_SIZE * self.size as u64 + 2, self.next_available);
+        fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// Takes the next chain the device is done with and returns the index of its first
+    /// descriptor and the number of bytes the device wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_index: u16 = self.read(self.used_offset + 2);
+        if used_index == self.last_used {
+            return None;
+        }
+        // the entry must not be read before the index announcing it
+        fence(Ordering::SeqCst);
+        let entry = self.used_offset + 4 + 8 * (self.last_used % self.size) as u64;
+        let head = self.read::<u32>(entry) as u16;
+        let len = self.read(entry + 4);
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let mut tail = head;
+        let mut count = 1;
+        while self.read::<u16>(Self::descriptor_offset(tail) + 12) & DESCRIPTOR_NEXT != 0 {
+            tail = self.read(Self::descriptor_offset(tail) + 14);
+            count += 1;
+        }
+        self.write_descriptor_next(tail, self.free_head);
+        self.free_head = head;
+        self.free_count += count;
+        Some((head, len))
+    }
+}
+
+/// Returns a future that resolves to the next used chain of the queue.
+pub fn used(queue: &Mutex<Virtqueue>) -> Used<'_> {
+    Used { queue }
+}
+
+pub struct Used<'a> {
+    queue: &'a Mutex<Virtqueue>,
+}
+
+impl Future for Used<'_> {
+    type Output = (u16, u32);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<(u16, u32)> {
+        let mut queue = self.queue.lock();
+        // fast path
+        if let Some(used) = queue.pop_used() {
+            return Poll::Ready(used);
+        }
+
+        queue.waker.register(&cx.waker());
+        match queue.pop_used() {
+            Some(used) => {
+                queue.waker.take();
+                Poll::Ready(used)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_layout() {
+    // the sizes legacy QEMU devices use
+    assert_eq!(layout(128), (4096, 8192));
+    assert_eq!(layout(256), (8192, 12288));
+    assert_eq!(layout(8), (4096, 8192));
+}
@@ -0,0 +1,96 @@
+//! VirtIO entropy devices, which feed the kernel entropy pool.
+//!
+//! With QEMU, a device is attached with `-device virtio-rng-pci`.
+
+use super::{
+    queue::{self, Buffer, Virtqueue},
+    VirtioDevice, VirtioError,
+};
+use crate::{
+    memory::{self, dma},
+    random, warn,
+};
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+/// The most bytes requested at once.
+const MAX_REQUEST: usize = 4096;
+
+/// The bytes requested whenever the entropy pool runs low.
+const FEED_SIZE: usize = random::POOL_BITS / 8;
+
+/// A VirtIO entropy device.
+pub struct VirtioRng {
+    device: VirtioDevice,
+    queue: Mutex<Virtqueue>,
+    /// The frame the device writes random bytes to.
+    frame: PhysFrame,
+}
+
+static RNG: OnceCell<VirtioRng> = OnceCell::uninit();
+
+impl VirtioRng {
+    /// Fills the start of `buf` with random bytes from the device and returns their
+    /// number, which is at most 4096 and may be less than requested.
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize, VirtioError> {
+        let len = buf.len().min(MAX_REQUEST);
+        let _guard = self.device.lock(&self.queue).await?;
+        {
+            let mut queue = self.queue.lock();
+            queue.add(&[Buffer {
+                address: self.frame.start_address(),
+                len: len as u32,
+                writable: true,
+            }])?;
+            self.device.notify(&queue);
+        }
+        let (_, written) = queue::used(&self.queue).await;
+
+        let written = (written as usize).min(len);
+        dma::copy_from_frames(&[self.frame], &mut buf[..written]);
+        Ok(written)
+    }
+}
+
+/// Returns the entropy device, if one was found.
+pub fn get() -> Option<&'static VirtioRng> {
+    RNG.get()
+}
+
+/// Sets up the entropy device; only the first one is used.
+pub(super) fn init(mut device: VirtioDevice) -> Result<(), VirtioError> {
+    device.negotiate(0)?;
+    let queue = device.queue(0, 8)?;
+    let frame = memory::with_kernel_memory(|memory| memory.frame_allocator.allocate_zeroed_frame())
+        .ok_or(VirtioError::OutOfMemory)?;
+    device.finish()?;
+
+    let _ = RNG.try_init_once(|| VirtioRng {
+        device,
+        queue,
+        frame,
+    });
+    Ok(())
+}
+
+/// Refills the entropy pool from the device whenever it runs low.
+///
+/// Returns at once if there is no entropy device.
+pub async fn feed_pool() {
+    let rng = match get() {
+        Some(rng) => rng,
+        None => return,
+    };
+    let mut buf = [0; FEED_SIZE];
+    loop {
+        random::low_entropy().await;
+        match rng.read(&mut buf).await {
+            Ok(len) => random::add_entropy(&buf[..len], len * 8),
+            Err(err) => {
+                warn!("virtio-rng: {}", err);
+                return;
+            }
+        }
+    }
+}
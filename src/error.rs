@@ -1,6 +1,7 @@
 #[derive(Debug)]
 pub enum MyError {
     InvalidFuture,
+    InvalidArgument,
     // Add other error variants as needed
 }
 
@@ -8,6 +9,7 @@ impl core::fmt::Display for MyError {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             MyError::InvalidFuture => write!(f, "\nInvalid Task"),
+            MyError::InvalidArgument => write!(f, "\nInvalid Argument"),
             // Handle other error variants here
         }
     }
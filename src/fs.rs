@@ -0,0 +1,514 @@
+//! The virtual file system: a tree of mounted file systems, addressed by paths.
+//!
+//! File systems implement `FileSystem` and `Inode`. Paths are resolved lexically: `.` and
+//! `..` are removed before the components are looked up, starting at the root of the
+//! mount with the longest matching mount point. Relative paths start at the working
+//! directory, which is shared by all shells.
+//!
+//! The operations are synchronous, so only file systems that keep their data in memory,
+//! like tmpfs, can implement them. A file system on a block device would have to wait for
+//! the asynchronous `BlockDevice` requests inside them, blocking the executor; supporting
+//! one requires turning the traits into ones returning futures, like `BlockDevice`.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt;
+use spin::Mutex;
+
+pub mod initramfs;
+pub mod tmpfs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// The path is a mount point or has mounts below it.
+    Busy,
+    InvalidPath,
+    /// A seek to a negative or overflowing position.
+    InvalidSeek,
+    /// The handle wasn't opened for the access.
+    PermissionDenied,
+    /// The file system doesn't support the operation.
+    NotSupported,
+    NoSpace,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsError::NotFound => write!(f, "no such file or directory"),
+            FsError::NotADirectory => write!(f, "not a directory"),
+            FsError::IsADirectory => write!(f, "is a directory"),
+            FsError::AlreadyExists => write!(f, "file exists"),
+            FsError::DirectoryNotEmpty => write!(f, "directory not empty"),
+            FsError::Busy => write!(f, "mount point busy"),
+            FsError::InvalidPath => write!(f, "invalid path"),
+            FsError::InvalidSeek => write!(f, "invalid seek"),
+            FsError::PermissionDenied => write!(f, "permission denied"),
+            FsError::NotSupported => write!(f, "operation not supported"),
+            FsError::NoSpace => write!(f, "no space left"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// The inode number, unique within the file system.
+    pub inode: u64,
+    pub file_type: FileType,
+    /// The size in bytes; 0 for directories.
+    pub size: u64,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+/// A file or directory of a file system.
+///
+/// The methods must complete without waiting for devices; see the module documentation.
+/// The default implementations fail like the operations on the wrong kind of inode, so
+/// files only implement the data methods and directories the name methods.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// Returns the entry `name` of a directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Creates the entry `name` in a directory.
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Removes the entry `name` from a directory; directories must be empty.
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Returns the entries of a directory, without `.` and `..`.
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Reads from a file at `offset` and returns the number of bytes read, 0 at the end.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Writes to a file at `offset`, extending it as needed.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Sets the size of a file, filling extensions with zeros.
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::IsADirectory)
+    }
+}
+
+/// A mountable file system, which keeps its data in memory.
+pub trait FileSystem: Send + Sync {
+    /// The type name shown in the mount table, e.g. `tmpfs`.
+    fn fs_type(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+}
+
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// The mounts, ordered by decreasing path length, so that the first match is the longest.
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+/// The working directory; empty before it is first set, which stands for `/`.
+static CURRENT_DIR: Mutex<String> = Mutex::new(String::new());
+
+/// Returns the working directory.
+pub fn current_dir() -> String {
+    match CURRENT_DIR.lock().as_str() {
+        "" => "/".to_string(),
+        dir => dir.to_string(),
+    }
+}
+
+/// Changes the working directory to an existing directory.
+pub fn set_current_dir(path: &str) -> Result<(), FsError> {
+    let dentry = lookup(path)?;
+    if !dentry.metadata().is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+    *CURRENT_DIR.lock() = dentry.path;
+    Ok(())
+}
+
+/// Turns a path into an absolute path without `.`, `..` and repeated slashes.
+///
+/// `..` at the root stays at the root.
+pub fn absolute(path: &str) -> Result<String, FsError> {
+    if path.is_empty() {
+        return Err(FsError::InvalidPath);
+    }
+    let base = if path.starts_with('/') {
+        String::new()
+    } else {
+        current_dir()
+    };
+    let mut components = Vec::new();
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    Ok(format!("/{}", components.join("/")))
+}
+
+/// Returns whether the absolute path `path` is `mount_point` or below it.
+fn is_below(path: &str, mount_point: &str) -> bool {
+    mount_point == "/"
+        || path == mount_point
+        || (path.starts_with(mount_point) && path.as_bytes()[mount_point.len()] == b'/')
+}
+
+/// A resolved path: an absolute path and the inode it names.
+#[derive(Clone)]
+pub struct Dentry {
+    path: String,
+    inode: Arc<dyn Inode>,
+}
+
+impl Dentry {
+    /// The absolute, normalized path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The last component of the path, or `/` for the root.
+    pub fn name(&self) -> &str {
+        match self.path.rsplit('/').next() {
+            Some("") | None => "/",
+            Some(name) => name,
+        }
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.inode.metadata()
+    }
+}
+
+/// Resolves a path.
+pub fn lookup(path: &str) -> Result<Dentry, FsError> {
+    let path = absolute(path)?;
+    let (mount_point, fs) = {
+        let mounts = MOUNTS.lock();
+        let mount = mounts
+            .iter()
+            .find(|mount| is_below(&path, &mount.path))
+            .ok_or(FsError::NotFound)?;
+        (mount.path.clone(), mount.fs.clone())
+    };
+    let mut inode = fs.root();
+    for name in path[mount_point.len()..]
+        .split('/')
+        .filter(|c| !c.is_empty())
+    {
+        inode = inode.lookup(name)?;
+    }
+    Ok(Dentry { path, inode })
+}
+
+/// Resolves the parent directory of a path and returns it with the last component.
+fn lookup_parent(path: &str) -> Result<(Dentry, String), FsError> {
+    let path = absolute(path)?;
+    let split = path.rfind('/').unwrap_or(0);
+    let name = &path[split + 1..];
+    if name.is_empty() {
+        return Err(FsError::InvalidPath);
+    }
+    let parent = lookup(if split == 0 { "/" } else { &path[..split] })?;
+    Ok((parent, name.to_string()))
+}
+
+fn is_mount_point(path: &str) -> bool {
+    MOUNTS.lock().iter().any(|mount| mount.path == path)
+}
+
+/// Mounts a file system at a path, which must be an existing directory unless it is `/`.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = absolute(path)?;
+    if path != "/" && !lookup(&path)?.metadata().is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FsError::Busy);
+    }
+    let position = mounts
+        .iter()
+        .position(|mount| mount.path.len() < path.len())
+        .unwrap_or(mounts.len());
+    mounts.insert(position, Mount { path, fs });
+    Ok(())
+}
+
+/// Unmounts the file system at a path, which must not have mounts below it.
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let path = absolute(path)?;
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(FsError::NotFound)?;
+    let nested = mounts
+        .iter()
+        .any(|mount| mount.path != path && is_below(&mount.path, &path));
+    if nested {
+        return Err(FsError::Busy);
+    }
+    mounts.remove(index);
+    Ok(())
+}
+
+/// Returns the mount points with the types of the mounted file systems, sorted by path.
+pub fn mounts() -> Vec<(String, &'static str)> {
+    let mut mounts: Vec<_> = MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| (mount.path.clone(), mount.fs.fs_type()))
+        .collect();
+    mounts.sort();
+    mounts
+}
+
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    Ok(lookup(path)?.metadata())
+}
+
+/// Returns the entries of a directory, sorted by name.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    let mut entries = lookup(path)?.inode.read_dir()?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+pub fn create_dir(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.inode.create(&name, FileType::Directory)?;
+    Ok(())
+}
+
+/// Removes a file or an empty directory.
+pub fn remove(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    let path = absolute(path)?;
+    if is_mount_point(&path) {
+        return Err(FsError::Busy);
+    }
+    parent.inode.unlink(&name)
+}
+
+/// Reads a whole file.
+pub fn read(path: &str) -> Result<Vec<u8>, FsError> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Creates or replaces a file with the given contents.
+pub fn write(path: &str, data: &[u8]) -> Result<(), FsError> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write(data)?;
+    Ok(())
+}
+
+/// The position to seek to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// Options for opening files, like `std::fs::OpenOptions`.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    truncate: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Makes every write go to the end of the file; implies `write`.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Creates the file if it doesn't exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Truncates the file to 0 bytes; requires `write`.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn open(&self, path: &str) -> Result<File, FsError> {
+        let writable = self.write || self.append;
+        let dentry = match lookup(path) {
+            Err(FsError::NotFound) if self.create => {
+                let (parent, name) = lookup_parent(path)?;
+                let inode = parent.inode.create(&name, FileType::File)?;
+                Dentry {
+                    path: absolute(path)?,
+                    inode,
+                }
+            }
+            result => result?,
+        };
+        if dentry.metadata().is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        if self.truncate {
+            if !writable {
+                return Err(FsError::PermissionDenied);
+            }
+            dentry.inode.truncate(0)?;
+        }
+        Ok(File {
+            dentry,
+            offset: 0,
+            readable: self.read,
+            writable,
+            append: self.append,
+        })
+    }
+}
+
+/// An open file with its position.
+pub struct File {
+    dentry: Dentry,
+    offset: u64,
+    readable: bool,
+    writable: bool,
+    append: bool,
+}
+
+impl File {
+    pub fn path(&self) -> &str {
+        self.dentry.path()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.dentry.metadata()
+    }
+
+    /// Reads from the current position and advances it.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.readable {
+            return Err(FsError::PermissionDenied);
+        }
+        let len = self.dentry.inode.read_at(self.offset, buf)?;
+        self.offset += len as u64;
+        Ok(len)
+    }
+
+    /// Reads from the current position to the end of the file.
+    pub fn read_to_end(&mut self, data: &mut Vec<u8>) -> Result<usize, FsError> {
+        let start = data.len();
+        let mut chunk = [0; 512];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(data.len() - start),
+                len => data.extend_from_slice(&chunk[..len]),
+            }
+        }
+    }
+
+    /// Writes at the current position, or at the end in append mode, and advances it.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.writable {
+            return Err(FsError::PermissionDenied);
+        }
+        if self.append {
+            self.offset = self.metadata().size;
+        }
+        let len = self.dentry.inode.write_at(self.offset, buf)?;
+        self.offset += len as u64;
+        Ok(len)
+    }
+
+    /// Moves the position and returns it; it may lie beyond the end of the file.
+    pub fn seek(&mut self, position: SeekFrom) -> Result<u64, FsError> {
+        let (base, delta) = match position {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::End(delta) => (self.metadata().size, delta),
+            SeekFrom::Current(delta) => (self.offset, delta),
+        };
+        let offset = if delta < 0 {
+            base.checked_sub(delta.unsigned_abs())
+        } else {
+            base.checked_add(delta as u64)
+        };
+        self.offset = offset.ok_or(FsError::InvalidSeek)?;
+        Ok(self.offset)
+    }
+}
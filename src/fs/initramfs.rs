@@ -0,0 +1,437 @@
+//! The initial RAM file system: a tmpfs mounted at `/` and filled from an archive at boot.
+//!
+//! The bootloader doesn't load additional files, so the archive is embedded in the kernel
+//! image. The build script packs the `initramfs` directory into a newc cpio archive, or
+//! embeds the archive at the path in the `BLOG_OS_INITRAMFS` environment variable, which
+//! may also be a ustar archive. `unpack` accepts both formats, so archives obtained
+//! otherwise can be unpacked the same way.
+
+use super::{self as fs, tmpfs::TmpFs, FsError};
+use crate::{allocator, info, memory, warn};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use bootloader::bootinfo::MemoryRegionType;
+use core::{fmt, str};
+
+/// The archive embedded by the build script.
+static ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs"));
+
+const CPIO_MAGIC: &[u8; 6] = b"070701";
+/// The magic of newc archives with checksums, which aren't verified.
+const CPIO_CRC_MAGIC: &[u8; 6] = b"070702";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+const TAR_BLOCK_SIZE: usize = 512;
+const TAR_MAGIC: &[u8; 5] = b"ustar";
+
+const MODE_TYPE_MASK: u32 = 0o170_000;
+const MODE_DIRECTORY: u32 = 0o040_000;
+const MODE_FILE: u32 = 0o100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// The data is neither a newc cpio nor a ustar archive.
+    UnknownFormat,
+    /// The archive ends within an entry.
+    Truncated,
+    /// A header is malformed or its checksum is wrong.
+    InvalidHeader,
+    /// An entry's path leaves the target directory.
+    InvalidPath,
+    Fs(FsError),
+}
+
+impl From<FsError> for ArchiveError {
+    fn from(err: FsError) -> Self {
+        ArchiveError::Fs(err)
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::UnknownFormat => write!(f, "unknown archive format"),
+            ArchiveError::Truncated => write!(f, "archive is truncated"),
+            ArchiveError::InvalidHeader => write!(f, "invalid archive header"),
+            ArchiveError::InvalidPath => write!(f, "entry path leaves the target directory"),
+            ArchiveError::Fs(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// Links, devices and other special files, which are skipped when unpacking.
+    Other,
+}
+
+/// An entry of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// The ustar name prefix, which is prepended to `name`; empty for cpio.
+    pub prefix: &'a str,
+    pub name: &'a str,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the components of the entry's path, without empty and `.` components.
+    pub fn components(&self) -> impl Iterator<Item = &'a str> {
+        self.prefix
+            .split('/')
+            .chain(self.name.split('/'))
+            .filter(|&component| !component.is_empty() && component != ".")
+    }
+}
+
+fn kind_from_mode(mode: u32) -> EntryKind {
+    match mode & MODE_TYPE_MASK {
+        MODE_DIRECTORY => EntryKind::Directory,
+        MODE_FILE => EntryKind::File,
+        _ => EntryKind::Other,
+    }
+}
+
+/// Parses a field of 8 hexadecimal digits, as used by cpio.
+fn parse_hex(field: &[u8]) -> Option<u32> {
+    let field = str::from_utf8(field).ok()?;
+    u32::from_str_radix(field, 16).ok()
+}
+
+/// Parses an octal number terminated by a space or NUL, as used by tar; an empty field is 0.
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ');
+    digits.fold(Some(0), |value: Option<u64>, &byte| match byte {
+        b'0'..=b'7' => value?.checked_mul(8)?.checked_add((byte - b'0') as u64),
+        _ => None,
+    })
+}
+
+/// Returns the string up to the first NUL.
+fn parse_name(field: &[u8]) -> Option<&str> {
+    let len = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    str::from_utf8(&field[..len]).ok()
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+/// The entries of a newc cpio archive.
+pub struct CpioEntries<'a> {
+    archive: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> CpioEntries<'a> {
+    fn parse(&mut self) -> Result<Option<Entry<'a>>, ArchiveError> {
+        let header = self
+            .archive
+            .get(self.offset..self.offset + CPIO_HEADER_SIZE)
+            .ok_or(ArchiveError::Truncated)?;
+        if &header[..6] != CPIO_MAGIC && &header[..6] != CPIO_CRC_MAGIC {
+            return Err(ArchiveError::InvalidHeader);
+        }
+        let field = |index: usize| {
+            parse_hex(&header[6 + 8 * index..][..8]).ok_or(ArchiveError::InvalidHeader)
+        };
+        let mode = field(1)?;
+        let size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_start = self.offset + CPIO_HEADER_SIZE;
+        let name = self
+            .archive
+            .get(name_start..name_start + name_size)
+            .ok_or(ArchiveError::Truncated)?;
+        let name = parse_name(name).ok_or(ArchiveError::InvalidHeader)?;
+        let data_start = align(name_start + name_size, 4);
+        let data = self
+            .archive
+            .get(data_start..data_start + size)
+            .ok_or(ArchiveError::Truncated)?;
+        self.offset = align(data_start + size, 4);
+
+        if name == CPIO_TRAILER {
+            return Ok(None);
+        }
+        Ok(Some(Entry {
+            prefix: "",
+            name,
+            kind: kind_from_mode(mode),
+            mode: mode & !MODE_TYPE_MASK,
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for CpioEntries<'a> {
+    type Item = Result<Entry<'a>, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.parse();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result.transpose()
+    }
+}
+
+/// The entries of a ustar archive.
+pub struct TarEntries<'a> {
+    archive: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+/// Returns whether the checksum of a tar header is right: the sum of its bytes, with the
+/// checksum field taken as spaces.
+fn tar_checksum_ok(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| match index {
+            148..=155 => b' ' as u64,
+            _ => byte as u64,
+        })
+        .sum();
+    parse_octal(&header[148..156]) == Some(sum)
+}
+
+impl<'a> TarEntries<'a> {
+    fn parse(&mut self) -> Result<Option<Entry<'a>>, ArchiveError> {
+        if self.offset >= self.archive.len() {
+            return Ok(None);
+        }
+        let header = self
+            .archive
+            .get(self.offset..self.offset + TAR_BLOCK_SIZE)
+            .ok_or(ArchiveError::Truncated)?;
+        // the archive ends with zeroed blocks
+        if header.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        if &header[257..262] != TAR_MAGIC || !tar_checksum_ok(header) {
+            return Err(ArchiveError::InvalidHeader);
+        }
+        let name = parse_name(&header[..100]).ok_or(ArchiveError::InvalidHeader)?;
+        let prefix = parse_name(&header[345..500]).ok_or(ArchiveError::InvalidHeader)?;
+        let mode = parse_octal(&header[100..108]).ok_or(ArchiveError::InvalidHeader)? as u32;
+        let size = parse_octal(&header[124..136]).ok_or(ArchiveError::InvalidHeader)? as usize;
+        let kind = match header[156] {
+            b'0' | 0 => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            _ => EntryKind::Other,
+        };
+
+        let data_start = self.offset + TAR_BLOCK_SIZE;
+        let data = self
+            .archive
+            .get(data_start..data_start + size)
+            .ok_or(ArchiveError::Truncated)?;
+        self.offset = data_start + align(size, TAR_BLOCK_SIZE);
+        Ok(Some(Entry {
+            prefix,
+            name,
+            kind,
+            mode: mode & !MODE_TYPE_MASK,
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for TarEntries<'a> {
+    type Item = Result<Entry<'a>, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.parse();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result.transpose()
+    }
+}
+
+/// The entries of an archive of either format.
+pub enum Entries<'a> {
+    Cpio(CpioEntries<'a>),
+    Tar(TarEntries<'a>),
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Entries::Cpio(entries) => entries.next(),
+            Entries::Tar(entries) => entries.next(),
+        }
+    }
+}
+
+/// Detects the format of an archive and returns its entries.
+pub fn entries(archive: &[u8]) -> Result<Entries<'_>, ArchiveError> {
+    if archive.starts_with(CPIO_MAGIC) || archive.starts_with(CPIO_CRC_MAGIC) {
+        Ok(Entries::Cpio(CpioEntries {
+            archive,
+            offset: 0,
+            done: false,
+        }))
+    } else if archive.len() >= TAR_BLOCK_SIZE && &archive[257..262] == TAR_MAGIC {
+        Ok(Entries::Tar(TarEntries {
+            archive,
+            offset: 0,
+            done: false,
+        }))
+    } else {
+        Err(ArchiveError::UnknownFormat)
+    }
+}
+
+/// Creates a directory, unless it exists.
+fn create_dir(path: &str) -> Result<(), FsError> {
+    match fs::create_dir(path) {
+        Err(FsError::AlreadyExists) if fs::metadata(path)?.is_dir() => Ok(()),
+        result => result,
+    }
+}
+
+/// Unpacks the files and directories of an archive into the directory `target`, creating
+/// missing parent directories and replacing existing files.
+///
+/// Returns the number of entries unpacked. Special files are skipped.
+pub fn unpack(archive: &[u8], target: &str) -> Result<usize, ArchiveError> {
+    let target = fs::absolute(target)?;
+    let mut unpacked = 0;
+    for entry in entries(archive)? {
+        let entry = entry?;
+        if entry.kind == EntryKind::Other {
+            continue;
+        }
+        let components: Vec<&str> = entry.components().collect();
+        if components.contains(&"..") {
+            return Err(ArchiveError::InvalidPath);
+        }
+        let (last, parents) = match components.split_last() {
+            Some(split) => split,
+            // the target directory itself
+            None => continue,
+        };
+
+        let mut path = String::from(target.trim_end_matches('/'));
+        for parent in parents {
+            path.push('/');
+            path.push_str(parent);
+            create_dir(&path)?;
+        }
+        path.push('/');
+        path.push_str(last);
+        match entry.kind {
+            EntryKind::Directory => create_dir(&path)?,
+            _ => fs::write(&path, entry.data)?,
+        }
+        unpacked += 1;
+    }
+    Ok(unpacked)
+}
+
+/// The capacity of the root tmpfs: half of the memory its files can take from the heap,
+/// like the default size of a Linux tmpfs, so that writing files can't exhaust the heap.
+fn root_capacity() -> u64 {
+    let usable: u64 = memory::memory_regions()
+        .filter(|region| region.region_type == MemoryRegionType::Usable)
+        .map(|region| region.range.end_addr() - region.range.start_addr())
+        .sum();
+    usable.min(allocator::HEAP_MAX_SIZE as u64) / 2
+}
+
+/// Mounts a tmpfs at `/` and unpacks the embedded archive into it.
+///
+/// Returns the number of entries unpacked. Requires `memory::init_kernel_memory` to have
+/// been called and the heap to be initialized.
+pub fn init() -> usize {
+    let root = TmpFs::with_capacity(root_capacity());
+    if let Err(err) = fs::mount("/", Arc::new(root)) {
+        warn!("initramfs: can't mount the root: {}", err);
+        return 0;
+    }
+    match unpack(ARCHIVE, "/") {
+        Ok(unpacked) => {
+            info!("initramfs: {} entries, {} bytes", unpacked, ARCHIVE.len());
+            unpacked
+        }
+        Err(err) => {
+            warn!("initramfs: {}", err);
+            0
+        }
+    }
+}
+
+#[test_case]
+fn test_field_parsing() {
+    assert_eq!(parse_hex(b"000081a4"), Some(0o100_644));
+    assert_eq!(parse_hex(b"0000008g"), None);
+    assert_eq!(parse_octal(b"00000001750\0"), Some(1000));
+    assert_eq!(parse_octal(b"   644 \0"), Some(0o644));
+    assert_eq!(parse_octal(b"\0\0\0\0"), Some(0));
+    assert_eq!(parse_octal(b"0009"), None);
+}
+
+#[test_case]
+fn test_cpio_entries() {
+    const ARCHIVE: &str = concat!(
+        // magic, inode, mode, uid, gid, nlink, mtime and file size
+        "07070100000001000081a40000000000000000000000010000000000000003",
+        // device numbers, name size and checksum
+        "000000000000000000000000000000000000000600000000",
+        "./a/b\0",
+        "hi\n\0",
+        "07070100000000000000000000000000000000000000010000000000000000",
+        "000000000000000000000000000000000000000b00000000",
+        "TRAILER!!!\0\0\0\0",
+    );
+    let mut entries = entries(ARCHIVE.as_bytes()).unwrap();
+    let entry = entries.next().unwrap().unwrap();
+    assert_eq!(entry.kind, EntryKind::File);
+    assert_eq!(entry.mode, 0o644);
+    assert_eq!(entry.data, b"hi\n");
+    let mut components = entry.components();
+    assert_eq!(components.next(), Some("a"));
+    assert_eq!(components.next(), Some("b"));
+    assert_eq!(components.next(), None);
+    assert!(entries.next().is_none());
+
+    let truncated = &ARCHIVE.as_bytes()[..118];
+    let mut entries = self::entries(truncated).unwrap();
+    assert_eq!(entries.next(), Some(Err(ArchiveError::Truncated)));
+    assert!(entries.next().is_none());
+}
+
+#[test_case]
+fn test_tar_checksum() {
+    let mut header = [0; TAR_BLOCK_SIZE];
+    header[..4].copy_from_slice(b"file");
+    header[257..263].copy_from_slice(b"ustar\0");
+    // 'f' + 'i' + 'l' + 'e' + "ustar" + 8 spaces = 1231 = 0o2317
+    header[148..156].copy_from_slice(b"002317\0 ");
+    assert!(tar_checksum_ok(&header));
+    header[0] = b'g';
+    assert!(!tar_checksum_ok(&header));
+}
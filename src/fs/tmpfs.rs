@@ -0,0 +1,253 @@
+//! tmpfs: a file system that keeps its files in heap memory.
+//!
+//! Its contents are lost when it is unmounted. An optional capacity limits the total size
+//! of the files.
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// The state shared by the nodes of a file system.
+struct Shared {
+    next_inode: AtomicU64,
+    /// The total size of the files in bytes, and the limit.
+    used: AtomicU64,
+    capacity: u64,
+}
+
+impl Shared {
+    /// Accounts for a file growing by `grow` bytes and shrinking by `shrink` bytes.
+    fn resize(&self, grow: u64, shrink: u64) -> Result<(), FsError> {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let new = used.saturating_add(grow).saturating_sub(shrink);
+            if grow > shrink && new > self.capacity {
+                return Err(FsError::NoSpace);
+            }
+            match self
+                .used
+                .compare_exchange(used, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return Ok(()),
+                Err(current) => used = current,
+            }
+        }
+    }
+}
+
+enum Contents {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<Node>>),
+}
+
+struct Node {
+    inode: u64,
+    shared: Arc<Shared>,
+    contents: Mutex<Contents>,
+}
+
+impl Node {
+    fn new(shared: Arc<Shared>, file_type: FileType) -> Arc<Self> {
+        let contents = match file_type {
+            FileType::File => Contents::File(Vec::new()),
+            FileType::Directory => Contents::Directory(BTreeMap::new()),
+        };
+        Arc::new(Node {
+            inode: shared.next_inode.fetch_add(1, Ordering::Relaxed),
+            shared,
+            contents: Mutex::new(contents),
+        })
+    }
+
+    /// Resizes a file's data, keeping the usage accounting in step.
+    ///
+    /// Fails with `NoSpace` if the heap can't hold the data, rather than aborting.
+    fn resize(&self, data: &mut Vec<u8>, len: usize) -> Result<(), FsError> {
+        let old = data.len() as u64;
+        let new = len as u64;
+        self.shared
+            .resize(new.saturating_sub(old), old.saturating_sub(new))?;
+        if data.try_reserve(len.saturating_sub(data.len())).is_err() {
+            // give back the space accounted for above
+            let _ = self.shared.resize(0, new - old);
+            return Err(FsError::NoSpace);
+        }
+        data.resize(len, 0);
+        Ok(())
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Contents::File(data) = &*self.contents.lock() {
+            let _ = self.shared.resize(0, data.len() as u64);
+        }
+    }
+}
+
+impl Inode for Node {
+    fn metadata(&self) -> Metadata {
+        let (file_type, size) = match &*self.contents.lock() {
+            Contents::File(data) => (FileType::File, data.len() as u64),
+            Contents::Directory(_) => (FileType::Directory, 0),
+        };
+        Metadata {
+            inode: self.inode,
+            file_type,
+            size,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match &*self.contents.lock() {
+            Contents::Directory(entries) => entries
+                .get(name)
+                .map(|node| node.clone() as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound),
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+        match &mut *self.contents.lock() {
+            Contents::Directory(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::AlreadyExists);
+                }
+                let node = Node::new(self.shared.clone(), file_type);
+                entries.insert(name.to_string(), node.clone());
+                Ok(node as Arc<dyn Inode>)
+            }
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        match &mut *self.contents.lock() {
+            Contents::Directory(entries) => {
+                let node = entries.get(name).ok_or(FsError::NotFound)?;
+                if let Contents::Directory(children) = &*node.contents.lock() {
+                    if !children.is_empty() {
+                        return Err(FsError::DirectoryNotEmpty);
+                    }
+                }
+                // open handles keep the node, and its data, until they are dropped
+                entries.remove(name);
+                Ok(())
+            }
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        match &*self.contents.lock() {
+            Contents::Directory(entries) => Ok(entries
+                .iter()
+                .map(|(name, node)| DirEntry {
+                    name: name.clone(),
+                    inode: node.inode,
+                    file_type: match &*node.contents.lock() {
+                        Contents::File(_) => FileType::File,
+                        Contents::Directory(_) => FileType::Directory,
+                    },
+                })
+                .collect()),
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match &*self.contents.lock() {
+            Contents::File(data) => {
+                let start = offset.min(data.len() as u64) as usize;
+                let len = buf.len().min(data.len() - start);
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(len)
+            }
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        match &mut *self.contents.lock() {
+            Contents::File(data) => {
+                let end = offset
+                    .checked_add(buf.len() as u64)
+                    .filter(|&end| end <= usize::MAX as u64)
+                    .ok_or(FsError::NoSpace)? as usize;
+                if data.len() < end {
+                    self.resize(data, end)?;
+                }
+                data[offset as usize..end].copy_from_slice(buf);
+                Ok(buf.len())
+            }
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        match &mut *self.contents.lock() {
+            Contents::File(data) => {
+                if size > usize::MAX as u64 {
+                    return Err(FsError::NoSpace);
+                }
+                self.resize(data, size as usize)
+            }
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+}
+
+/// A tmpfs instance.
+pub struct TmpFs {
+    root: Arc<Node>,
+}
+
+impl TmpFs {
+    /// Creates an empty file system without a size limit.
+    pub fn new() -> Self {
+        Self::with_capacity(u64::MAX)
+    }
+
+    /// Creates an empty file system whose files may take up to `capacity` bytes.
+    pub fn with_capacity(capacity: u64) -> Self {
+        let shared = Arc::new(Shared {
+            next_inode: AtomicU64::new(1),
+            used: AtomicU64::new(0),
+            capacity,
+        });
+        TmpFs {
+            root: Node::new(shared, FileType::Directory),
+        }
+    }
+
+    /// Returns the total size of the files in bytes.
+    pub fn used(&self) -> u64 {
+        self.root.shared.used.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
@@ -1,30 +1,38 @@
+use crate::memory::{stack, vma::AreaError};
+use core::ptr::{addr_of, addr_of_mut};
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
-}
+/// The IST entries in use, with the names of their stacks.
+const IST_STACKS: [(u16, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault stack"),
+    (NMI_IST_INDEX, "NMI stack"),
+    (MACHINE_CHECK_IST_INDEX, "machine check stack"),
+];
+
+/// The size of an IST stack allocated by `init_ist_stacks`, excluding its guard page.
+const IST_STACK_PAGES: u64 = 5;
+
+/// The size of the stacks used for the IST entries until `init_ist_stacks` is called.
+const BOOT_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_STACKS: [[u8; BOOT_STACK_SIZE]; IST_STACKS.len()] =
+    [[0; BOOT_STACK_SIZE]; IST_STACKS.len()];
+
+/// The task state segment. It is only modified with interrupts disabled, to replace the
+/// IST stacks.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (
             gdt,
             Selectors {
@@ -44,9 +52,36 @@ pub fn init() {
     use x86_64::instructions::segmentation::{Segment, CS};
     use x86_64::instructions::tables::load_tss;
 
+    for (boot_stack, &(index, _)) in IST_STACKS.iter().enumerate() {
+        let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(BOOT_STACKS[boot_stack]) });
+        let stack_end = stack_start + BOOT_STACK_SIZE;
+        unsafe { set_ist_stack(index, stack_end) };
+    }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Replaces the boot IST stacks with guard-paged stacks from the kernel stack allocator.
+///
+/// Requires `memory::init_kernel_memory` to have been called.
+pub fn init_ist_stacks() -> Result<(), AreaError> {
+    use x86_64::instructions::interrupts;
+
+    for &(index, name) in IST_STACKS.iter() {
+        let stack = stack::allocate(name, IST_STACK_PAGES)?;
+        interrupts::without_interrupts(|| unsafe { set_ist_stack(index, stack.top()) });
+    }
+    Ok(())
+}
+
+/// Sets the stack pointer the CPU loads for the given IST entry.
+///
+/// Unsafe because the stack must be valid and unused, and no interrupt may currently be
+/// running on the replaced stack.
+unsafe fn set_ist_stack(index: u16, stack_end: VirtAddr) {
+    (*addr_of_mut!(TSS)).interrupt_stack_table[index as usize] = stack_end;
+}
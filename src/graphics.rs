@@ -0,0 +1,243 @@
+//! Drawing into a linear framebuffer with 32 bits per pixel.
+
+use crate::{
+    config,
+    drivers::bga::{self, BgaError},
+    fs, vga_buffer, warn,
+};
+use alloc::vec::Vec;
+use core::{fmt, ops::Range, ptr};
+use font::Font;
+
+pub mod console;
+pub mod font;
+
+/// Switches the Bochs graphics adapter to the given resolution and moves the console
+/// output from the VGA text buffer to a framebuffer console.
+///
+/// The console uses the PSF font file given by the `font` kernel parameter, or the VGA
+/// font if there is none or it can't be loaded. Requires the heap,
+/// `memory::init_kernel_memory` and the root file system to be initialized.
+pub fn init(width: u16, height: u16) -> Result<(), BgaError> {
+    // the VGA font has to be read while the card is still in text mode
+    let font = config::get("font")
+        .and_then(load_font)
+        .unwrap_or_else(Font::from_vga);
+    let framebuffer = bga::set_mode(width, height)?;
+    vga_buffer::disable_text_mode();
+    console::init(framebuffer, font);
+    Ok(())
+}
+
+/// Reads a PSF font from a file, logging why if that fails.
+fn load_font(path: &str) -> Option<Font> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("failed to read font '{}': {}", path, err);
+            return None;
+        }
+    };
+    match Font::parse(&data) {
+        Ok(font) => Some(font),
+        Err(err) => {
+            warn!("failed to load font '{}': {}", path, err);
+            None
+        }
+    }
+}
+
+/// A color given by its red, green and blue components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    fn to_pixel(self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    fn from_pixel(pixel: u32) -> Self {
+        Rgb::new((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+    }
+}
+
+/// A linear framebuffer with 32 bits per pixel in `0x00RRGGBB` format.
+///
+/// All drawing operations are clipped to the framebuffer. Reading video memory is slow, so
+/// a copy of the pixels is kept in RAM and the framebuffer memory is only written to.
+pub struct Framebuffer {
+    base: *mut u32,
+    width: usize,
+    height: usize,
+    /// The number of pixels from the start of one row to the start of the next.
+    stride: usize,
+    /// The pixels shown on the screen, `width` per row.
+    shadow: Vec<u32>,
+}
+
+// the framebuffer is only accessed through `&mut self`
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// Creates a framebuffer over `stride * height` pixels starting at `base`.
+    ///
+    /// The current pixels are read once to initialize the copy in RAM, which requires the
+    /// heap.
+    ///
+    /// Unsafe because the caller must guarantee that the memory is valid for the lifetime
+    /// of the framebuffer and not accessed otherwise.
+    pub unsafe fn new(base: *mut u32, width: usize, height: usize, stride: usize) -> Self {
+        assert!(width <= stride);
+        let mut shadow = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = base.add(y * stride);
+            shadow.extend((0..width).map(|x| row.add(x).read_volatile()));
+        }
+        Framebuffer {
+            base,
+            width,
+            height,
+            stride,
+            shadow,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn row(&self, y: usize) -> *mut u32 {
+        unsafe { self.base.add(y * self.stride) }
+    }
+
+    /// Copies the given rows from the copy in RAM to the framebuffer memory.
+    fn flush_rows(&mut self, rows: Range<usize>) {
+        for y in rows {
+            let from = self.shadow[y * self.width..].as_ptr();
+            unsafe { ptr::copy_nonoverlapping(from, self.row(y), self.width) };
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.width && y < self.height {
+            let pixel = color.to_pixel();
+            self.shadow[y * self.width + x] = pixel;
+            unsafe { self.row(y).add(x).write_volatile(pixel) };
+        }
+    }
+
+    /// Returns the color of the given pixel, or `None` if it is outside the framebuffer.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x < self.width && y < self.height {
+            Some(Rgb::from_pixel(self.shadow[y * self.width + x]))
+        } else {
+            None
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        let pixel = color.to_pixel();
+        for y in y..y_end {
+            let row = self.row(y);
+            for x in x..x_end {
+                self.shadow[y * self.width + x] = pixel;
+                unsafe { row.add(x).write_volatile(pixel) };
+            }
+        }
+    }
+
+    /// Draws the outline of a rectangle.
+    pub fn draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height - 1, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x + width - 1, y, 1, height, color);
+    }
+
+    /// Draws a line from `(x0, y0)` to `(x1, y1)`, both inclusive.
+    pub fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: Rgb) {
+        // Bresenham's algorithm for all octants
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            if x >= 0 && y >= 0 {
+                self.set_pixel(x as usize, y as usize, color);
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            let double_error = 2 * error;
+            if double_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if double_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Copies a `width` × `height` image, given row by row, to `(x, y)`.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, height: usize, pixels: &[Rgb]) {
+        assert!(
+            pixels.len() >= width * height,
+            "image smaller than its size"
+        );
+        for image_y in 0..height {
+            for image_x in 0..width {
+                let color = pixels[image_y * width + image_x];
+                self.set_pixel(x.saturating_add(image_x), y.saturating_add(image_y), color);
+            }
+        }
+    }
+
+    /// Moves the contents up by `lines` rows of pixels and fills the freed rows with `fill`.
+    pub fn scroll_up(&mut self, lines: usize, fill: Rgb) {
+        let lines = lines.min(self.height);
+        let (width, height) = (self.width, self.height);
+        self.shadow.copy_within(lines * width.., 0);
+        self.shadow[(height - lines) * width..].fill(fill.to_pixel());
+        self.flush_rows(0..height);
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        let (width, height) = (self.width, self.height);
+        self.fill_rect(0, 0, width, height, color);
+    }
+}
+
+impl fmt::Debug for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Framebuffer")
+            .field("base", &self.base)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("stride", &self.stride)
+            .finish()
+    }
+}
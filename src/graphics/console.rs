@@ -0,0 +1,334 @@
+//! A text console drawn into a framebuffer with a bitmap font.
+
+use super::{font::Font, Framebuffer, Rgb};
+use crate::vga_buffer::{
+    ansi::{Action, Attributes, Csi, Parser},
+    Color,
+};
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+/// The framebuffer console, if graphics mode is enabled.
+static CONSOLE: Mutex<Option<FramebufferConsole>> = Mutex::new(None);
+
+/// The colors of the standard VGA palette, indexed by `Color`.
+const PALETTE: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+/// Returns the RGB value of a VGA color.
+pub fn rgb(color: Color) -> Rgb {
+    PALETTE[color as usize]
+}
+
+/// A text console that interprets the same escape sequences as the VGA consoles.
+///
+/// The number of rows and columns follows from the framebuffer and font size. The cursor
+/// is drawn as an underline.
+pub struct FramebufferConsole {
+    framebuffer: Framebuffer,
+    font: Font,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    attributes: Attributes,
+    saved_position: (usize, usize),
+    parser: Parser,
+}
+
+impl FramebufferConsole {
+    /// Creates a console covering the framebuffer and clears it.
+    pub fn new(framebuffer: Framebuffer, font: Font) -> Self {
+        let columns = (framebuffer.width() / font.width()).max(1);
+        let rows = (framebuffer.height() / font.height()).max(1);
+        let mut console = FramebufferConsole {
+            framebuffer,
+            font,
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            attributes: Attributes::DEFAULT,
+            saved_position: (0, 0),
+            parser: Parser::new(),
+        };
+        console.clear();
+        console
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the `(row, column)` the next character is written to.
+    pub fn position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    /// Clears the screen and moves the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        let (_, background) = self.colors();
+        self.framebuffer.clear(background);
+        self.row = 0;
+        self.column = 0;
+    }
+
+    fn colors(&self) -> (Rgb, Rgb) {
+        let (foreground, background) = self.attributes.colors();
+        (rgb(foreground), rgb(background))
+    }
+
+    fn draw_char(&mut self, row: usize, column: usize, c: char) {
+        let (foreground, background) = self.colors();
+        let glyph = self
+            .font
+            .glyph_index(c)
+            .or_else(|| self.font.glyph_index('■'))
+            .or_else(|| self.font.glyph_index('?'));
+        let (width, height) = (self.font.width(), self.font.height());
+        let (x, y) = (column * width, row * height);
+        for glyph_y in 0..height {
+            for glyph_x in 0..width {
+                let set = glyph.map_or(false, |glyph| self.font.is_set(glyph, glyph_x, glyph_y));
+                let color = if set { foreground } else { background };
+                self.framebuffer.set_pixel(x + glyph_x, y + glyph_y, color);
+            }
+        }
+    }
+
+    /// Inverts the bottom rows of the cell under the cursor, showing or hiding the cursor.
+    fn toggle_cursor(&mut self) {
+        let (width, height) = (self.font.width(), self.font.height());
+        let column = self.column.min(self.columns - 1);
+        let (x, y) = (column * width, self.row * height);
+        for cursor_y in height.saturating_sub(2)..height {
+            for cursor_x in 0..width {
+                if let Some(color) = self.framebuffer.pixel(x + cursor_x, y + cursor_y) {
+                    let inverted = Rgb::new(!color.r, !color.g, !color.b);
+                    self.framebuffer
+                        .set_pixel(x + cursor_x, y + cursor_y, inverted);
+                }
+            }
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        if self.column >= self.columns {
+            self.new_line();
+        }
+        self.draw_char(self.row, self.column, c);
+        self.column += 1;
+    }
+
+    fn new_line(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            let (_, background) = self.colors();
+            self.framebuffer.scroll_up(self.font.height(), background);
+        }
+        self.column = 0;
+    }
+
+    fn set_position(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns - 1);
+    }
+
+    fn erase(&mut self, row: usize, columns: core::ops::Range<usize>) {
+        let (_, background) = self.colors();
+        let (width, height) = (self.font.width(), self.font.height());
+        let columns = columns.start.min(self.columns)..columns.end.min(self.columns);
+        self.framebuffer.fill_rect(
+            columns.start * width,
+            row * height,
+            columns.len() * width,
+            height,
+            background,
+        );
+    }
+
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::Print(c) => self.write_char(c),
+            Action::Control('\n') => self.new_line(),
+            Action::Control('\r') => self.column = 0,
+            Action::Control('\t') => {
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+                let next_stop = (self.column / 8 + 1) * 8;
+                while self.column < next_stop.min(self.columns) {
+                    self.write_char(' ');
+                }
+            }
+            Action::Control('\u{8}') => self.column = self.column.saturating_sub(1),
+            Action::Control(_) => {}
+            Action::Csi(csi) => self.perform_csi(&csi),
+            Action::SaveCursor => self.saved_position = self.position(),
+            Action::RestoreCursor => {
+                let (row, column) = self.saved_position;
+                self.set_position(row, column);
+            }
+            Action::Reset => {
+                self.attributes = Attributes::DEFAULT;
+                self.clear();
+            }
+        }
+    }
+
+    fn perform_csi(&mut self, csi: &Csi) {
+        if csi.private {
+            return;
+        }
+        let (row, column) = (self.row, self.column.min(self.columns - 1));
+        let n = csi.param(0, 1) as usize;
+        let mode = csi.params().first().copied().unwrap_or(0);
+        match csi.final_byte {
+            b'A' => self.set_position(row.saturating_sub(n), column),
+            b'B' => self.set_position(row + n, column),
+            b'C' => self.set_position(row, column + n),
+            b'D' => self.set_position(row, column.saturating_sub(n)),
+            b'E' => self.set_position(row + n, 0),
+            b'F' => self.set_position(row.saturating_sub(n), 0),
+            b'G' => self.set_position(row, n - 1),
+            b'd' => self.set_position(n - 1, column),
+            b'H' | b'f' => {
+                self.set_position(csi.param(0, 1) as usize - 1, csi.param(1, 1) as usize - 1)
+            }
+            b'J' => match mode {
+                0 => {
+                    self.erase(row, column..self.columns);
+                    for row in row + 1..self.rows {
+                        self.erase(row, 0..self.columns);
+                    }
+                }
+                1 => {
+                    for row in 0..row {
+                        self.erase(row, 0..self.columns);
+                    }
+                    self.erase(row, 0..column + 1);
+                }
+                2 | 3 => {
+                    for row in 0..self.rows {
+                        self.erase(row, 0..self.columns);
+                    }
+                }
+                _ => {}
+            },
+            b'K' => match mode {
+                0 => self.erase(row, column..self.columns),
+                1 => self.erase(row, 0..column + 1),
+                2 => self.erase(row, 0..self.columns),
+                _ => {}
+            },
+            b'm' => self.attributes.apply_sgr(csi.params()),
+            b's' => self.saved_position = (row, column),
+            b'u' => {
+                let (row, column) = self.saved_position;
+                self.set_position(row, column);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Write for FramebufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.toggle_cursor();
+        for c in s.chars() {
+            if let Some(action) = self.parser.advance(c) {
+                self.perform(action);
+            }
+        }
+        self.toggle_cursor();
+        Ok(())
+    }
+}
+
+/// Shows the console output on the given framebuffer instead of the VGA text buffer.
+pub fn init(framebuffer: Framebuffer, font: Font) {
+    use x86_64::instructions::interrupts;
+
+    let console = FramebufferConsole::new(framebuffer, font);
+    interrupts::without_interrupts(|| *CONSOLE.lock() = Some(console));
+}
+
+/// Returns whether the console output is shown on a framebuffer.
+pub fn is_enabled() -> bool {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| CONSOLE.lock().is_some())
+}
+
+/// Calls `f` with the framebuffer console, if graphics mode is enabled.
+pub fn with_console<R>(f: impl FnOnce(&mut FramebufferConsole) -> R) -> Option<R> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| CONSOLE.lock().as_mut().map(f))
+}
+
+/// Writes to the framebuffer console, if graphics mode is enabled.
+///
+/// Called with interrupts disabled by `print!`.
+pub(crate) fn write_fmt(args: fmt::Arguments) {
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        let _ = console.write_fmt(args);
+    }
+}
+
+/// Like `write_fmt`, but forcibly releases the lock if it is held.
+///
+/// Used by exception handlers, which might have interrupted the code holding the lock.
+pub(crate) fn force_write_fmt(args: fmt::Arguments) {
+    let mut console = match CONSOLE.try_lock() {
+        Some(console) => console,
+        None => {
+            unsafe { CONSOLE.force_unlock() };
+            CONSOLE.lock()
+        }
+    };
+    if let Some(console) = console.as_mut() {
+        let _ = console.write_fmt(args);
+    }
+}
+
+/// Like `write_fmt`, but drops the output if the lock is held.
+///
+/// Used by exception handlers that return to the interrupted code and by the log sink.
+/// Returns whether the output wasn't dropped.
+pub(crate) fn try_write_fmt(args: fmt::Arguments) -> bool {
+    match CONSOLE.try_lock() {
+        Some(mut console) => {
+            if let Some(console) = console.as_mut() {
+                let _ = console.write_fmt(args);
+            }
+            true
+        }
+        None => false,
+    }
+}
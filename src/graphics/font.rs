@@ -0,0 +1,260 @@
+//! Bitmap fonts in the PC Screen Font (PSF) format, as used by the Linux console.
+
+use crate::{memory, vga_buffer::cp437};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{fmt, str};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQUENCE: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQUENCE: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// The data doesn't start with a PSF1 or PSF2 magic number.
+    UnknownFormat,
+    /// The data ends before the glyphs or the unicode table.
+    Truncated,
+    /// The header describes glyphs without pixels.
+    InvalidHeader,
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::UnknownFormat => write!(f, "not a PSF font"),
+            FontError::Truncated => write!(f, "truncated font"),
+            FontError::InvalidHeader => write!(f, "invalid font header"),
+        }
+    }
+}
+
+/// A bitmap font with glyphs of equal size.
+///
+/// Each glyph is stored row by row, each row padded to whole bytes with the leftmost pixel
+/// in the most significant bit.
+#[derive(Debug, Clone)]
+pub struct Font {
+    width: usize,
+    height: usize,
+    glyph_count: usize,
+    bytes_per_row: usize,
+    glyphs: Vec<u8>,
+    /// Maps characters to glyphs. Without a table, glyphs are in code page 437 order.
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl Font {
+    /// Parses a PSF1 or PSF2 font.
+    pub fn parse(data: &[u8]) -> Result<Font, FontError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Font::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Font::parse_psf1(data)
+        } else {
+            Err(FontError::UnknownFormat)
+        }
+    }
+
+    fn parse_psf1(data: &[u8]) -> Result<Font, FontError> {
+        let mode = *data.get(2).ok_or(FontError::Truncated)?;
+        let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+        if height == 0 {
+            return Err(FontError::InvalidHeader);
+        }
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs_end = 4 + glyph_count * height;
+        let glyphs = data.get(4..glyphs_end).ok_or(FontError::Truncated)?;
+
+        let unicode = if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) != 0 {
+            let mut table = BTreeMap::new();
+            let mut entries = data[glyphs_end..]
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+            for glyph in 0..glyph_count {
+                let mut in_sequence = false;
+                loop {
+                    match entries.next().ok_or(FontError::Truncated)? {
+                        PSF1_SEPARATOR => break,
+                        PSF1_START_SEQUENCE => in_sequence = true,
+                        // sequences of combining characters can't be rendered
+                        _ if in_sequence => {}
+                        code_point => {
+                            if let Some(c) = char::from_u32(code_point as u32) {
+                                table.entry(c).or_insert(glyph);
+                            }
+                        }
+                    }
+                }
+            }
+            Some(table)
+        } else {
+            None
+        };
+
+        Ok(Font {
+            width: 8,
+            height,
+            glyph_count,
+            bytes_per_row: 1,
+            glyphs: glyphs.to_vec(),
+            unicode,
+        })
+    }
+
+    fn parse_psf2(data: &[u8]) -> Result<Font, FontError> {
+        let field = |index: usize| -> Result<usize, FontError> {
+            let bytes = data
+                .get(4 + 4 * index..8 + 4 * index)
+                .ok_or(FontError::Truncated)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        let header_size = field(1)?;
+        let flags = field(2)? as u32;
+        let glyph_count = field(3)?;
+        let bytes_per_glyph = field(4)?;
+        let height = field(5)?;
+        let width = field(6)?;
+        let bytes_per_row = (width + 7) / 8;
+        if width == 0 || height == 0 || bytes_per_glyph < bytes_per_row * height {
+            return Err(FontError::InvalidHeader);
+        }
+
+        let glyphs_end = header_size + glyph_count * bytes_per_glyph;
+        let raw_glyphs = data
+            .get(header_size..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+        // drop any padding after the rows of each glyph
+        let mut glyphs = Vec::with_capacity(glyph_count * bytes_per_row * height);
+        for glyph in raw_glyphs.chunks_exact(bytes_per_glyph) {
+            glyphs.extend_from_slice(&glyph[..bytes_per_row * height]);
+        }
+
+        let unicode = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            let mut table = BTreeMap::new();
+            let mut entries = data[glyphs_end..].split(|&byte| byte == PSF2_SEPARATOR);
+            for glyph in 0..glyph_count {
+                let entry = entries.next().ok_or(FontError::Truncated)?;
+                // single characters come before the first sequence
+                let singles = entry
+                    .split(|&byte| byte == PSF2_START_SEQUENCE)
+                    .next()
+                    .unwrap_or(&[]);
+                if let Ok(singles) = str::from_utf8(singles) {
+                    for c in singles.chars() {
+                        table.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+            Some(table)
+        } else {
+            None
+        };
+
+        Ok(Font {
+            width,
+            height,
+            glyph_count,
+            bytes_per_row,
+            glyphs,
+            unicode,
+        })
+    }
+
+    /// Reads the 8×16 font of the VGA card.
+    ///
+    /// Must be called while the card is still in text mode; the font is lost when
+    /// switching to a graphics mode. Requires `memory::init_kernel_memory` to have been
+    /// called.
+    pub fn from_vga() -> Font {
+        use x86_64::instructions::{interrupts, port::Port};
+
+        const SEQUENCER: u16 = 0x3c4;
+        const GRAPHICS_CONTROLLER: u16 = 0x3ce;
+        const CRT_CONTROLLER: u16 = 0x3d4;
+        const GLYPH_COUNT: usize = 256;
+        /// The font memory reserves 32 bytes per glyph.
+        const GLYPH_STRIDE: usize = 32;
+
+        fn read_register(index_port: u16, register: u8) -> u8 {
+            unsafe {
+                Port::<u8>::new(index_port).write(register);
+                Port::<u8>::new(index_port + 1).read()
+            }
+        }
+
+        fn write_register(index_port: u16, register: u8, value: u8) {
+            unsafe {
+                Port::<u8>::new(index_port).write(register);
+                Port::<u8>::new(index_port + 1).write(value);
+            }
+        }
+
+        // the maximum scan line register holds the character height
+        let height = (read_register(CRT_CONTROLLER, 0x09) & 0x1f) as usize + 1;
+        let window = (memory::physical_memory_offset() + 0xa_0000u64).as_ptr::<u8>();
+        let mut glyphs = Vec::with_capacity(GLYPH_COUNT * height);
+
+        interrupts::without_interrupts(|| {
+            // make plane 2, which holds the font, readable at 0xa0000
+            write_register(SEQUENCER, 0x02, 0x04);
+            write_register(SEQUENCER, 0x04, 0x06);
+            write_register(GRAPHICS_CONTROLLER, 0x04, 0x02);
+            write_register(GRAPHICS_CONTROLLER, 0x05, 0x00);
+            write_register(GRAPHICS_CONTROLLER, 0x06, 0x04);
+
+            for glyph in 0..GLYPH_COUNT {
+                for row in 0..height {
+                    let byte = unsafe { window.add(glyph * GLYPH_STRIDE + row).read_volatile() };
+                    glyphs.push(byte);
+                }
+            }
+
+            // restore the text mode settings
+            write_register(SEQUENCER, 0x02, 0x03);
+            write_register(SEQUENCER, 0x04, 0x02);
+            write_register(GRAPHICS_CONTROLLER, 0x04, 0x00);
+            write_register(GRAPHICS_CONTROLLER, 0x05, 0x10);
+            write_register(GRAPHICS_CONTROLLER, 0x06, 0x0e);
+        });
+
+        Font {
+            width: 8,
+            height,
+            glyph_count: GLYPH_COUNT,
+            bytes_per_row: 1,
+            glyphs,
+            unicode: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the glyph showing `c`, if the font has one.
+    pub fn glyph_index(&self, c: char) -> Option<usize> {
+        let index = match &self.unicode {
+            Some(table) => table.get(&c).copied(),
+            None => cp437::encode(c).map(usize::from),
+        };
+        index.filter(|&index| index < self.glyph_count)
+    }
+
+    /// Returns whether the pixel at `(x, y)` of the given glyph is set.
+    pub fn is_set(&self, glyph: usize, x: usize, y: usize) -> bool {
+        let row = (glyph * self.height + y) * self.bytes_per_row;
+        self.glyphs[row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
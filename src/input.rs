@@ -0,0 +1,303 @@
+//! Keyboard input events.
+//!
+//! The keyboard task decodes scancodes into [`KeyEvent`]s with a [`Decoder`] and publishes
+//! them to every subscriber. Each subscriber has its own queue, so a slow subscriber only
+//! loses its own events.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
+use pc_keyboard::{layouts, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
+use spin::Mutex;
+
+pub mod layout;
+
+pub use layout::Layout;
+
+/// The number of events a subscriber can fall behind before events are dropped.
+const QUEUE_SIZE: usize = 100;
+
+/// The modifier keys held down when a key event happened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    /// The right Alt key, in layouts that use it as AltGr.
+    pub alt_gr: bool,
+}
+
+/// The state of the lock keys, which is shown by the keyboard LEDs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Locks {
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+/// A key being pressed or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    /// `true` for a key press (also sent when a held key repeats), `false` for a release.
+    pub pressed: bool,
+    pub modifiers: Modifiers,
+    pub locks: Locks,
+    /// The character the key produces in the current layout; only set for key presses.
+    pub character: Option<char>,
+}
+
+impl KeyEvent {
+    /// Returns whether the key is a modifier or lock key, which only changes the state of
+    /// the keyboard.
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self.code,
+            KeyCode::ShiftLeft
+                | KeyCode::ShiftRight
+                | KeyCode::ControlLeft
+                | KeyCode::ControlRight
+                | KeyCode::AltLeft
+                | KeyCode::AltRight
+                | KeyCode::CapsLock
+                | KeyCode::NumpadLock
+                | KeyCode::ScrollLock
+        )
+    }
+}
+
+/// Turns scancodes into key events, keeping track of the modifier and lock keys.
+pub struct Decoder {
+    /// Only used to decode scancode sequences; the layout is applied by the decoder.
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    layout: Layout,
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    locks: Locks,
+    /// The lock keys currently held down, so that a repeating lock key toggles only once.
+    held_locks: Locks,
+}
+
+impl Decoder {
+    pub fn new(layout: Layout) -> Self {
+        Decoder {
+            keyboard: Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore),
+            layout,
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            left_alt: false,
+            right_alt: false,
+            locks: Locks::default(),
+            held_locks: Locks::default(),
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub fn locks(&self) -> Locks {
+        self.locks
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        let alt_gr = self.layout.has_alt_gr();
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            ctrl: self.left_ctrl || self.right_ctrl,
+            alt: self.left_alt || (self.right_alt && !alt_gr),
+            alt_gr: self.right_alt && alt_gr,
+        }
+    }
+
+    /// Adds a byte received from the keyboard, returning an event once a complete
+    /// scancode was received.
+    ///
+    /// Responses of the keyboard to commands, like the acknowledgement of an LED change,
+    /// are skipped.
+    pub fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        const ACK: u8 = 0xfa;
+        const RESEND: u8 = 0xfe;
+        if byte == ACK || byte == RESEND {
+            return None;
+        }
+
+        let event = self.keyboard.add_byte(byte).ok()??;
+        let pressed = event.state == KeyState::Down;
+        match event.code {
+            KeyCode::ShiftLeft => self.left_shift = pressed,
+            KeyCode::ShiftRight => self.right_shift = pressed,
+            KeyCode::ControlLeft => self.left_ctrl = pressed,
+            KeyCode::ControlRight => self.right_ctrl = pressed,
+            KeyCode::AltLeft => self.left_alt = pressed,
+            KeyCode::AltRight => self.right_alt = pressed,
+            KeyCode::CapsLock => toggle(
+                &mut self.locks.caps_lock,
+                &mut self.held_locks.caps_lock,
+                pressed,
+            ),
+            KeyCode::NumpadLock => toggle(
+                &mut self.locks.num_lock,
+                &mut self.held_locks.num_lock,
+                pressed,
+            ),
+            KeyCode::ScrollLock => toggle(
+                &mut self.locks.scroll_lock,
+                &mut self.held_locks.scroll_lock,
+                pressed,
+            ),
+            _ => {}
+        }
+
+        let modifiers = self.modifiers();
+        let character = if pressed {
+            self.layout.map(event.code, &modifiers, &self.locks)
+        } else {
+            None
+        };
+        Some(KeyEvent {
+            code: event.code,
+            pressed,
+            modifiers,
+            locks: self.locks,
+            character,
+        })
+    }
+}
+
+/// Toggles a lock when its key is pressed, but not again while the key repeats.
+fn toggle(lock: &mut bool, held: &mut bool, pressed: bool) {
+    if pressed && !*held {
+        *lock = !*lock;
+    }
+    *held = pressed;
+}
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+
+/// Returns the keyboard layout used to decode key presses.
+pub fn layout() -> Layout {
+    Layout::ALL[LAYOUT.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+struct Subscriber {
+    queue: ArrayQueue<KeyEvent>,
+    waker: AtomicWaker,
+}
+
+/// The subscribers; only used by tasks, never by interrupt handlers.
+static SUBSCRIBERS: Mutex<Vec<Arc<Subscriber>>> = Mutex::new(Vec::new());
+
+/// Returns a stream of all key events published from now on.
+pub fn subscribe() -> KeyEvents {
+    let subscriber = Arc::new(Subscriber {
+        queue: ArrayQueue::new(QUEUE_SIZE),
+        waker: AtomicWaker::new(),
+    });
+    SUBSCRIBERS.lock().push(subscriber.clone());
+    KeyEvents { subscriber }
+}
+
+/// Sends an event to all subscribers, dropping it for those whose queue is full.
+pub fn publish(event: KeyEvent) {
+    for subscriber in SUBSCRIBERS.lock().iter() {
+        if subscriber.queue.push(event).is_ok() {
+            subscriber.waker.wake();
+        }
+    }
+}
+
+/// The key events received by a subscriber. Dropping it unsubscribes.
+pub struct KeyEvents {
+    subscriber: Arc<Subscriber>,
+}
+
+impl KeyEvents {
+    /// Returns the next event if one is queued, without waiting.
+    pub fn try_next(&self) -> Option<KeyEvent> {
+        self.subscriber.queue.pop().ok()
+    }
+}
+
+impl Stream for KeyEvents {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        // fast path
+        if let Some(event) = self.try_next() {
+            return Poll::Ready(Some(event));
+        }
+
+        self.subscriber.waker.register(&cx.waker());
+        match self.try_next() {
+            Some(event) => {
+                self.subscriber.waker.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for KeyEvents {
+    fn drop(&mut self) {
+        SUBSCRIBERS
+            .lock()
+            .retain(|subscriber| !Arc::ptr_eq(subscriber, &self.subscriber));
+    }
+}
+
+#[test_case]
+fn test_decoder_tracks_modifiers_and_locks() {
+    let mut decoder = Decoder::new(Layout::Us);
+    let character = |decoder: &mut Decoder, scancode| decoder.add_byte(scancode)?.character;
+
+    assert_eq!(character(&mut decoder, 0x1e), Some('a'));
+    assert_eq!(character(&mut decoder, 0x9e), None);
+
+    // shift
+    decoder.add_byte(0x2a);
+    assert_eq!(character(&mut decoder, 0x1e), Some('A'));
+    decoder.add_byte(0xaa);
+    assert!(!decoder.modifiers().shift);
+
+    // caps lock toggles once, even when its key repeats
+    decoder.add_byte(0x3a);
+    decoder.add_byte(0x3a);
+    decoder.add_byte(0xba);
+    assert!(decoder.locks().caps_lock);
+    assert_eq!(character(&mut decoder, 0x1e), Some('A'));
+    decoder.add_byte(0x3a);
+    decoder.add_byte(0xba);
+    assert!(!decoder.locks().caps_lock);
+
+    // ctrl
+    decoder.add_byte(0x1d);
+    assert_eq!(character(&mut decoder, 0x2e), Some('\u{3}'));
+    decoder.add_byte(0x9d);
+
+    // AltGr
+    decoder.set_layout(Layout::De);
+    decoder.add_byte(0xe0);
+    let event = decoder.add_byte(0x38).expect("no event for AltGr");
+    assert!(event.is_modifier() && event.modifiers.alt_gr && !event.modifiers.alt);
+    assert_eq!(character(&mut decoder, 0x10), Some('@'));
+
+    // acknowledgements are skipped
+    assert!(decoder.add_byte(0xfa).is_none());
+}
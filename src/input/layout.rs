@@ -0,0 +1,391 @@
+//! Keyboard layouts, which map the keys of the keyboard to characters.
+
+use super::{Locks, Modifiers};
+use pc_keyboard::KeyCode;
+
+/// A keyboard layout that can be selected at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    /// US English (QWERTY)
+    Us,
+    /// UK English (QWERTY)
+    Uk,
+    /// German (QWERTZ)
+    De,
+    /// French (AZERTY)
+    Fr,
+    /// US Dvorak
+    Dvorak,
+}
+
+/// The characters a key produces without modifiers, with shift and with AltGr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    normal: char,
+    shifted: char,
+    alt_gr: Option<char>,
+}
+
+const fn key(normal: char, shifted: char) -> Key {
+    Key {
+        normal,
+        shifted,
+        alt_gr: None,
+    }
+}
+
+const fn key_alt_gr(normal: char, shifted: char, alt_gr: char) -> Key {
+    Key {
+        normal,
+        shifted,
+        alt_gr: Some(alt_gr),
+    }
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::De,
+        Layout::Fr,
+        Layout::Dvorak,
+    ];
+
+    /// The name used to select the layout, e.g. in the `layout` shell command.
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::De => "de",
+            Layout::Fr => "fr",
+            Layout::Dvorak => "dvorak",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|layout| layout.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the right Alt key is AltGr, which selects the third character of a key,
+    /// instead of a second Alt key.
+    pub fn has_alt_gr(self) -> bool {
+        matches!(self, Layout::De | Layout::Fr)
+    }
+
+    /// Returns the character produced by pressing `code`, if any.
+    ///
+    /// Caps lock only affects keys for letters. With Ctrl, letters and `@[\]^_` produce
+    /// the corresponding control characters (e.g. Ctrl+C produces `'\u{3}'`).
+    pub fn map(self, code: KeyCode, modifiers: &Modifiers, locks: &Locks) -> Option<char> {
+        let character = match control_key(code).or_else(|| numpad_key(code, locks.num_lock)) {
+            Some(character) => character,
+            None => {
+                let key = self.key(code)?;
+                if modifiers.alt_gr {
+                    key.alt_gr?
+                } else if modifiers.shift != (locks.caps_lock && key.normal.is_alphabetic()) {
+                    key.shifted
+                } else {
+                    key.normal
+                }
+            }
+        };
+        if modifiers.ctrl {
+            Some(control_character(character).unwrap_or(character))
+        } else {
+            Some(character)
+        }
+    }
+
+    fn key(self, code: KeyCode) -> Option<Key> {
+        match self {
+            Layout::Us => us_key(code),
+            Layout::Uk => uk_key(code).or_else(|| us_key(code)),
+            Layout::De => de_key(code).or_else(|| us_key(code)),
+            Layout::Fr => fr_key(code).or_else(|| us_key(code)),
+            Layout::Dvorak => dvorak_key(code).or_else(|| us_key(code)),
+        }
+    }
+}
+
+/// Returns the control character Ctrl produces together with `character`.
+fn control_character(character: char) -> Option<char> {
+    match character {
+        '@'..='_' => Some((character as u8 - b'@') as char),
+        'a'..='z' => Some((character as u8 - b'a' + 1) as char),
+        _ => None,
+    }
+}
+
+/// Keys that produce the same character in every layout, regardless of shift.
+fn control_key(code: KeyCode) -> Option<char> {
+    match code {
+        KeyCode::Spacebar => Some(' '),
+        KeyCode::Tab => Some('\t'),
+        KeyCode::Enter | KeyCode::NumpadEnter => Some('\n'),
+        KeyCode::Backspace => Some('\u{8}'),
+        KeyCode::Escape => Some('\u{1b}'),
+        KeyCode::Delete => Some('\u{7f}'),
+        _ => None,
+    }
+}
+
+/// Keys of the number pad; the digits are cursor keys without num lock.
+fn numpad_key(code: KeyCode, num_lock: bool) -> Option<char> {
+    let digit = match code {
+        KeyCode::NumpadSlash => return Some('/'),
+        KeyCode::NumpadStar => return Some('*'),
+        KeyCode::NumpadMinus => return Some('-'),
+        KeyCode::NumpadPlus => return Some('+'),
+        KeyCode::Numpad0 => '0',
+        KeyCode::Numpad1 => '1',
+        KeyCode::Numpad2 => '2',
+        KeyCode::Numpad3 => '3',
+        KeyCode::Numpad4 => '4',
+        KeyCode::Numpad5 => '5',
+        KeyCode::Numpad6 => '6',
+        KeyCode::Numpad7 => '7',
+        KeyCode::Numpad8 => '8',
+        KeyCode::Numpad9 => '9',
+        KeyCode::NumpadPeriod => '.',
+        _ => return None,
+    };
+    if num_lock {
+        Some(digit)
+    } else {
+        None
+    }
+}
+
+fn us_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::BackTick => key('`', '~'),
+        KeyCode::Key1 => key('1', '!'),
+        KeyCode::Key2 => key('2', '@'),
+        KeyCode::Key3 => key('3', '#'),
+        KeyCode::Key4 => key('4', '$'),
+        KeyCode::Key5 => key('5', '%'),
+        KeyCode::Key6 => key('6', '^'),
+        KeyCode::Key7 => key('7', '&'),
+        KeyCode::Key8 => key('8', '*'),
+        KeyCode::Key9 => key('9', '('),
+        KeyCode::Key0 => key('0', ')'),
+        KeyCode::Minus => key('-', '_'),
+        KeyCode::Equals => key('=', '+'),
+        KeyCode::Q => key('q', 'Q'),
+        KeyCode::W => key('w', 'W'),
+        KeyCode::E => key('e', 'E'),
+        KeyCode::R => key('r', 'R'),
+        KeyCode::T => key('t', 'T'),
+        KeyCode::Y => key('y', 'Y'),
+        KeyCode::U => key('u', 'U'),
+        KeyCode::I => key('i', 'I'),
+        KeyCode::O => key('o', 'O'),
+        KeyCode::P => key('p', 'P'),
+        KeyCode::BracketSquareLeft => key('[', '{'),
+        KeyCode::BracketSquareRight => key(']', '}'),
+        KeyCode::BackSlash => key('\\', '|'),
+        KeyCode::A => key('a', 'A'),
+        KeyCode::S => key('s', 'S'),
+        KeyCode::D => key('d', 'D'),
+        KeyCode::F => key('f', 'F'),
+        KeyCode::G => key('g', 'G'),
+        KeyCode::H => key('h', 'H'),
+        KeyCode::J => key('j', 'J'),
+        KeyCode::K => key('k', 'K'),
+        KeyCode::L => key('l', 'L'),
+        KeyCode::SemiColon => key(';', ':'),
+        KeyCode::Quote => key('\'', '"'),
+        KeyCode::Z => key('z', 'Z'),
+        KeyCode::X => key('x', 'X'),
+        KeyCode::C => key('c', 'C'),
+        KeyCode::V => key('v', 'V'),
+        KeyCode::B => key('b', 'B'),
+        KeyCode::N => key('n', 'N'),
+        KeyCode::M => key('m', 'M'),
+        KeyCode::Comma => key(',', '<'),
+        KeyCode::Fullstop => key('.', '>'),
+        KeyCode::Slash => key('/', '?'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The keys that differ from the US layout.
+///
+/// The key left of Enter is reported as `BackSlash` by scancode set 1.
+fn uk_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::BackTick => key('`', '¬'),
+        KeyCode::Key2 => key('2', '"'),
+        KeyCode::Key3 => key('3', '£'),
+        KeyCode::Quote => key('\'', '@'),
+        KeyCode::BackSlash => key('#', '~'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn de_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::BackTick => key('^', '°'),
+        KeyCode::Key2 => key_alt_gr('2', '"', '²'),
+        KeyCode::Key3 => key_alt_gr('3', '§', '³'),
+        KeyCode::Key6 => key('6', '&'),
+        KeyCode::Key7 => key_alt_gr('7', '/', '{'),
+        KeyCode::Key8 => key_alt_gr('8', '(', '['),
+        KeyCode::Key9 => key_alt_gr('9', ')', ']'),
+        KeyCode::Key0 => key_alt_gr('0', '=', '}'),
+        KeyCode::Minus => key_alt_gr('ß', '?', '\\'),
+        KeyCode::Equals => key('´', '`'),
+        KeyCode::Q => key_alt_gr('q', 'Q', '@'),
+        KeyCode::E => key_alt_gr('e', 'E', '€'),
+        KeyCode::Y => key('z', 'Z'),
+        KeyCode::BracketSquareLeft => key('ü', 'Ü'),
+        KeyCode::BracketSquareRight => key_alt_gr('+', '*', '~'),
+        KeyCode::BackSlash => key('#', '\''),
+        KeyCode::SemiColon => key('ö', 'Ö'),
+        KeyCode::Quote => key('ä', 'Ä'),
+        KeyCode::Z => key('y', 'Y'),
+        KeyCode::M => key_alt_gr('m', 'M', 'µ'),
+        KeyCode::Comma => key(',', ';'),
+        KeyCode::Fullstop => key('.', ':'),
+        KeyCode::Slash => key('-', '_'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn fr_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::BackTick => key('²', '²'),
+        KeyCode::Key1 => key('&', '1'),
+        KeyCode::Key2 => key_alt_gr('é', '2', '~'),
+        KeyCode::Key3 => key_alt_gr('"', '3', '#'),
+        KeyCode::Key4 => key_alt_gr('\'', '4', '{'),
+        KeyCode::Key5 => key_alt_gr('(', '5', '['),
+        KeyCode::Key6 => key_alt_gr('-', '6', '|'),
+        KeyCode::Key7 => key_alt_gr('è', '7', '`'),
+        KeyCode::Key8 => key_alt_gr('_', '8', '\\'),
+        KeyCode::Key9 => key_alt_gr('ç', '9', '^'),
+        KeyCode::Key0 => key_alt_gr('à', '0', '@'),
+        KeyCode::Minus => key_alt_gr(')', '°', ']'),
+        KeyCode::Equals => key_alt_gr('=', '+', '}'),
+        KeyCode::Q => key('a', 'A'),
+        KeyCode::W => key('z', 'Z'),
+        KeyCode::E => key_alt_gr('e', 'E', '€'),
+        KeyCode::BracketSquareLeft => key('^', '¨'),
+        KeyCode::BracketSquareRight => key_alt_gr('$', '£', '¤'),
+        KeyCode::BackSlash => key('*', 'µ'),
+        KeyCode::A => key('q', 'Q'),
+        KeyCode::SemiColon => key('m', 'M'),
+        KeyCode::Quote => key('ù', '%'),
+        KeyCode::Z => key('w', 'W'),
+        KeyCode::M => key(',', '?'),
+        KeyCode::Comma => key(';', '.'),
+        KeyCode::Fullstop => key(':', '/'),
+        KeyCode::Slash => key('!', '§'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn dvorak_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::Minus => key('[', '{'),
+        KeyCode::Equals => key(']', '}'),
+        KeyCode::Q => key('\'', '"'),
+        KeyCode::W => key(',', '<'),
+        KeyCode::E => key('.', '>'),
+        KeyCode::R => key('p', 'P'),
+        KeyCode::T => key('y', 'Y'),
+        KeyCode::Y => key('f', 'F'),
+        KeyCode::U => key('g', 'G'),
+        KeyCode::I => key('c', 'C'),
+        KeyCode::O => key('r', 'R'),
+        KeyCode::P => key('l', 'L'),
+        KeyCode::BracketSquareLeft => key('/', '?'),
+        KeyCode::BracketSquareRight => key('=', '+'),
+        KeyCode::S => key('o', 'O'),
+        KeyCode::D => key('e', 'E'),
+        KeyCode::F => key('u', 'U'),
+        KeyCode::G => key('i', 'I'),
+        KeyCode::H => key('d', 'D'),
+        KeyCode::J => key('h', 'H'),
+        KeyCode::K => key('t', 'T'),
+        KeyCode::L => key('n', 'N'),
+        KeyCode::SemiColon => key('s', 'S'),
+        KeyCode::Quote => key('-', '_'),
+        KeyCode::Z => key(';', ':'),
+        KeyCode::X => key('q', 'Q'),
+        KeyCode::C => key('j', 'J'),
+        KeyCode::V => key('k', 'K'),
+        KeyCode::B => key('x', 'X'),
+        KeyCode::N => key('b', 'B'),
+        KeyCode::Comma => key('w', 'W'),
+        KeyCode::Fullstop => key('v', 'V'),
+        KeyCode::Slash => key('z', 'Z'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[test_case]
+fn test_layouts_map_letters() {
+    let none = Modifiers::default();
+    let locks = Locks::default();
+    assert_eq!(Layout::Us.map(KeyCode::Y, &none, &locks), Some('y'));
+    assert_eq!(Layout::De.map(KeyCode::Y, &none, &locks), Some('z'));
+    assert_eq!(Layout::Fr.map(KeyCode::Q, &none, &locks), Some('a'));
+    assert_eq!(Layout::Fr.map(KeyCode::Key2, &none, &locks), Some('é'));
+    assert_eq!(Layout::Dvorak.map(KeyCode::S, &none, &locks), Some('o'));
+    assert_eq!(Layout::Uk.map(KeyCode::Key1, &none, &locks), Some('1'));
+    assert_eq!(Layout::Us.map(KeyCode::ShiftLeft, &none, &locks), None);
+}
+
+#[test_case]
+fn test_shift_and_caps_lock() {
+    let shift = Modifiers {
+        shift: true,
+        ..Modifiers::default()
+    };
+    let caps_lock = Locks {
+        caps_lock: true,
+        ..Locks::default()
+    };
+    let none = Modifiers::default();
+    assert_eq!(
+        Layout::Uk.map(KeyCode::Key3, &shift, &Locks::default()),
+        Some('£')
+    );
+    assert_eq!(Layout::Us.map(KeyCode::A, &none, &caps_lock), Some('A'));
+    assert_eq!(Layout::Us.map(KeyCode::A, &shift, &caps_lock), Some('a'));
+    // caps lock doesn't affect digits
+    assert_eq!(Layout::Us.map(KeyCode::Key1, &none, &caps_lock), Some('1'));
+    assert_eq!(Layout::De.map(KeyCode::Quote, &none, &caps_lock), Some('Ä'));
+}
+
+#[test_case]
+fn test_alt_gr_and_control() {
+    let alt_gr = Modifiers {
+        alt_gr: true,
+        ..Modifiers::default()
+    };
+    let ctrl = Modifiers {
+        ctrl: true,
+        ..Modifiers::default()
+    };
+    let locks = Locks::default();
+    assert_eq!(Layout::De.map(KeyCode::Q, &alt_gr, &locks), Some('@'));
+    assert_eq!(Layout::Fr.map(KeyCode::Key0, &alt_gr, &locks), Some('@'));
+    assert_eq!(Layout::De.map(KeyCode::A, &alt_gr, &locks), None);
+    assert_eq!(Layout::Us.map(KeyCode::C, &ctrl, &locks), Some('\u{3}'));
+    assert_eq!(Layout::Dvorak.map(KeyCode::I, &ctrl, &locks), Some('\u{3}'));
+    assert_eq!(Layout::Us.map(KeyCode::Key1, &ctrl, &locks), Some('1'));
+    assert!(Layout::De.has_alt_gr() && !Layout::Us.has_alt_gr());
+    assert_eq!(Layout::from_name("DE"), Some(Layout::De));
+}
@@ -1,8 +1,19 @@
-use crate::{gdt, hlt_loop, println, task::spawner::SPAWNER, vga_buffer};
+use crate::{
+    drivers::ps2,
+    serial::{self, ComPort},
+    vga_buffer, warn,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use futures_util::stream::StreamExt;
+use irq::{dispatch_irq, IrqEvents, IrqReturn};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::set_general_handler;
+use x86_64::structures::idt::InterruptDescriptorTable;
+
+pub mod exceptions;
+pub mod irq;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -12,6 +23,7 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
 impl InterruptIndex {
@@ -19,26 +31,38 @@ impl InterruptIndex {
         self as u8
     }
 
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
+    /// The IRQ line of the interrupt, as used by the `irq` registration API.
+    pub fn as_irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
     }
 }
 
+/// The frequency of the timer interrupt in millihertz: the PIT runs at 1.193182 MHz with
+/// its default divisor of 65536.
+const TIMER_FREQUENCY_MILLIHERTZ: u64 = 18_207;
+
+/// The number of timer interrupts since `init_irqs`.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of timer interrupts since the interrupts were set up.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since the interrupts were set up in milliseconds, with the
+/// resolution of the timer interrupt (about 55 ms).
+pub fn uptime_ms() -> u64 {
+    ticks() * 1_000_000 / TIMER_FREQUENCY_MILLIHERTZ
+}
+
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        exceptions::set_handlers(&mut idt);
+        set_general_handler!(&mut idt, dispatch_irq, 32..48);
         idt
     };
 }
@@ -47,50 +71,83 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+/// Masks all IRQ lines, initializes the PS/2 controller and registers the handlers of the
+/// kernel's own devices.
+///
+/// Must be called after the PICs are initialized, while interrupts are disabled.
+pub fn init_irqs() {
+    irq::mask_all();
+    let devices = ps2::init().unwrap_or_else(|err| {
+        // the keyboard may still work with the configuration of the firmware
+        warn!("PS/2 controller initialization failed: {}", err);
+        ps2::devices()
+    });
+    irq::register(InterruptIndex::Timer.as_irq(), "timer", timer_interrupt_handler)
+        .expect("failed to register timer interrupt");
+    if devices.keyboard {
+        irq::register(
+            InterruptIndex::Keyboard.as_irq(),
+            "keyboard",
+            keyboard_interrupt_handler,
+        )
+        .expect("failed to register keyboard interrupt");
+    }
+    if devices.mouse {
+        irq::register(
+            InterruptIndex::Mouse.as_irq(),
+            "mouse",
+            mouse_interrupt_handler,
+        )
+        .expect("failed to register mouse interrupt");
+    }
+    irq::register(ComPort::Com1.irq(), "COM1/COM3", serial::interrupt_handler)
+        .expect("failed to register serial interrupt");
+    irq::register(ComPort::Com2.irq(), "COM2/COM4", serial::interrupt_handler)
+        .expect("failed to register serial interrupt");
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+/// Moves the hardware cursor to the position of the active console after every timer
+/// interrupt, in a bottom half task that is spawned once.
+///
+/// Requires the heap to be initialized.
+pub fn enable_cursor_updates() {
+    irq::register_with_bottom_half(
+        InterruptIndex::Timer.as_irq(),
+        "cursor",
+        cursor_interrupt_handler,
+        update_cursor_on_ticks,
+    )
+    .expect("failed to register cursor update");
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+fn timer_interrupt_handler(_irq: u8) -> IrqReturn {
+    TICKS.fetch_add(1, Ordering::Relaxed);
     // print!(".");
-    SPAWNER.lock().add(vga_buffer::update_cursor());
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    IrqReturn::Handled
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use x86_64::instructions::port::Port;
+fn cursor_interrupt_handler(_irq: u8) -> IrqReturn {
+    IrqReturn::WakeBottomHalf
+}
 
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
+async fn update_cursor_on_ticks(mut ticks: IrqEvents) {
+    while ticks.next().await.is_some() {
+        vga_buffer::update_cursor().await;
+    }
+}
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+fn keyboard_interrupt_handler(_irq: u8) -> IrqReturn {
+    let scancode = ps2::read_byte();
+    if !ps2::keyboard_response(scancode) {
+        crate::task::keyboard::add_scancode(scancode);
     }
+    IrqReturn::Handled
+}
+
+fn mouse_interrupt_handler(_irq: u8) -> IrqReturn {
+    let byte = ps2::read_byte();
+    crate::task::mouse::add_byte(byte);
+    IrqReturn::Handled
 }
 
 #[test_case]
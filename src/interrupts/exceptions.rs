@@ -0,0 +1,513 @@
+use crate::{
+    gdt, graphics,
+    memory::{
+        inspect,
+        vma::{self, AreaKind, FaultOutcome},
+    },
+    serial::SERIAL1,
+    vga_buffer,
+};
+use core::{
+    arch::naked_asm,
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrameValue, PageFaultErrorCode,
+};
+use x86_64::VirtAddr;
+
+/// Whether crash reports include a backtrace obtained by walking frame pointers.
+static BACKTRACE: AtomicBool = AtomicBool::new(true);
+
+/// The maximum number of return addresses printed in a backtrace.
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Enables or disables the backtrace in crash reports.
+///
+/// The backtrace relies on frame pointers, which the target specification forces on.
+pub fn set_backtrace(enabled: bool) {
+    BACKTRACE.store(enabled, Ordering::Relaxed);
+}
+
+/// The error code pushed by the CPU, decoded according to the exception type.
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorCode {
+    /// The exception does not push an error code.
+    None,
+    /// A segment selector error code (`#TS`, `#NP`, `#SS`, `#GP`).
+    Selector(u64),
+    /// A page fault error code.
+    PageFault(PageFaultErrorCode),
+    /// An error code without further structure.
+    Raw(u64),
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorCode::None => write!(f, "none"),
+            ErrorCode::Selector(code) => {
+                let (external, table, index) = decode_selector(code);
+                write!(
+                    f,
+                    "{:#x} (external: {}, table: {}, index: {})",
+                    code, external, table, index
+                )
+            }
+            ErrorCode::PageFault(code) => write!(f, "{:#x} ({:?})", code.bits(), code),
+            ErrorCode::Raw(code) => write!(f, "{:#x}", code),
+        }
+    }
+}
+
+/// Splits a selector error code into its external flag, descriptor table and index.
+fn decode_selector(code: u64) -> (bool, &'static str, u64) {
+    let table = match (code >> 1) & 0b11 {
+        0b00 => "GDT",
+        0b10 => "LDT",
+        _ => "IDT",
+    };
+    (code & 1 != 0, table, (code >> 3) & 0x1fff)
+}
+
+/// The general purpose registers of the interrupted code, in the order the entry stubs
+/// push them.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers = [
+            ("RAX", self.rax),
+            ("RBX", self.rbx),
+            ("RCX", self.rcx),
+            ("RDX", self.rdx),
+            ("RSI", self.rsi),
+            ("RDI", self.rdi),
+            ("RBP", self.rbp),
+            ("R8 ", self.r8),
+            ("R9 ", self.r9),
+            ("R10", self.r10),
+            ("R11", self.r11),
+            ("R12", self.r12),
+            ("R13", self.r13),
+            ("R14", self.r14),
+            ("R15", self.r15),
+        ];
+        for row in registers.chunks(3) {
+            for (i, (name, value)) in row.iter().enumerate() {
+                let separator = if i + 1 < row.len() { "  " } else { "\n" };
+                write!(f, "{}: {:#018x}{}", name, value, separator)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The stack of an exception handler as built by its entry stub: the saved registers,
+/// the error code (zero if the exception doesn't push one) and the CPU's exception frame.
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub registers: Registers,
+    pub error_code: u64,
+    pub stack_frame: InterruptStackFrameValue,
+}
+
+/// Everything known about an exception at the time it was raised.
+pub struct CrashReport<'a> {
+    pub exception: &'static str,
+    pub vector: u8,
+    pub frame: &'a ExceptionFrame,
+    pub error_code: ErrorCode,
+}
+
+impl fmt::Display for CrashReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = &self.frame.stack_frame;
+        let (cr3_frame, cr3_flags) = Cr3::read();
+
+        writeln!(f, "EXCEPTION: {} (vector {})", self.exception, self.vector)?;
+        writeln!(f, "Error Code: {}", self.error_code)?;
+        writeln!(
+            f,
+            "RIP: {:#018x}  CS: {:#06x}",
+            frame.instruction_pointer.as_u64(),
+            frame.code_segment
+        )?;
+        writeln!(
+            f,
+            "RSP: {:#018x}  SS: {:#06x}",
+            frame.stack_pointer.as_u64(),
+            frame.stack_segment
+        )?;
+        writeln!(
+            f,
+            "RFLAGS: {:#x} {:?}",
+            frame.cpu_flags,
+            RFlags::from_bits_truncate(frame.cpu_flags)
+        )?;
+        write!(f, "{}", self.frame.registers)?;
+        writeln!(f, "CR0: {:#x}  CR4: {:#x}", Cr0::read_raw(), Cr4::read_raw())?;
+        writeln!(
+            f,
+            "CR2: {:#018x}  CR3: {:#x} {:?}",
+            Cr2::read().as_u64(),
+            cr3_frame.start_address().as_u64(),
+            cr3_flags
+        )
+    }
+}
+
+/// Prints the given crash report of an exception the kernel doesn't survive, followed by
+/// a backtrace if enabled.
+pub fn report(crash: &CrashReport) {
+    report_with(crash, Locking::Force);
+}
+
+fn report_with(crash: &CrashReport, locking: Locking) {
+    emit(format_args!("{}", crash), locking);
+    if BACKTRACE.load(Ordering::Relaxed) {
+        emit(format_args!("Backtrace:\n"), locking);
+        backtrace(crash.frame.registers.rbp, |depth, return_address| {
+            emit(
+                format_args!("  #{:<2} {:#018x}\n", depth, return_address),
+                locking,
+            );
+        });
+    }
+}
+
+/// How `emit` gets hold of the output locks, which the interrupted code might hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Locking {
+    /// Forcibly releases held locks. Only sound when the kernel panics afterwards, so that
+    /// the interrupted holder never runs again.
+    Force,
+    /// Skips the outputs whose lock is held, because the interrupted code resumes.
+    Try,
+}
+
+/// Writes to the active console, the framebuffer console and the serial port.
+fn emit(args: fmt::Arguments, locking: Locking) {
+    let console = vga_buffer::active();
+    let writer = match (console.try_lock(), locking) {
+        (Some(writer), _) => Some(writer),
+        (None, Locking::Force) => {
+            unsafe { console.force_unlock() };
+            Some(console.lock())
+        }
+        (None, Locking::Try) => None,
+    };
+    if let Some(mut writer) = writer {
+        let _ = writer.write_fmt(args);
+    }
+    match locking {
+        Locking::Force => graphics::console::force_write_fmt(args),
+        Locking::Try => {
+            graphics::console::try_write_fmt(args);
+        }
+    }
+
+    let serial = match (SERIAL1.try_lock(), locking) {
+        (Some(serial), _) => Some(serial),
+        (None, Locking::Force) => {
+            unsafe { SERIAL1.force_unlock() };
+            Some(SERIAL1.lock())
+        }
+        (None, Locking::Try) => None,
+    };
+    if let Some(mut serial) = serial {
+        let _ = serial.write_fmt(args);
+    }
+}
+
+/// Walks the frame pointer chain of the interrupted code, starting at its `rbp`.
+///
+/// Every frame is looked up in the page tables before it is read. The walk stops at a null,
+/// misaligned or unmapped frame pointer, or when the chain does not move up the stack by a
+/// plausible amount, so that a corrupted stack ends the backtrace instead of faulting.
+fn backtrace(mut rbp: u64, mut f: impl FnMut(usize, u64)) {
+    let is_mapped = |addr: u64| {
+        VirtAddr::try_new(addr)
+            .ok()
+            .and_then(inspect::translate)
+            .is_some()
+    };
+
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        // the frame holds the saved `rbp` and the return address
+        if rbp == 0 || rbp % 8 != 0 || !is_mapped(rbp) || !is_mapped(rbp.wrapping_add(8)) {
+            break;
+        }
+        let frame = rbp as *const u64;
+        let (next_rbp, return_address) = unsafe { (*frame, *frame.add(1)) };
+        if return_address == 0 {
+            break;
+        }
+        f(depth, return_address);
+        if next_rbp <= rbp || next_rbp - rbp > 1024 * 1024 {
+            break;
+        }
+        rbp = next_rbp;
+    }
+}
+
+/// Defines the entry stub of an exception, which saves the general purpose registers,
+/// pushes a zero error code for exceptions without one, calls the handler with the
+/// resulting `ExceptionFrame` and returns from the exception if the handler returns.
+///
+/// The `x86-interrupt` calling convention doesn't give handlers access to the registers of
+/// the interrupted code, which the crash reports include.
+macro_rules! entry_stub {
+    (fn $stub:ident => $handler:ident, error_code) => {
+        entry_stub!(@ $stub, $handler, "");
+    };
+    (fn $stub:ident => $handler:ident) => {
+        entry_stub!(@ $stub, $handler, "push 0");
+    };
+    (@ $stub:ident, $handler:ident, $push_error_code:literal) => {
+        #[unsafe(naked)]
+        extern "C" fn $stub() {
+            naked_asm!(
+                $push_error_code,
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                // the CPU aligns the stack to 16 bytes before pushing its frame, so the 21
+                // quadwords above leave it off by 8
+                "mov rdi, rsp",
+                "sub rsp, 8",
+                "cld",
+                "call {handler}",
+                "add rsp, 8",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "add rsp, 8",
+                "iretq",
+                handler = sym $handler,
+            )
+        }
+    };
+}
+
+macro_rules! exception_handler {
+    (fn $name:ident => $stub:ident, $title:expr, $vector:expr, fatal) => {
+        entry_stub!(fn $stub => $name);
+        extern "C" fn $name(frame: &ExceptionFrame) {
+            report(&CrashReport {
+                exception: $title,
+                vector: $vector,
+                frame,
+                error_code: ErrorCode::None,
+            });
+            panic!("EXCEPTION: {}", $title);
+        }
+    };
+    (fn $name:ident => $stub:ident, $title:expr, $vector:expr, recoverable) => {
+        entry_stub!(fn $stub => $name);
+        extern "C" fn $name(frame: &ExceptionFrame) {
+            let crash = CrashReport {
+                exception: $title,
+                vector: $vector,
+                frame,
+                error_code: ErrorCode::None,
+            };
+            report_with(&crash, Locking::Try);
+        }
+    };
+    (fn $name:ident => $stub:ident, $title:expr, $vector:expr, $decode:expr) => {
+        entry_stub!(fn $stub => $name, error_code);
+        extern "C" fn $name(frame: &ExceptionFrame) {
+            report(&CrashReport {
+                exception: $title,
+                vector: $vector,
+                error_code: $decode(frame.error_code),
+                frame,
+            });
+            panic!("EXCEPTION: {}", $title);
+        }
+    };
+}
+
+exception_handler!(fn divide_error_handler => divide_error_entry, "DIVIDE ERROR", 0, fatal);
+exception_handler!(fn debug_handler => debug_entry, "DEBUG", 1, recoverable);
+exception_handler!(fn non_maskable_interrupt_handler => non_maskable_interrupt_entry, "NON-MASKABLE INTERRUPT", 2, recoverable);
+exception_handler!(fn breakpoint_handler => breakpoint_entry, "BREAKPOINT", 3, recoverable);
+exception_handler!(fn overflow_handler => overflow_entry, "OVERFLOW", 4, fatal);
+exception_handler!(fn bound_range_exceeded_handler => bound_range_exceeded_entry, "BOUND RANGE EXCEEDED", 5, fatal);
+exception_handler!(fn invalid_opcode_handler => invalid_opcode_entry, "INVALID OPCODE", 6, fatal);
+exception_handler!(fn device_not_available_handler => device_not_available_entry, "DEVICE NOT AVAILABLE", 7, fatal);
+exception_handler!(fn invalid_tss_handler => invalid_tss_entry, "INVALID TSS", 10, ErrorCode::Selector);
+exception_handler!(fn segment_not_present_handler => segment_not_present_entry, "SEGMENT NOT PRESENT", 11, ErrorCode::Selector);
+exception_handler!(fn stack_segment_fault_handler => stack_segment_fault_entry, "STACK SEGMENT FAULT", 12, ErrorCode::Selector);
+exception_handler!(fn general_protection_fault_handler => general_protection_fault_entry, "GENERAL PROTECTION FAULT", 13, ErrorCode::Selector);
+exception_handler!(fn x87_floating_point_handler => x87_floating_point_entry, "X87 FLOATING POINT", 16, fatal);
+exception_handler!(fn alignment_check_handler => alignment_check_entry, "ALIGNMENT CHECK", 17, ErrorCode::Raw);
+exception_handler!(fn simd_floating_point_handler => simd_floating_point_entry, "SIMD FLOATING POINT", 19, fatal);
+exception_handler!(fn virtualization_handler => virtualization_entry, "VIRTUALIZATION", 20, fatal);
+exception_handler!(fn vmm_communication_exception_handler => vmm_communication_exception_entry, "VMM COMMUNICATION", 29, ErrorCode::Raw);
+exception_handler!(fn security_exception_handler => security_exception_entry, "SECURITY EXCEPTION", 30, ErrorCode::Raw);
+
+entry_stub!(fn page_fault_entry => page_fault_handler, error_code);
+entry_stub!(fn double_fault_entry => double_fault_handler, error_code);
+entry_stub!(fn machine_check_entry => machine_check_handler);
+
+extern "C" fn page_fault_handler(frame: &ExceptionFrame) {
+    let address = Cr2::read();
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+    let violation = match vma::handle_page_fault(address, error_code) {
+        FaultOutcome::Resolved => return,
+        FaultOutcome::Violation(violation) => violation,
+    };
+    if violation.user_mode && vma::signal_user_fault(address, &violation) {
+        return;
+    }
+
+    report(&CrashReport {
+        exception: "PAGE FAULT",
+        vector: 14,
+        frame,
+        error_code: ErrorCode::PageFault(error_code),
+    });
+    emit(format_args!("Cause: {}\n", violation), Locking::Force);
+    panic!("EXCEPTION: PAGE FAULT at {:?}", address);
+}
+
+extern "C" fn double_fault_handler(frame: &ExceptionFrame) -> ! {
+    report(&CrashReport {
+        exception: "DOUBLE FAULT",
+        vector: 8,
+        frame,
+        error_code: ErrorCode::Raw(frame.error_code),
+    });
+    // a kernel stack overflow ends up here, because the page fault handler can't push its
+    // exception frame onto the overflowed stack
+    if let Some(area) = vma::try_find(Cr2::read()) {
+        if area.kind == AreaKind::Guard {
+            emit(
+                format_args!("Cause: stack overflow of '{}'\n", area.name),
+                Locking::Force,
+            );
+        }
+    }
+    panic!("EXCEPTION: DOUBLE FAULT");
+}
+
+extern "C" fn machine_check_handler(frame: &ExceptionFrame) -> ! {
+    report(&CrashReport {
+        exception: "MACHINE CHECK",
+        vector: 18,
+        frame,
+        error_code: ErrorCode::None,
+    });
+    panic!("EXCEPTION: MACHINE CHECK");
+}
+
+/// Installs a handler for every architectural exception in the given IDT.
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    let entry = |stub: extern "C" fn()| VirtAddr::new(stub as usize as u64);
+
+    // the entry stubs save the registers and return with `iretq` like an `x86-interrupt`
+    // handler would
+    unsafe {
+        idt.divide_error.set_handler_addr(entry(divide_error_entry));
+        idt.debug.set_handler_addr(entry(debug_entry));
+        idt.non_maskable_interrupt
+            .set_handler_addr(entry(non_maskable_interrupt_entry))
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.breakpoint.set_handler_addr(entry(breakpoint_entry));
+        idt.overflow.set_handler_addr(entry(overflow_entry));
+        idt.bound_range_exceeded
+            .set_handler_addr(entry(bound_range_exceeded_entry));
+        idt.invalid_opcode
+            .set_handler_addr(entry(invalid_opcode_entry));
+        idt.device_not_available
+            .set_handler_addr(entry(device_not_available_entry));
+        idt.double_fault
+            .set_handler_addr(entry(double_fault_entry))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss.set_handler_addr(entry(invalid_tss_entry));
+        idt.segment_not_present
+            .set_handler_addr(entry(segment_not_present_entry));
+        idt.stack_segment_fault
+            .set_handler_addr(entry(stack_segment_fault_entry));
+        idt.general_protection_fault
+            .set_handler_addr(entry(general_protection_fault_entry));
+        idt.page_fault.set_handler_addr(entry(page_fault_entry));
+        idt.x87_floating_point
+            .set_handler_addr(entry(x87_floating_point_entry));
+        idt.alignment_check
+            .set_handler_addr(entry(alignment_check_entry));
+        idt.machine_check
+            .set_handler_addr(entry(machine_check_entry))
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        idt.simd_floating_point
+            .set_handler_addr(entry(simd_floating_point_entry));
+        idt.virtualization
+            .set_handler_addr(entry(virtualization_entry));
+        idt.vmm_communication_exception
+            .set_handler_addr(entry(vmm_communication_exception_entry));
+        idt.security_exception
+            .set_handler_addr(entry(security_exception_entry));
+    }
+}
+
+#[test_case]
+fn test_decode_selector() {
+    // index 5 in the IDT, raised by an external event
+    assert_eq!(decode_selector((5 << 3) | 0b011), (true, "IDT", 5));
+    // index 2 in the GDT
+    assert_eq!(decode_selector(2 << 3), (false, "GDT", 2));
+    assert_eq!(decode_selector(0b100), (false, "LDT", 0));
+}
+
+#[test_case]
+fn test_exception_frame_layout() {
+    // the 15 registers pushed by the entry stubs, the error code and the CPU's frame
+    assert_eq!(core::mem::size_of::<ExceptionFrame>(), 21 * 8);
+}
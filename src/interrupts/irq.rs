@@ -0,0 +1,382 @@
+use super::{PICS, PIC_1_OFFSET, PIC_2_OFFSET};
+use crate::task::spawner::SPAWNER;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::{stream::Stream, task::AtomicWaker};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// The number of IRQ lines provided by the two chained PICs.
+pub const IRQ_LINES: usize = 16;
+/// The maximum number of handlers that can share a single IRQ line.
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// The IRQ line the secondary PIC is cascaded on.
+const CASCADE_IRQ: u8 = 2;
+
+/// The value returned by a top half handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// The interrupt was not raised by the handler's device (for shared lines).
+    NotMine,
+    /// The interrupt was handled completely.
+    Handled,
+    /// The interrupt was handled and the bottom half task should be woken.
+    WakeBottomHalf,
+}
+
+/// A top half handler, called in interrupt context with the IRQ line number.
+///
+/// Must not block or allocate.
+pub type IrqHandler = fn(irq: u8) -> IrqReturn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The IRQ line does not exist.
+    InvalidLine(u8),
+    /// The IRQ line is reserved for the PIC cascade.
+    Reserved(u8),
+    /// All handler slots of the IRQ line are taken.
+    LineFull(u8),
+}
+
+impl fmt::Display for IrqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrqError::InvalidLine(irq) => write!(f, "IRQ {} does not exist", irq),
+            IrqError::Reserved(irq) => write!(f, "IRQ {} is reserved", irq),
+            IrqError::LineFull(irq) => write!(f, "IRQ {} has no free handler slot", irq),
+        }
+    }
+}
+
+/// Identifies a registered handler; used to unregister it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandle {
+    irq: u8,
+    slot: usize,
+    id: u64,
+}
+
+impl IrqHandle {
+    /// The IRQ line this handler is registered on.
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Action {
+    name: &'static str,
+    handler: IrqHandler,
+}
+
+struct Line {
+    actions: [Option<Action>; MAX_SHARED_HANDLERS],
+}
+
+impl Line {
+    const fn new() -> Self {
+        Line {
+            actions: [None; MAX_SHARED_HANDLERS],
+        }
+    }
+}
+
+/// Interrupt counters of a single IRQ line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IrqStats {
+    /// Number of interrupts raised on the line.
+    pub count: u64,
+    /// Number of interrupts no registered handler claimed.
+    pub unhandled: u64,
+    /// Number of spurious interrupts (only possible on IRQ 7 and 15).
+    pub spurious: u64,
+}
+
+const EMPTY_LINE: Mutex<Line> = Mutex::new(Line::new());
+const ZERO: AtomicU64 = AtomicU64::new(0);
+const ZERO_ROW: [AtomicU64; MAX_SHARED_HANDLERS] = [ZERO; MAX_SHARED_HANDLERS];
+const NO_WAKER: AtomicWaker = AtomicWaker::new();
+const NO_WAKER_ROW: [AtomicWaker; MAX_SHARED_HANDLERS] = [NO_WAKER; MAX_SHARED_HANDLERS];
+
+static LINES: [Mutex<Line>; IRQ_LINES] = [EMPTY_LINE; IRQ_LINES];
+static COUNT: [AtomicU64; IRQ_LINES] = [ZERO; IRQ_LINES];
+static UNHANDLED: [AtomicU64; IRQ_LINES] = [ZERO; IRQ_LINES];
+static SPURIOUS: [AtomicU64; IRQ_LINES] = [ZERO; IRQ_LINES];
+
+/// The id of the handler occupying each slot, 0 if the slot is free.
+static SLOT_IDS: [[AtomicU64; MAX_SHARED_HANDLERS]; IRQ_LINES] = [ZERO_ROW; IRQ_LINES];
+/// Number of bottom half wakeups not yet consumed by the slot's `IrqEvents` stream.
+static PENDING: [[AtomicU64; MAX_SHARED_HANDLERS]; IRQ_LINES] = [ZERO_ROW; IRQ_LINES];
+static WAKERS: [[AtomicWaker; MAX_SHARED_HANDLERS]; IRQ_LINES] = [NO_WAKER_ROW; IRQ_LINES];
+
+/// Registers a top half handler on the given IRQ line and unmasks the line.
+///
+/// Up to `MAX_SHARED_HANDLERS` handlers can share a line; all of them are called on every
+/// interrupt. The end of interrupt is sent by the dispatcher, handlers must not do it.
+pub fn register(irq: u8, name: &'static str, handler: IrqHandler) -> Result<IrqHandle, IrqError> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    if irq as usize >= IRQ_LINES {
+        return Err(IrqError::InvalidLine(irq));
+    }
+    if irq == CASCADE_IRQ {
+        return Err(IrqError::Reserved(irq));
+    }
+
+    interrupts::without_interrupts(|| -> Result<IrqHandle, IrqError> {
+        let mut line = LINES[irq as usize].lock();
+        let slot = line
+            .actions
+            .iter()
+            .position(|action| action.is_none())
+            .ok_or(IrqError::LineFull(irq))?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        line.actions[slot] = Some(Action { name, handler });
+        PENDING[irq as usize][slot].store(0, Ordering::Relaxed);
+        SLOT_IDS[irq as usize][slot].store(id, Ordering::Release);
+        set_masked(irq, false);
+
+        Ok(IrqHandle { irq, slot, id })
+    })
+}
+
+/// Registers a top half handler together with an async bottom half task.
+///
+/// `bottom_half` is called once with the `IrqEvents` stream of the new handler and the
+/// returned future is spawned. The stream yields whenever the top half returns
+/// `IrqReturn::WakeBottomHalf` and ends when the handler is unregistered.
+pub fn register_with_bottom_half<F, Fut>(
+    irq: u8,
+    name: &'static str,
+    handler: IrqHandler,
+    bottom_half: F,
+) -> Result<IrqHandle, IrqError>
+where
+    F: FnOnce(IrqEvents) -> Fut,
+    Fut: Future<Output = ()> + 'static,
+{
+    let handle = register(irq, name, handler)?;
+    SPAWNER.lock().add(bottom_half(IrqEvents { handle }));
+    Ok(handle)
+}
+
+/// Removes a registered handler. The line is masked once its last handler is gone.
+pub fn unregister(handle: IrqHandle) {
+    let IrqHandle { irq, slot, id } = handle;
+
+    interrupts::without_interrupts(|| {
+        let mut line = LINES[irq as usize].lock();
+        if SLOT_IDS[irq as usize][slot].load(Ordering::Acquire) != id {
+            return; // already unregistered
+        }
+        line.actions[slot] = None;
+        SLOT_IDS[irq as usize][slot].store(0, Ordering::Release);
+        if line.actions.iter().all(|action| action.is_none()) {
+            set_masked(irq, true);
+        }
+    });
+
+    // let the bottom half observe the end of its stream
+    WAKERS[irq as usize][slot].wake();
+}
+
+/// Returns the interrupt counters of the given IRQ line, or `None` if it doesn't exist.
+pub fn stats(irq: u8) -> Option<IrqStats> {
+    let irq = irq as usize;
+    if irq >= IRQ_LINES {
+        return None;
+    }
+    Some(IrqStats {
+        count: COUNT[irq].load(Ordering::Relaxed),
+        unhandled: UNHANDLED[irq].load(Ordering::Relaxed),
+        spurious: SPURIOUS[irq].load(Ordering::Relaxed),
+    })
+}
+
+/// Calls `f` with the name of every handler registered on the given IRQ line.
+///
+/// Does nothing if the line doesn't exist.
+pub fn for_each_handler(irq: u8, mut f: impl FnMut(&'static str)) {
+    if irq as usize >= IRQ_LINES {
+        return;
+    }
+    let names = interrupts::without_interrupts(|| {
+        let line = LINES[irq as usize].lock();
+        line.actions.map(|action| action.map(|action| action.name))
+    });
+    for name in names.iter().flatten() {
+        f(name);
+    }
+}
+
+/// Masks all IRQ lines except the PIC cascade.
+///
+/// Called once after the PICs are initialized, before any handler is registered.
+pub fn mask_all() {
+    unsafe {
+        Port::<u8>::new(0x21).write(!(1 << CASCADE_IRQ));
+        Port::<u8>::new(0xa1).write(0xff);
+    }
+}
+
+fn set_masked(irq: u8, masked: bool) {
+    let (mut port, bit) = if irq < 8 {
+        (Port::<u8>::new(0x21), irq)
+    } else {
+        (Port::<u8>::new(0xa1), irq - 8)
+    };
+    unsafe {
+        let mask = port.read();
+        if masked {
+            port.write(mask | (1 << bit));
+        } else {
+            port.write(mask & !(1 << bit));
+        }
+    }
+}
+
+/// Reads the in-service register of the PIC responsible for the given IRQ line.
+fn in_service(irq: u8) -> bool {
+    let (mut command, bit) = if irq < 8 {
+        (Port::<u8>::new(0x20), irq)
+    } else {
+        (Port::<u8>::new(0xa0), irq - 8)
+    };
+    unsafe {
+        command.write(0x0b); // OCW3: read ISR
+        command.read() & (1 << bit) != 0
+    }
+}
+
+/// Common entry point for all PIC interrupts, installed for vectors 32..48.
+pub(super) fn dispatch_irq(_stack_frame: InterruptStackFrame, index: u8, _error_code: Option<u64>) {
+    let irq = index - PIC_1_OFFSET;
+
+    // a spurious IRQ 7 or 15 is not in service and must not be acknowledged on its own PIC
+    if (irq == 7 || irq == 15) && !in_service(irq) {
+        SPURIOUS[irq as usize].fetch_add(1, Ordering::Relaxed);
+        if index >= PIC_2_OFFSET {
+            unsafe { Port::<u8>::new(0x20).write(0x20) };
+        }
+        return;
+    }
+
+    COUNT[irq as usize].fetch_add(1, Ordering::Relaxed);
+
+    let mut handled = false;
+    let line = LINES[irq as usize].lock();
+    for (slot, action) in line.actions.iter().enumerate() {
+        if let Some(action) = action {
+            match (action.handler)(irq) {
+                IrqReturn::NotMine => {}
+                IrqReturn::Handled => handled = true,
+                IrqReturn::WakeBottomHalf => {
+                    handled = true;
+                    PENDING[irq as usize][slot].fetch_add(1, Ordering::Release);
+                    WAKERS[irq as usize][slot].wake();
+                }
+            }
+        }
+    }
+    drop(line);
+
+    if !handled {
+        UNHANDLED[irq as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(index);
+    }
+}
+
+/// The stream of wakeups for a bottom half, see `register_with_bottom_half`.
+///
+/// Each item is the number of times the top half requested a wakeup since the last item.
+pub struct IrqEvents {
+    handle: IrqHandle,
+}
+
+impl IrqEvents {
+    /// The handle of the top half this stream belongs to.
+    pub fn handle(&self) -> IrqHandle {
+        self.handle
+    }
+
+    fn is_registered(&self) -> bool {
+        let IrqHandle { irq, slot, id } = self.handle;
+        SLOT_IDS[irq as usize][slot].load(Ordering::Acquire) == id
+    }
+
+    fn take_pending(&self) -> u64 {
+        PENDING[self.handle.irq as usize][self.handle.slot].swap(0, Ordering::Acquire)
+    }
+}
+
+impl Stream for IrqEvents {
+    type Item = u64;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u64>> {
+        if !self.is_registered() {
+            return Poll::Ready(None);
+        }
+
+        // fast path
+        let pending = self.take_pending();
+        if pending > 0 {
+            return Poll::Ready(Some(pending));
+        }
+
+        let waker = &WAKERS[self.handle.irq as usize][self.handle.slot];
+        waker.register(&cx.waker());
+        let pending = self.take_pending();
+        if pending > 0 {
+            waker.take();
+            Poll::Ready(Some(pending))
+        } else if !self.is_registered() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[test_case]
+fn test_register_shared_line() {
+    fn handler(_irq: u8) -> IrqReturn {
+        IrqReturn::NotMine
+    }
+    fn handler_count(irq: u8) -> usize {
+        let mut count = 0;
+        for_each_handler(irq, |_| count += 1);
+        count
+    }
+
+    let first = register(5, "first", handler).expect("register failed");
+    let second = register(5, "second", handler).expect("register failed");
+    assert_eq!(handler_count(5), 2);
+    unregister(first);
+    assert_eq!(handler_count(5), 1);
+    unregister(second);
+    assert_eq!(handler_count(5), 0);
+}
+
+#[test_case]
+fn test_register_invalid_line() {
+    fn handler(_irq: u8) -> IrqReturn {
+        IrqReturn::Handled
+    }
+
+    assert_eq!(register(2, "cascade", handler), Err(IrqError::Reserved(2)));
+    assert_eq!(register(16, "none", handler), Err(IrqError::InvalidLine(16)));
+    assert!(stats(16).is_none());
+    for_each_handler(16, |_| panic!("line 16 has no handlers"));
+}
@@ -10,11 +10,20 @@
 extern crate alloc;
 use core::panic::PanicInfo;
 
+pub mod acpi;
 pub mod allocator;
+pub mod block;
+pub mod config;
+pub mod drivers;
 pub mod error;
+pub mod fs;
 pub mod gdt;
+pub mod graphics;
+pub mod input;
 pub mod interrupts;
+pub mod log;
 pub mod memory;
+pub mod random;
 pub mod serial;
 pub mod task;
 pub mod vga_buffer;
@@ -23,8 +32,37 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    serial::probe_ports();
+    config::apply();
+    interrupts::init_irqs();
     x86_64::instructions::interrupts::enable();
 }
+
+/// Runs `init` and sets up the memory management: the page table, the heap with growth
+/// enabled, the kernel's frame allocator, the console scrollback and the IST stacks.
+///
+/// Shared by `kernel_main` and `test_init`, so that the integration tests boot like the
+/// kernel.
+pub fn init_kernel(boot_info: &'static bootloader::BootInfo) {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator, phys_mem_offset);
+    allocator::enable_heap_growth().expect("heap growth initialization failed");
+    vga_buffer::enable_scrollback();
+    gdt::init_ist_stacks().expect("IST stack allocation failed");
+}
+
+/// The boot sequence of the integration tests, see `init_kernel`.
+pub fn test_init(boot_info: &'static bootloader::BootInfo) {
+    init_kernel(boot_info);
+}
+
 pub trait Testable {
     fn run(&self) -> ();
 }
@@ -0,0 +1,557 @@
+//! Kernel logging with levels, per-module filters and a ring buffer of recent records.
+//!
+//! Records are created with the `error!`, `warn!`, `info!`, `debug!` and `trace!` macros,
+//! which use the calling module as the target. A record that passes the filters is stored
+//! in the ring buffer, which the `dmesg` command prints, and handed to every sink whose
+//! level allows it.
+//!
+//! Logging never allocates or waits for a lock, so it can be used from interrupt
+//! handlers. If a lock is taken, e.g. because an exception interrupted the logger, the
+//! record is dropped and counted. The same holds for the screen and serial sinks: a record
+//! they can't write because the console or port is locked is counted as dropped.
+
+use crate::{interrupts, serial, vga_buffer};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// The number of records kept in the ring buffer.
+pub const BUFFER_CAPACITY: usize = 128;
+/// The maximum length of a message in bytes; longer messages are truncated.
+pub const MESSAGE_CAPACITY: usize = 160;
+/// The maximum number of per-module filters.
+pub const MAX_FILTERS: usize = 16;
+/// The maximum length of a filter target in bytes.
+pub const MAX_TARGET_LEN: usize = 48;
+/// The maximum number of sinks.
+pub const MAX_SINKS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// Parses a level name like `warn`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ]
+        .iter()
+        .copied()
+        .find(|level| name.eq_ignore_ascii_case(level.name()))
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    TooManyFilters,
+    TargetTooLong,
+    InvalidLevel,
+    TooManySinks,
+    SinkExists,
+    NoSuchSink,
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogError::TooManyFilters => write!(f, "too many log filters"),
+            LogError::TargetTooLong => write!(f, "log target too long"),
+            LogError::InvalidLevel => write!(f, "invalid log level"),
+            LogError::TooManySinks => write!(f, "too many log sinks"),
+            LogError::SinkExists => write!(f, "log sink already exists"),
+            LogError::NoSuchSink => write!(f, "no such log sink"),
+        }
+    }
+}
+
+/// A log message with its metadata.
+#[derive(Clone, Copy)]
+pub struct Record {
+    /// The position of the record in the log, counting from 0 at boot.
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub level: Level,
+    /// The module the record was logged from.
+    pub target: &'static str,
+    message: [u8; MESSAGE_CAPACITY],
+    len: usize,
+}
+
+impl Record {
+    fn new(level: Level, target: &'static str, args: fmt::Arguments) -> Self {
+        use core::fmt::Write;
+
+        let mut record = Record {
+            seq: 0,
+            timestamp_ms: interrupts::uptime_ms(),
+            level,
+            target,
+            message: [0; MESSAGE_CAPACITY],
+            len: 0,
+        };
+        // a truncated message is not an error
+        let _ = record.write_fmt(args);
+        record
+    }
+
+    pub fn message(&self) -> &str {
+        // `write_str` only truncates at character boundaries
+        core::str::from_utf8(&self.message[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let free = MESSAGE_CAPACITY - self.len;
+        let mut len = s.len().min(free);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.message[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Record")
+            .field("seq", &self.seq)
+            .field("timestamp_ms", &self.timestamp_ms)
+            .field("level", &self.level)
+            .field("target", &self.target)
+            .field("message", &self.message())
+            .finish()
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:03}] {:<5} {}: {}",
+            self.timestamp_ms / 1000,
+            self.timestamp_ms % 1000,
+            self.level,
+            self.target,
+            self.message()
+        )
+    }
+}
+
+struct RingBuffer {
+    records: [Option<Record>; BUFFER_CAPACITY],
+    next_seq: u64,
+}
+
+impl RingBuffer {
+    const fn new() -> Self {
+        RingBuffer {
+            records: [None; BUFFER_CAPACITY],
+            next_seq: 0,
+        }
+    }
+
+    /// Stores the record, overwriting the oldest one if the buffer is full.
+    fn push(&mut self, record: &mut Record) {
+        record.seq = self.next_seq;
+        self.records[self.next_seq as usize % BUFFER_CAPACITY] = Some(*record);
+        self.next_seq += 1;
+    }
+
+    fn get(&self, seq: u64) -> Option<Record> {
+        self.records[seq as usize % BUFFER_CAPACITY].filter(|record| record.seq == seq)
+    }
+
+    fn oldest(&self) -> u64 {
+        self.next_seq.saturating_sub(BUFFER_CAPACITY as u64)
+    }
+}
+
+/// A level for all modules whose path starts with the target.
+#[derive(Clone, Copy)]
+struct Filter {
+    target: [u8; MAX_TARGET_LEN],
+    len: usize,
+    level: Level,
+}
+
+impl Filter {
+    fn target(&self) -> &[u8] {
+        &self.target[..self.len]
+    }
+
+    /// Returns whether the module path is the target or one of its submodules.
+    fn matches(&self, module: &str) -> bool {
+        let module = module.as_bytes();
+        module.starts_with(self.target())
+            && (module.len() == self.len || module[self.len..].starts_with(b"::"))
+    }
+}
+
+struct Filters {
+    default: Level,
+    targets: [Option<Filter>; MAX_FILTERS],
+}
+
+impl Filters {
+    const fn new() -> Self {
+        Filters {
+            default: Level::Info,
+            targets: [None; MAX_FILTERS],
+        }
+    }
+
+    /// Returns the level of the most specific filter matching the module.
+    fn level(&self, module: &str) -> Level {
+        self.targets
+            .iter()
+            .flatten()
+            .filter(|filter| filter.matches(module))
+            .max_by_key(|filter| filter.len)
+            .map_or(self.default, |filter| filter.level)
+    }
+
+    fn set(&mut self, target: Option<&str>, level: Level) -> Result<(), LogError> {
+        let target = match target {
+            Some(target) => target.as_bytes(),
+            None => {
+                self.default = level;
+                return Ok(());
+            }
+        };
+        if target.len() > MAX_TARGET_LEN {
+            return Err(LogError::TargetTooLong);
+        }
+        if let Some(filter) = self
+            .targets
+            .iter_mut()
+            .flatten()
+            .find(|filter| filter.target() == target)
+        {
+            filter.level = level;
+            return Ok(());
+        }
+        let slot = self
+            .targets
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(LogError::TooManyFilters)?;
+        let mut filter = Filter {
+            target: [0; MAX_TARGET_LEN],
+            len: target.len(),
+            level,
+        };
+        filter.target[..target.len()].copy_from_slice(target);
+        *slot = Some(filter);
+        Ok(())
+    }
+}
+
+/// A destination for log records, e.g. a screen or a serial port.
+///
+/// Called with interrupts disabled, possibly from an interrupt handler, so it must not
+/// block or allocate. A sink that can't take a lock should drop the record and call
+/// `count_dropped`.
+pub type Sink = fn(&Record);
+
+#[derive(Clone, Copy)]
+struct SinkEntry {
+    name: &'static str,
+    level: Level,
+    sink: Sink,
+}
+
+static BUFFER: Mutex<RingBuffer> = Mutex::new(RingBuffer::new());
+static FILTERS: Mutex<Filters> = Mutex::new(Filters::new());
+static SINKS: Mutex<[Option<SinkEntry>; MAX_SINKS]> = Mutex::new([
+    Some(SinkEntry {
+        name: "vga",
+        level: Level::Warn,
+        sink: vga_sink,
+    }),
+    Some(SinkEntry {
+        name: "serial",
+        level: Level::Info,
+        sink: serial_sink,
+    }),
+    None,
+    None,
+]);
+
+/// The number of records dropped because the logger or a sink was locked.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Prints records to the current virtual console.
+pub fn vga_sink(record: &Record) {
+    if !vga_buffer::try_print(format_args!("{}\n", record)) {
+        count_dropped();
+    }
+}
+
+/// Writes records to the serial log port, see `serial::set_log_port`.
+pub fn serial_sink(record: &Record) {
+    if !serial::try_write_log(format_args!("{}\n", record)) {
+        count_dropped();
+    }
+}
+
+/// Returns whether a record of the given level from the module would be logged.
+pub fn enabled(level: Level, module: &str) -> bool {
+    without_interrupts(|| match FILTERS.try_lock() {
+        Some(filters) => level <= filters.level(module),
+        None => false,
+    })
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, target: &'static str, args: fmt::Arguments) {
+    without_interrupts(|| {
+        let filters = match FILTERS.try_lock() {
+            Some(filters) => filters,
+            None => return count_dropped(),
+        };
+        if level > filters.level(target) {
+            return;
+        }
+        drop(filters);
+
+        let mut record = Record::new(level, target, args);
+        match BUFFER.try_lock() {
+            Some(mut buffer) => buffer.push(&mut record),
+            None => return count_dropped(),
+        }
+        let sinks = match SINKS.try_lock() {
+            Some(sinks) => *sinks,
+            None => return count_dropped(),
+        };
+        for entry in sinks.iter().flatten() {
+            if level <= entry.level {
+                (entry.sink)(&record);
+            }
+        }
+    });
+}
+
+/// Counts a record that was dropped, by the logger or by a sink.
+pub fn count_dropped() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of records dropped because the logger or a sink was locked.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Sets the level for the given module and its submodules, or the default level for all
+/// modules without a filter if `target` is `None`.
+pub fn set_level(target: Option<&str>, level: Level) -> Result<(), LogError> {
+    without_interrupts(|| FILTERS.lock().set(target, level))
+}
+
+/// Applies a filter specification like `info,blog_os::task=debug`: a bare level sets
+/// the default level, `target=level` the level of a module.
+pub fn apply_filters(spec: &str) -> Result<(), LogError> {
+    for directive in spec.split(',').filter(|directive| !directive.is_empty()) {
+        let mut parts = directive.splitn(2, '=');
+        let first = parts.next().unwrap_or_default();
+        let (target, level) = match parts.next() {
+            Some(level) => (Some(first), level),
+            None => (None, first),
+        };
+        let level = Level::from_name(level).ok_or(LogError::InvalidLevel)?;
+        set_level(target, level)?;
+    }
+    Ok(())
+}
+
+/// Calls `f` with the target and level of every filter, starting with the default level
+/// and an empty target.
+pub fn for_each_filter(mut f: impl FnMut(&str, Level)) {
+    let filters = without_interrupts(|| {
+        let filters = FILTERS.lock();
+        (filters.default, filters.targets)
+    });
+    f("", filters.0);
+    for filter in filters.1.iter().flatten() {
+        f(
+            core::str::from_utf8(filter.target()).unwrap_or(""),
+            filter.level,
+        );
+    }
+}
+
+/// Adds a sink that receives all records up to the given level.
+pub fn add_sink(name: &'static str, level: Level, sink: Sink) -> Result<(), LogError> {
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        if sinks.iter().flatten().any(|entry| entry.name == name) {
+            return Err(LogError::SinkExists);
+        }
+        let slot = sinks
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(LogError::TooManySinks)?;
+        *slot = Some(SinkEntry { name, level, sink });
+        Ok(())
+    })
+}
+
+pub fn remove_sink(name: &str) -> Result<(), LogError> {
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let slot = sinks
+            .iter_mut()
+            .find(|slot| slot.map_or(false, |entry| entry.name == name))
+            .ok_or(LogError::NoSuchSink)?;
+        *slot = None;
+        Ok(())
+    })
+}
+
+/// Changes the most verbose level a sink receives.
+pub fn set_sink_level(name: &str, level: Level) -> Result<(), LogError> {
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let entry = sinks
+            .iter_mut()
+            .flatten()
+            .find(|entry| entry.name == name)
+            .ok_or(LogError::NoSuchSink)?;
+        entry.level = level;
+        Ok(())
+    })
+}
+
+/// Calls `f` with the name and level of every sink.
+pub fn for_each_sink(mut f: impl FnMut(&str, Level)) {
+    let sinks = without_interrupts(|| *SINKS.lock());
+    for entry in sinks.iter().flatten() {
+        f(entry.name, entry.level);
+    }
+}
+
+/// Returns the range of sequence numbers in the ring buffer.
+pub fn buffered() -> core::ops::Range<u64> {
+    without_interrupts(|| {
+        let buffer = BUFFER.lock();
+        buffer.oldest()..buffer.next_seq
+    })
+}
+
+/// Returns the record with the given sequence number, if it is still in the ring buffer.
+pub fn record(seq: u64) -> Option<Record> {
+    without_interrupts(|| BUFFER.lock().get(seq))
+}
+
+/// Logs a message at the given level, with the calling module as the target.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::_log($level, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Trace, $($arg)+));
+}
+
+#[test_case]
+fn test_message_truncation() {
+    use core::fmt::Write;
+
+    let mut record = Record::new(Level::Info, "test", format_args!(""));
+    for _ in 0..MESSAGE_CAPACITY {
+        let _ = record.write_str("€");
+    }
+    // the last character doesn't fit completely and is left out
+    assert_eq!(
+        record.message().len(),
+        MESSAGE_CAPACITY - MESSAGE_CAPACITY % 3
+    );
+    assert!(record.message().chars().all(|c| c == '€'));
+}
+
+#[test_case]
+fn test_ring_buffer_wraps() {
+    let mut buffer = RingBuffer::new();
+    for _ in 0..BUFFER_CAPACITY + 3 {
+        buffer.push(&mut Record::new(Level::Info, "test", format_args!("x")));
+    }
+    assert_eq!(buffer.oldest(), 3);
+    assert!(buffer.get(2).is_none());
+    assert_eq!(buffer.get(3).map(|record| record.seq), Some(3));
+    assert!(buffer.get(BUFFER_CAPACITY as u64 + 3).is_none());
+}
+
+#[test_case]
+fn test_filters() {
+    let mut filters = Filters::new();
+    filters
+        .set(Some("blog_os::task"), Level::Debug)
+        .expect("setting filter failed");
+    filters
+        .set(Some("blog_os::task::keyboard"), Level::Error)
+        .expect("setting filter failed");
+    assert_eq!(filters.level("blog_os::task::job"), Level::Debug);
+    assert_eq!(filters.level("blog_os::task::keyboard"), Level::Error);
+    assert_eq!(filters.level("blog_os::tasks"), Level::Info);
+    filters
+        .set(None, Level::Warn)
+        .expect("setting default level failed");
+    assert_eq!(filters.level("blog_os::memory"), Level::Warn);
+}
@@ -5,37 +5,85 @@
 #![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
+use alloc::string::String;
 use blog_os::task::spawner::SPAWNER;
-use blog_os::task::{executor::Executor, keyboard};
-use blog_os::{println, vga_buffer};
+use blog_os::task::{
+    executor::Executor, keyboard, mouse, serial_console, simple_executor::block_on, task_loader,
+};
+use blog_os::{
+    block::partition,
+    config,
+    drivers::{ahci, ata, pci, ps2, virtio},
+    fs::{self, initramfs},
+    interrupts, print, println,
+    serial::ComPort,
+    vga_buffer, warn,
+};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
-    use blog_os::allocator;
-    use blog_os::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
-
-    blog_os::init();
-
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    blog_os::init_kernel(boot_info);
+    initramfs::init();
+    pci::init();
+    ata::init();
+    ahci::init();
+    virtio::init();
+    block_on(partition::scan_devices());
+    #[cfg(feature = "framebuffer")]
+    blog_os::graphics::init(1024, 768).expect("framebuffer console initialization failed");
 
     #[cfg(test)]
     test_main();
 
     let mut executor = Executor::new();
 
+    SPAWNER.lock().add(keyboard::publish_key_events());
     SPAWNER.lock().add(keyboard::print_keypresses());
+    let console = config::get_parsed("console", Some(ComPort::Com1), |value| match value {
+        "none" => Some(None),
+        name => ComPort::from_name(name).map(Some),
+    });
+    if let Some(port) = console {
+        SPAWNER.lock().add(serial_console::run_shell(port));
+    }
+    if virtio::console::get().is_some() {
+        SPAWNER.lock().add(virtio::console::run_shell());
+    }
+    if virtio::rng::get().is_some() {
+        SPAWNER.lock().add(virtio::rng::feed_pool());
+    }
+    if ps2::devices().mouse {
+        SPAWNER.lock().add(mouse::move_pointer());
+    }
     SPAWNER.lock().add(vga_buffer::enable_cursor(0, 24));
+    interrupts::enable_cursor_updates();
+    SPAWNER.lock().add(print_motd());
+    if let Some(command) = config::get("init") {
+        SPAWNER.lock().add(run_init(command));
+    }
     executor.run();
 }
 
+/// Prints `/etc/motd` from the initramfs, if there is one.
+///
+/// Spawned after the shells, which attach to the console on their first poll, so that the
+/// message is mirrored to them too.
+async fn print_motd() {
+    if let Ok(motd) = fs::read("/etc/motd") {
+        print!("{}", String::from_utf8_lossy(&motd));
+    }
+}
+
+/// Runs the `init` command from the kernel command line like a command typed in the shell.
+async fn run_init(command: &'static str) {
+    if let Err(err) = task_loader::load_task(String::from(command)).await {
+        warn!("init command '{}' failed: {:?}", command, err);
+    }
+}
+
 /// This function is called on panic.
 #[cfg(not(test))]
 #[panic_handler]
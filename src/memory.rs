@@ -1,9 +1,19 @@
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use spin::{Mutex, MutexGuard};
 use x86_64::{
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB,
+    },
     PhysAddr, VirtAddr,
 };
 
+pub mod dma;
+pub mod inspect;
+pub mod mmio;
+pub mod stack;
+pub mod vma;
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -82,3 +92,164 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
         frame
     }
 }
+
+/// A frame allocator that hands out frames from the bootloader's memory map and
+/// recycles deallocated frames.
+///
+/// Freed frames are kept in a list that is threaded through the frames themselves, so
+/// neither allocation nor deallocation touches the heap.
+pub struct KernelFrameAllocator {
+    boot: BootInfoFrameAllocator,
+    free_list: Option<PhysFrame>,
+    physical_memory_offset: VirtAddr,
+}
+
+impl KernelFrameAllocator {
+    /// Returns a pointer to the start of the given frame in the physical memory mapping.
+    pub fn frame_ptr(&self, frame: PhysFrame) -> *mut u8 {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    /// Allocates a frame and fills it with zeros.
+    pub fn allocate_zeroed_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.allocate_frame()?;
+        unsafe { core::ptr::write_bytes(self.frame_ptr(frame), 0, 4096) };
+        Some(frame)
+    }
+
+    /// Allocates `count` physically contiguous frames, fills them with zeros and returns
+    /// the first one; used for device memory like DMA rings.
+    ///
+    /// Takes the frames from the bootloader's memory map, which yields frames in ascending
+    /// order; frames skipped while looking for a contiguous run are recycled, as is an
+    /// incomplete run once memory is exhausted.
+    pub fn allocate_contiguous(&mut self, count: u64) -> Option<PhysFrame> {
+        let mut first = self.boot.allocate_frame()?;
+        let mut length = 1;
+        while length < count {
+            let frame = match self.boot.allocate_frame() {
+                Some(frame) => frame,
+                None => {
+                    for allocated in PhysFrame::range(first, first + length) {
+                        unsafe { self.deallocate_frame(allocated) };
+                    }
+                    return None;
+                }
+            };
+            if frame != first + length {
+                for skipped in PhysFrame::range(first, first + length) {
+                    unsafe { self.deallocate_frame(skipped) };
+                }
+                first = frame;
+                length = 0;
+            }
+            length += 1;
+        }
+        let size = (count * 4096) as usize;
+        unsafe { core::ptr::write_bytes(self.frame_ptr(first), 0, size) };
+        Some(first)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        match self.free_list {
+            Some(frame) => {
+                let next = unsafe { *(self.frame_ptr(frame) as *const u64) };
+                self.free_list = if next == 0 {
+                    None
+                } else {
+                    Some(PhysFrame::containing_address(PhysAddr::new(next)))
+                };
+                Some(frame)
+            }
+            None => self.boot.allocate_frame(),
+        }
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for KernelFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let next = self.free_list.map_or(0, |next| next.start_address().as_u64());
+        *(self.frame_ptr(frame) as *mut u64) = next;
+        self.free_list = Some(frame);
+    }
+}
+
+/// The active page table and frame allocator, shared by all parts of the kernel that
+/// change mappings after boot.
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: KernelFrameAllocator,
+}
+
+static KERNEL_MEMORY: OnceCell<Mutex<KernelMemory>> = OnceCell::uninit();
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+static MEMORY_MAP: OnceCell<&'static MemoryMap> = OnceCell::uninit();
+
+/// Hands the page table and frame allocator created at boot over to the kernel.
+///
+/// Code holding the kernel memory lock must not allocate on the heap, since heap growth
+/// is served by the page fault handler, which needs the same lock.
+pub fn init_kernel_memory(
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+    physical_memory_offset: VirtAddr,
+) {
+    PHYSICAL_MEMORY_OFFSET
+        .try_init_once(|| physical_memory_offset)
+        .expect("init_kernel_memory should only be called once");
+    MEMORY_MAP
+        .try_init_once(|| frame_allocator.memory_map)
+        .expect("init_kernel_memory should only be called once");
+
+    let memory = KernelMemory {
+        mapper,
+        frame_allocator: KernelFrameAllocator {
+            boot: frame_allocator,
+            free_list: None,
+            physical_memory_offset,
+        },
+    };
+    KERNEL_MEMORY
+        .try_init_once(|| Mutex::new(memory))
+        .expect("init_kernel_memory should only be called once");
+}
+
+/// Returns the virtual address at which the complete physical memory is mapped.
+///
+/// Panics if `init_kernel_memory` was not called.
+pub fn physical_memory_offset() -> VirtAddr {
+    *PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .expect("kernel memory not initialized")
+}
+
+/// Returns the physical memory map passed by the bootloader.
+///
+/// Panics if `init_kernel_memory` was not called.
+pub fn memory_regions() -> impl Iterator<Item = &'static MemoryRegion> {
+    MEMORY_MAP
+        .try_get()
+        .expect("kernel memory not initialized")
+        .iter()
+}
+
+/// Runs `f` with exclusive access to the kernel's page table and frame allocator.
+///
+/// Panics if `init_kernel_memory` was not called.
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> R {
+    use x86_64::instructions::interrupts;
+
+    let memory = KERNEL_MEMORY
+        .try_get()
+        .expect("kernel memory not initialized");
+    interrupts::without_interrupts(|| f(&mut memory.lock()))
+}
+
+/// Returns the kernel memory if it is initialized and not locked.
+///
+/// Used from exception handlers, which can't wait for the lock.
+pub(crate) fn try_kernel_memory() -> Option<MutexGuard<'static, KernelMemory>> {
+    KERNEL_MEMORY.try_get().ok()?.try_lock()
+}
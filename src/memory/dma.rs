@@ -0,0 +1,54 @@
+//! Frames shared with devices that transfer data by DMA.
+//!
+//! Drivers copy the buffers passed to them through bounce frames, so that the buffers don't
+//! need to be physically contiguous. A control frame holds the driver's command structures.
+
+use super::{physical_memory_offset, with_kernel_memory};
+use core::ptr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+pub const FRAME_SIZE: usize = 4096;
+
+/// Returns a pointer to the start of a frame in the physical memory mapping.
+pub fn frame_ptr(frame: PhysFrame) -> *mut u8 {
+    (physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+/// Allocates a zeroed control frame and `N` bounce frames, or frees the frames allocated
+/// so far and returns `None` if memory is exhausted.
+pub fn allocate_frames<const N: usize>() -> Option<(PhysFrame, [PhysFrame; N])> {
+    with_kernel_memory(|memory| {
+        let allocator = &mut memory.frame_allocator;
+        let control = allocator.allocate_zeroed_frame()?;
+        let mut bounce = [control; N];
+        let mut allocated = 0;
+        for slot in bounce.iter_mut() {
+            match allocator.allocate_frame() {
+                Some(frame) => *slot = frame,
+                None => break,
+            }
+            allocated += 1;
+        }
+        if allocated < N {
+            for &frame in bounce[..allocated].iter().chain(Some(&control)) {
+                unsafe { allocator.deallocate_frame(frame) };
+            }
+            return None;
+        }
+        Some((control, bounce))
+    })
+}
+
+/// Copies the first `buf.len()` bytes of the bounce frames into `buf`.
+pub fn copy_from_frames(frames: &[PhysFrame], buf: &mut [u8]) {
+    for (frame, part) in frames.iter().zip(buf.chunks_mut(FRAME_SIZE)) {
+        unsafe { ptr::copy_nonoverlapping(frame_ptr(*frame), part.as_mut_ptr(), part.len()) };
+    }
+}
+
+/// Copies `buf` to the start of the bounce frames.
+pub fn copy_to_frames(frames: &[PhysFrame], buf: &[u8]) {
+    for (frame, part) in frames.iter().zip(buf.chunks(FRAME_SIZE)) {
+        unsafe { ptr::copy_nonoverlapping(part.as_ptr(), frame_ptr(*frame), part.len()) };
+    }
+}
@@ -0,0 +1,248 @@
+use super::physical_memory_offset;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{PageTable, PageTableFlags},
+    PhysAddr, VirtAddr,
+};
+
+/// The size of the pages a range is mapped with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl MappingSize {
+    pub fn bytes(self) -> u64 {
+        match self {
+            MappingSize::Size4KiB => 4096,
+            MappingSize::Size2MiB => 2 * 1024 * 1024,
+            MappingSize::Size1GiB => 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// A run of virtually and physically contiguous pages with identical flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub start: VirtAddr,
+    /// The first address after the range.
+    pub end: VirtAddr,
+    pub phys_start: PhysAddr,
+    /// The flags of the lowest-level entries, without the accessed and dirty bits.
+    pub flags: PageTableFlags,
+    pub page_size: MappingSize,
+}
+
+impl MappedRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Extends `self` by `next` if it directly continues the range.
+    fn try_merge(&mut self, next: &MappedRange) -> bool {
+        let contiguous = self.end == next.start
+            && self.phys_start + self.len() == next.phys_start
+            && self.flags == next.flags
+            && self.page_size == next.page_size;
+        if contiguous {
+            self.end = next.end;
+        }
+        contiguous
+    }
+}
+
+/// The result of translating a virtual address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub phys_addr: PhysAddr,
+    pub flags: PageTableFlags,
+    pub page_size: MappingSize,
+}
+
+/// Returns a reference to the page table in the given physical frame.
+///
+/// Page table frames are never freed by the mapper, so reading them without holding the
+/// kernel memory lock can at worst observe an entry that is being changed.
+unsafe fn table(phys: PhysAddr) -> &'static PageTable {
+    let virt = physical_memory_offset() + phys.as_u64();
+    &*virt.as_ptr::<PageTable>()
+}
+
+fn virt_addr(p4: usize, p3: usize, p2: usize, p1: usize) -> VirtAddr {
+    VirtAddr::new_truncate(
+        ((p4 as u64) << 39) | ((p3 as u64) << 30) | ((p2 as u64) << 21) | ((p1 as u64) << 12),
+    )
+}
+
+/// Walks the active page tables and calls `f` for every mapped range, in ascending
+/// virtual address order.
+///
+/// Requires `init_kernel_memory` to have been called.
+pub fn for_each_mapped_range(mut f: impl FnMut(&MappedRange)) {
+    let (level_4_frame, _) = Cr3::read();
+    let mut current: Option<MappedRange> = None;
+    let mut push = |range: MappedRange| {
+        if let Some(current) = current.as_mut() {
+            if current.try_merge(&range) {
+                return;
+            }
+        }
+        if let Some(previous) = current.replace(range) {
+            f(&previous);
+        }
+    };
+
+    let level_4 = unsafe { table(level_4_frame.start_address()) };
+    for (p4, entry) in level_4.iter().enumerate() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let level_3 = unsafe { table(entry.addr()) };
+        for (p3, entry) in level_3.iter().enumerate() {
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            if flags.contains(PageTableFlags::HUGE_PAGE) {
+                push(range(
+                    virt_addr(p4, p3, 0, 0),
+                    entry.addr(),
+                    flags,
+                    MappingSize::Size1GiB,
+                ));
+                continue;
+            }
+            let level_2 = unsafe { table(entry.addr()) };
+            for (p2, entry) in level_2.iter().enumerate() {
+                let flags = entry.flags();
+                if !flags.contains(PageTableFlags::PRESENT) {
+                    continue;
+                }
+                if flags.contains(PageTableFlags::HUGE_PAGE) {
+                    push(range(
+                        virt_addr(p4, p3, p2, 0),
+                        entry.addr(),
+                        flags,
+                        MappingSize::Size2MiB,
+                    ));
+                    continue;
+                }
+                let level_1 = unsafe { table(entry.addr()) };
+                for (p1, entry) in level_1.iter().enumerate() {
+                    let flags = entry.flags();
+                    if flags.contains(PageTableFlags::PRESENT) {
+                        push(range(
+                            virt_addr(p4, p3, p2, p1),
+                            entry.addr(),
+                            flags,
+                            MappingSize::Size4KiB,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(last) = current {
+        f(&last);
+    }
+}
+
+fn range(
+    start: VirtAddr,
+    phys_start: PhysAddr,
+    flags: PageTableFlags,
+    page_size: MappingSize,
+) -> MappedRange {
+    MappedRange {
+        start,
+        end: start + page_size.bytes(),
+        phys_start,
+        // the accessed and dirty bits would prevent merging otherwise identical ranges
+        flags: flags - (PageTableFlags::ACCESSED | PageTableFlags::DIRTY),
+        page_size,
+    }
+}
+
+/// Translates a virtual address to the physical address it is mapped to.
+///
+/// Returns `None` if the address isn't mapped or `init_kernel_memory` hasn't been called
+/// yet, so that exception handlers can use it at any time.
+pub fn translate(addr: VirtAddr) -> Option<Translation> {
+    super::PHYSICAL_MEMORY_OFFSET.try_get().ok()?;
+    let (level_4_frame, _) = Cr3::read();
+    let mut table_addr = level_4_frame.start_address();
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let sizes = [
+        None,
+        Some(MappingSize::Size1GiB),
+        Some(MappingSize::Size2MiB),
+        Some(MappingSize::Size4KiB),
+    ];
+
+    for (level, &index) in indices.iter().enumerate() {
+        let page_table = unsafe { table(table_addr) };
+        let entry = &page_table[index];
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        let is_leaf = level == 3 || (level > 0 && flags.contains(PageTableFlags::HUGE_PAGE));
+        if is_leaf {
+            let page_size = sizes[level]?;
+            let offset = addr.as_u64() & (page_size.bytes() - 1);
+            return Some(Translation {
+                phys_addr: entry.addr() + offset,
+                flags,
+                page_size,
+            });
+        }
+        table_addr = entry.addr();
+    }
+    None
+}
+
+#[test_case]
+fn test_adjacent_ranges_are_merged() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let mut first = range(
+        VirtAddr::new(0x1000),
+        PhysAddr::new(0x8000),
+        flags | PageTableFlags::ACCESSED,
+        MappingSize::Size4KiB,
+    );
+    let second = range(
+        VirtAddr::new(0x2000),
+        PhysAddr::new(0x9000),
+        flags | PageTableFlags::DIRTY,
+        MappingSize::Size4KiB,
+    );
+    assert!(first.try_merge(&second));
+    assert_eq!(first.end, VirtAddr::new(0x3000));
+    assert_eq!(first.len(), 0x2000);
+
+    // not physically contiguous
+    let third = range(
+        VirtAddr::new(0x3000),
+        PhysAddr::new(0x20000),
+        flags,
+        MappingSize::Size4KiB,
+    );
+    assert!(!first.try_merge(&third));
+    // different flags
+    let fourth = range(
+        VirtAddr::new(0x3000),
+        PhysAddr::new(0xa000),
+        PageTableFlags::PRESENT,
+        MappingSize::Size4KiB,
+    );
+    assert!(!first.try_merge(&fourth));
+    assert_eq!(first.end, VirtAddr::new(0x3000));
+}
@@ -0,0 +1,70 @@
+use super::{
+    vma::{self, Area, AreaError, AreaKind, Backing},
+    with_kernel_memory,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    structures::paging::{mapper::MapToError, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// The start of the virtual region device memory is mapped to.
+pub const MMIO_REGION_START: u64 = 0x_6666_0000_0000;
+/// The size of the virtual region device memory is mapped to.
+pub const MMIO_REGION_SIZE: u64 = 1 << 36; // 64 GiB
+
+const PAGE_SIZE: u64 = 4096;
+
+static NEXT_MAPPING: AtomicU64 = AtomicU64::new(MMIO_REGION_START);
+
+/// Maps `size` bytes of device memory starting at `phys` and returns their virtual address.
+///
+/// The pages are mapped uncached and registered as an `Mmio` area with the given name.
+/// Requires `init_kernel_memory` to have been called.
+pub fn map(name: &'static str, phys: PhysAddr, size: u64) -> Result<VirtAddr, AreaError> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let offset = phys - first_frame.start_address();
+    let mapped_size = x86_64::align_up(offset + size.max(1), PAGE_SIZE);
+
+    let start = NEXT_MAPPING.fetch_add(mapped_size, Ordering::Relaxed);
+    if start + mapped_size > MMIO_REGION_START + MMIO_REGION_SIZE {
+        return Err(AreaError::Exhausted);
+    }
+    let start = VirtAddr::new(start);
+    let area = Area {
+        name,
+        start,
+        end: start + mapped_size,
+        kind: AreaKind::Mmio,
+        backing: Backing::Eager,
+        flags: PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::WRITE_THROUGH
+            | PageTableFlags::NO_CACHE,
+    };
+    vma::register_mapped(area)?;
+
+    let mapped = with_kernel_memory(|memory| -> Result<(), MapToError<Size4KiB>> {
+        for i in 0..mapped_size / PAGE_SIZE {
+            let page = Page::<Size4KiB>::containing_address(start + i * PAGE_SIZE);
+            let frame = first_frame + i;
+            unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, area.flags, &mut memory.frame_allocator)?
+                    .flush()
+            };
+        }
+        Ok(())
+    });
+    if let Err(err) = mapped {
+        vma::unregister(start);
+        return Err(AreaError::Map(err));
+    }
+    Ok(start + offset)
+}
+
+/// Unmaps device memory mapped by `map`.
+pub fn unmap(addr: VirtAddr) {
+    vma::unregister(addr.align_down(PAGE_SIZE));
+}
@@ -0,0 +1,118 @@
+use super::vma::{self, Area, AreaError, AreaKind, Backing};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+/// The start of the virtual region kernel stacks are allocated from.
+pub const KERNEL_STACK_REGION_START: u64 = 0x_5555_0000_0000;
+/// The size of the virtual region kernel stacks are allocated from.
+pub const KERNEL_STACK_REGION_SIZE: u64 = 1 << 30; // 1 GiB
+
+const PAGE_SIZE: u64 = 4096;
+
+static NEXT_STACK: AtomicU64 = AtomicU64::new(KERNEL_STACK_REGION_START);
+
+/// A kernel stack with an unmapped guard page directly below it.
+#[derive(Debug)]
+pub struct KernelStack {
+    name: &'static str,
+    guard: VirtAddr,
+    bottom: VirtAddr,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The address of the guard page; any access to it is reported as a stack overflow.
+    pub fn guard_page(&self) -> VirtAddr {
+        self.guard
+    }
+
+    /// The lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    /// The initial stack pointer (the stack grows downwards).
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+}
+
+/// Allocates a stack of `pages` pages in the kernel stack region.
+///
+/// The stack is mapped eagerly: a page fault on the current stack can't be resolved,
+/// because the CPU has to push the exception frame onto that same stack. The guard page
+/// is registered as a guard area named after the stack, so an overflow is reported with
+/// the name of the stack.
+pub fn allocate(name: &'static str, pages: u64) -> Result<KernelStack, AreaError> {
+    const REGION_END: u64 = KERNEL_STACK_REGION_START + KERNEL_STACK_REGION_SIZE;
+
+    let size = pages
+        .checked_add(1)
+        .and_then(|pages| pages.checked_mul(PAGE_SIZE))
+        .ok_or(AreaError::Exhausted)?;
+    // the counter is only advanced if the stack fits
+    let start = NEXT_STACK
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            next.checked_add(size).filter(|&end| end <= REGION_END)
+        })
+        .map_err(|_| AreaError::Exhausted)?;
+
+    let guard = VirtAddr::new(start);
+    let bottom = guard + PAGE_SIZE;
+    let top = guard + size;
+
+    if let Err(err) = register_areas(name, guard, bottom, top) {
+        // give the range back, unless another stack was allocated after it meanwhile
+        let end = start + size;
+        let _ = NEXT_STACK.compare_exchange(end, start, Ordering::Relaxed, Ordering::Relaxed);
+        return Err(err);
+    }
+
+    Ok(KernelStack {
+        name,
+        guard,
+        bottom,
+        top,
+    })
+}
+
+/// Registers the guard page and the eagerly mapped stack above it.
+fn register_areas(
+    name: &'static str,
+    guard: VirtAddr,
+    bottom: VirtAddr,
+    top: VirtAddr,
+) -> Result<(), AreaError> {
+    vma::register(Area {
+        name,
+        start: guard,
+        end: bottom,
+        kind: AreaKind::Guard,
+        backing: Backing::Lazy,
+        flags: PageTableFlags::empty(),
+    })?;
+    if let Err(err) = vma::register(Area {
+        name,
+        start: bottom,
+        end: top,
+        kind: AreaKind::Stack,
+        backing: Backing::Eager,
+        flags: PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+    }) {
+        vma::unregister(guard);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Unmaps the given stack and frees its frames.
+///
+/// The virtual address range is not reused, so a stale pointer into the stack faults.
+pub fn free(stack: KernelStack) {
+    vma::unregister(stack.bottom);
+    vma::unregister(stack.guard);
+}
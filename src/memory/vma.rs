@@ -0,0 +1,339 @@
+use super::{try_kernel_memory, with_kernel_memory};
+use core::fmt;
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            mapper::MapToError, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+/// The maximum number of virtual memory areas.
+///
+/// The registry is a fixed-size array so that registering an area never allocates, which
+/// would deadlock if the allocation had to grow the heap through a page fault.
+pub const MAX_AREAS: usize = 64;
+
+/// What a virtual memory area is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaKind {
+    Heap,
+    Stack,
+    User,
+    /// An area that must never be accessed, e.g. below a stack.
+    Guard,
+    /// Device memory; its frames don't belong to the frame allocator.
+    Mmio,
+}
+
+/// How the pages of a virtual memory area are backed by physical frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// All pages are mapped when the area is created.
+    Eager,
+    /// Pages are mapped to zeroed frames on first access.
+    Lazy,
+}
+
+/// A range of virtual memory with uniform use and access rights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub name: &'static str,
+    /// The first address of the area (page aligned).
+    pub start: VirtAddr,
+    /// The first address after the area (page aligned).
+    pub end: VirtAddr,
+    pub kind: AreaKind,
+    pub backing: Backing,
+    /// The flags pages of the area are mapped with.
+    pub flags: PageTableFlags,
+}
+
+impl Area {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    fn overlaps(&self, other: &Area) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum AreaError {
+    Unaligned,
+    Overlap,
+    RegistryFull,
+    /// The virtual region the area should be placed in is used up.
+    Exhausted,
+    Map(MapToError<Size4KiB>),
+}
+
+impl fmt::Display for AreaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AreaError::Unaligned => write!(f, "area is not page aligned"),
+            AreaError::Overlap => write!(f, "area overlaps an existing area"),
+            AreaError::RegistryFull => write!(f, "too many virtual memory areas"),
+            AreaError::Exhausted => write!(f, "virtual region exhausted"),
+            AreaError::Map(err) => write!(f, "mapping failed: {:?}", err),
+        }
+    }
+}
+
+static AREAS: Mutex<[Option<Area>; MAX_AREAS]> = Mutex::new([None; MAX_AREAS]);
+
+/// Adds an area to the registry, mapping all of its pages if it is eagerly backed.
+pub fn register(area: Area) -> Result<(), AreaError> {
+    insert(area)?;
+    if area.backing == Backing::Eager && area.kind != AreaKind::Guard {
+        if let Err(err) = map_area(&area) {
+            unregister(area.start);
+            return Err(AreaError::Map(err));
+        }
+    }
+    Ok(())
+}
+
+/// Adds an area whose pages were already mapped by someone else.
+pub fn register_mapped(area: Area) -> Result<(), AreaError> {
+    insert(area)
+}
+
+fn insert(area: Area) -> Result<(), AreaError> {
+    if !area.start.is_aligned(4096u64) || !area.end.is_aligned(4096u64) || area.end <= area.start {
+        return Err(AreaError::Unaligned);
+    }
+
+    interrupts::without_interrupts(|| -> Result<(), AreaError> {
+        let mut areas = AREAS.lock();
+        if areas.iter().flatten().any(|other| other.overlaps(&area)) {
+            return Err(AreaError::Overlap);
+        }
+        let slot = areas
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or(AreaError::RegistryFull)?;
+        areas[slot] = Some(area);
+        Ok(())
+    })
+}
+
+/// Removes the area starting at `start`, unmapping its pages and freeing their frames.
+///
+/// The frames of `Mmio` areas are device memory and are only unmapped.
+pub fn unregister(start: VirtAddr) -> Option<Area> {
+    let area = interrupts::without_interrupts(|| {
+        let mut areas = AREAS.lock();
+        let slot = areas
+            .iter_mut()
+            .find(|slot| slot.map_or(false, |area| area.start == start))?;
+        slot.take()
+    })?;
+
+    with_kernel_memory(|memory| {
+        for page in area.pages() {
+            if let Ok((frame, flush)) = memory.mapper.unmap(page) {
+                flush.flush();
+                if area.kind != AreaKind::Mmio {
+                    unsafe { memory.frame_allocator.deallocate_frame(frame) };
+                }
+            }
+        }
+    });
+    Some(area)
+}
+
+/// Returns the area containing the given address.
+pub fn find(addr: VirtAddr) -> Option<Area> {
+    interrupts::without_interrupts(|| {
+        AREAS
+            .lock()
+            .iter()
+            .flatten()
+            .find(|area| area.contains(addr))
+            .copied()
+    })
+}
+
+/// Like `find`, but returns `None` instead of waiting if the registry is locked.
+///
+/// Used from exception handlers.
+pub(crate) fn try_find(addr: VirtAddr) -> Option<Area> {
+    AREAS
+        .try_lock()?
+        .iter()
+        .flatten()
+        .find(|area| area.contains(addr))
+        .copied()
+}
+
+/// Calls `f` for every registered area, ordered by start address.
+pub fn for_each(mut f: impl FnMut(&Area)) {
+    let mut areas = interrupts::without_interrupts(|| *AREAS.lock());
+    areas.sort_unstable_by_key(|area| area.map_or(u64::MAX, |area| area.start.as_u64()));
+    for area in areas.iter().flatten() {
+        f(area);
+    }
+}
+
+fn map_area(area: &Area) -> Result<(), MapToError<Size4KiB>> {
+    with_kernel_memory(|memory| -> Result<(), MapToError<Size4KiB>> {
+        for page in area.pages() {
+            let frame = memory
+                .frame_allocator
+                .allocate_zeroed_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, area.flags, &mut memory.frame_allocator)?
+                    .flush()
+            };
+        }
+        Ok(())
+    })
+}
+
+/// An access the page fault handler could not resolve.
+#[derive(Debug, Clone, Copy)]
+pub struct Violation {
+    pub reason: &'static str,
+    /// The area containing the faulting address, if any.
+    pub area: Option<Area>,
+    /// Whether the access was made from user mode.
+    pub user_mode: bool,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if let Some(area) = self.area {
+            write!(
+                f,
+                " in area '{}' ({:?}, {:#x}..{:#x})",
+                area.name,
+                area.kind,
+                area.start.as_u64(),
+                area.end.as_u64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of trying to resolve a page fault.
+#[derive(Debug, Clone, Copy)]
+pub enum FaultOutcome {
+    /// A frame was mapped; the faulting instruction can be restarted.
+    Resolved,
+    Violation(Violation),
+}
+
+/// A handler for violations caused by user mode code, e.g. to signal the process.
+///
+/// Returns `true` if the fault was dealt with and the handler should return.
+pub type UserFaultHandler = fn(addr: VirtAddr, violation: &Violation) -> bool;
+
+static USER_FAULT_HANDLER: Mutex<Option<UserFaultHandler>> = Mutex::new(None);
+
+/// Sets the handler that is given user mode violations instead of treating them as fatal.
+pub fn set_user_fault_handler(handler: UserFaultHandler) {
+    interrupts::without_interrupts(|| *USER_FAULT_HANDLER.lock() = Some(handler));
+}
+
+/// Passes a user mode violation to the user fault handler, if one is set.
+pub(crate) fn signal_user_fault(addr: VirtAddr, violation: &Violation) -> bool {
+    let handler = match USER_FAULT_HANDLER.try_lock() {
+        Some(handler) => *handler,
+        None => None,
+    };
+    handler.map_or(false, |handler| handler(addr, violation))
+}
+
+/// Tries to resolve a page fault at `addr` by mapping a frame into a lazily backed area.
+///
+/// Called from the page fault handler, so it must not block or allocate.
+pub(crate) fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> FaultOutcome {
+    let user_mode = error_code.contains(PageFaultErrorCode::USER_MODE);
+    let violation = |reason: &'static str, area: Option<Area>| {
+        FaultOutcome::Violation(Violation {
+            reason,
+            area,
+            user_mode,
+        })
+    };
+
+    let area = match AREAS.try_lock() {
+        Some(areas) => areas.iter().flatten().find(|area| area.contains(addr)).copied(),
+        None => return violation("area registry locked", None),
+    };
+    let area = match area {
+        Some(area) => area,
+        None => return violation("access outside of any area", None),
+    };
+
+    if area.kind == AreaKind::Guard {
+        return violation("stack overflow (guard page hit)", Some(area));
+    }
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return violation("protection violation", Some(area));
+    }
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !area.flags.contains(PageTableFlags::WRITABLE)
+    {
+        return violation("write to read-only area", Some(area));
+    }
+    if user_mode && !area.flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+        return violation("user access to kernel area", Some(area));
+    }
+    if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+        && area.flags.contains(PageTableFlags::NO_EXECUTE)
+    {
+        return violation("instruction fetch from non-executable area", Some(area));
+    }
+    if area.backing == Backing::Eager {
+        return violation("missing page in eagerly mapped area", Some(area));
+    }
+
+    let mut memory = match try_kernel_memory() {
+        Some(memory) => memory,
+        None => return violation("kernel memory locked", Some(area)),
+    };
+    let memory = &mut *memory;
+    let frame = match memory.frame_allocator.allocate_zeroed_frame() {
+        Some(frame) => frame,
+        None => return violation("out of physical memory", Some(area)),
+    };
+    let page = Page::<Size4KiB>::containing_address(addr);
+    match unsafe {
+        memory
+            .mapper
+            .map_to(page, frame, area.flags, &mut memory.frame_allocator)
+    } {
+        Ok(flush) => {
+            flush.flush();
+            FaultOutcome::Resolved
+        }
+        Err(MapToError::PageAlreadyMapped(_)) => {
+            // another fault mapped the page in the meantime
+            unsafe { memory.frame_allocator.deallocate_frame(frame) };
+            FaultOutcome::Resolved
+        }
+        Err(_) => {
+            unsafe { memory.frame_allocator.deallocate_frame(frame) };
+            violation("failed to map page", Some(area))
+        }
+    }
+}
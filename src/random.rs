@@ -0,0 +1,194 @@
+//! The kernel entropy pool, fed by hardware random number generators like virtio-rng.
+//!
+//! Input is mixed into a 256-bit state with SipHash rounds. Output is generated from the
+//! state and a counter. After every request, the state is replaced by a one-way function
+//! of itself, so that earlier output can't be computed from it. The construction is not a
+//! vetted cryptographic generator: the output is good for randomization, but shouldn't be
+//! used as key material.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// The size of the state, and the most entropy the pool can hold.
+pub const POOL_BITS: usize = 256;
+/// Sources are asked for more input while the pool holds less entropy.
+pub const LOW_WATERMARK: usize = 128;
+
+struct Pool {
+    state: [u64; 4],
+    counter: u64,
+    /// The estimated entropy of the state in bits.
+    entropy: usize,
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    // the SipHash initialization constants
+    state: [
+        0x736f_6d65_7073_6575,
+        0x646f_7261_6e64_6f6d,
+        0x6c79_6765_6e65_7261,
+        0x7465_6462_7974_6573,
+    ],
+    counter: 0,
+    entropy: 0,
+});
+
+static LOW_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// A SipHash round.
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+/// Mixes a word into the state like SipHash mixes a message word.
+fn absorb(state: &mut [u64; 4], word: u64) {
+    state[3] ^= word;
+    sip_round(state);
+    sip_round(state);
+    state[0] ^= word;
+}
+
+/// Replaces the state with a one-way function of itself.
+///
+/// The SipHash rounds alone are a permutation, which could be run backwards to the earlier
+/// state. Adding the permuted state to the original one, like the feed-forward of a
+/// Davies-Meyer compression function, makes that infeasible.
+fn stir(state: &mut [u64; 4], counter: u64) {
+    let mut v = *state;
+    v[3] ^= counter;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    for (word, permuted) in state.iter_mut().zip(v.iter()) {
+        *word ^= permuted;
+    }
+}
+
+fn with_pool<R>(f: impl FnOnce(&mut Pool) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut POOL.lock()))
+}
+
+/// Mixes `data` into the pool, crediting it with `bits` bits of entropy.
+///
+/// Input of unknown quality, e.g. timestamps, can be added with 0 bits.
+pub fn add_entropy(data: &[u8], bits: usize) {
+    with_pool(|pool| {
+        for chunk in data.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            absorb(&mut pool.state, u64::from_le_bytes(word));
+        }
+        pool.entropy = (pool.entropy + bits).min(POOL_BITS);
+    });
+}
+
+/// Returns the estimated entropy in the pool in bits.
+pub fn entropy() -> usize {
+    with_pool(|pool| pool.entropy)
+}
+
+/// Fills `buf` with random bytes, debiting the pool by the number of bits produced.
+pub fn fill_bytes(buf: &mut [u8]) {
+    let low = with_pool(|pool| {
+        for chunk in buf.chunks_mut(8) {
+            pool.counter = pool.counter.wrapping_add(1);
+            let mut v = pool.state;
+            v[1] ^= pool.counter;
+            for _ in 0..4 {
+                sip_round(&mut v);
+            }
+            let output = (v[0] ^ v[1] ^ v[2] ^ v[3]).to_le_bytes();
+            chunk.copy_from_slice(&output[..chunk.len()]);
+        }
+        // stir, so that the state doesn't reveal the output
+        let counter = pool.counter;
+        stir(&mut pool.state, counter);
+        pool.entropy = pool.entropy.saturating_sub(buf.len() * 8);
+        pool.entropy < LOW_WATERMARK
+    });
+    if low {
+        LOW_WAKER.wake();
+    }
+}
+
+/// Returns a random number.
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns a future that resolves once the pool's entropy is below `LOW_WATERMARK`;
+/// used by the tasks feeding the pool.
+pub fn low_entropy() -> LowEntropy {
+    LowEntropy { _private: () }
+}
+
+pub struct LowEntropy {
+    _private: (),
+}
+
+impl Future for LowEntropy {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // fast path
+        if entropy() < LOW_WATERMARK {
+            return Poll::Ready(());
+        }
+
+        LOW_WAKER.register(&cx.waker());
+        if entropy() < LOW_WATERMARK {
+            LOW_WAKER.take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[test_case]
+fn test_output_changes() {
+    add_entropy(b"test input", 0);
+    let first = next_u64();
+    let second = next_u64();
+    assert_ne!(first, second);
+
+    let mut bytes = [0; 13];
+    fill_bytes(&mut bytes);
+    assert!(bytes.iter().any(|&byte| byte != 0));
+}
+
+#[test_case]
+fn test_stir_depends_on_state_and_counter() {
+    let state = [1, 2, 3, 4];
+    let mut stirred = state;
+    stir(&mut stirred, 1);
+    assert_ne!(stirred, state);
+    let mut other = state;
+    stir(&mut other, 2);
+    assert_ne!(other, stirred);
+}
+
+#[test_case]
+fn test_entropy_accounting() {
+    add_entropy(&[0; 64], 2 * POOL_BITS);
+    assert_eq!(entropy(), POOL_BITS);
+    fill_bytes(&mut [0; 8]);
+    assert_eq!(entropy(), POOL_BITS - 64);
+}
@@ -1,19 +1,205 @@
+use crate::drivers::uart::{
+    InterruptCause, LineConfig, Uart, INTERRUPT_RECEIVED, INTERRUPT_TRANSMIT_EMPTY,
+};
+use crate::interrupts::irq::IrqReturn;
+use conquer_once::spin::OnceCell;
+use core::{
+    fmt,
+    pin::Pin,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
 use lazy_static::lazy_static;
 use spin::Mutex;
-use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+
+/// One of the four standard PC serial ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    pub fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3f8,
+            ComPort::Com2 => 0x2f8,
+            ComPort::Com3 => 0x3e8,
+            ComPort::Com4 => 0x2e8,
+        }
+    }
+
+    /// The IRQ line of the port; COM1 and COM3, and COM2 and COM4 share a line.
+    pub fn irq(self) -> u8 {
+        match self {
+            ComPort::Com1 | ComPort::Com3 => 4,
+            ComPort::Com2 | ComPort::Com4 => 3,
+        }
+    }
+
+    /// Parses a port name like `com2`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|port| name.eq_ignore_ascii_case(port.name()))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ComPort::Com1 => "COM1",
+            ComPort::Com2 => "COM2",
+            ComPort::Com3 => "COM3",
+            ComPort::Com4 => "COM4",
+        }
+    }
+
+    fn from_index(index: u8) -> Self {
+        Self::ALL[index as usize]
+    }
+}
+
+impl fmt::Display for ComPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    NotPresent(ComPort),
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerialError::NotPresent(port) => write!(f, "{} not present", port),
+        }
+    }
+}
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
+    /// COM1, which is always assumed to be present, as the test runner reports through it.
+    pub static ref SERIAL1: Mutex<Uart> = {
+        let mut serial_port = unsafe { Uart::new(ComPort::Com1.base()) };
+        serial_port.init(&LineConfig::DEFAULT);
         Mutex::new(serial_port)
     };
 }
 
+/// COM2 to COM4, once `probe_ports` has found them.
+static OTHER_PORTS: [Mutex<Option<Uart>>; 3] =
+    [Mutex::new(None), Mutex::new(None), Mutex::new(None)];
+
+/// The port kernel logs are written to.
+static LOG_PORT: AtomicU8 = AtomicU8::new(ComPort::Com1 as u8);
+
+/// The port the console is attached to; only meaningful while `CONSOLE` is set.
+static CONSOLE_PORT: AtomicU8 = AtomicU8::new(ComPort::Com1 as u8);
+
+/// Probes COM2 to COM4 and initializes the ports that are present with the default line
+/// configuration.
+pub fn probe_ports() {
+    for (slot, &port) in OTHER_PORTS.iter().zip(&ComPort::ALL[1..]) {
+        let mut uart = unsafe { Uart::new(port.base()) };
+        let present = uart.probe();
+        if present {
+            uart.init(&LineConfig::DEFAULT);
+        }
+        interrupts::without_interrupts(|| *slot.lock() = if present { Some(uart) } else { None });
+    }
+}
+
+/// Returns whether the port was found by `probe_ports`.
+pub fn is_present(port: ComPort) -> bool {
+    with_port(port, |_| ()).is_some()
+}
+
+/// Calls `f` with the given port, if it is present.
+pub fn with_port<T>(port: ComPort, f: impl FnOnce(&mut Uart) -> T) -> Option<T> {
+    interrupts::without_interrupts(|| match port {
+        ComPort::Com1 => Some(f(&mut SERIAL1.lock())),
+        _ => OTHER_PORTS[port as usize - 1].lock().as_mut().map(f),
+    })
+}
+
+/// Like `with_port`, but returns `None` instead of waiting if the port is locked.
+///
+/// Used from the interrupt handler and by the log sink.
+fn try_with_port<T>(port: ComPort, f: impl FnOnce(&mut Uart) -> T) -> Option<T> {
+    match port {
+        ComPort::Com1 => Some(f(&mut *SERIAL1.try_lock()?)),
+        _ => OTHER_PORTS[port as usize - 1].try_lock()?.as_mut().map(f),
+    }
+}
+
+/// Changes the baud rate and character format of a port, keeping its enabled interrupts.
+pub fn configure(port: ComPort, config: &LineConfig) -> Result<(), SerialError> {
+    with_port(port, |uart| {
+        let enabled = uart.interrupts();
+        uart.init(config);
+        uart.set_interrupts(enabled);
+    })
+    .ok_or(SerialError::NotPresent(port))
+}
+
+/// Routes kernel logs to the given port, so that another port can carry the console.
+pub fn set_log_port(port: ComPort) -> Result<(), SerialError> {
+    if !is_present(port) {
+        return Err(SerialError::NotPresent(port));
+    }
+    LOG_PORT.store(port as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn log_port() -> ComPort {
+    ComPort::from_index(LOG_PORT.load(Ordering::Relaxed))
+}
+
+/// Writes kernel log output to the log port by polling.
+pub fn write_log(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    with_port(log_port(), |uart| {
+        let _ = uart.write_fmt(args);
+    });
+}
+
+/// Like `write_log`, but gives up instead of waiting if the port is locked.
+///
+/// Returns whether the output was written.
+pub(crate) fn try_write_log(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        try_with_port(log_port(), |uart| {
+            let _ = uart.write_fmt(args);
+        })
+        .is_some()
+    })
+}
+
+/// The bytes received on the console port, until they are read from the `SerialStream`.
+static INPUT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static INPUT_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// The console output waiting to be sent by the transmit interrupt.
+static OUTPUT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+
+/// The virtual console whose output is mirrored to the console port, or `NO_CONSOLE`.
+static CONSOLE: AtomicUsize = AtomicUsize::new(NO_CONSOLE);
+const NO_CONSOLE: usize = usize::MAX;
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
         SERIAL1
@@ -39,3 +225,223 @@ macro_rules! serial_println {
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
         concat!($fmt, "\n"), $($arg)*));
 }
+
+/// The interrupt handler for IRQs 3 and 4: queues bytes received on the console port
+/// and sends queued console output.
+///
+/// Must not block or allocate.
+pub(crate) fn interrupt_handler(irq: u8) -> IrqReturn {
+    let port = console_port();
+    if console().is_none() || port.irq() != irq {
+        return IrqReturn::NotMine;
+    }
+    // the interrupted code can't hold the lock, as it's only taken with interrupts disabled
+    let handled = try_with_port(port, handle_interrupts).unwrap_or(false);
+    if handled {
+        IrqReturn::Handled
+    } else {
+        IrqReturn::NotMine
+    }
+}
+
+fn handle_interrupts(serial: &mut Uart) -> bool {
+    let mut handled = false;
+    while let Some(cause) = serial.pending_interrupt() {
+        handled = true;
+        match cause {
+            InterruptCause::Received => {
+                while let Some(byte) = serial.try_receive() {
+                    if let Ok(queue) = INPUT_QUEUE.try_get() {
+                        if queue.push(byte).is_ok() {
+                            INPUT_WAKER.wake();
+                        }
+                    }
+                }
+            }
+            InterruptCause::TransmitEmpty => send_queued(serial),
+            InterruptCause::LineStatus | InterruptCause::ModemStatus => {}
+        }
+    }
+    handled
+}
+
+/// Moves queued output into the UART while it can take it, and stops the transmit
+/// interrupt once the queue is empty.
+fn send_queued(serial: &mut Uart) {
+    let queue = match OUTPUT_QUEUE.try_get() {
+        Ok(queue) => queue,
+        Err(_) => return,
+    };
+    while serial.is_transmit_empty() {
+        match queue.pop() {
+            Ok(byte) => serial.send(byte),
+            Err(_) => {
+                serial.set_interrupts(serial.interrupts() & !INTERRUPT_TRANSMIT_EMPTY);
+                return;
+            }
+        }
+    }
+}
+
+/// Mirrors the output of the given virtual console to a serial port and delivers the
+/// bytes received on the port to the `SerialStream`.
+///
+/// Requires the heap to be initialized.
+pub fn attach_console(console: usize, port: ComPort) -> Result<(), SerialError> {
+    OUTPUT_QUEUE.init_once(|| ArrayQueue::new(4096));
+    interrupts::without_interrupts(|| {
+        if let Some(previous) = self::console().map(|_| console_port()) {
+            with_port(previous, |uart| uart.set_interrupts(0));
+        }
+        with_port(port, |uart| uart.set_interrupts(INTERRUPT_RECEIVED))
+            .ok_or(SerialError::NotPresent(port))?;
+        CONSOLE_PORT.store(port as u8, Ordering::Relaxed);
+        CONSOLE.store(console, Ordering::Relaxed);
+        Ok(())
+    })
+}
+
+/// Returns the virtual console mirrored to the console port.
+pub fn console() -> Option<usize> {
+    match CONSOLE.load(Ordering::Relaxed) {
+        NO_CONSOLE => None,
+        console => Some(console),
+    }
+}
+
+/// Returns the port the console is attached to, while `console` returns a console.
+pub fn console_port() -> ComPort {
+    ComPort::from_index(CONSOLE_PORT.load(Ordering::Relaxed))
+}
+
+/// Queues console output for the console port, translating `\n` to `\r\n` for terminals.
+///
+/// Called by `vga_buffer::_print` with interrupts disabled. When the queue is full, the
+/// oldest output is sent by polling, so output is never lost.
+pub(crate) fn write_console(args: fmt::Arguments) {
+    with_port(console_port(), |serial| queue_console_output(serial, args));
+}
+
+/// Like `write_console`, but gives up instead of waiting if the port is locked.
+///
+/// Returns whether the output was queued.
+pub(crate) fn try_write_console(args: fmt::Arguments) -> bool {
+    try_with_port(console_port(), |serial| queue_console_output(serial, args)).is_some()
+}
+
+fn queue_console_output(serial: &mut Uart, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let queue = match OUTPUT_QUEUE.try_get() {
+        Ok(queue) => queue,
+        Err(_) => return,
+    };
+    let _ = ConsoleWriter {
+        serial: &mut *serial,
+        queue,
+    }
+    .write_fmt(args);
+    serial.set_interrupts(INTERRUPT_RECEIVED | INTERRUPT_TRANSMIT_EMPTY);
+}
+
+struct ConsoleWriter<'a> {
+    serial: &'a mut Uart,
+    queue: &'a ArrayQueue<u8>,
+}
+
+impl ConsoleWriter<'_> {
+    fn push(&mut self, byte: u8) {
+        let mut byte = byte;
+        while let Err(full) = self.queue.push(byte) {
+            byte = full.0;
+            if let Ok(oldest) = self.queue.pop() {
+                self.serial.send(oldest);
+            }
+        }
+    }
+}
+
+impl fmt::Write for ConsoleWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.push(b'\r');
+            }
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+/// The bytes received on the console port.
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        INPUT_QUEUE
+            .try_init_once(|| ArrayQueue::new(256))
+            .expect("SerialStream::new should only be called once");
+        SerialStream { _private: () }
+    }
+
+    /// Returns the next received byte if there is one, without waiting.
+    pub fn try_next(&mut self) -> Option<u8> {
+        INPUT_QUEUE
+            .try_get()
+            .expect("serial input queue not initialized")
+            .pop()
+            .ok()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // fast path
+        if let Some(byte) = self.try_next() {
+            return Poll::Ready(Some(byte));
+        }
+
+        INPUT_WAKER.register(&cx.waker());
+        match self.try_next() {
+            Some(byte) => {
+                INPUT_WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_loopback() {
+    // with interrupts disabled, the interrupt handler can't take the byte
+    let received = interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        serial.set_loopback(true);
+        serial.send(b'x');
+        let mut received = None;
+        for _ in 0..100_000 {
+            received = serial.try_receive();
+            if received.is_some() {
+                break;
+            }
+        }
+        serial.set_loopback(false);
+        received
+    });
+    assert_eq!(received, Some(b'x'));
+}
+
+#[test_case]
+fn test_com_port_names() {
+    assert_eq!(ComPort::from_name("com3"), Some(ComPort::Com3));
+    assert_eq!(ComPort::from_name("COM2"), Some(ComPort::Com2));
+    assert_eq!(ComPort::from_name("com5"), None);
+    assert!(ComPort::ALL
+        .iter()
+        .all(|&port| ComPort::from_index(port as u8) == port));
+}
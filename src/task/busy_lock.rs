@@ -0,0 +1,60 @@
+//! A lock that serializes the requests of tasks on a device.
+//!
+//! Waiting tasks yield to the executor instead of spinning, so that the task holding the
+//! lock can run to completion on the same executor.
+
+use super::yield_now;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct BusyLock {
+    busy: AtomicBool,
+}
+
+impl BusyLock {
+    pub const fn new() -> Self {
+        BusyLock {
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the guard if no other task holds the lock.
+    pub fn try_lock(&self) -> Option<BusyGuard<'_>> {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| BusyGuard { lock: self })
+    }
+
+    /// Waits until no other task holds the lock.
+    pub async fn lock(&self) -> BusyGuard<'_> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            yield_now().await;
+        }
+    }
+}
+
+/// Releases the lock when dropped.
+///
+/// Drivers keep it in their own guard, whose `drop` runs before the fields are dropped, so
+/// that the device is back in a usable state before the next task gets the lock.
+pub struct BusyGuard<'a> {
+    lock: &'a BusyLock,
+}
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.busy.store(false, Ordering::Release);
+    }
+}
+
+#[test_case]
+fn test_lock_is_exclusive() {
+    let lock = BusyLock::new();
+    let guard = lock.try_lock().expect("lock is free");
+    assert!(lock.try_lock().is_none());
+    drop(guard);
+    assert!(lock.try_lock().is_some());
+}
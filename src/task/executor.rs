@@ -1,4 +1,5 @@
 use super::{spawner::SPAWNER, Task, TaskId};
+use crate::config;
 use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
@@ -13,7 +14,7 @@ impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            task_queue: Arc::new(ArrayQueue::new(config::get_capacity("executor_queue", 100))),
             waker_cache: BTreeMap::new(),
         }
     }
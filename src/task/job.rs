@@ -0,0 +1,339 @@
+//! Job control for the commands started from the shell.
+//!
+//! Every command runs as a job, in the foreground or in the background of the console it
+//! was started from. A job can be stopped, which keeps its future from being polled until
+//! it's resumed, and cancelled, which drops its future at its next `await`.
+//!
+//! A job that wants to clean up when it's cancelled observes the cancellation with
+//! `is_cancelled` or `cancelled`. It then keeps running until it finishes on its own, or
+//! until it's cancelled a second time.
+
+use super::spawner::SPAWNER;
+use crate::{println, vga_buffer};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// The number shown for a job, e.g. `[1]`; numbers are reused once a job finishes.
+pub type JobId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobError {
+    NoSuchJob,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::NoSuchJob => write!(f, "no such job"),
+        }
+    }
+}
+
+/// The signals shared between the job table and the future of a job.
+struct Control {
+    /// The number of times the job was cancelled.
+    cancelled: AtomicUsize,
+    /// Whether the job looked at its cancellation signal.
+    observed: AtomicBool,
+    stopped: AtomicBool,
+    /// Wakes the task of the job when it's cancelled or resumed.
+    waker: AtomicWaker,
+}
+
+impl Control {
+    fn signal(&self) {
+        self.waker.wake();
+    }
+}
+
+struct Job {
+    id: JobId,
+    /// Increases with every job started, unlike `id`, to find the latest job.
+    sequence: u64,
+    command: String,
+    console: usize,
+    foreground: bool,
+    control: Arc<Control>,
+}
+
+impl Job {
+    fn state(&self) -> JobState {
+        if self.control.stopped.load(Ordering::Relaxed) {
+            JobState::Stopped
+        } else {
+            JobState::Running
+        }
+    }
+}
+
+/// A job as listed by `for_each`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobInfo<'a> {
+    pub id: JobId,
+    pub command: &'a str,
+    pub console: usize,
+    pub foreground: bool,
+    pub state: JobState,
+}
+
+/// The jobs that haven't finished yet, ordered by ID. Only used by tasks, never by
+/// interrupt handlers.
+static JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+
+/// The sequence number of the next job.
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// The control of the job whose future is being polled.
+static CURRENT_JOB: Mutex<Option<Arc<Control>>> = Mutex::new(None);
+
+/// Starts `future` as a job of the current console and returns its ID.
+pub fn spawn(
+    command: String,
+    future: impl Future<Output = ()> + 'static,
+    foreground: bool,
+) -> JobId {
+    let (id, future) = create(command, future, foreground);
+    SPAWNER.lock().add(future);
+    id
+}
+
+/// Adds `future` as a job of the current console without spawning it.
+///
+/// Returns the ID of the job and the future that runs it, e.g. with
+/// `simple_executor::block_on`. The job ends when that future completes.
+pub fn create(
+    command: String,
+    future: impl Future<Output = ()> + 'static,
+    foreground: bool,
+) -> (JobId, impl Future<Output = ()>) {
+    let control = Arc::new(Control {
+        cancelled: AtomicUsize::new(0),
+        observed: AtomicBool::new(false),
+        stopped: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+
+    let mut jobs = JOBS.lock();
+    // the lowest free ID, like shells do
+    let id = (1..)
+        .find(|&id| jobs.iter().all(|job| job.id != id))
+        .expect("job IDs exhausted");
+    let index = jobs
+        .iter()
+        .position(|job| job.id > id)
+        .unwrap_or(jobs.len());
+    jobs.insert(
+        index,
+        Job {
+            id,
+            sequence: NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            command,
+            console: vga_buffer::current_console(),
+            foreground,
+            control: control.clone(),
+        },
+    );
+    drop(jobs);
+
+    let future = JobFuture {
+        id,
+        control,
+        future: Box::pin(future),
+    };
+    (id, future)
+}
+
+fn with_job<T>(id: JobId, f: impl FnOnce(&mut Job) -> T) -> Result<T, JobError> {
+    let mut jobs = JOBS.lock();
+    let job = jobs
+        .iter_mut()
+        .find(|job| job.id == id)
+        .ok_or(JobError::NoSuchJob)?;
+    Ok(f(job))
+}
+
+/// Returns the job running in the foreground of the given console.
+pub fn foreground(console: usize) -> Option<JobId> {
+    JOBS.lock()
+        .iter()
+        .find(|job| job.console == console && job.foreground)
+        .map(|job| job.id)
+}
+
+/// Returns the most recently started job of the given console.
+pub fn latest(console: usize) -> Option<JobId> {
+    JOBS.lock()
+        .iter()
+        .filter(|job| job.console == console)
+        .max_by_key(|job| job.sequence)
+        .map(|job| job.id)
+}
+
+/// Cancels a job. A stopped job is resumed, so that it can finish.
+pub fn cancel(id: JobId) -> Result<(), JobError> {
+    with_job(id, |job| {
+        job.control.cancelled.fetch_add(1, Ordering::Relaxed);
+        job.control.stopped.store(false, Ordering::Relaxed);
+        job.control.signal();
+    })
+}
+
+/// Stops a job and moves it to the background.
+pub fn stop(id: JobId) -> Result<(), JobError> {
+    with_job(id, |job| {
+        job.control.stopped.store(true, Ordering::Relaxed);
+        job.foreground = false;
+    })
+}
+
+/// Resumes a stopped job, in the foreground (`fg`) or in the background (`bg`).
+///
+/// Only one job runs in the foreground of a console, so moving a job to the foreground
+/// moves the previous foreground job to the background.
+pub fn resume(id: JobId, foreground: bool) -> Result<(), JobError> {
+    let mut jobs = JOBS.lock();
+    let console = jobs
+        .iter()
+        .find(|job| job.id == id)
+        .ok_or(JobError::NoSuchJob)?
+        .console;
+    for job in jobs.iter_mut() {
+        if job.id == id {
+            job.foreground = foreground;
+            job.control.stopped.store(false, Ordering::Relaxed);
+            job.control.signal();
+        } else if foreground && job.console == console {
+            job.foreground = false;
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` for every job that hasn't finished yet, ordered by ID.
+pub fn for_each(mut f: impl FnMut(&JobInfo)) {
+    for job in JOBS.lock().iter() {
+        f(&JobInfo {
+            id: job.id,
+            command: &job.command,
+            console: job.console,
+            foreground: job.foreground,
+            state: job.state(),
+        });
+    }
+}
+
+/// Returns whether the current job was cancelled.
+///
+/// Once a job called this, cancelling it no longer drops its future; the job is expected
+/// to stop on its own. Outside of a job, it always returns `false`.
+pub fn is_cancelled() -> bool {
+    match &*CURRENT_JOB.lock() {
+        Some(control) => {
+            control.observed.store(true, Ordering::Relaxed);
+            control.cancelled.load(Ordering::Relaxed) > 0
+        }
+        None => false,
+    }
+}
+
+/// Returns a future that completes when the current job is cancelled.
+///
+/// Like `is_cancelled`, awaiting it makes the job responsible for stopping on its own.
+/// Outside of a job, the future never completes.
+pub fn cancelled() -> Cancelled {
+    Cancelled {
+        control: CURRENT_JOB.lock().clone(),
+    }
+}
+
+pub struct Cancelled {
+    control: Option<Arc<Control>>,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        // the job's task is woken on cancellation, so there's no waker to register
+        match &self.control {
+            Some(control) => {
+                control.observed.store(true, Ordering::Relaxed);
+                if control.cancelled.load(Ordering::Relaxed) > 0 {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps the future of a job, so that it can be stopped and cancelled.
+struct JobFuture {
+    id: JobId,
+    control: Arc<Control>,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl JobFuture {
+    /// Removes the job from the job table, reporting the end of background jobs.
+    fn finish(&self, how: &str) {
+        let mut jobs = JOBS.lock();
+        if let Some(index) = jobs.iter().position(|job| job.id == self.id) {
+            let job = jobs.remove(index);
+            drop(jobs);
+            if !job.foreground {
+                println!("[{}] {}  {}", job.id, how, job.command);
+            }
+        }
+    }
+}
+
+impl Future for JobFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let cancelled = self.control.cancelled.load(Ordering::Relaxed);
+        if cancelled > 1 || (cancelled == 1 && !self.control.observed.load(Ordering::Relaxed)) {
+            self.finish("Cancelled");
+            return Poll::Ready(());
+        }
+
+        self.control.waker.register(cx.waker());
+        if self.control.stopped.load(Ordering::Relaxed) {
+            return Poll::Pending;
+        }
+
+        *CURRENT_JOB.lock() = Some(self.control.clone());
+        let result = self.future.as_mut().poll(cx);
+        *CURRENT_JOB.lock() = None;
+        if result.is_ready() {
+            self.finish("Done");
+        }
+        result
+    }
+}
@@ -1,4 +1,12 @@
-use crate::{print, println, vga_buffer::WRITER, task::task_loader::load_task};
+use crate::{
+    config,
+    drivers::ps2,
+    input::{self, Decoder, Locks},
+    print, println,
+    task::{job, task_loader::load_task},
+    vga_buffer::{self, SCROLL_PAGE_LINES},
+    warn,
+};
 use conquer_once::spin::OnceCell;
 use core::{
     pin::Pin,
@@ -9,7 +17,7 @@ use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::KeyCode;
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -20,12 +28,12 @@ static WAKER: AtomicWaker = AtomicWaker::new();
 pub(crate) fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
+            warn!("scancode queue full; dropping keyboard input");
         } else {
             WAKER.wake();
         }
     } else {
-        println!("WARNING: scancode queue uninitialized");
+        warn!("scancode queue uninitialized");
     }
 }
 
@@ -36,7 +44,7 @@ pub struct ScancodeStream {
 impl ScancodeStream {
     pub fn new() -> Self {
         SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
+            .try_init_once(|| ArrayQueue::new(config::get_capacity("scancode_queue", 100)))
             .expect("ScancodeStream::new should only be called once");
         ScancodeStream { _private: () }
     }
@@ -66,39 +74,114 @@ impl Stream for ScancodeStream {
     }
 }
 
-const SHELL: bool = true;
-
-pub async fn print_keypresses() {
+/// Decodes the scancodes of the keyboard and publishes them as input events.
+///
+/// Also keeps the keyboard LEDs in sync with the lock keys.
+pub async fn publish_key_events() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+    let mut decoder = Decoder::new(input::layout());
+    update_leds(decoder.locks());
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                //print key input
-                // if let DecodedKey::Unicode(character) = key {
-                //     println!("Received character: {:?} (Unicode: U+{:04X})", character, character as u32);
-                // }
-                match key {
-                    DecodedKey::Unicode(character) => {
-                        if character == '\u{8}' {
-                            WRITER.lock().handle_backspace();
-                        } else {
-                            if SHELL {
-                                if character == '\n' {
-                                    let row = WRITER.lock().get_row();
-                                    match load_task(row).await{
-                                        Ok(()) => (),
-                                        Err(e) => println!("{}", e),
-                                    };
-                                }
-                            }
-                            print!("{}", character);
-                        }
+        decoder.set_layout(input::layout());
+        let locks = decoder.locks();
+        if let Some(event) = decoder.add_byte(scancode) {
+            if event.locks != locks {
+                update_leds(event.locks);
+            }
+            input::publish(event);
+        }
+    }
+}
+
+fn update_leds(locks: Locks) {
+    if let Err(err) = ps2::keyboard::set_leds(locks) {
+        warn!("failed to set keyboard LEDs: {}", err);
+    }
+}
+
+pub async fn print_keypresses() {
+    let mut events = input::subscribe();
+    // without the shell, the keyboard only types
+    let shell = config::get_bool("shell", true);
+
+    while let Some(event) = events.next().await {
+        if !event.pressed || event.is_modifier() {
+            continue;
+        }
+
+        // Alt+F1..F4 switch the console
+        if event.modifiers.alt {
+            let console = match event.code {
+                KeyCode::F1 => Some(0),
+                KeyCode::F2 => Some(1),
+                KeyCode::F3 => Some(2),
+                KeyCode::F4 => Some(3),
+                _ => None,
+            };
+            if let Some(console) = console {
+                vga_buffer::switch_console(console);
+                continue;
+            }
+        }
+
+        // the input goes to the active console, and so does the output of the
+        // commands started from it
+        vga_buffer::set_current_console(vga_buffer::active_console());
+        let console = vga_buffer::current();
+
+        // Shift+PageUp/PageDown scroll through the history, any other key
+        // returns to the bottom
+        match event.code {
+            KeyCode::PageUp if event.modifiers.shift => {
+                console.lock().scroll_up(SCROLL_PAGE_LINES);
+                continue;
+            }
+            KeyCode::PageDown if event.modifiers.shift => {
+                console.lock().scroll_down(SCROLL_PAGE_LINES);
+                continue;
+            }
+            _ => console.lock().scroll_to_bottom(),
+        }
+
+        match event.character {
+            Some('\u{8}') => {
+                // also erases the character on the framebuffer console
+                print!("\u{8} \u{8}");
+            }
+            Some(character @ '\n') => {
+                // the foreground job gets the console until it finishes
+                if shell && job::foreground(vga_buffer::active_console()).is_none() {
+                    let row = console.lock().get_row();
+                    match load_task(row).await {
+                        Ok(()) => (),
+                        Err(e) => println!("{}", e),
+                    };
+                }
+                print!("{}", character);
+            }
+            // Ctrl+C cancels the foreground job
+            Some('\u{3}') => {
+                println!("^C");
+                if let Some(id) = job::foreground(vga_buffer::active_console()) {
+                    let _ = job::cancel(id);
+                }
+            }
+            // Ctrl+Z stops the foreground job and moves it to the background
+            Some('\u{1a}') => {
+                println!("^Z");
+                if let Some(id) = job::foreground(vga_buffer::active_console()) {
+                    if job::stop(id).is_ok() {
+                        println!("[{}] Stopped", id);
                     }
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
                 }
             }
+            // other control characters are shown in caret notation, e.g. `^C`
+            Some(character) if character.is_ascii_control() && character != '\t' => {
+                print!("^{}", (character as u8 ^ 0x40) as char)
+            }
+            Some(character) => print!("{}", character),
+            None => print!("{:?}", event.code),
         }
     }
 }
@@ -1,3 +1,4 @@
+use crate::vga_buffer;
 use alloc::boxed::Box;
 use core::{
     future::Future,
@@ -6,8 +7,12 @@ use core::{
     task::{Context, Poll},
 };
 
+pub mod busy_lock;
 pub mod executor;
+pub mod job;
 pub mod keyboard;
+pub mod mouse;
+pub mod serial_console;
 pub mod simple_executor;
 pub mod spawner;
 pub mod task_loader;
@@ -15,6 +20,8 @@ pub mod task_loader;
 pub struct Task {
     id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    /// The console the task prints to; inherited from the code that created the task.
+    console: usize,
 }
 
 impl Task {
@@ -22,14 +29,39 @@ impl Task {
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
+            console: vga_buffer::current_console(),
         }
     }
 
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        vga_buffer::set_current_console(self.console);
         self.future.as_mut().poll(context)
     }
 }
 
+/// Returns a future that yields to the executor once, so that other tasks can run.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
@@ -0,0 +1,105 @@
+use crate::{
+    drivers::ps2::mouse::{MouseEvent, PacketDecoder},
+    vga_buffer::{self, BUFFER_HEIGHT, BUFFER_WIDTH},
+    warn,
+};
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Called by the mouse interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        if let Err(_) = queue.push(byte) {
+            warn!("mouse queue full; dropping mouse input");
+        } else {
+            WAKER.wake();
+        }
+    }
+}
+
+/// The movements and button changes of the PS/2 mouse.
+pub struct MouseStream {
+    decoder: PacketDecoder,
+}
+
+impl MouseStream {
+    pub fn new() -> Self {
+        BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(300))
+            .expect("MouseStream::new should only be called once");
+        MouseStream {
+            decoder: PacketDecoder::new(),
+        }
+    }
+
+    fn decode_queued(&mut self, queue: &ArrayQueue<u8>) -> Option<MouseEvent> {
+        while let Ok(byte) = queue.pop() {
+            if let Some(event) = self.decoder.add_byte(byte) {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let queue = BYTE_QUEUE.try_get().expect("mouse queue not initialized");
+
+        // fast path
+        if let Some(event) = self.decode_queued(queue) {
+            return Poll::Ready(Some(event));
+        }
+
+        WAKER.register(&cx.waker());
+        match self.decode_queued(queue) {
+            Some(event) => {
+                WAKER.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// How far the mouse has to move to cross a character cell, in mouse units.
+const CELL_WIDTH: i32 = 8;
+const CELL_HEIGHT: i32 = 16;
+
+/// Moves a pointer block over the text console as the mouse moves.
+pub async fn move_pointer() {
+    let mut events = MouseStream::new();
+    let (max_x, max_y) = (
+        BUFFER_WIDTH as i32 * CELL_WIDTH - 1,
+        BUFFER_HEIGHT as i32 * CELL_HEIGHT - 1,
+    );
+    let (mut x, mut y) = (max_x / 2, max_y / 2);
+    vga_buffer::set_mouse_pointer(Some(cell(x, y)));
+
+    while let Some(event) = events.next().await {
+        x = (x + i32::from(event.dx)).clamp(0, max_x);
+        // the mouse reports upward movement as positive, but rows count downwards
+        y = (y - i32::from(event.dy)).clamp(0, max_y);
+        vga_buffer::set_mouse_pointer(Some(cell(x, y)));
+    }
+}
+
+/// Returns the `(row, col)` of the cell containing the given pointer position.
+fn cell(x: i32, y: i32) -> (usize, usize) {
+    ((y / CELL_HEIGHT) as usize, (x / CELL_WIDTH) as usize)
+}
@@ -0,0 +1,86 @@
+use crate::{
+    print, println,
+    serial::{self, ComPort, SerialStream},
+    task::{job, task_loader::load_task},
+    vga_buffer, warn,
+};
+use alloc::string::String;
+use futures_util::stream::{Stream, StreamExt};
+
+/// The longest command line accepted over the serial port.
+const MAX_LINE: usize = 256;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_Z: u8 = 0x1a;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const ESCAPE: u8 = 0x1b;
+
+/// Runs a shell on the given serial port, mirroring the current console to it.
+///
+/// Commands typed on the serial port run on the console the task was started on, so
+/// their output is sent back over the serial port. Together with `-serial stdio`, this
+/// allows using the kernel without a display, e.g. from scripts on the host.
+pub async fn run_shell(port: ComPort) {
+    let bytes = SerialStream::new();
+    let console = vga_buffer::current_console();
+    if let Err(err) = serial::attach_console(console, port) {
+        warn!("no serial shell: {}", err);
+        return;
+    }
+    run_shell_on(bytes, console).await;
+}
+
+/// Runs a shell reading its input from `bytes`, whose commands print to the given
+/// virtual console; the caller forwards the console's output to the terminal.
+pub async fn run_shell_on(mut bytes: impl Stream<Item = u8> + Unpin, console: usize) {
+    let mut line = String::new();
+    // escape sequences sent by terminals, e.g. for the arrow keys, are skipped
+    let mut in_escape = false;
+
+    while let Some(byte) = bytes.next().await {
+        if in_escape {
+            // a CSI sequence ends with a byte in 0x40..=0x7e, other than its `[`
+            in_escape = byte == b'[' || !(0x40..=0x7e).contains(&byte);
+            continue;
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                // the foreground job gets the console until it finishes
+                if !line.trim().is_empty() && job::foreground(console).is_none() {
+                    if let Err(e) = load_task(core::mem::take(&mut line)).await {
+                        println!("{}", e);
+                    }
+                }
+                line.clear();
+                println!();
+            }
+            BACKSPACE | DELETE => {
+                if line.pop().is_some() {
+                    print!("\u{8} \u{8}");
+                }
+            }
+            CTRL_C => {
+                line.clear();
+                println!("^C");
+                if let Some(id) = job::foreground(console) {
+                    let _ = job::cancel(id);
+                }
+            }
+            CTRL_Z => {
+                println!("^Z");
+                if let Some(id) = job::foreground(console) {
+                    if job::stop(id).is_ok() {
+                        println!("[{}] Stopped", id);
+                    }
+                }
+            }
+            ESCAPE => in_escape = true,
+            b' '..=b'~' if line.len() < MAX_LINE => {
+                line.push(byte as char);
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use super::Task;
 use alloc::collections::VecDeque;
-use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::{
+    future::Future,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+use futures_util::pin_mut;
 
 pub struct SimpleExecutor {
     task_queue: VecDeque<Task>,
@@ -29,6 +33,23 @@ impl SimpleExecutor {
     }
 }
 
+/// Polls the future until it completes, halting the CPU between polls until the next
+/// interrupt.
+///
+/// For code that can't run on the executor, e.g. during boot or in tests. Interrupts must
+/// be enabled, and the future must be woken by an interrupt handler or complete on its own.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    pin_mut!(future);
+    let waker = dummy_waker();
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
 fn dummy_raw_waker() -> RawWaker {
     fn no_op(_: *const ()) {}
     fn clone(_: *const ()) -> RawWaker {
@@ -7,9 +7,11 @@ use crossbeam_queue::{ArrayQueue, PopError};
 use spin::Mutex;
 
 use super::Task;
+use crate::config;
 
 lazy_static! {
-    pub static ref SPAWNER: Mutex<Spawner> = Mutex::new(Spawner::new(100));
+    pub static ref SPAWNER: Mutex<Spawner> =
+        Mutex::new(Spawner::new(config::get_capacity("spawn_queue", 100)));
 }
 
 #[derive(Clone)]
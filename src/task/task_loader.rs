@@ -1,19 +1,133 @@
-use alloc::{string::{String, self}, vec::Vec};
+use alloc::{boxed::Box, format, string::{String, self}, vec::Vec};
+use core::{future::Future, pin::Pin};
 
-use crate::{error::MyError, println};
+use crate::{
+    block,
+    drivers::{pci, uart::LineConfig},
+    error::MyError,
+    fs::{self, FileType},
+    input::{self, Layout},
+    interrupts::irq,
+    log::{self, Level},
+    memory::{self, inspect},
+    print, println,
+    serial::{self, ComPort},
+    vga_buffer,
+};
+use x86_64::VirtAddr;
 
-use super::spawner::SPAWNER;
+use super::{job, spawner::SPAWNER, yield_now};
 
 pub async fn load_task(input_str: String) -> Result<(), MyError> {
-    match parse_string(input_str).await.get(0).expect("Invalid Argument").as_str() {
-        "example_task" => SPAWNER.lock().add(example_task()),
-        _ => return Err(MyError::InvalidFuture),
+    let mut args = parse_string(input_str).await;
+    // a trailing `&` starts the command in the background
+    let background = match args.last_mut() {
+        Some(last) if last.ends_with('&') => {
+            last.pop();
+            if last.is_empty() {
+                args.pop();
+            }
+            true
+        }
+        _ => false,
     };
+    // a blank line or a lone `&` names no command
+    if args.is_empty() {
+        return Err(MyError::InvalidArgument);
+    }
+    let command = args.join(" ");
 
+    let future: Pin<Box<dyn Future<Output = ()>>> =
+        match args[0].as_str() {
+            "example_task" => Box::pin(example_task()),
+            "irqs" => Box::pin(irq_stats()),
+            "vmmap" => Box::pin(vmmap()),
+            "memmap" => Box::pin(memmap()),
+            "translate" => {
+                let addr = args
+                    .get(1)
+                    .and_then(|arg| parse_addr(arg))
+                    .ok_or(MyError::InvalidArgument)?;
+                Box::pin(translate(addr))
+            }
+            "layout" => match args.get(1) {
+                Some(name) => {
+                    let layout = Layout::from_name(name).ok_or(MyError::InvalidArgument)?;
+                    input::set_layout(layout);
+                    return Ok(());
+                }
+                None => Box::pin(layouts()),
+            },
+            "serial" => match (args.get(1), args.get(2)) {
+                (None, _) => Box::pin(serial_ports()),
+                (Some(arg), Some(port)) if arg == "log" => {
+                    let port = ComPort::from_name(port).ok_or(MyError::InvalidArgument)?;
+                    serial::set_log_port(port).map_err(|_| MyError::InvalidArgument)?;
+                    return Ok(());
+                }
+                (Some(port), Some(config)) => {
+                    let port = ComPort::from_name(port).ok_or(MyError::InvalidArgument)?;
+                    let config = LineConfig::parse(config).ok_or(MyError::InvalidArgument)?;
+                    serial::configure(port, &config).map_err(|_| MyError::InvalidArgument)?;
+                    return Ok(());
+                }
+                (Some(_), None) => return Err(MyError::InvalidArgument),
+            },
+            "dmesg" => Box::pin(dmesg()),
+            "ls" => Box::pin(ls(args.get(1).cloned())),
+            "cat" if args.len() > 1 => Box::pin(cat(args[1..].to_vec())),
+            "cd" => Box::pin(cd(args.get(1).cloned())),
+            "pwd" => Box::pin(async { println!("{}", fs::current_dir()) }),
+            "mkdir" if args.len() > 1 => Box::pin(mkdir(args[1..].to_vec())),
+            "rm" if args.len() > 1 => Box::pin(rm(args[1..].to_vec())),
+            "mount" => Box::pin(mounts()),
+            "lsblk" => Box::pin(lsblk()),
+            "lspci" => match args.get(1).map(String::as_str) {
+                None => Box::pin(lspci(false)),
+                Some("-v") => Box::pin(lspci(true)),
+                Some(_) => return Err(MyError::InvalidArgument),
+            },
+            "loglevel" => match (args.get(1), args.get(2), args.get(3)) {
+                (None, _, _) => Box::pin(log_levels()),
+                (Some(arg), Some(name), Some(level)) if arg == "sink" => {
+                    let level = Level::from_name(level).ok_or(MyError::InvalidArgument)?;
+                    log::set_sink_level(name, level).map_err(|_| MyError::InvalidArgument)?;
+                    return Ok(());
+                }
+                (Some(spec), None, _) => {
+                    log::apply_filters(spec).map_err(|_| MyError::InvalidArgument)?;
+                    return Ok(());
+                }
+                _ => return Err(MyError::InvalidArgument),
+            },
+            "spin" => Box::pin(spin()),
+            "jobs" => {
+                SPAWNER.lock().add(jobs());
+                return Ok(());
+            }
+            name @ "fg" | name @ "bg" => {
+                let id = match args.get(1) {
+                    Some(arg) => arg
+                        .trim_start_matches('%')
+                        .parse()
+                        .map_err(|_| MyError::InvalidArgument)?,
+                    None => job::latest(vga_buffer::current_console())
+                        .ok_or(MyError::InvalidArgument)?,
+                };
+                job::resume(id, name == "fg").map_err(|_| MyError::InvalidArgument)?;
+                return Ok(());
+            }
+            _ => return Err(MyError::InvalidFuture),
+        };
+
+    let id = job::spawn(command, future, !background);
+    if background {
+        // printed by a task, so that it appears after the command line
+        SPAWNER.lock().add(async move { println!("[{}]", id) });
+    }
     Ok(())
 }
 
-
 pub async fn parse_string(input_str: String) -> Vec<String> {
     let mut to_return: Vec<String> = Vec::new();
     for str in input_str.split_whitespace().map(|s| String::from(s)){
@@ -29,4 +143,273 @@ async fn async_number() -> u32 {
 async fn example_task() {
     let number = async_number().await;
     println!("async number: {}", number);
-}
\ No newline at end of file
+}
+
+async fn irq_stats() {
+    println!("IRQ      COUNT  UNHANDLED  SPURIOUS  HANDLERS");
+    for line in 0..irq::IRQ_LINES as u8 {
+        let stats = irq::stats(line).expect("IRQ line out of range");
+        print!("{:>3} {:>10} {:>10} {:>9} ", line, stats.count, stats.unhandled, stats.spurious);
+        irq::for_each_handler(line, |name| print!(" {}", name));
+        println!();
+    }
+}
+
+fn parse_addr(arg: &str) -> Option<VirtAddr> {
+    let digits = arg.trim_start_matches("0x").replace('_', "");
+    let addr = u64::from_str_radix(&digits, 16).ok()?;
+    VirtAddr::try_new(addr).ok()
+}
+
+async fn vmmap() {
+    println!("VIRTUAL START       VIRTUAL END         PHYSICAL START  PAGE  FLAGS");
+    inspect::for_each_mapped_range(|range| {
+        println!(
+            "{:#018x}  {:#018x}  {:#014x}  {:>4}  {:?}",
+            range.start.as_u64(),
+            range.end.as_u64(),
+            range.phys_start.as_u64(),
+            match range.page_size {
+                inspect::MappingSize::Size4KiB => "4K",
+                inspect::MappingSize::Size2MiB => "2M",
+                inspect::MappingSize::Size1GiB => "1G",
+            },
+            range.flags
+        );
+    });
+}
+
+async fn lsblk() {
+    println!("NAME       SECTORS       SIZE  MODEL");
+    for device in block::devices() {
+        println!(
+            "{:<6} {:>12} {:>6} MiB  {}",
+            device.name(),
+            device.sector_count(),
+            device.capacity() / (1024 * 1024),
+            device.model()
+        );
+    }
+}
+
+async fn lspci(verbose: bool) {
+    for address in pci::functions() {
+        let (class, subclass, prog_if) = address.class();
+        print!(
+            "{} {} [{:02x}{:02x}]: {:04x}:{:04x}",
+            address,
+            pci::class_name(class, subclass),
+            class,
+            subclass,
+            address.vendor_id(),
+            address.device_id()
+        );
+        match pci::driver_of(address) {
+            Some(driver) => println!(" ({})", driver),
+            None => println!(),
+        }
+        if !verbose {
+            continue;
+        }
+        println!(
+            "    prog-if {:02x}, IRQ {}",
+            prog_if,
+            address.interrupt_line()
+        );
+        for (index, bar) in address.bars() {
+            println!("    BAR {}: {}", index, bar);
+        }
+        if let Some(msi) = address.msi() {
+            println!(
+                "    MSI: {} vectors, {}-bit{}, {}",
+                msi.vectors,
+                if msi.is_64bit { 64 } else { 32 },
+                if msi.per_vector_masking {
+                    ", maskable"
+                } else {
+                    ""
+                },
+                if msi.enabled { "enabled" } else { "disabled" }
+            );
+        }
+        if let Some(msix) = address.msix() {
+            println!(
+                "    MSI-X: {} vectors, table in BAR {} at {:#x}, PBA in BAR {} at {:#x}, {}",
+                msix.table_size,
+                msix.table_bar,
+                msix.table_offset,
+                msix.pba_bar,
+                msix.pba_offset,
+                if msix.enabled { "enabled" } else { "disabled" }
+            );
+        }
+    }
+}
+
+async fn ls(path: Option<String>) {
+    let path = path.as_deref().unwrap_or(".");
+    let entries = match fs::metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => {
+            println!("- {:>8}  {}", metadata.size, path);
+            return;
+        }
+        Ok(_) => fs::read_dir(path),
+        Err(err) => Err(err),
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("ls: {}: {}", path, err);
+            return;
+        }
+    };
+    for entry in entries {
+        match entry.file_type {
+            FileType::Directory => println!("d {:>8}  {}/", "", entry.name),
+            FileType::File => {
+                let child = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+                let size = fs::metadata(&child).map_or(0, |metadata| metadata.size);
+                println!("- {:>8}  {}", size, entry.name);
+            }
+        }
+    }
+}
+
+async fn cat(paths: Vec<String>) {
+    for path in paths {
+        match fs::read(&path) {
+            Ok(data) => print!("{}", String::from_utf8_lossy(&data)),
+            Err(err) => println!("cat: {}: {}", path, err),
+        }
+    }
+}
+
+async fn cd(path: Option<String>) {
+    let path = path.as_deref().unwrap_or("/");
+    if let Err(err) = fs::set_current_dir(path) {
+        println!("cd: {}: {}", path, err);
+    }
+}
+
+async fn mkdir(paths: Vec<String>) {
+    for path in paths {
+        if let Err(err) = fs::create_dir(&path) {
+            println!("mkdir: {}: {}", path, err);
+        }
+    }
+}
+
+async fn rm(paths: Vec<String>) {
+    for path in paths {
+        if let Err(err) = fs::remove(&path) {
+            println!("rm: {}: {}", path, err);
+        }
+    }
+}
+
+async fn mounts() {
+    for (path, fs_type) in fs::mounts() {
+        println!("{} on {}", fs_type, path);
+    }
+}
+
+async fn dmesg() {
+    for seq in log::buffered() {
+        if let Some(record) = log::record(seq) {
+            println!("{}", record);
+        }
+    }
+    let dropped = log::dropped();
+    if dropped > 0 {
+        println!("({} records dropped)", dropped);
+    }
+}
+
+async fn log_levels() {
+    log::for_each_filter(|target, level| match target {
+        "" => println!("default  {}", level),
+        target => println!("{}  {}", target, level),
+    });
+    log::for_each_sink(|name, level| println!("sink {}  {}", name, level));
+}
+
+async fn serial_ports() {
+    println!("PORT  BASE   IRQ  CONFIG        USE");
+    for &port in ComPort::ALL.iter() {
+        let config = match serial::with_port(port, |uart| uart.config()) {
+            Some(config) => config,
+            None => {
+                println!(
+                    "{}  {:#x}  {:>3}  not present",
+                    port,
+                    port.base(),
+                    port.irq()
+                );
+                continue;
+            }
+        };
+        print!(
+            "{}  {:#x}  {:>3}  {:<12} ",
+            port,
+            port.base(),
+            port.irq(),
+            config
+        );
+        if serial::console().is_some() && serial::console_port() == port {
+            print!(" console");
+        }
+        if serial::log_port() == port {
+            print!(" log");
+        }
+        println!();
+    }
+}
+
+async fn memmap() {
+    println!("PHYSICAL START  PHYSICAL END    TYPE");
+    for region in memory::memory_regions() {
+        println!(
+            "{:#014x}  {:#014x}  {:?}",
+            region.range.start_addr(),
+            region.range.end_addr(),
+            region.region_type
+        );
+    }
+}
+
+async fn translate(addr: VirtAddr) {
+    match inspect::translate(addr) {
+        Some(translation) => println!(
+            "{:#x} -> {:#x} ({:?} page, {:?})",
+            addr.as_u64(),
+            translation.phys_addr.as_u64(),
+            translation.page_size,
+            translation.flags
+        ),
+        None => println!("{:#x} is not mapped", addr.as_u64()),
+    }
+}
+
+async fn layouts() {
+    for &layout in Layout::ALL.iter() {
+        let marker = if layout == input::layout() { '*' } else { ' ' };
+        println!("{} {}", marker, layout.name());
+    }
+}
+
+async fn jobs() {
+    job::for_each(|job| {
+        let background = if job.foreground { "" } else { " &" };
+        println!("[{}] {}  {}{}", job.id, job.state, job.command, background);
+    });
+}
+
+/// Keeps running until it's cancelled, e.g. to try out job control.
+async fn spin() {
+    let mut rounds: u64 = 0;
+    while !job::is_cancelled() {
+        yield_now().await;
+        rounds += 1;
+    }
+    println!("spin: cancelled after {} rounds", rounds);
+}
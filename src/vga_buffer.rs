@@ -1,20 +1,43 @@
-use core::fmt;
-use alloc::string::String;
-use lazy_static::lazy_static;
+use crate::{config, drivers::virtio, graphics, serial};
+use alloc::{boxed::Box, string::String, vec};
+use ansi::{Action, Attributes, Csi, Parser};
+use capture::ScreenCapture;
+use core::{
+    fmt, ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 use spin::Mutex;
-use volatile::Volatile;
 use x86_64::instructions::port::{Port, PortGeneric, ReadWriteAccess};
 
-lazy_static! {
-    /// A global `Writer` instance that can be used for printing to the VGA text buffer.
-    ///
-    /// Used by the `print!` and `println!` macros.
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
-}
+pub mod ansi;
+pub mod capture;
+pub mod cp437;
+
+/// The number of virtual consoles.
+pub const CONSOLE_COUNT: usize = 4;
+
+/// The virtual consoles, each with its own screen contents, cursor, colors and scrollback.
+///
+/// Only the active console is shown in the VGA text buffer; the others are kept off-screen
+/// until they are switched to with `switch_console`.
+pub static CONSOLES: [Mutex<Writer>; CONSOLE_COUNT] = [
+    Mutex::new(Writer::new(0)),
+    Mutex::new(Writer::new(1)),
+    Mutex::new(Writer::new(2)),
+    Mutex::new(Writer::new(3)),
+];
+
+/// The console shown on the screen, which also receives the keyboard input.
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(0);
+/// Whether the VGA card shows the text buffer; cleared when switching to a graphics mode.
+static TEXT_MODE: AtomicBool = AtomicBool::new(true);
+/// The console the `print!` and `println!` macros write to.
+///
+/// Set by the executor to the console of the task it is polling.
+static CURRENT_CONSOLE: AtomicUsize = AtomicUsize::new(0);
+/// The cell the mouse pointer is drawn on, as `row * BUFFER_WIDTH + col`, or `NO_POINTER`.
+static MOUSE_POINTER: AtomicUsize = AtomicUsize::new(NO_POINTER);
+const NO_POINTER: usize = usize::MAX;
 
 /// The standard color palette in VGA text mode.
 #[allow(dead_code)]
@@ -46,9 +69,21 @@ struct ColorCode(u8);
 
 impl ColorCode {
     /// Create a new `ColorCode` with the given foreground and background colors.
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    fn from_attributes(attributes: &Attributes) -> ColorCode {
+        let (foreground, background) = attributes.colors();
+        ColorCode::new(foreground, background)
+    }
+
+    /// Swaps the foreground and background colors.
+    ///
+    /// The background can't be bright, as its high bit makes the character blink.
+    fn inverted(self) -> ColorCode {
+        ColorCode((self.0 & 0x07) << 4 | self.0 >> 4)
+    }
 }
 
 /// A screen character in the VGA text buffer, consisting of an ASCII character and a `ColorCode`.
@@ -60,28 +95,132 @@ struct ScreenChar {
 }
 
 /// The height of the text buffer (normally 25 lines).
-const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_HEIGHT: usize = 25;
 /// The width of the text buffer (normally 80 columns).
-const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_WIDTH: usize = 80;
+
+/// The number of lines kept in the scrollback buffer of each console once
+/// `enable_scrollback` was called, unless the `scrollback` command line key sets another.
+pub const SCROLLBACK_LINES: usize = 500;
+/// The number of lines Shift+PageUp/PageDown scroll by.
+pub const SCROLL_PAGE_LINES: usize = BUFFER_HEIGHT - 1;
+
+const DEFAULT_COLOR: ColorCode = ColorCode::new(
+    Attributes::DEFAULT.foreground,
+    Attributes::DEFAULT.background,
+);
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: DEFAULT_COLOR,
+};
 
 /// A structure representing the VGA text buffer.
 #[repr(transparent)]
 struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+impl Buffer {
+    /// The VGA text buffer. All consoles share it, so no references to it are created:
+    /// cells are only accessed with volatile reads and writes through the raw pointer.
+    const ADDRESS: *mut Buffer = 0xb8000 as *mut Buffer;
+
+    fn read(row: usize, col: usize) -> ScreenChar {
+        unsafe { ptr::addr_of!((*Self::ADDRESS).chars[row][col]).read_volatile() }
+    }
+
+    /// Only the active console may write, while holding its lock.
+    fn write(row: usize, col: usize, character: ScreenChar) {
+        unsafe { ptr::addr_of_mut!((*Self::ADDRESS).chars[row][col]).write_volatile(character) }
+    }
+}
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
+/// A ring buffer of the lines that scrolled off the top of the screen.
+///
+/// All lines are allocated up front, so that printing never allocates: `println!` is also
+/// used from interrupt handlers.
+struct Scrollback {
+    lines: Box<[Line]>,
+    /// The index of the oldest line in `lines`.
+    start: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    fn new(capacity: usize) -> Self {
+        Scrollback {
+            lines: vec![[BLANK; BUFFER_WIDTH]; capacity].into_boxed_slice(),
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends a line, dropping the oldest one if the buffer is full.
+    fn push(&mut self, line: Line) {
+        let capacity = self.lines.len();
+        if self.len < capacity {
+            self.lines[(self.start + self.len) % capacity] = line;
+            self.len += 1;
+        } else {
+            self.lines[self.start] = line;
+            self.start = (self.start + 1) % capacity;
+        }
+    }
+
+    /// Returns the line at `index`, counted from the oldest line.
+    fn get(&self, index: usize) -> &Line {
+        &self.lines[(self.start + index) % self.lines.len()]
+    }
 }
 
 /// A writer type that allows writing ASCII bytes and strings to an underlying `Buffer`.
 ///
 /// Wraps lines at `BUFFER_WIDTH`. Supports newline characters and implements the
 /// `core::fmt::Write` trait.
+///
+/// Strings are interpreted like a VT100 terminal does: SGR sequences (`ESC [ ... m`) set
+/// the colors, and CSI sequences move the cursor and erase parts of the screen, so the
+/// same formatted output works on the VGA console and on a serial terminal.
+///
+/// Each writer is a virtual console and keeps its own copy of the screen contents, so
+/// that the view can be scrolled back into the scrollback buffer and another console can
+/// be shown in the meantime. Only the active console writes to the buffer, and only while
+/// its view is at the bottom; other output just updates the copy and becomes visible once
+/// the console is shown again.
 pub struct Writer {
     column_position: usize,
+    row_position: usize,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    attributes: Attributes,
+    saved_position: (usize, usize),
+    parser: Parser,
+    screen: [Line; BUFFER_HEIGHT],
+    scrollback: Option<Scrollback>,
+    /// How many lines the view is scrolled back from the live screen.
+    view_offset: usize,
+    /// The index of the console in `CONSOLES`.
+    index: usize,
 }
 
 impl Writer {
-    /// Writes an ASCII byte to the buffer.
+    const fn new(index: usize) -> Writer {
+        Writer {
+            column_position: 0,
+            row_position: BUFFER_HEIGHT - 1,
+            color_code: DEFAULT_COLOR,
+            attributes: Attributes::DEFAULT,
+            saved_position: (0, 0),
+            parser: Parser::new(),
+            screen: [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            scrollback: None,
+            view_offset: 0,
+            index,
+        }
+    }
+
+    /// Writes a code page 437 byte to the buffer.
     ///
     /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character.
     pub fn write_byte(&mut self, byte: u8) {
@@ -92,47 +231,189 @@ impl Writer {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
+                let row = self.row_position;
                 let col = self.column_position;
 
                 let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
+                self.put(
+                    row,
+                    col,
+                    ScreenChar {
+                        ascii_character: byte,
+                        color_code,
+                    },
+                );
                 self.column_position += 1;
             }
         }
     }
 
-    /// Writes the given ASCII string to the buffer.
+    /// Writes the given string to the buffer, interpreting escape sequences.
     ///
-    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character. Does **not**
-    /// support strings with non-ASCII characters, since they can't be printed in the VGA text
-    /// mode.
+    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n`, `\r`, `\t` and backspace control
+    /// characters. Characters are translated to code page 437; those that have no glyph in
+    /// the VGA font are shown as `■`.
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
+        for c in s.chars() {
+            if let Some(action) = self.parser.advance(c) {
+                self.perform(action);
             }
         }
     }
 
-    /// Shifts all lines one line up and clears the last row.
-    fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::Print(c) => self.write_byte(glyph(c)),
+            Action::Control('\n') => self.new_line(),
+            Action::Control('\r') => self.column_position = 0,
+            Action::Control('\t') => {
+                if self.column_position >= BUFFER_WIDTH {
+                    self.new_line();
+                }
+                let next_stop = (self.column_position / 8 + 1) * 8;
+                while self.column_position < next_stop.min(BUFFER_WIDTH) {
+                    self.write_byte(b' ');
+                }
+            }
+            // a cursor after the last column moves back onto it, so that `\u{8} \u{8}`
+            // erases the last character of a full row
+            Action::Control('\u{8}') => {
+                self.column_position = self.column_position.saturating_sub(1)
+            }
+            Action::Control(_) => {}
+            Action::Csi(csi) => self.perform_csi(&csi),
+            Action::SaveCursor => self.saved_position = self.position(),
+            Action::RestoreCursor => {
+                let (row, col) = self.saved_position;
+                self.set_position(row, col);
             }
+            Action::Reset => {
+                self.set_attributes(Attributes::DEFAULT);
+                self.clear_screen();
+            }
+        }
+    }
+
+    fn perform_csi(&mut self, csi: &Csi) {
+        if csi.private {
+            // e.g. showing or hiding the cursor, which the console doesn't support
+            return;
+        }
+        let (row, col) = self.position();
+        let col = col.min(BUFFER_WIDTH - 1);
+        let n = csi.param(0, 1) as usize;
+        let mode = csi.params().first().copied().unwrap_or(0);
+        match csi.final_byte {
+            b'A' => self.set_position(row.saturating_sub(n), col),
+            b'B' => self.set_position(row + n, col),
+            b'C' => self.set_position(row, col + n),
+            b'D' => self.set_position(row, col.saturating_sub(n)),
+            b'E' => self.set_position(row + n, 0),
+            b'F' => self.set_position(row.saturating_sub(n), 0),
+            b'G' => self.set_position(row, n - 1),
+            b'd' => self.set_position(n - 1, col),
+            b'H' | b'f' => {
+                self.set_position(csi.param(0, 1) as usize - 1, csi.param(1, 1) as usize - 1)
+            }
+            b'J' => self.erase_display(mode),
+            b'K' => self.erase_line(mode),
+            b'm' => self.select_graphic_rendition(csi.params()),
+            b's' => self.saved_position = (row, col),
+            b'u' => {
+                let (row, col) = self.saved_position;
+                self.set_position(row, col);
+            }
+            _ => {}
+        }
+    }
+
+    /// Erases part of the screen: 0 from the cursor to the end, 1 from the start to the
+    /// cursor, 2 and 3 everything. The cursor doesn't move.
+    fn erase_display(&mut self, mode: u16) {
+        let row = self.row_position;
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for row in row + 1..BUFFER_HEIGHT {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                for row in 0..row {
+                    self.clear_row(row);
+                }
+                self.erase_line(1);
+            }
+            2 | 3 => {
+                for row in 0..BUFFER_HEIGHT {
+                    self.clear_row(row);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Erases part of the current row: 0 from the cursor to the end, 1 from the start to
+    /// the cursor, 2 the whole row. The cursor doesn't move.
+    fn erase_line(&mut self, mode: u16) {
+        let (row, col) = (
+            self.row_position,
+            self.column_position.min(BUFFER_WIDTH - 1),
+        );
+        let columns = match mode {
+            0 => col..BUFFER_WIDTH,
+            1 => 0..col + 1,
+            2 => 0..BUFFER_WIDTH,
+            _ => return,
+        };
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in columns {
+            self.put(row, col, blank);
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let mut attributes = self.attributes;
+        attributes.apply_sgr(params);
+        self.set_attributes(attributes);
+    }
+
+    fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+        self.color_code = ColorCode::from_attributes(&attributes);
+    }
+
+    /// Moves to the start of the next row, scrolling the screen if on the last row.
+    fn new_line(&mut self) {
+        if self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+        } else {
+            self.scroll();
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
         self.column_position = 0;
     }
 
+    /// Shifts all lines one line up, moving the first line into the scrollback buffer,
+    /// and clears the last row.
+    fn scroll(&mut self) {
+        if let Some(scrollback) = &mut self.scrollback {
+            scrollback.push(self.screen[0]);
+            if self.view_offset > 0 {
+                // keep showing the same lines while the user reads the history
+                self.view_offset = (self.view_offset + 1).min(scrollback.len);
+            }
+        }
+        self.screen.copy_within(1.., 0);
+        self.screen[BUFFER_HEIGHT - 1] = [ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        }; BUFFER_WIDTH];
+        self.render();
+    }
+
     /// Clears a row by overwriting it with blank characters.
     fn clear_row(&mut self, row: usize) {
         let blank = ScreenChar {
@@ -140,22 +421,168 @@ impl Writer {
             color_code: self.color_code,
         };
         for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+            self.put(row, col, blank);
+        }
+    }
+
+    /// Writes a character to the screen, and to the buffer if the screen is shown.
+    fn put(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.screen[row][col] = character;
+        if self.view_offset == 0 && self.is_shown() {
+            Self::show(row, col, character);
+        }
+    }
+
+    /// Writes a character to the buffer, with inverted colors if the mouse pointer is on
+    /// it.
+    fn show(row: usize, col: usize, mut character: ScreenChar) {
+        if MOUSE_POINTER.load(Ordering::Relaxed) == row * BUFFER_WIDTH + col {
+            character.color_code = character.color_code.inverted();
+        }
+        Buffer::write(row, col, character);
+    }
+
+    /// Returns whether this console is the one shown on the screen.
+    pub fn is_active(&self) -> bool {
+        ACTIVE_CONSOLE.load(Ordering::Relaxed) == self.index
+    }
+
+    /// Returns whether this console is shown in the VGA text buffer.
+    fn is_shown(&self) -> bool {
+        self.is_active() && TEXT_MODE.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of lines in the scrollback buffer.
+    fn history_len(&self) -> usize {
+        self.scrollback
+            .as_ref()
+            .map_or(0, |scrollback| scrollback.len)
+    }
+
+    /// Returns the line shown at the given row of the current view.
+    fn view_line(&self, row: usize) -> &Line {
+        let history = self.history_len();
+        let index = history - self.view_offset + row;
+        match &self.scrollback {
+            Some(scrollback) if index < history => scrollback.get(index),
+            _ => &self.screen[index - history],
+        }
+    }
+
+    /// Copies the current view into the buffer, if the console is shown.
+    fn render(&mut self) {
+        if !self.is_shown() {
+            return;
+        }
+        for row in 0..BUFFER_HEIGHT {
+            for (col, &character) in self.view_line(row).iter().enumerate() {
+                Self::show(row, col, character);
+            }
+        }
+    }
+
+    /// Scrolls the view back by up to `lines` lines into the scrollback buffer.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let history = self.history_len();
+        let view_offset = (self.view_offset + lines).min(history);
+        if view_offset != self.view_offset {
+            self.view_offset = view_offset;
+            self.render();
+        }
+    }
+
+    /// Scrolls the view forward by up to `lines` lines towards the live screen.
+    pub fn scroll_down(&mut self, lines: usize) {
+        let view_offset = self.view_offset.saturating_sub(lines);
+        if view_offset != self.view_offset {
+            self.view_offset = view_offset;
+            self.render();
+        }
+    }
+
+    /// Returns the view to the live screen.
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_down(self.view_offset);
+    }
+
+    /// Returns whether the view is scrolled back into the scrollback buffer.
+    pub fn is_scrolled(&self) -> bool {
+        self.view_offset > 0
+    }
+
+    /// Returns the `(row, column)` the next character is written to.
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    /// Moves the position the next character is written to.
+    ///
+    /// Positions outside of the screen are clamped to the last row or column.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+    }
+
+    /// Writes the given string starting at `(row, col)` without moving the cursor.
+    ///
+    /// For full-screen programs: the string is cut off at the end of the row instead of
+    /// wrapping, and the screen never scrolls.
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        let color_code = self.color_code;
+        for (col, c) in (col..BUFFER_WIDTH).zip(s.chars()) {
+            let ascii_character = glyph(c);
+            self.put(
+                row,
+                col,
+                ScreenChar {
+                    ascii_character,
+                    color_code,
+                },
+            );
+        }
+    }
+
+    /// Clears the screen and moves the cursor to the top left corner.
+    pub fn clear_screen(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
         }
+        self.set_position(0, 0);
+    }
+
+    /// Returns the characters and colors of the console's current view.
+    pub fn capture(&self) -> ScreenCapture {
+        let mut cells = [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, line) in cells.iter_mut().enumerate() {
+            *line = *self.view_line(row);
+        }
+        ScreenCapture::new(cells)
+    }
+
+    /// Returns the character shown at `(row, col)` of the console's current view.
+    pub fn char_at(&self, row: usize, col: usize) -> u8 {
+        self.view_line(row)[col].ascii_character
     }
 
     pub fn handle_backspace(&mut self) {
         if self.column_position > 0 {
             // Calculate the position of the previous character
-            let row = BUFFER_HEIGHT - 1;
+            let row = self.row_position;
             let col = self.column_position - 1;
 
             // Overwrite the character with a space
             let color_code = self.color_code;
-            self.buffer.chars[row][col].write(ScreenChar {
-                ascii_character: b' ',
-                color_code,
-            });
+            self.put(
+                row,
+                col,
+                ScreenChar {
+                    ascii_character: b' ',
+                    color_code,
+                },
+            );
 
             // Move the cursor back
             self.column_position -= 1;
@@ -168,9 +595,11 @@ impl Writer {
 
     pub fn get_row(&self) -> String {
         let mut to_return = String::new();
-        for x in 0..self.column_position{
-            to_return.push(self.buffer.chars[BUFFER_HEIGHT-1][x].read().ascii_character as char);
-        };
+        for x in 0..self.column_position {
+            to_return.push(cp437::decode(
+                self.screen[self.row_position][x].ascii_character,
+            ));
+        }
         to_return
     }
 }
@@ -196,15 +625,220 @@ macro_rules! println {
 }
 
 /// Prints the given formatted string to the VGA text buffer
-/// through the global `WRITER` instance.
+/// through the current console.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let console = current_console();
+        CONSOLES[console].lock().write_fmt(args).unwrap();
+        if console == active_console() {
+            graphics::console::write_fmt(args);
+        }
+        if Some(console) == serial::console() {
+            serial::write_console(args);
+        }
+        if Some(console) == virtio::console::console() {
+            virtio::console::write_console(args);
+        }
+    });
+}
+
+/// Like `_print`, but gives up on each output instead of waiting for its lock.
+///
+/// Used by the log sink, which may run in an interrupt handler. Returns whether the
+/// output was written everywhere.
+pub(crate) fn try_print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let console = current_console();
+        let mut written = match CONSOLES[console].try_lock() {
+            Some(mut writer) => writer.write_fmt(args).is_ok(),
+            None => false,
+        };
+        if console == active_console() {
+            written &= graphics::console::try_write_fmt(args);
+        }
+        if Some(console) == serial::console() {
+            written &= serial::try_write_console(args);
+        }
+        if Some(console) == virtio::console::console() {
+            // only queues the output, without a lock
+            virtio::console::write_console(args);
+        }
+        written
+    })
+}
+
+/// Stops writing to the VGA text buffer, because the card was switched to a graphics
+/// mode.
+///
+/// The consoles still keep track of their contents, and the output of the active console
+/// is also written to the framebuffer console.
+pub fn disable_text_mode() {
+    TEXT_MODE.store(false, Ordering::Relaxed);
+}
+
+/// Programs the VGA card for 80x25 text mode and draws the active console again.
+///
+/// Used to return from a graphics mode, see `drivers::bga::disable`.
+pub fn enable_text_mode() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        set_text_mode_registers();
+        TEXT_MODE.store(true, Ordering::Relaxed);
+        active().lock().render();
+    });
+}
+
+/// Writes the standard register values of BIOS mode 3.
+fn set_text_mode_registers() {
+    const MISC_OUTPUT: u8 = 0x67;
+    const SEQUENCER: [u8; 5] = [0x03, 0x00, 0x03, 0x00, 0x02];
+    const CRT_CONTROLLER: [u8; 25] = [
+        0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f, 0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00, 0x00,
+        0x50, 0x9c, 0x0e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3, 0xff,
+    ];
+    const GRAPHICS_CONTROLLER: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0e, 0x00, 0xff];
+    const ATTRIBUTE_CONTROLLER: [u8; 21] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e,
+        0x3f, 0x0c, 0x00, 0x0f, 0x08, 0x00,
+    ];
+    /// Enables the display after the attribute controller is programmed.
+    const PALETTE_ADDRESS_SOURCE: u8 = 0x20;
+
+    fn write_indexed(index_port: u16, values: &[u8]) {
+        for (index, &value) in values.iter().enumerate() {
+            unsafe {
+                Port::<u8>::new(index_port).write(index as u8);
+                Port::<u8>::new(index_port + 1).write(value);
+            }
+        }
+    }
+
+    let mut attribute: PortGeneric<u8, ReadWriteAccess> = Port::new(0x3c0);
+    let mut input_status: PortGeneric<u8, ReadWriteAccess> = Port::new(0x3da);
+    unsafe {
+        Port::<u8>::new(0x3c2).write(MISC_OUTPUT);
+        write_indexed(0x3c4, &SEQUENCER);
+        // unlock the CRT controller registers 0 to 7 before writing them
+        Port::<u8>::new(0x3d4).write(0x11);
+        Port::<u8>::new(0x3d5).write(0x00);
+        write_indexed(0x3d4, &CRT_CONTROLLER);
+        write_indexed(0x3ce, &GRAPHICS_CONTROLLER);
+        // reading the input status resets the attribute controller to expect an index
+        input_status.read();
+        for (index, &value) in ATTRIBUTE_CONTROLLER.iter().enumerate() {
+            attribute.write(index as u8);
+            attribute.write(value);
+        }
+        input_status.read();
+        attribute.write(PALETTE_ADDRESS_SOURCE);
+    }
+}
+
+/// Returns the characters and colors in the VGA text buffer.
+pub fn capture_screen() -> ScreenCapture {
+    ScreenCapture::from_buffer()
+}
+
+/// Returns the index of the console shown on the screen.
+pub fn active_console() -> usize {
+    ACTIVE_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Returns the console shown on the screen.
+pub fn active() -> &'static Mutex<Writer> {
+    &CONSOLES[active_console()]
+}
+
+/// Returns the index of the console `print!` writes to.
+pub fn current_console() -> usize {
+    CURRENT_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Returns the console `print!` writes to.
+pub fn current() -> &'static Mutex<Writer> {
+    &CONSOLES[current_console()]
+}
+
+/// Makes `print!` write to the console with the given index.
+///
+/// Indices beyond `CONSOLE_COUNT` are ignored.
+pub fn set_current_console(index: usize) {
+    if index < CONSOLE_COUNT {
+        CURRENT_CONSOLE.store(index, Ordering::Relaxed);
+    }
+}
+
+/// Shows the console with the given index on the screen and gives it the keyboard input.
+///
+/// Indices beyond `CONSOLE_COUNT` are ignored.
+pub fn switch_console(index: usize) {
+    use x86_64::instructions::interrupts;
+
+    if index >= CONSOLE_COUNT {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        // holding the lock of the previous console keeps it from writing to the buffer
+        // while the new console is drawn
+        let previous = active().lock();
+        ACTIVE_CONSOLE.store(index, Ordering::Relaxed);
+        drop(previous);
+        CONSOLES[index].lock().render();
     });
+    // the framebuffer console only shows the output of the active console from now on
+    graphics::console::with_console(|console| console.clear());
+}
+
+/// Draws the mouse pointer as a block of inverted colors on the given `(row, col)` cell of
+/// the text buffer, or hides it.
+pub fn set_mouse_pointer(position: Option<(usize, usize)>) {
+    use x86_64::instructions::interrupts;
+
+    let index = position.map_or(NO_POINTER, |(row, col)| {
+        row.min(BUFFER_HEIGHT - 1) * BUFFER_WIDTH + col.min(BUFFER_WIDTH - 1)
+    });
+    interrupts::without_interrupts(|| {
+        // holding the lock keeps the console from drawing the cells at the same time
+        let writer = active().lock();
+        let previous = MOUSE_POINTER.swap(index, Ordering::Relaxed);
+        if writer.is_shown() {
+            for &cell in [previous, index].iter().filter(|&&cell| cell != NO_POINTER) {
+                let (row, col) = (cell / BUFFER_WIDTH, cell % BUFFER_WIDTH);
+                Writer::show(row, col, writer.view_line(row)[col]);
+            }
+        }
+    });
+}
+
+/// Returns the byte to write to the VGA buffer to display the given character.
+fn glyph(c: char) -> u8 {
+    // characters the VGA font has no glyph for are shown as `■`
+    cp437::encode(c).unwrap_or(0xfe)
+}
+
+/// Allocates the scrollback buffers of all consoles, so that lines scrolling off the top of
+/// the screen can be viewed again with Shift+PageUp.
+///
+/// A line takes 160 bytes in the buffer of every console, so the `scrollback` command line
+/// key can lower or raise the number of lines kept. Requires the heap to be initialized.
+pub fn enable_scrollback() {
+    use x86_64::instructions::interrupts;
+
+    let lines = config::get_capacity("scrollback", SCROLLBACK_LINES);
+    for console in CONSOLES.iter() {
+        let scrollback = Scrollback::new(lines);
+        interrupts::without_interrupts(|| {
+            console.lock().scrollback = Some(scrollback);
+        });
+    }
 }
 
 pub async fn enable_cursor(cursor_start: u32, cursor_end: u32) {
@@ -226,8 +860,16 @@ pub async fn update_cursor() {
     let mut port1: PortGeneric<u8, ReadWriteAccess> = Port::new(0x3D4);
     let mut port2: PortGeneric<u8, ReadWriteAccess> = Port::new(0x3D5);
 
-    let pos: u16 = (((BUFFER_HEIGHT - 1) * (BUFFER_WIDTH as usize))
-        + WRITER.lock().get_column_pos().await) as u16;
+    let pos: u16 = {
+        let writer = active().lock();
+        if writer.is_scrolled() {
+            // move the cursor off the screen while viewing the history
+            BUFFER_HEIGHT * BUFFER_WIDTH
+        } else {
+            let (row, col) = writer.position();
+            row * BUFFER_WIDTH + col
+        }
+    } as u16;
 
     unsafe {
         port1.write(0x0F as u8);
@@ -237,6 +879,19 @@ pub async fn update_cursor() {
     };
 }
 
+#[test_case]
+fn test_mouse_pointer_inverts_colors() {
+    use x86_64::instructions::interrupts;
+
+    let shown = || Buffer::read(0, 0).color_code;
+    interrupts::without_interrupts(|| CONSOLES[0].lock().write_at(0, 0, "x"));
+    let normal = shown();
+    set_mouse_pointer(Some((0, 0)));
+    assert_eq!(shown(), normal.inverted());
+    set_mouse_pointer(None);
+    assert_eq!(shown(), normal);
+}
+
 #[test_case]
 fn test_println_simple() {
     println!("test_println_simple output");
@@ -256,11 +911,105 @@ fn test_println_output() {
 
     let s = "Some test string that fits on a single line";
     interrupts::without_interrupts(|| {
-        let mut writer = WRITER.lock();
+        let mut writer = CONSOLES[0].lock();
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = Buffer::read(BUFFER_HEIGHT - 2, i);
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
 }
+
+#[test_case]
+fn test_write_at() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        let position = writer.position();
+        writer.write_at(3, BUFFER_WIDTH - 4, "abcdefgh");
+        assert_eq!(writer.char_at(3, BUFFER_WIDTH - 4), b'a');
+        assert_eq!(writer.char_at(3, BUFFER_WIDTH - 1), b'd');
+        assert_eq!(writer.char_at(4, 0), b' ');
+        assert_eq!(writer.position(), position);
+    });
+}
+
+#[test_case]
+fn test_set_position() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        writer.set_position(5, 10);
+        write!(writer, "xy").expect("write failed");
+        assert_eq!(writer.char_at(5, 10), b'x');
+        assert_eq!(writer.char_at(5, 11), b'y');
+        assert_eq!(writer.position(), (5, 12));
+        writer.set_position(BUFFER_HEIGHT, BUFFER_WIDTH);
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1));
+        writer.set_position(BUFFER_HEIGHT - 1, 0);
+    });
+}
+
+#[test_case]
+fn test_escape_sequences() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        write!(writer, "\x1b[2;3Hab\x1b[1;31mc\x1b[0md").expect("write failed");
+        assert_eq!(writer.char_at(1, 2), b'a');
+        assert_eq!(writer.char_at(1, 3), b'b');
+        assert_eq!(writer.char_at(1, 4), b'c');
+        assert_eq!(writer.char_at(1, 5), b'd');
+        let color_code = Buffer::read(1, 4).color_code;
+        assert_eq!(color_code, ColorCode::new(Color::LightRed, Color::Black));
+        assert_eq!(Buffer::read(1, 5).color_code, DEFAULT_COLOR);
+
+        write!(writer, "\x1b[2De\x1b[K").expect("write failed");
+        assert_eq!(writer.char_at(1, 3), b'b');
+        assert_eq!(writer.char_at(1, 4), b'e');
+        assert_eq!(writer.char_at(1, 5), b' ');
+        writer.set_position(BUFFER_HEIGHT - 1, 0);
+    });
+}
+
+#[test_case]
+fn test_code_page_437_output() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        write!(writer, "\n│ é ░ €").expect("write failed");
+        let row = BUFFER_HEIGHT - 1;
+        assert_eq!(writer.char_at(row, 0), 0xb3);
+        assert_eq!(writer.char_at(row, 2), 0x82);
+        assert_eq!(writer.char_at(row, 4), 0xb0);
+        assert_eq!(writer.char_at(row, 6), 0xfe);
+    });
+}
+
+#[test_case]
+fn test_inactive_console_is_not_shown() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut console = CONSOLES[1].lock();
+        console.set_position(2, 0);
+        write!(console, "on console 1").expect("write failed");
+        assert_eq!(console.char_at(2, 0), b'o');
+    });
+    let shown = |row: usize, col: usize| Buffer::read(row, col).ascii_character;
+    assert_ne!(shown(2, 0), b'o');
+
+    switch_console(1);
+    assert_eq!(shown(2, 0), b'o');
+    switch_console(0);
+    assert_eq!(active_console(), 0);
+    assert_eq!(shown(2, 0), CONSOLES[0].lock().char_at(2, 0));
+}
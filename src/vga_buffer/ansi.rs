@@ -0,0 +1,345 @@
+//! A parser for the ANSI/VT100 escape sequences understood by the consoles.
+//!
+//! The parser only splits the input into characters, control characters and escape
+//! sequences; interpreting them is up to the console.
+
+use super::Color;
+
+/// The maximum number of parameters of a control sequence; further parameters are ignored.
+pub const MAX_PARAMS: usize = 8;
+
+/// The result of feeding a character to the `Parser`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A character to display.
+    Print(char),
+    /// A C0 control character like `\n`, `\r`, `\t` or backspace.
+    Control(char),
+    /// A control sequence (`ESC [ parameters final`).
+    Csi(Csi),
+    /// `ESC 7`: save the cursor position.
+    SaveCursor,
+    /// `ESC 8`: restore the saved cursor position.
+    RestoreCursor,
+    /// `ESC c`: reset the terminal.
+    Reset,
+}
+
+/// A parsed control sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    /// The number of parameters seen, including ignored ones.
+    count: usize,
+    /// Whether the sequence has the `?` private marker, e.g. `ESC [ ? 25 l`.
+    pub private: bool,
+    /// The byte that ends the sequence and selects its function, e.g. `b'm'`.
+    pub final_byte: u8,
+}
+
+impl Csi {
+    const fn new() -> Self {
+        Csi {
+            params: [0; MAX_PARAMS],
+            count: 0,
+            private: false,
+            final_byte: 0,
+        }
+    }
+
+    /// Returns the parameters of the sequence. Omitted parameters are 0.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.count.min(MAX_PARAMS)]
+    }
+
+    /// Returns the parameter at `index`, or `default` if it is omitted or 0.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&param) if param != 0 => param,
+            _ => default,
+        }
+    }
+
+    fn push_digit(&mut self, digit: u16) {
+        if self.count == 0 {
+            self.count = 1;
+        }
+        if let Some(param) = self.params.get_mut(self.count - 1) {
+            *param = param.saturating_mul(10).saturating_add(digit);
+        }
+    }
+
+    fn next_param(&mut self) {
+        // an omitted first parameter still counts
+        self.count = self.count.max(1).saturating_add(1);
+    }
+}
+
+/// The VGA colors, indexed by their value.
+const PALETTE: [Color; 16] = [
+    Color::Black,
+    Color::Blue,
+    Color::Green,
+    Color::Cyan,
+    Color::Red,
+    Color::Magenta,
+    Color::Brown,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightBlue,
+    Color::LightGreen,
+    Color::LightCyan,
+    Color::LightRed,
+    Color::Pink,
+    Color::Yellow,
+    Color::White,
+];
+
+/// The VGA colors for the ANSI colors 0 to 15 (black, red, green, yellow, blue, magenta,
+/// cyan, white, then their bright variants).
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::Yellow,
+    Color::LightBlue,
+    Color::Pink,
+    Color::LightCyan,
+    Color::White,
+];
+
+/// Returns the bright variant of a dark color.
+fn bright(color: Color) -> Color {
+    PALETTE[color as usize | 8]
+}
+
+/// The text attributes set by SGR escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    pub foreground: Color,
+    pub background: Color,
+    /// Shows the foreground in its bright variant.
+    pub bold: bool,
+    /// Swaps the foreground and background colors.
+    pub reverse: bool,
+}
+
+impl Attributes {
+    pub const DEFAULT: Attributes = Attributes {
+        foreground: Color::Yellow,
+        background: Color::Black,
+        bold: false,
+        reverse: false,
+    };
+
+    /// Returns the foreground and background colors to draw with.
+    pub fn colors(&self) -> (Color, Color) {
+        let foreground = if self.bold {
+            bright(self.foreground)
+        } else {
+            self.foreground
+        };
+        if self.reverse {
+            (self.background, foreground)
+        } else {
+            (foreground, self.background)
+        }
+    }
+
+    /// Applies the parameters of an SGR sequence (`ESC [ ... m`).
+    pub fn apply_sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            *self = Attributes::DEFAULT;
+        }
+        for &param in params {
+            match param {
+                0 => *self = Attributes::DEFAULT,
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.foreground = ANSI_COLORS[param as usize - 30],
+                39 => self.foreground = Attributes::DEFAULT.foreground,
+                40..=47 => self.background = ANSI_COLORS[param as usize - 40],
+                49 => self.background = Attributes::DEFAULT.background,
+                90..=97 => self.foreground = ANSI_COLORS[param as usize - 90 + 8],
+                100..=107 => self.background = ANSI_COLORS[param as usize - 100 + 8],
+                // the parameters of 256 and true color sequences would be misread as
+                // attributes
+                38 | 48 => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// A state machine that recognizes escape sequences in a stream of characters.
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            csi: Csi::new(),
+        }
+    }
+
+    /// Feeds the next character to the parser.
+    ///
+    /// Returns an action once a character, control character or complete escape
+    /// sequence was read. Unsupported escape sequences are dropped.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match self.state {
+            State::Ground => match c {
+                '\x1b' => {
+                    self.state = State::Escape;
+                    None
+                }
+                c if is_control(c) => Some(Action::Control(c)),
+                c => Some(Action::Print(c)),
+            },
+            State::Escape => {
+                self.state = State::Ground;
+                match c {
+                    '[' => {
+                        self.state = State::Csi;
+                        self.csi = Csi::new();
+                        None
+                    }
+                    '\x1b' => {
+                        self.state = State::Escape;
+                        None
+                    }
+                    '7' => Some(Action::SaveCursor),
+                    '8' => Some(Action::RestoreCursor),
+                    'c' => Some(Action::Reset),
+                    _ => None,
+                }
+            }
+            State::Csi => match c {
+                '0'..='9' => {
+                    self.csi.push_digit(c as u16 - '0' as u16);
+                    None
+                }
+                ';' => {
+                    self.csi.next_param();
+                    None
+                }
+                '?' => {
+                    self.csi.private = true;
+                    None
+                }
+                '\x40'..='\x7e' => {
+                    self.state = State::Ground;
+                    self.csi.final_byte = c as u8;
+                    Some(Action::Csi(self.csi))
+                }
+                '\x1b' => {
+                    self.state = State::Escape;
+                    None
+                }
+                // control characters are executed in the middle of a sequence
+                c if is_control(c) => Some(Action::Control(c)),
+                // intermediate bytes of sequences we don't support
+                _ => None,
+            },
+        }
+    }
+}
+
+fn is_control(c: char) -> bool {
+    (c as u32) < 0x20 || c == '\x7f'
+}
+
+#[test_case]
+fn test_sgr_attributes() {
+    let mut attributes = Attributes::DEFAULT;
+    attributes.apply_sgr(&[1, 34, 47]);
+    assert_eq!(attributes.colors(), (Color::LightBlue, Color::LightGray));
+    attributes.apply_sgr(&[7]);
+    assert_eq!(attributes.colors(), (Color::LightGray, Color::LightBlue));
+    attributes.apply_sgr(&[]);
+    assert_eq!(attributes, Attributes::DEFAULT);
+}
+
+/// Returns the actions for `s`, in a fixed-size array so that the tests don't allocate.
+#[cfg(test)]
+fn parse(s: &str) -> ([Option<Action>; 8], usize) {
+    let mut parser = Parser::new();
+    let mut actions = [None; 8];
+    let mut count = 0;
+    for action in s.chars().filter_map(|c| parser.advance(c)) {
+        actions[count] = Some(action);
+        count += 1;
+    }
+    (actions, count)
+}
+
+#[test_case]
+fn test_parse_sgr() {
+    let (actions, count) = parse("a\x1b[1;31mb");
+    assert_eq!(count, 3);
+    assert_eq!(actions[0], Some(Action::Print('a')));
+    match actions[1] {
+        Some(Action::Csi(csi)) => {
+            assert_eq!(csi.final_byte, b'm');
+            assert_eq!(csi.params(), &[1, 31]);
+            assert!(!csi.private);
+        }
+        other => panic!("expected CSI, got {:?}", other),
+    }
+    assert_eq!(actions[2], Some(Action::Print('b')));
+}
+
+#[test_case]
+fn test_parse_omitted_params() {
+    let (actions, count) = parse("\x1b[;5H\x1b[?25l");
+    assert_eq!(count, 2);
+    match actions[0] {
+        Some(Action::Csi(csi)) => {
+            assert_eq!(csi.params(), &[0, 5]);
+            assert_eq!(csi.param(0, 1), 1);
+            assert_eq!(csi.param(1, 1), 5);
+            assert_eq!(csi.param(2, 1), 1);
+        }
+        other => panic!("expected CSI, got {:?}", other),
+    }
+    match actions[1] {
+        Some(Action::Csi(csi)) => {
+            assert!(csi.private);
+            assert_eq!(csi.final_byte, b'l');
+        }
+        other => panic!("expected CSI, got {:?}", other),
+    }
+}
+
+#[test_case]
+fn test_parse_controls_and_escapes() {
+    let (actions, count) = parse("\r\n\x1b7\x1b8\x1b(B");
+    assert_eq!(count, 5);
+    assert_eq!(actions[0], Some(Action::Control('\r')));
+    assert_eq!(actions[1], Some(Action::Control('\n')));
+    assert_eq!(actions[2], Some(Action::SaveCursor));
+    assert_eq!(actions[3], Some(Action::RestoreCursor));
+    // the unsupported `ESC (` is dropped, the `B` is printed
+    assert_eq!(actions[4], Some(Action::Print('B')));
+}
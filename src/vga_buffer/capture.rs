@@ -0,0 +1,191 @@
+//! Snapshots of the screen contents for golden-output tests.
+//!
+//! A capture is rendered as text: the characters framed by a border, followed by one grid
+//! of hex digits for the foreground colors and one for the background colors. Golden files
+//! store this rendering, so a whole screen can be checked with one assertion.
+
+use super::{cp437, Buffer, ScreenChar, BUFFER_HEIGHT, BUFFER_WIDTH};
+use crate::serial_println;
+use core::fmt::{self, Write};
+
+/// The number of lines of the text rendering.
+const LINE_COUNT: usize = 3 * BUFFER_HEIGHT + 4;
+
+/// The characters and colors of all cells of the screen.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScreenCapture {
+    cells: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+impl ScreenCapture {
+    pub(super) fn new(cells: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT]) -> Self {
+        ScreenCapture { cells }
+    }
+
+    /// Captures the contents of the VGA text buffer.
+    pub(super) fn from_buffer() -> Self {
+        let mut cells = [[super::BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, line) in cells.iter_mut().enumerate() {
+            for (col, cell) in line.iter_mut().enumerate() {
+                *cell = Buffer::read(row, col);
+            }
+        }
+        ScreenCapture { cells }
+    }
+
+    /// Returns the code page 437 byte at `(row, col)`.
+    pub fn character(&self, row: usize, col: usize) -> u8 {
+        self.cells[row][col].ascii_character
+    }
+
+    /// Returns the foreground color index (0 to 15) at `(row, col)`.
+    pub fn foreground(&self, row: usize, col: usize) -> u8 {
+        self.cells[row][col].color_code.0 & 0x0f
+    }
+
+    /// Returns the background color index (0 to 15) at `(row, col)`.
+    pub fn background(&self, row: usize, col: usize) -> u8 {
+        self.cells[row][col].color_code.0 >> 4
+    }
+
+    /// Writes line `index` of the text rendering, without the line break.
+    fn write_line(&self, index: usize, f: &mut impl Write) -> fmt::Result {
+        let border = |f: &mut dyn Write| -> fmt::Result {
+            f.write_char('+')?;
+            for _ in 0..BUFFER_WIDTH {
+                f.write_char('-')?;
+            }
+            f.write_char('+')
+        };
+        let colors =
+            |f: &mut dyn Write, row: usize, color: fn(&Self, usize, usize) -> u8| -> fmt::Result {
+                for col in 0..BUFFER_WIDTH {
+                    write!(f, "{:x}", color(self, row, col))?;
+                }
+                Ok(())
+            };
+
+        match index {
+            0 => border(f),
+            1..=BUFFER_HEIGHT => {
+                let row = index - 1;
+                f.write_char('|')?;
+                for col in 0..BUFFER_WIDTH {
+                    match cp437::decode(self.character(row, col)) {
+                        '\0' => f.write_char(' ')?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('|')
+            }
+            i if i == BUFFER_HEIGHT + 1 => border(f),
+            i if i == BUFFER_HEIGHT + 2 => f.write_str("foreground:"),
+            i if i < 2 * BUFFER_HEIGHT + 3 => colors(f, i - BUFFER_HEIGHT - 3, Self::foreground),
+            i if i == 2 * BUFFER_HEIGHT + 3 => f.write_str("background:"),
+            i => colors(f, i - 2 * BUFFER_HEIGHT - 4, Self::background),
+        }
+    }
+
+    /// Compares the text rendering with a golden file.
+    ///
+    /// Line endings and a trailing newline in `golden` are ignored. Returns the number of
+    /// the first differing line (starting at 1) on a mismatch.
+    pub fn compare(&self, golden: &str) -> Result<(), usize> {
+        let mut lines = golden.lines();
+        for index in 0..LINE_COUNT {
+            let mut line = LineBuffer::new();
+            self.write_line(index, &mut line).map_err(|_| index + 1)?;
+            match lines.next() {
+                Some(expected) if expected.trim_end_matches('\r') == line.as_str() => {}
+                _ => return Err(index + 1),
+            }
+        }
+        match lines.next() {
+            None => Ok(()),
+            Some(_) => Err(LINE_COUNT + 1),
+        }
+    }
+
+    /// Panics if the capture doesn't match the golden file, after printing the capture to
+    /// the serial port so that the golden file can be updated from it.
+    pub fn assert_matches(&self, name: &str, golden: &str) {
+        if let Err(line) = self.compare(golden) {
+            serial_println!("\n--- actual screen for golden file '{}' ---", name);
+            serial_println!("{}--- end of screen ---", self);
+            panic!(
+                "screen differs from golden file '{}' in line {}",
+                name, line
+            );
+        }
+    }
+}
+
+impl fmt::Display for ScreenCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for index in 0..LINE_COUNT {
+            self.write_line(index, f)?;
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ScreenCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// The maximum length of a line of the text rendering: a row of characters that each take
+/// 3 bytes in UTF-8, and the borders.
+const LINE_CAPACITY: usize = 3 * BUFFER_WIDTH + 2;
+
+/// A fixed-size buffer for one line of the text rendering, so that comparing doesn't
+/// allocate.
+struct LineBuffer {
+    bytes: [u8; LINE_CAPACITY],
+    len: usize,
+}
+
+impl LineBuffer {
+    fn new() -> Self {
+        LineBuffer {
+            bytes: [0; LINE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // only whole `str`s are ever copied in
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > LINE_CAPACITY {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_compare_detects_differences() {
+    let mut cells = [[super::BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
+    cells[0][0].ascii_character = b'x';
+    let capture = ScreenCapture::new(cells);
+    assert_eq!(capture.character(0, 0), b'x');
+    assert_eq!(capture.foreground(0, 0), 0xe);
+    assert_eq!(capture.background(0, 0), 0);
+    assert_eq!(capture.compare(""), Err(1));
+
+    // the first row of characters is the second line
+    let mut line = LineBuffer::new();
+    capture.write_line(1, &mut line).expect("line too long");
+    assert!(line.as_str().starts_with("|x "));
+    assert!(line.as_str().ends_with(" |"));
+}
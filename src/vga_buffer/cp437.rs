@@ -0,0 +1,86 @@
+//! Conversion between Unicode and code page 437, the character set of the VGA font.
+
+/// The characters shown for the bytes 0x00 to 0x1f, which are glyphs in the VGA font.
+const LOW: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// The character shown for 0x7f.
+const HOUSE: char = '⌂';
+
+/// The characters shown for the bytes 0x80 to 0xff.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Characters that look like a glyph of the font, but aren't the character the code page
+/// assigns to it.
+const ALIASES: [(char, u8); 4] = [
+    // Greek small letter beta
+    ('β', 0xe1),
+    // Greek small letter mu
+    ('μ', 0xe6),
+    // ohm sign
+    ('\u{2126}', 0xea),
+    // element of
+    ('∈', 0xee),
+];
+
+/// Returns the code page 437 byte showing `c`, if the VGA font has a glyph for it.
+///
+/// Control characters have no byte, because the consoles interpret them.
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        HOUSE => Some(0x7f),
+        '\0' => None,
+        _ => LOW
+            .iter()
+            .position(|&low| low == c)
+            .or_else(|| HIGH.iter().position(|&high| high == c).map(|i| i + 0x80))
+            .map(|i| i as u8)
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|&&(alias, _)| alias == c)
+                    .map(|&(_, byte)| byte)
+            }),
+    }
+}
+
+/// Returns the character shown for the given code page 437 byte.
+pub fn decode(byte: u8) -> char {
+    match byte {
+        0x00..=0x1f => LOW[byte as usize],
+        0x7f => HOUSE,
+        0x20..=0x7e => byte as char,
+        _ => HIGH[byte as usize - 0x80],
+    }
+}
+
+#[test_case]
+fn test_encode() {
+    assert_eq!(encode('a'), Some(b'a'));
+    assert_eq!(encode('é'), Some(0x82));
+    assert_eq!(encode('│'), Some(0xb3));
+    assert_eq!(encode('░'), Some(0xb0));
+    assert_eq!(encode('☺'), Some(0x01));
+    assert_eq!(encode('β'), Some(0xe1));
+    assert_eq!(encode('€'), None);
+    assert_eq!(encode('\n'), None);
+}
+
+#[test_case]
+fn test_round_trip() {
+    for byte in 0x01..=0xffu8 {
+        assert_eq!(encode(decode(byte)), Some(byte));
+    }
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use blog_os::{
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    drivers::ahci,
+    task::simple_executor::block_on,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    assert_eq!(ahci::init(), 1);
+
+    test_main();
+    loop {}
+}
+
+/// The test runner attaches an empty 16 MiB image, with writes discarded when QEMU exits,
+/// to the first port of an `ich9-ahci` controller.
+fn disk() -> Arc<dyn BlockDevice + Send + Sync> {
+    block::device("sata0").expect("SATA disk not detected")
+}
+
+#[test_case]
+fn disk_is_detected() {
+    let disk = disk();
+    assert_eq!(disk.sector_size(), SECTOR_SIZE);
+    assert_eq!(disk.capacity(), 16 * 1024 * 1024);
+    assert!(!disk.model().is_empty());
+}
+
+// The second write leaves its pattern in the bounce frames, so reading the first pattern
+// back shows that the data was transferred by DMA both ways.
+#[test_case]
+fn read_after_write_transfers_data() {
+    let disk = disk();
+    let pattern: Vec<u8> = (0..8 * SECTOR_SIZE).map(|i| (i % 251) as u8).collect();
+    block_on(disk.write(8, &pattern)).expect("writing sectors failed");
+    block_on(disk.write(16, &[0x5a; 8 * SECTOR_SIZE])).expect("writing sectors failed");
+    block_on(disk.flush()).expect("flushing failed");
+
+    let mut read_back = vec![0xff; 8 * SECTOR_SIZE];
+    block_on(disk.read(8, &mut read_back)).expect("reading sectors failed");
+    assert_eq!(read_back, pattern);
+}
+
+#[test_case]
+fn dropped_request_releases_the_port() {
+    use core::task::Context;
+    use futures_util::task::noop_waker;
+
+    let disk = disk();
+    let mut buf = vec![0; 64 * SECTOR_SIZE];
+    let waker = noop_waker();
+    let mut context = Context::from_waker(&waker);
+    let mut read = disk.read(64, &mut buf);
+    // the command may complete before the first poll returns
+    let _ = read.as_mut().poll(&mut context);
+    drop(read);
+
+    let mut sector = [0xff; SECTOR_SIZE];
+    block_on(disk.read(disk.sector_count() - 1, &mut sector)).expect("reading failed");
+    assert!(sector.iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn large_read_spans_several_commands() {
+    let disk = disk();
+    let mut buf = vec![0xff; 300 * SECTOR_SIZE];
+    block_on(disk.read(disk.sector_count() - 300, &mut buf)).expect("reading sectors failed");
+    assert!(buf.iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn invalid_requests_are_rejected() {
+    let disk = disk();
+    let mut sector = [0; SECTOR_SIZE];
+    let end = disk.sector_count();
+    assert_eq!(
+        block_on(disk.read(end, &mut sector)),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        block_on(disk.write(0, &sector[..100])),
+        Err(BlockError::InvalidBuffer)
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
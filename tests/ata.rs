@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec};
+use blog_os::{
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    drivers::ata,
+    task::simple_executor::block_on,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    ata::init();
+
+    test_main();
+    loop {}
+}
+
+/// QEMU attaches the boot image as the primary master.
+fn boot_disk() -> Arc<dyn BlockDevice + Send + Sync> {
+    block::device("ata0").expect("boot disk not detected")
+}
+
+/// The test runner attaches an empty 4 MiB image as the primary slave, with writes
+/// discarded when QEMU exits.
+fn scratch_disk() -> Arc<dyn BlockDevice + Send + Sync> {
+    block::device("ata1").expect("scratch disk not detected")
+}
+
+#[test_case]
+fn boot_disk_is_detected() {
+    let disk = boot_disk();
+    assert_eq!(disk.sector_size(), SECTOR_SIZE);
+    assert!(disk.sector_count() > 0);
+    assert!(!disk.model().is_empty());
+}
+
+#[test_case]
+fn boot_sector_has_signature() {
+    let disk = boot_disk();
+    let mut sector = [0; SECTOR_SIZE];
+    block_on(disk.read(0, &mut sector)).expect("reading boot sector failed");
+    assert_eq!(&sector[510..], &[0x55, 0xaa]);
+}
+
+#[test_case]
+fn multi_sector_read_matches_single_reads() {
+    let disk = boot_disk();
+    let mut all = vec![0; 4 * SECTOR_SIZE];
+    block_on(disk.read(0, &mut all)).expect("reading sectors failed");
+    for (index, expected) in all.chunks(SECTOR_SIZE).enumerate() {
+        let mut sector = [0; SECTOR_SIZE];
+        block_on(disk.read(index as u64, &mut sector)).expect("reading sector failed");
+        assert_eq!(&sector[..], expected);
+    }
+}
+
+#[test_case]
+fn scratch_disk_is_detected() {
+    let disk = scratch_disk();
+    assert_eq!(disk.capacity(), 4 * 1024 * 1024);
+}
+
+#[test_case]
+fn written_sector_reads_back() {
+    let disk = scratch_disk();
+    let last = disk.sector_count() - 1;
+    let pattern: [u8; SECTOR_SIZE] = core::array::from_fn(|i| i as u8 ^ 0x5a);
+    block_on(disk.write(last, &pattern)).expect("writing sector failed");
+    block_on(disk.flush()).expect("flushing failed");
+    let mut read_back = [0; SECTOR_SIZE];
+    block_on(disk.read(last, &mut read_back)).expect("reading sector failed");
+    assert_eq!(&read_back[..], &pattern[..]);
+
+    // the boot disk on the same channel is unaffected
+    let mut sector = [0; SECTOR_SIZE];
+    block_on(boot_disk().read(0, &mut sector)).expect("reading boot sector failed");
+    assert_eq!(&sector[510..], &[0x55, 0xaa]);
+}
+
+// Dropping a write after its first sector leaves the drive waiting for data; the channel
+// has to be usable afterwards.
+#[test_case]
+fn dropped_request_releases_the_channel() {
+    use core::task::{Context, Poll};
+    use futures_util::task::noop_waker;
+
+    let disk = scratch_disk();
+    let data = vec![0x33; 4 * SECTOR_SIZE];
+    let mut write = disk.write(0, &data);
+    let waker = noop_waker();
+    let mut context = Context::from_waker(&waker);
+    assert_eq!(write.as_mut().poll(&mut context), Poll::Pending);
+    drop(write);
+
+    let mut sector = [0; SECTOR_SIZE];
+    block_on(boot_disk().read(0, &mut sector)).expect("reading after a dropped write failed");
+    assert_eq!(&sector[510..], &[0x55, 0xaa]);
+}
+
+#[test_case]
+fn invalid_requests_are_rejected() {
+    let disk = boot_disk();
+    let mut sector = [0; SECTOR_SIZE];
+    let end = disk.sector_count();
+    assert_eq!(
+        block_on(disk.read(end, &mut sector)),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        block_on(disk.read(0, &mut sector[..100])),
+        Err(BlockError::InvalidBuffer)
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use blog_os::{
+    allocator, config,
+    input::{self, Layout},
+    log::{self, Level},
+    memory::vma::{self, Backing},
+    serial::{self, ComPort},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+const CMDLINE: &str = "heap_size=256K layout=de log=warn,config=debug com2=9600,7e1 \
+                       log_port=com2 init=\"translate 0xb8000\" executor_queue=0";
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    config::init(CMDLINE);
+    blog_os::test_init(boot_info);
+
+    // the test output goes to COM1, so only the settings are checked
+    serial::set_log_port(ComPort::Com1).expect("resetting log port failed");
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn heap_size_is_configured() {
+    assert_eq!(allocator::heap_size(), 256 * 1024);
+    // the eagerly mapped part of the heap has the configured size, the rest grows lazily
+    let heap = vma::find(VirtAddr::new(allocator::HEAP_START as u64)).expect("heap not registered");
+    assert_eq!((heap.name, heap.end - heap.start), ("heap", 256 * 1024));
+    let growth = vma::find(heap.end).expect("heap growth not registered");
+    assert_eq!(growth.backing, Backing::Lazy);
+    let vec: Vec<u8> = Vec::with_capacity(200 * 1024);
+    assert!(vec.capacity() >= 200 * 1024);
+}
+
+#[test_case]
+fn boot_settings_are_applied() {
+    assert_eq!(input::layout(), Layout::De);
+    assert!(!log::enabled(Level::Info, "blog_os::task"));
+    assert!(log::enabled(Level::Debug, "config"));
+    let com2 = serial::with_port(ComPort::Com2, |uart| uart.config());
+    assert_eq!(com2.map(|config| config.baud()), Some(9600));
+}
+
+#[test_case]
+fn typed_getters() {
+    assert_eq!(config::get("init"), Some("translate 0xb8000"));
+    assert_eq!(config::get_size("heap_size", 0), 256 * 1024);
+    assert_eq!(config::get_usize("spawn_queue", 100), 100);
+    assert!(config::get_bool("shell", true));
+    // invalid values fall back to the default
+    assert_eq!(config::get_capacity("executor_queue", 100), 100);
+    assert_eq!(config::get_usize("layout", 7), 7);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
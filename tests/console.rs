@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::{
+    println,
+    vga_buffer::{BUFFER_HEIGHT, CONSOLES},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn scrolled_off_lines_can_be_viewed() {
+    println!("first line");
+    for _ in 0..BUFFER_HEIGHT {
+        println!("filler");
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        assert_ne!(writer.char_at(0, 0), b'f');
+        writer.scroll_up(1);
+        assert!(writer.is_scrolled());
+        assert_eq!(writer.char_at(0, 0), b'f');
+        assert_eq!(writer.char_at(0, 1), b'i');
+        writer.scroll_to_bottom();
+        assert!(!writer.is_scrolled());
+        assert_eq!(writer.char_at(BUFFER_HEIGHT - 2, 0), b'f');
+    });
+}
+
+#[test_case]
+fn output_while_scrolled_back_is_not_shown() {
+    interrupts::without_interrupts(|| CONSOLES[0].lock().scroll_up(BUFFER_HEIGHT));
+    println!("hidden");
+
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        assert_ne!(writer.char_at(BUFFER_HEIGHT - 2, 0), b'h');
+        writer.scroll_to_bottom();
+        assert_eq!(writer.char_at(BUFFER_HEIGHT - 2, 0), b'h');
+    });
+}
+
+#[test_case]
+fn scrolling_is_limited_to_the_history() {
+    interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        writer.scroll_up(usize::MAX / 2);
+        assert!(writer.is_scrolled());
+        writer.scroll_down(usize::MAX / 2);
+        assert!(!writer.is_scrolled());
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
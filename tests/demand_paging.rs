@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::HEAP_SIZE;
+use blog_os::memory::{
+    self, inspect, stack,
+    vma::{self, Area, AreaError, AreaKind, Backing},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{structures::paging::PageTableFlags, PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+const LAZY_AREA_START: u64 = 0x_5000_0000_0000;
+
+#[test_case]
+fn lazy_area_is_mapped_on_access() {
+    let start = VirtAddr::new(LAZY_AREA_START);
+    vma::register(Area {
+        name: "test",
+        start,
+        end: start + 4 * 4096u64,
+        kind: AreaKind::User,
+        backing: Backing::Lazy,
+        flags: PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+    })
+    .expect("registering area failed");
+
+    let ptr: *mut u64 = (start + 2 * 4096u64 + 8u64).as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+
+    vma::unregister(start).expect("area not registered");
+    assert!(vma::find(start).is_none());
+}
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    let n = HEAP_SIZE / 8 * 2;
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<usize>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn overlapping_area_is_rejected() {
+    let start = VirtAddr::new(LAZY_AREA_START + 0x10_0000);
+    let area = Area {
+        name: "test",
+        start,
+        end: start + 4096u64,
+        kind: AreaKind::User,
+        backing: Backing::Lazy,
+        flags: PageTableFlags::PRESENT,
+    };
+    vma::register(area).expect("registering area failed");
+    assert!(vma::register(area).is_err());
+    vma::unregister(start);
+}
+
+#[test_case]
+fn kernel_stack_has_guard_page() {
+    let kernel_stack = stack::allocate("test stack", 2).expect("stack allocation failed");
+
+    let guard = vma::find(kernel_stack.guard_page()).expect("guard page not registered");
+    assert_eq!(guard.kind, AreaKind::Guard);
+    assert_eq!(guard.name, "test stack");
+    assert_eq!(kernel_stack.bottom(), kernel_stack.guard_page() + 4096u64);
+
+    let ptr: *mut u64 = (kernel_stack.top() - 8u64).as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(7);
+        assert_eq!(ptr.read_volatile(), 7);
+    }
+    stack::free(kernel_stack);
+}
+
+#[test_case]
+fn exhausted_stack_region_is_not_consumed() {
+    let first = stack::allocate("first", 1).expect("stack allocation failed");
+    assert!(matches!(
+        stack::allocate("huge", stack::KERNEL_STACK_REGION_SIZE / 4096),
+        Err(AreaError::Exhausted)
+    ));
+    assert!(matches!(
+        stack::allocate("overflowing", u64::MAX),
+        Err(AreaError::Exhausted)
+    ));
+    let second = stack::allocate("second", 1).expect("stack allocation failed");
+    assert_eq!(second.guard_page(), first.guard_page() + 2 * 4096u64);
+    stack::free(first);
+    stack::free(second);
+}
+
+#[test_case]
+fn translation_matches_physical_memory_mapping() {
+    let value = Box::new(0x1234_5678_u64);
+    let addr = VirtAddr::from_ptr(&*value);
+    let translation = inspect::translate(addr).expect("heap address not mapped");
+    assert!(translation.flags.contains(PageTableFlags::WRITABLE));
+
+    // the same memory through the mapping of the complete physical memory
+    let alias = memory::physical_memory_offset() + translation.phys_addr.as_u64();
+    assert_eq!(unsafe { *alias.as_ptr::<u64>() }, 0x1234_5678);
+    let offset = inspect::translate(alias).expect("physical memory not mapped");
+    assert_eq!(offset.phys_addr, translation.phys_addr);
+
+    assert_eq!(inspect::translate(VirtAddr::new(0)), None);
+}
+
+#[test_case]
+fn physical_memory_mapping_is_merged() {
+    let offset = memory::physical_memory_offset();
+    let mut found = None;
+    inspect::for_each_mapped_range(|range| {
+        if range.start <= offset && offset < range.end {
+            found = Some(*range);
+        }
+    });
+    let range = found.expect("physical memory mapping not listed");
+    assert_eq!(range.phys_start + (offset - range.start), PhysAddr::new(0));
+    // the physical memory is mapped contiguously, so it's listed as one range
+    assert!(range.len() >= 1024 * 1024);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use blog_os::{
+    drivers::bga,
+    graphics::{
+        console::{self, FramebufferConsole},
+        font::{Font, FontError},
+        Framebuffer, Rgb,
+    },
+    vga_buffer::{self, Color},
+};
+use bootloader::{entry_point, BootInfo};
+use core::{fmt::Write, panic::PanicInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 16;
+
+/// Returns a framebuffer over freshly allocated memory.
+fn memory_framebuffer() -> Framebuffer {
+    let pixels = vec![0u32; WIDTH * HEIGHT].leak();
+    unsafe { Framebuffer::new(pixels.as_mut_ptr(), WIDTH, HEIGHT, WIDTH) }
+}
+
+/// A PSF2 font with two 8x2 glyphs: an empty one for ' ' and a checkered one for 'A'/'a'.
+const PSF2_FONT: &[u8] = &[
+    0x72, 0xb5, 0x4a, 0x86, // magic
+    0, 0, 0, 0, // version
+    32, 0, 0, 0, // header size
+    1, 0, 0, 0, // flags: has unicode table
+    2, 0, 0, 0, // glyph count
+    2, 0, 0, 0, // bytes per glyph
+    2, 0, 0, 0, // height
+    8, 0, 0, 0, // width
+    0x00, 0x00, // ' '
+    0xaa, 0x55, // 'A'
+    b' ', 0xff, // unicode table
+    b'A', b'a', 0xff,
+];
+
+#[test_case]
+fn drawing_is_clipped() {
+    let mut framebuffer = memory_framebuffer();
+    let red = Rgb::new(0xff, 0, 0);
+    framebuffer.fill_rect(WIDTH - 2, HEIGHT - 2, 10, 10, red);
+    assert_eq!(framebuffer.pixel(WIDTH - 1, HEIGHT - 1), Some(red));
+    assert_eq!(framebuffer.pixel(WIDTH - 3, HEIGHT - 1), Some(Rgb::BLACK));
+    assert_eq!(framebuffer.pixel(WIDTH, 0), None);
+}
+
+#[test_case]
+fn lines_include_both_ends() {
+    let mut framebuffer = memory_framebuffer();
+    framebuffer.draw_line(0, 0, 7, 7, Rgb::WHITE);
+    framebuffer.draw_line(10, 3, 4, 3, Rgb::WHITE);
+    for i in 0..8 {
+        assert_eq!(framebuffer.pixel(i, i), Some(Rgb::WHITE));
+    }
+    assert_eq!(framebuffer.pixel(1, 0), Some(Rgb::BLACK));
+    for x in 4..=10 {
+        assert_eq!(framebuffer.pixel(x, 3), Some(Rgb::WHITE));
+    }
+}
+
+#[test_case]
+fn blit_and_scroll() {
+    let mut framebuffer = memory_framebuffer();
+    let blue = Rgb::new(0, 0, 0xff);
+    framebuffer.blit(1, 2, 2, 1, &[Rgb::WHITE, blue]);
+    assert_eq!(framebuffer.pixel(1, 2), Some(Rgb::WHITE));
+    assert_eq!(framebuffer.pixel(2, 2), Some(blue));
+
+    framebuffer.scroll_up(2, blue);
+    assert_eq!(framebuffer.pixel(1, 0), Some(Rgb::WHITE));
+    assert_eq!(framebuffer.pixel(1, HEIGHT - 1), Some(blue));
+}
+
+#[test_case]
+fn psf2_font_is_parsed() {
+    let font = Font::parse(PSF2_FONT).expect("parsing font failed");
+    assert_eq!((font.width(), font.height()), (8, 2));
+    assert_eq!(font.glyph_index('a'), Some(1));
+    assert_eq!(font.glyph_index(' '), Some(0));
+    assert_eq!(font.glyph_index('b'), None);
+    assert!(font.is_set(1, 0, 0));
+    assert!(!font.is_set(1, 1, 0));
+    assert!(font.is_set(1, 1, 1));
+}
+
+#[test_case]
+fn psf1_font_is_parsed() {
+    let mut data = vec![0x36, 0x04, 0x00, 1];
+    data.extend(0..=255u8);
+    let font = Font::parse(&data).expect("parsing font failed");
+    assert_eq!((font.width(), font.height()), (8, 1));
+    // without a unicode table, glyphs are in code page 437 order
+    assert_eq!(font.glyph_index('é'), Some(0x82));
+    assert!(font.is_set(0x82, 0, 0));
+    assert!(!font.is_set(0x82, 1, 0));
+
+    assert_eq!(Font::parse(&data[..100]).err(), Some(FontError::Truncated));
+    assert_eq!(Font::parse(b"font").err(), Some(FontError::UnknownFormat));
+}
+
+#[test_case]
+fn console_draws_glyphs_in_color() {
+    let font = Font::parse(PSF2_FONT).expect("parsing font failed");
+    let mut console = FramebufferConsole::new(memory_framebuffer(), font);
+    assert_eq!((console.columns(), console.rows()), (WIDTH / 8, HEIGHT / 2));
+
+    write!(console, " \x1b[32mA").expect("write failed");
+    assert_eq!(console.position(), (0, 2));
+    let green = console::rgb(Color::Green);
+    let framebuffer = console.framebuffer();
+    assert_eq!(framebuffer.pixel(8, 0), Some(green));
+    assert_eq!(framebuffer.pixel(9, 0), Some(console::rgb(Color::Black)));
+    assert_eq!(framebuffer.pixel(0, 0), Some(console::rgb(Color::Black)));
+}
+
+#[test_case]
+fn bga_mode_can_be_set() {
+    assert!(bga::is_present());
+    let mut framebuffer = bga::set_mode(640, 480).expect("setting mode failed");
+    assert_eq!((framebuffer.width(), framebuffer.height()), (640, 480));
+    framebuffer.set_pixel(639, 479, Rgb::WHITE);
+    assert_eq!(framebuffer.pixel(639, 479), Some(Rgb::WHITE));
+
+    bga::disable();
+    vga_buffer::enable_text_mode();
+    assert!(!bga::is_enabled());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
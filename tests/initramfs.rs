@@ -0,0 +1,264 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use blog_os::fs::{
+    self,
+    initramfs::{self, ArchiveError},
+    tmpfs::TmpFs,
+    FileType, FsError, OpenOptions, SeekFrom,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    assert!(initramfs::init() > 0, "the embedded archive is empty");
+
+    test_main();
+    loop {}
+}
+
+/// Appends a newc cpio entry.
+fn cpio_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let fields = [
+        1,
+        mode,
+        0,
+        0,
+        1,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields.iter() {
+        archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize((archive.len() + 3) & !3, 0);
+    archive.extend_from_slice(data);
+    archive.resize((archive.len() + 3) & !3, 0);
+}
+
+/// Appends a ustar entry.
+fn tar_entry(archive: &mut Vec<u8>, prefix: &str, name: &str, typeflag: u8, data: &[u8]) {
+    let mut header = vec![0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize((archive.len() + 511) & !511, 0);
+}
+
+fn names(path: &str) -> Vec<(String, FileType)> {
+    fs::read_dir(path)
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.name, entry.file_type))
+        .collect()
+}
+
+#[test_case]
+fn embedded_archive_is_unpacked() {
+    assert!(fs::mounts().contains(&(String::from("/"), "tmpfs")));
+    assert_eq!(fs::read("/etc/motd").unwrap(), b"Welcome to blog_os!\n");
+    assert!(fs::metadata("/etc").unwrap().is_dir());
+}
+
+#[test_case]
+fn cpio_archives_are_unpacked() {
+    let mut archive = Vec::new();
+    cpio_entry(&mut archive, ".", 0o040_755, b"");
+    // the parent directory follows its file
+    cpio_entry(&mut archive, "bin/hello", 0o100_755, b"hello\n");
+    cpio_entry(&mut archive, "bin", 0o040_755, b"");
+    cpio_entry(&mut archive, "bin/link", 0o120_777, b"hello");
+    cpio_entry(&mut archive, "TRAILER!!!", 0, b"");
+
+    fs::create_dir("/cpio").unwrap();
+    assert_eq!(initramfs::unpack(&archive, "/cpio"), Ok(2));
+    assert_eq!(
+        names("/cpio/bin"),
+        [(String::from("hello"), FileType::File)]
+    );
+    assert_eq!(fs::read("/cpio/bin/hello").unwrap(), b"hello\n");
+
+    // unpacking again replaces the files
+    assert_eq!(initramfs::unpack(&archive, "/cpio"), Ok(2));
+    assert_eq!(fs::read("/cpio/bin/hello").unwrap(), b"hello\n");
+}
+
+#[test_case]
+fn tar_archives_are_unpacked() {
+    let mut archive = Vec::new();
+    tar_entry(&mut archive, "", "./deep/", b'5', b"");
+    tar_entry(&mut archive, "deep/nested", "file.txt", b'0', &[b'x'; 600]);
+    tar_entry(&mut archive, "", "deep/link", b'2', b"");
+    archive.extend_from_slice(&[0; 1024]);
+
+    fs::create_dir("/tar").unwrap();
+    assert_eq!(initramfs::unpack(&archive, "/tar"), Ok(2));
+    assert_eq!(
+        names("/tar/deep"),
+        [(String::from("nested"), FileType::Directory)]
+    );
+    assert_eq!(fs::read("/tar/deep/nested/file.txt").unwrap(), [b'x'; 600]);
+}
+
+#[test_case]
+fn invalid_archives_are_rejected() {
+    fs::create_dir("/bad").unwrap();
+    assert_eq!(
+        initramfs::unpack(b"not an archive", "/bad"),
+        Err(ArchiveError::UnknownFormat)
+    );
+
+    let mut escaping = Vec::new();
+    cpio_entry(&mut escaping, "../escaped", 0o100_644, b"");
+    assert_eq!(
+        initramfs::unpack(&escaping, "/bad"),
+        Err(ArchiveError::InvalidPath)
+    );
+    assert_eq!(fs::metadata("/escaped"), Err(FsError::NotFound));
+
+    let mut truncated = Vec::new();
+    tar_entry(&mut truncated, "", "file", b'0', &[0; 100]);
+    truncated.truncate(550);
+    assert_eq!(
+        initramfs::unpack(&truncated, "/bad"),
+        Err(ArchiveError::Truncated)
+    );
+
+    let mut corrupted = Vec::new();
+    tar_entry(&mut corrupted, "", "file", b'0', b"");
+    corrupted[0] = b'g';
+    assert_eq!(
+        initramfs::unpack(&corrupted, "/bad"),
+        Err(ArchiveError::InvalidHeader)
+    );
+
+    let mut missing = Vec::new();
+    cpio_entry(&mut missing, "file", 0o100_644, b"");
+    cpio_entry(&mut missing, "TRAILER!!!", 0, b"");
+    assert_eq!(
+        initramfs::unpack(&missing, "/missing"),
+        Err(ArchiveError::Fs(FsError::NotFound))
+    );
+}
+
+#[test_case]
+fn tmpfs_capacity_is_enforced() {
+    let tmpfs = Arc::new(TmpFs::with_capacity(16));
+    fs::create_dir("/small").unwrap();
+    fs::mount("/small", tmpfs.clone()).unwrap();
+
+    fs::write("/small/a", &[1; 10]).unwrap();
+    assert_eq!(tmpfs.used(), 10);
+    assert_eq!(fs::write("/small/b", &[2; 10]), Err(FsError::NoSpace));
+    fs::write("/small/a", &[3; 4]).unwrap();
+    assert_eq!(tmpfs.used(), 4);
+    fs::write("/small/b", &[2; 10]).unwrap();
+    assert_eq!(tmpfs.used(), 14);
+
+    fs::remove("/small/a").unwrap();
+    fs::remove("/small/b").unwrap();
+    assert_eq!(tmpfs.used(), 0);
+    fs::unmount("/small").unwrap();
+}
+
+#[test_case]
+fn writes_beyond_the_heap_fail() {
+    let tmpfs = Arc::new(TmpFs::new());
+    fs::create_dir("/huge").unwrap();
+    fs::mount("/huge", tmpfs.clone()).unwrap();
+
+    for path in ["/huge/file", "/far"].iter() {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(path)
+            .unwrap();
+        file.seek(SeekFrom::Start(1 << 40)).unwrap();
+        assert_eq!(file.write(b"x"), Err(FsError::NoSpace));
+        assert_eq!(file.metadata().size, 0);
+        drop(file);
+        fs::remove(path).unwrap();
+    }
+    assert_eq!(tmpfs.used(), 0);
+    fs::unmount("/huge").unwrap();
+}
+
+#[test_case]
+fn tmpfs_handles_keep_offsets() {
+    fs::write("/seek", b"0123456789").unwrap();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/seek")
+        .unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(file.read(&mut buf), Ok(4));
+    assert_eq!(&buf, b"0123");
+    assert_eq!(file.seek(SeekFrom::End(-2)), Ok(8));
+    assert_eq!(file.read(&mut buf), Ok(2));
+    assert_eq!(file.read(&mut buf), Ok(0));
+    file.seek(SeekFrom::Start(1)).unwrap();
+    file.write(b"ab").unwrap();
+    assert_eq!(fs::read("/seek").unwrap(), b"0ab3456789");
+
+    // writing past the end fills the gap with zeros
+    file.seek(SeekFrom::Start(12)).unwrap();
+    file.write(b"!").unwrap();
+    assert_eq!(fs::read("/seek").unwrap(), b"0ab3456789\0\0!");
+    drop(file);
+    fs::remove("/seek").unwrap();
+}
+
+#[test_case]
+fn tmpfs_removal_checks_directories() {
+    fs::create_dir("/full").unwrap();
+    fs::write("/full/file", b"x").unwrap();
+    assert_eq!(fs::remove("/full"), Err(FsError::DirectoryNotEmpty));
+    fs::remove("/full/file").unwrap();
+    fs::remove("/full").unwrap();
+    assert_eq!(fs::metadata("/full"), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn tmpfs_mounts_report_their_type() {
+    fs::create_dir("/mnt").unwrap();
+    fs::write("/mnt/hidden", b"").unwrap();
+    fs::mount("/mnt", Arc::new(TmpFs::new())).unwrap();
+    assert!(fs::read_dir("/mnt").unwrap().is_empty());
+    assert!(fs::mounts().contains(&(String::from("/mnt"), "tmpfs")));
+
+    fs::unmount("/mnt").unwrap();
+    assert!(fs::metadata("/mnt/hidden").is_ok());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
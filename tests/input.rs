@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::input::{self, Decoder, Layout};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use pc_keyboard::KeyCode;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn events_are_published_to_all_subscribers() {
+    let first = input::subscribe();
+    let second = input::subscribe();
+
+    let mut decoder = Decoder::new(Layout::Us);
+    let event = decoder.add_byte(0x1e).expect("no event for key press");
+    input::publish(event);
+
+    for events in [&first, &second].iter() {
+        let received = events.try_next().expect("event not received");
+        assert_eq!(received.code, KeyCode::A);
+        assert!(received.pressed);
+        assert_eq!(received.character, Some('a'));
+        assert!(events.try_next().is_none());
+    }
+}
+
+#[test_case]
+fn dropping_a_subscriber_keeps_others_subscribed() {
+    let events = input::subscribe();
+    drop(input::subscribe());
+
+    let mut decoder = Decoder::new(Layout::Us);
+    let event = decoder.add_byte(0x9e).expect("no event for key release");
+    input::publish(event);
+
+    let received = events.try_next().expect("event not received");
+    assert!(!received.pressed);
+    assert_eq!(received.character, None);
+}
+
+#[test_case]
+fn layout_can_be_switched() {
+    assert_eq!(input::layout(), Layout::Us);
+    for &layout in Layout::ALL.iter() {
+        input::set_layout(layout);
+        assert_eq!(input::layout(), layout);
+        assert_eq!(Layout::from_name(layout.name()), Some(layout));
+    }
+    input::set_layout(Layout::Us);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,201 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, sync::Arc};
+use blog_os::task::{
+    job::{self, JobState},
+    simple_executor::block_on,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    future::{self, Future},
+    panic::PanicInfo,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+fn state(id: job::JobId) -> Option<(JobState, bool)> {
+    let mut state = None;
+    job::for_each(|job| {
+        if job.id == id {
+            state = Some((job.state, job.foreground));
+        }
+    });
+    state
+}
+
+/// A future that never completes and records how often it was polled and whether it was
+/// dropped.
+#[derive(Clone, Default)]
+struct Probe {
+    polls: Arc<AtomicUsize>,
+    dropped: Arc<AtomicBool>,
+    /// Whether the future looks at its cancellation signal.
+    observes_cancellation: bool,
+}
+
+impl Probe {
+    fn polls(&self) -> usize {
+        self.polls.load(Ordering::Relaxed)
+    }
+
+    fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Future for Probe {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        if self.observes_cancellation {
+            // ignores the cancellation after noticing it
+            job::is_cancelled();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Creates a job running a clone of `probe`.
+fn create_job(probe: &Probe) -> (job::JobId, Pin<Box<dyn Future<Output = ()>>>) {
+    let (id, future) = job::create(String::from("probe"), probe.clone(), true);
+    (id, Box::pin(future))
+}
+
+/// Polls a future once.
+fn poll_once(future: &mut Pin<Box<dyn Future<Output = ()>>>) -> Poll<()> {
+    block_on(future::poll_fn(|cx| Poll::Ready(future.as_mut().poll(cx))))
+}
+
+#[test_case]
+fn jobs_get_the_lowest_free_ids() {
+    let first = job::spawn(String::from("first"), async {}, false);
+    let second = job::spawn(String::from("second"), async {}, false);
+    assert_eq!((first, second), (1, 2));
+    assert_eq!(job::latest(0), Some(2));
+
+    let mut commands = 0;
+    job::for_each(|job| {
+        assert_eq!(job.command, if job.id == 1 { "first" } else { "second" });
+        commands += 1;
+    });
+    assert_eq!(commands, 2);
+}
+
+#[test_case]
+fn foreground_job_can_be_stopped_and_resumed() {
+    let id = job::spawn(String::from("foreground"), async {}, true);
+    assert_eq!(job::foreground(0), Some(id));
+    assert_eq!(state(id), Some((JobState::Running, true)));
+
+    job::stop(id).expect("stopping failed");
+    assert_eq!(job::foreground(0), None);
+    assert_eq!(state(id), Some((JobState::Stopped, false)));
+
+    job::resume(id, false).expect("resuming failed");
+    assert_eq!(state(id), Some((JobState::Running, false)));
+    job::resume(id, true).expect("resuming failed");
+    assert_eq!(job::foreground(0), Some(id));
+}
+
+#[test_case]
+fn unknown_jobs_are_rejected() {
+    assert!(job::cancel(1000).is_err());
+    assert!(job::stop(1000).is_err());
+    assert!(job::resume(1000, true).is_err());
+}
+
+#[test_case]
+fn cancelling_drops_the_future() {
+    let probe = Probe::default();
+    let (id, mut future) = create_job(&probe);
+    assert_eq!(poll_once(&mut future), Poll::Pending);
+    assert_eq!(probe.polls(), 1);
+
+    job::cancel(id).expect("cancelling failed");
+    assert_eq!(poll_once(&mut future), Poll::Ready(()));
+    assert_eq!(probe.polls(), 1);
+    drop(future);
+    assert!(probe.is_dropped());
+    assert_eq!(state(id), None);
+}
+
+#[test_case]
+fn stopped_jobs_are_not_polled() {
+    let probe = Probe::default();
+    let (id, mut future) = create_job(&probe);
+    job::stop(id).expect("stopping failed");
+    assert_eq!(poll_once(&mut future), Poll::Pending);
+    assert_eq!(probe.polls(), 0);
+
+    job::resume(id, true).expect("resuming failed");
+    assert_eq!(poll_once(&mut future), Poll::Pending);
+    assert_eq!(probe.polls(), 1);
+
+    job::cancel(id).expect("cancelling failed");
+    assert_eq!(poll_once(&mut future), Poll::Ready(()));
+}
+
+#[test_case]
+fn second_cancel_drops_a_job_that_ignores_the_first() {
+    let mut probe = Probe::default();
+    probe.observes_cancellation = true;
+    let (id, mut future) = create_job(&probe);
+    assert_eq!(poll_once(&mut future), Poll::Pending);
+
+    job::cancel(id).expect("cancelling failed");
+    assert_eq!(poll_once(&mut future), Poll::Pending);
+    assert_eq!(probe.polls(), 2);
+    assert!(state(id).is_some());
+
+    job::cancel(id).expect("cancelling failed");
+    assert_eq!(poll_once(&mut future), Poll::Ready(()));
+    assert_eq!(probe.polls(), 2);
+    drop(future);
+    assert!(probe.is_dropped());
+    assert_eq!(state(id), None);
+}
+
+#[test_case]
+fn cancellation_is_not_signalled_outside_of_jobs() {
+    assert!(!job::is_cancelled());
+}
+
+#[test_case]
+fn latest_job_is_the_last_started_one() {
+    let (first, first_future) = job::create(String::from("first"), async {}, false);
+    let (second, _second_future) = job::create(String::from("second"), async {}, false);
+    block_on(first_future);
+    // the third job reuses the ID of the first one
+    let (third, _third_future) = job::create(String::from("third"), async {}, false);
+    assert_eq!(third, first);
+    assert!(third < second);
+    assert_eq!(job::latest(0), Some(third));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::{
+    debug, info,
+    log::{self, Level, LogError, Record},
+    serial, warn,
+};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    blog_os::init();
+    log::add_sink("test", Level::Trace, counting_sink).expect("adding sink failed");
+    test_main();
+    loop {}
+}
+
+/// The sequence number of the last record the test sink received, plus one.
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+fn counting_sink(record: &Record) {
+    RECEIVED.store(record.seq + 1, Ordering::Relaxed);
+}
+
+fn last_record() -> Record {
+    let seq = log::buffered().end - 1;
+    log::record(seq).expect("last record not buffered")
+}
+
+#[test_case]
+fn records_reach_buffer_and_sinks() {
+    info!("answer {}", 42);
+    let record = last_record();
+    assert_eq!(record.level, Level::Info);
+    assert_eq!(record.target, "logging");
+    assert_eq!(record.message(), "answer 42");
+    assert_eq!(RECEIVED.load(Ordering::Relaxed), record.seq + 1);
+}
+
+#[test_case]
+fn module_filters_apply() {
+    let before = log::buffered().end;
+    debug!("filtered");
+    assert_eq!(log::buffered().end, before);
+
+    log::apply_filters("logging=debug").expect("applying filters failed");
+    debug!("not filtered");
+    assert_eq!(last_record().message(), "not filtered");
+
+    log::set_level(Some("logging"), Level::Error).expect("setting level failed");
+    warn!("filtered");
+    assert_eq!(log::buffered().end, before + 1);
+    log::set_level(Some("logging"), Level::Info).expect("setting level failed");
+}
+
+#[test_case]
+fn logging_works_with_interrupts_disabled() {
+    x86_64::instructions::interrupts::without_interrupts(|| info!("from critical section"));
+    assert_eq!(last_record().message(), "from critical section");
+}
+
+#[test_case]
+fn records_are_dropped_by_a_locked_sink() {
+    let dropped = log::dropped();
+    // holding the log port keeps the serial sink from writing
+    serial::with_port(serial::log_port(), |_| info!("dropped by the serial sink"));
+    assert_eq!(log::dropped(), dropped + 1);
+    assert_eq!(last_record().message(), "dropped by the serial sink");
+}
+
+#[test_case]
+fn sinks_are_managed_by_name() {
+    assert_eq!(
+        log::add_sink("test", Level::Info, counting_sink),
+        Err(LogError::SinkExists)
+    );
+    assert_eq!(log::remove_sink("missing"), Err(LogError::NoSuchSink));
+    assert_eq!(log::apply_filters("loud"), Err(LogError::InvalidLevel));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,334 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use blog_os::{
+    block::{
+        self, check_range,
+        partition::{self, crc32, Guid, PartitionError},
+        BlockDevice, BlockError, BlockFuture, SECTOR_SIZE,
+    },
+    task::simple_executor::block_on,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+/// A disk in memory, for building partition tables.
+struct RamDisk {
+    name: &'static str,
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    fn new(name: &'static str, sectors: usize) -> Self {
+        RamDisk {
+            name,
+            data: Mutex::new(vec![0; sectors * SECTOR_SIZE]),
+        }
+    }
+
+    fn put(&self, offset: usize, bytes: &[u8]) {
+        self.data.lock()[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn sector(&self, sector: u64) -> Vec<u8> {
+        let start = sector as usize * SECTOR_SIZE;
+        self.data.lock()[start..start + SECTOR_SIZE].to_vec()
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            let start = sector as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+            Ok(())
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            self.put(sector as usize * SECTOR_SIZE, buf);
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Writes an MBR or extended boot record entry.
+fn put_mbr_entry(disk: &RamDisk, record: u64, index: usize, system_id: u8, start: u32, len: u32) {
+    let offset = record as usize * SECTOR_SIZE;
+    let entry = offset + 446 + 16 * index;
+    disk.put(entry + 4, &[system_id]);
+    disk.put(entry + 8, &start.to_le_bytes());
+    disk.put(entry + 12, &len.to_le_bytes());
+    disk.put(offset + 510, &[0x55, 0xaa]);
+}
+
+const LINUX_DATA: &str = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+const BOOT_GUID: &str = "5e2a1b3c-0000-4000-8000-000000000001";
+const DATA_GUID: &str = "5e2a1b3c-0000-4000-8000-000000000002";
+const ENTRY_COUNT: usize = 128;
+const ENTRY_SIZE: usize = 128;
+const ENTRY_SECTORS: u64 = (ENTRY_COUNT * ENTRY_SIZE / SECTOR_SIZE) as u64;
+
+fn gpt_entries() -> Vec<u8> {
+    let mut entries = vec![0; ENTRY_COUNT * ENTRY_SIZE];
+    let partitions = [
+        (BOOT_GUID, "boot", 34u64, 99u64),
+        (DATA_GUID, "data", 100, 1999),
+    ];
+    for (index, &(guid, label, first, last)) in partitions.iter().enumerate() {
+        let entry = &mut entries[index * ENTRY_SIZE..][..ENTRY_SIZE];
+        entry[..16].copy_from_slice(&Guid::parse(LINUX_DATA).unwrap().0);
+        entry[16..32].copy_from_slice(&Guid::parse(guid).unwrap().0);
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (unit, c) in label.encode_utf16().enumerate() {
+            entry[56 + 2 * unit..][..2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+    entries
+}
+
+fn gpt_header(lba: u64, alternate: u64, entries_lba: u64, entries: &[u8]) -> Vec<u8> {
+    let mut header = vec![0; SECTOR_SIZE];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate.to_le_bytes());
+    header[40..48].copy_from_slice(&34u64.to_le_bytes());
+    header[48..56].copy_from_slice(&2014u64.to_le_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(entries).to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+/// A 2048-sector disk with a protective MBR and the primary and backup GPT.
+fn gpt_disk(name: &'static str) -> Arc<RamDisk> {
+    let disk = RamDisk::new(name, 2048);
+    let last = 2047;
+    put_mbr_entry(&disk, 0, 0, 0xee, 1, last as u32);
+    let entries = gpt_entries();
+    disk.put(SECTOR_SIZE, &gpt_header(1, last, 2, &entries));
+    disk.put(2 * SECTOR_SIZE, &entries);
+    let backup_entries = last - ENTRY_SECTORS;
+    disk.put(
+        last as usize * SECTOR_SIZE,
+        &gpt_header(last, 1, backup_entries, &entries),
+    );
+    disk.put(backup_entries as usize * SECTOR_SIZE, &entries);
+    Arc::new(disk)
+}
+
+#[test_case]
+fn gpt_partitions_are_found() {
+    let disk = gpt_disk("gpt0");
+    block::register(disk.clone());
+    let partitions = block_on(partition::scan(disk)).expect("scanning failed");
+    assert_eq!(partitions.len(), 2);
+
+    let boot = &partitions[0];
+    assert_eq!(boot.name(), "gpt0p1");
+    assert_eq!(boot.label(), Some("boot"));
+    assert_eq!(boot.sector_count(), 66);
+    assert_eq!(boot.guid(), Guid::parse(BOOT_GUID));
+
+    let data = partition::find_by_label("data").expect("partition not found by label");
+    assert_eq!(data.name(), "gpt0p2");
+    assert_eq!(data.info().start, 100);
+    let by_guid = partition::find_by_guid(Guid::parse(DATA_GUID).unwrap());
+    assert_eq!(by_guid.map(|p| p.info().start), Some(100));
+    assert!(block::device("gpt0p2").is_some());
+}
+
+#[test_case]
+fn partition_accesses_are_translated() {
+    let disk = gpt_disk("gpt1");
+    let partitions = block_on(partition::scan(disk.clone())).expect("scanning failed");
+    let data = &partitions[1];
+
+    let pattern = vec![0x5a; 2 * SECTOR_SIZE];
+    block_on(data.write(3, &pattern)).expect("writing failed");
+    assert_eq!(disk.sector(103), &pattern[..SECTOR_SIZE]);
+    let mut buf = vec![0; SECTOR_SIZE];
+    block_on(data.read(4, &mut buf)).expect("reading failed");
+    assert_eq!(buf, &pattern[..SECTOR_SIZE]);
+
+    let last = data.sector_count();
+    assert_eq!(
+        block_on(data.read(last, &mut buf)),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        block_on(data.read(last - 1, &mut vec![0; 2 * SECTOR_SIZE])),
+        Err(BlockError::OutOfRange)
+    );
+}
+
+#[test_case]
+fn backup_gpt_is_used() {
+    let disk = gpt_disk("gpt2");
+    // corrupt the disk GUID, so that the header checksum fails
+    disk.put(SECTOR_SIZE + 60, &[0xff]);
+    let partitions = block_on(partition::scan(disk.clone())).expect("backup GPT not used");
+    assert_eq!(partitions.len(), 2);
+
+    disk.put(2047 * SECTOR_SIZE + 60, &[0xff]);
+    let disk: Arc<dyn BlockDevice + Send + Sync> = disk;
+    assert_eq!(
+        block_on(partition::read_table(&disk)),
+        Err(PartitionError::InvalidGpt)
+    );
+}
+
+#[test_case]
+fn oversized_gpt_entries_are_rejected() {
+    let entries = gpt_entries();
+    let with_entries = |count: u32, size: u32| {
+        let mut header = gpt_header(1, 2047, 2, &entries);
+        header[80..84].copy_from_slice(&count.to_le_bytes());
+        header[84..88].copy_from_slice(&size.to_le_bytes());
+        header[16..20].copy_from_slice(&[0; 4]);
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        header
+    };
+    assert!(partition::parse_gpt_header(&with_entries(128, 128), 1).is_ok());
+    assert_eq!(
+        partition::parse_gpt_header(&with_entries(1, 0xffff_fff8), 1),
+        Err(PartitionError::InvalidGpt)
+    );
+    assert_eq!(
+        partition::parse_gpt_header(&with_entries(1, 1024), 1),
+        Err(PartitionError::InvalidGpt)
+    );
+    assert_eq!(
+        partition::parse_gpt_header(&with_entries(512, 512), 1),
+        Err(PartitionError::InvalidGpt)
+    );
+}
+
+#[test_case]
+fn mbr_logical_partitions_are_found() {
+    let disk = RamDisk::new("mbr0", 4096);
+    put_mbr_entry(&disk, 0, 0, 0x83, 2048, 512);
+    put_mbr_entry(&disk, 0, 1, 0x05, 3000, 1000);
+    // the first extended boot record, linking to the second at 3000 + 200
+    put_mbr_entry(&disk, 3000, 0, 0x83, 16, 100);
+    put_mbr_entry(&disk, 3000, 1, 0x05, 200, 300);
+    put_mbr_entry(&disk, 3200, 0, 0x07, 8, 50);
+    let partitions = block_on(partition::scan(Arc::new(disk))).expect("scanning failed");
+
+    let found: Vec<_> = partitions
+        .iter()
+        .map(|p| (p.name(), p.info().start, p.sector_count()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("mbr0p1", 2048, 512),
+            ("mbr0p5", 3016, 100),
+            ("mbr0p6", 3208, 50)
+        ]
+    );
+    assert_eq!(partitions[2].label(), None);
+}
+
+#[test_case]
+fn cyclic_extended_chain_is_rejected() {
+    let disk = RamDisk::new("mbr1", 1024);
+    put_mbr_entry(&disk, 0, 0, 0x0f, 100, 500);
+    put_mbr_entry(&disk, 100, 0, 0x83, 1, 10);
+    put_mbr_entry(&disk, 100, 1, 0x05, 50, 10);
+    put_mbr_entry(&disk, 150, 1, 0x05, 50, 10);
+    let disk: Arc<dyn BlockDevice + Send + Sync> = Arc::new(disk);
+    assert_eq!(
+        block_on(partition::read_table(&disk)),
+        Err(PartitionError::InvalidExtended)
+    );
+}
+
+#[test_case]
+fn disks_without_table_are_skipped() {
+    let disk = RamDisk::new("blank0", 64);
+    assert_eq!(
+        block_on(partition::scan(Arc::new(disk))).err(),
+        Some(PartitionError::NoTable)
+    );
+}
+
+/// A disk that reports no sectors, like one whose driver failed to read the capacity, but
+/// still reads the sectors of the disk it wraps.
+struct SizelessDisk(RamDisk);
+
+impl BlockDevice for SizelessDisk {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        0
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        self.0.read(sector, buf)
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        self.0.write(sector, buf)
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        self.0.flush()
+    }
+}
+
+#[test_case]
+fn backup_gpt_is_not_read_from_sizeless_disks() {
+    let disk = RamDisk::new("sizeless0", 64);
+    put_mbr_entry(&disk, 0, 0, 0xee, 1, 63);
+    let disk: Arc<dyn BlockDevice + Send + Sync> = Arc::new(SizelessDisk(disk));
+    assert_eq!(
+        block_on(partition::read_table(&disk)),
+        Err(PartitionError::InvalidGpt)
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
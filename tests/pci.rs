@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::drivers::{
+    ahci,
+    pci::{self, Bar, PciAddress, PciError},
+    virtio,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    pci::init();
+    assert_eq!(ahci::init(), 1);
+    assert_eq!(virtio::init(), 3);
+
+    test_main();
+    loop {}
+}
+
+const TESTDEV_VENDOR_ID: u16 = 0x1b36;
+const TESTDEV_DEVICE_ID: u16 = 0x0005;
+
+fn ahci_controller() -> PciAddress {
+    pci::find_class(0x01, 0x06, 0x01).expect("AHCI controller not found")
+}
+
+#[test_case]
+fn host_bridge_is_enumerated() {
+    let functions = pci::functions();
+    let host_bridge = PciAddress::new(0, 0, 0);
+    assert_eq!(functions.first(), Some(&host_bridge));
+    assert_eq!(host_bridge.class().0, 0x06);
+    assert!(functions.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test_case]
+fn bar_sizes_are_decoded() {
+    let controller = ahci_controller();
+    let bar = controller.decode_bar(5).expect("ABAR not decoded");
+    match bar {
+        Bar::Memory { address, size, .. } => {
+            assert_eq!(Some(address), controller.memory_bar(5));
+            assert!(size >= 0x1000);
+            assert!(size.is_power_of_two());
+        }
+        Bar::Io { .. } => panic!("ABAR decoded as I/O BAR"),
+    }
+    // decoding restores the register
+    assert_eq!(controller.decode_bar(5), Some(bar));
+    assert_eq!(controller.decode_bar(6), None);
+}
+
+// The entropy device is legacy-only, so its registers are in I/O BAR 0.
+#[test_case]
+fn io_bars_are_not_mapped() {
+    let rng = pci::find(virtio::VENDOR_ID, 0x1005).expect("virtio-rng not found");
+    match rng.decode_bar(0) {
+        Some(Bar::Io { port, size }) => {
+            assert_eq!(Some(port), rng.io_bar(0));
+            assert!(size >= 0x20);
+        }
+        bar => panic!("unexpected BAR 0: {:?}", bar),
+    }
+    assert!(matches!(
+        rng.map_bar(0, "test"),
+        Err(PciError::NotMemory(0))
+    ));
+}
+
+#[test_case]
+fn msix_is_parsed() {
+    let block = pci::find(virtio::VENDOR_ID, 0x1042).expect("virtio-blk not found");
+    let msix = block.msix().expect("virtio-blk without MSI-X");
+    assert!(msix.table_size >= 2);
+    assert!(!msix.enabled);
+    assert!(block.decode_bar(msix.table_bar).is_some());
+}
+
+#[test_case]
+fn drivers_are_bound() {
+    assert_eq!(pci::driver_of(ahci_controller()), Some("ahci"));
+    let virtio_functions = pci::functions()
+        .into_iter()
+        .filter(|address| address.vendor_id() == virtio::VENDOR_ID)
+        .count();
+    assert_eq!(virtio_functions, 3);
+    for address in pci::functions() {
+        if address.vendor_id() == virtio::VENDOR_ID {
+            assert_eq!(pci::driver_of(address), Some("virtio"));
+        }
+    }
+    assert_eq!(pci::driver_of(PciAddress::new(0, 0, 0)), None);
+    // functions are bound once
+    assert_eq!(pci::probe_drivers(), 0);
+}
+
+/// The test runner attaches a `pci-testdev` behind a PCI-to-PCI bridge.
+#[test_case]
+fn device_behind_bridge_is_enumerated() {
+    let bridge = pci::find_class(0x06, 0x04, 0x00).expect("PCI-to-PCI bridge not found");
+    let secondary = bridge
+        .secondary_bus()
+        .expect("bridge without secondary bus");
+    assert_ne!(secondary, 0);
+    let testdev = pci::find(TESTDEV_VENDOR_ID, TESTDEV_DEVICE_ID).expect("pci-testdev not found");
+    assert_eq!(testdev.bus, secondary);
+    // the default machine has no MCFG table, so the configuration ports are used
+    assert!(!pci::is_ecam_mapped(0));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::{
+    acpi,
+    drivers::pci::{self, PciAddress},
+    exit_qemu, serial_println, QemuExitCode,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+const TESTDEV_VENDOR_ID: u16 = 0x1b36;
+const TESTDEV_DEVICE_ID: u16 = 0x0005;
+
+/// Runs on QEMU's q35 machine with `cargo test-q35`; the default machine has no MCFG table,
+/// so the tests are skipped there.
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    if acpi::find_table(b"MCFG").is_none() {
+        serial_println!("no MCFG table, skipping the ECAM tests (run `cargo test-q35`)");
+        exit_qemu(QemuExitCode::Success);
+        loop {}
+    }
+    pci::init();
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn host_bridge_is_read_through_ecam() {
+    let functions = pci::functions();
+    assert_eq!(functions.first(), Some(&PciAddress::new(0, 0, 0)));
+    assert!(pci::is_ecam_mapped(0));
+    // the q35 host bridge
+    assert_eq!(PciAddress::new(0, 0, 0).vendor_id(), 0x8086);
+    assert_eq!(PciAddress::new(0, 0, 0).device_id(), 0x29c0);
+}
+
+/// The test runner attaches a `pci-testdev` behind a PCI-to-PCI bridge.
+#[test_case]
+fn device_behind_bridge_is_read_through_ecam() {
+    let bridge = pci::find_class(0x06, 0x04, 0x00).expect("PCI-to-PCI bridge not found");
+    let secondary = bridge
+        .secondary_bus()
+        .expect("bridge without secondary bus");
+    let testdev = pci::find(TESTDEV_VENDOR_ID, TESTDEV_DEVICE_ID).expect("pci-testdev not found");
+    assert_eq!(testdev.bus, secondary);
+    assert!(pci::is_ecam_mapped(secondary));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::{
+    drivers::ps2::{
+        self,
+        keyboard::{self, Typematic},
+    },
+    input::Locks,
+    interrupts::irq,
+};
+use core::panic::PanicInfo;
+
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start() -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn controller_finds_keyboard_and_mouse() {
+    let devices = ps2::devices();
+    assert!(devices.keyboard);
+    assert!(devices.mouse);
+}
+
+#[test_case]
+fn mouse_interrupt_is_registered() {
+    let mut handlers = 0;
+    irq::for_each_handler(12, |name| {
+        assert_eq!(name, "mouse");
+        handlers += 1;
+    });
+    assert_eq!(handlers, 1);
+}
+
+#[test_case]
+fn keyboard_accepts_commands() {
+    let locks = Locks {
+        caps_lock: true,
+        ..Locks::default()
+    };
+    keyboard::set_leds(locks).expect("setting the LEDs failed");
+    keyboard::set_leds(Locks::default()).expect("setting the LEDs failed");
+    keyboard::set_typematic(Typematic::DEFAULT).expect("setting the typematic rate failed");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
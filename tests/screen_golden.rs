@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! Compares the screen with golden files in `tests/golden`.
+//!
+//! On a mismatch the actual screen is printed to the serial port in the golden file
+//! format, so an intended change can be reviewed and copied into the golden file.
+
+extern crate alloc;
+
+use blog_os::{
+    print, println,
+    vga_buffer::{self, CONSOLES},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+/// Resets the attributes, clears the screen and moves the cursor to the top left corner.
+fn clear_screen() {
+    print!("\x1b[0m\x1b[2J\x1b[H");
+}
+
+#[test_case]
+fn colors_and_box_drawing() {
+    clear_screen();
+    println!("plain");
+    println!("\x1b[1;32mbright green\x1b[0m");
+    println!("\x1b[41mred background\x1b[0m");
+    println!("\x1b[7mreverse\x1b[0m");
+    println!("┌──┐");
+    println!("│hi│");
+    println!("└──┘");
+
+    let capture = interrupts::without_interrupts(vga_buffer::capture_screen);
+    capture.assert_matches("colors", include_str!("golden/colors.txt"));
+}
+
+#[test_case]
+fn scrolling() {
+    clear_screen();
+    for i in 0..30 {
+        println!("line {}", i);
+    }
+
+    let capture = interrupts::without_interrupts(vga_buffer::capture_screen);
+    capture.assert_matches("scrolling", include_str!("golden/scrolling.txt"));
+}
+
+#[test_case]
+fn scrollback_view() {
+    clear_screen();
+    for i in 0..30 {
+        println!("line {}", i);
+    }
+
+    let capture = interrupts::without_interrupts(|| {
+        let mut writer = CONSOLES[0].lock();
+        writer.scroll_up(3);
+        let capture = vga_buffer::capture_screen();
+        assert_eq!(capture, writer.capture());
+        writer.scroll_to_bottom();
+        capture
+    });
+    capture.assert_matches("scrollback", include_str!("golden/scrollback.txt"));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use blog_os::{
+    error::MyError,
+    println,
+    serial::{self, ComPort, SerialStream, SERIAL1},
+    task::{simple_executor::block_on, task_loader::load_task},
+    vga_buffer,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+fn set_loopback(loopback: bool) {
+    interrupts::without_interrupts(|| SERIAL1.lock().set_loopback(loopback));
+}
+
+/// Waits for `count` bytes to arrive through the receive interrupt.
+fn receive(stream: &mut SerialStream, count: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for _ in 0..1_000_000 {
+        if let Some(byte) = stream.try_next() {
+            bytes.push(byte);
+            if bytes.len() == count {
+                break;
+            }
+        }
+        core::hint::spin_loop();
+    }
+    bytes
+}
+
+// While the UART is in loopback mode, nothing reaches the host, so the test output
+// is only printed after it's switched back.
+#[test_case]
+fn input_and_console_output_use_interrupts() {
+    let mut stream = SerialStream::new();
+    serial::attach_console(vga_buffer::current_console(), ComPort::Com1)
+        .expect("attaching console failed");
+
+    set_loopback(true);
+    interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        for &byte in b"ls\r" {
+            serial.send(byte);
+        }
+    });
+    let input = receive(&mut stream, 3);
+
+    println!("ok");
+    let output = receive(&mut stream, 4);
+    set_loopback(false);
+
+    assert_eq!(input, b"ls\r");
+    assert_eq!(output, b"ok\r\n");
+}
+
+#[test_case]
+fn blank_lines_are_rejected() {
+    for line in ["", "  \t ", " & "].iter() {
+        let result = block_on(load_task(String::from(*line)));
+        assert!(matches!(result, Err(MyError::InvalidArgument)));
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    set_loopback(false);
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::{
+    drivers::uart::{LineConfig, Parity},
+    serial::{self, ComPort, SerialError},
+};
+use core::panic::PanicInfo;
+use x86_64::instructions::interrupts;
+
+// The test runner attaches COM2 to `target/com2.log`, next to COM1 on stdio.
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn com1_and_com2_are_present() {
+    assert!(serial::is_present(ComPort::Com1));
+    assert!(serial::is_present(ComPort::Com2));
+}
+
+#[test_case]
+fn logs_go_to_the_log_port() {
+    serial::set_log_port(ComPort::Com2).expect("setting log port failed");
+    assert_eq!(serial::log_port(), ComPort::Com2);
+    serial::write_log(format_args!("log line on COM2\n"));
+    serial::set_log_port(ComPort::Com1).expect("setting log port failed");
+}
+
+#[test_case]
+fn log_output_is_sent_on_com2() {
+    serial::set_log_port(ComPort::Com2).expect("setting log port failed");
+    // with interrupts disabled, the interrupt handler can't take the bytes
+    let received = interrupts::without_interrupts(|| {
+        serial::with_port(ComPort::Com2, |uart| uart.set_loopback(true));
+        serial::write_log(format_args!("ok"));
+        serial::with_port(ComPort::Com2, |uart| {
+            let mut received = [0; 2];
+            for byte in received.iter_mut() {
+                *byte = (0..100_000).find_map(|_| uart.try_receive()).unwrap_or(0);
+            }
+            uart.set_loopback(false);
+            received
+        })
+    });
+    serial::set_log_port(ComPort::Com1).expect("setting log port failed");
+    assert_eq!(received, Some(*b"ok"));
+}
+
+#[test_case]
+fn absent_port_is_rejected() {
+    let absent = ComPort::ALL
+        .iter()
+        .copied()
+        .find(|&port| !serial::is_present(port));
+    if let Some(port) = absent {
+        assert_eq!(
+            serial::set_log_port(port),
+            Err(SerialError::NotPresent(port))
+        );
+        assert_eq!(serial::log_port(), ComPort::Com1);
+    }
+}
+
+#[test_case]
+fn port_can_be_reconfigured() {
+    let config = LineConfig::new(115_200, 7, Parity::Even, 1).expect("invalid configuration");
+    serial::configure(ComPort::Com2, &config).expect("configuring COM2 failed");
+    assert_eq!(
+        serial::with_port(ComPort::Com2, |uart| uart.config()),
+        Some(config)
+    );
+    // the divisor latch and line control registers hold the new configuration
+    assert_eq!(
+        serial::with_port(ComPort::Com2, |uart| uart.read_config()),
+        Some(Some(config))
+    );
+    serial::configure(ComPort::Com2, &LineConfig::DEFAULT).expect("configuring COM2 failed");
+    assert_eq!(
+        serial::with_port(ComPort::Com2, |uart| uart.read_config()),
+        Some(Some(LineConfig::DEFAULT))
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
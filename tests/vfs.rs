@@ -0,0 +1,321 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use blog_os::fs::{
+    self, DirEntry, FileSystem, FileType, FsError, Inode, Metadata, OpenOptions, SeekFrom,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    fs::mount("/", Arc::new(TestFs::new("testfs"))).expect("mounting the root failed");
+
+    test_main();
+    loop {}
+}
+
+static NEXT_INODE: AtomicU64 = AtomicU64::new(1);
+
+/// A minimal file system in memory, to exercise the VFS.
+struct Node {
+    inode: u64,
+    contents: Mutex<Contents>,
+}
+
+enum Contents {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<Node>>),
+}
+
+impl Node {
+    fn new(file_type: FileType) -> Arc<Self> {
+        let contents = match file_type {
+            FileType::File => Contents::File(Vec::new()),
+            FileType::Directory => Contents::Directory(BTreeMap::new()),
+        };
+        Arc::new(Node {
+            inode: NEXT_INODE.fetch_add(1, Ordering::Relaxed),
+            contents: Mutex::new(contents),
+        })
+    }
+}
+
+impl Inode for Node {
+    fn metadata(&self) -> Metadata {
+        let (file_type, size) = match &*self.contents.lock() {
+            Contents::File(data) => (FileType::File, data.len() as u64),
+            Contents::Directory(_) => (FileType::Directory, 0),
+        };
+        Metadata {
+            inode: self.inode,
+            file_type,
+            size,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match &*self.contents.lock() {
+            Contents::Directory(entries) => entries
+                .get(name)
+                .map(|node| node.clone() as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound),
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        match &mut *self.contents.lock() {
+            Contents::Directory(entries) if entries.contains_key(name) => {
+                Err(FsError::AlreadyExists)
+            }
+            Contents::Directory(entries) => {
+                let node = Node::new(file_type);
+                entries.insert(name.to_string(), node.clone());
+                Ok(node as Arc<dyn Inode>)
+            }
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        match &mut *self.contents.lock() {
+            Contents::Directory(entries) => {
+                let node = entries.get(name).ok_or(FsError::NotFound)?;
+                if let Contents::Directory(children) = &*node.contents.lock() {
+                    if !children.is_empty() {
+                        return Err(FsError::DirectoryNotEmpty);
+                    }
+                }
+                entries.remove(name);
+                Ok(())
+            }
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        match &*self.contents.lock() {
+            Contents::Directory(entries) => Ok(entries
+                .iter()
+                .map(|(name, node)| {
+                    let metadata = node.metadata();
+                    DirEntry {
+                        name: name.clone(),
+                        inode: metadata.inode,
+                        file_type: metadata.file_type,
+                    }
+                })
+                .collect()),
+            Contents::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match &*self.contents.lock() {
+            Contents::File(data) => {
+                let start = (offset as usize).min(data.len());
+                let len = buf.len().min(data.len() - start);
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(len)
+            }
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        match &mut *self.contents.lock() {
+            Contents::File(data) => {
+                let end = offset as usize + buf.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[offset as usize..end].copy_from_slice(buf);
+                Ok(buf.len())
+            }
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        match &mut *self.contents.lock() {
+            Contents::File(data) => {
+                data.resize(size as usize, 0);
+                Ok(())
+            }
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+}
+
+struct TestFs {
+    fs_type: &'static str,
+    root: Arc<Node>,
+}
+
+impl TestFs {
+    fn new(fs_type: &'static str) -> Self {
+        TestFs {
+            fs_type,
+            root: Node::new(FileType::Directory),
+        }
+    }
+}
+
+impl FileSystem for TestFs {
+    fn fs_type(&self) -> &'static str {
+        self.fs_type
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+#[test_case]
+fn paths_are_normalized() {
+    assert_eq!(fs::absolute("/a//b/./c/").unwrap(), "/a/b/c");
+    assert_eq!(fs::absolute("/a/../../b").unwrap(), "/b");
+    assert_eq!(fs::absolute("/..").unwrap(), "/");
+    assert_eq!(fs::absolute(""), Err(FsError::InvalidPath));
+}
+
+#[test_case]
+fn files_are_written_and_read() {
+    fs::create_dir("/files").unwrap();
+    fs::write("/files/hello.txt", b"hello world").unwrap();
+    assert_eq!(fs::read("/files/hello.txt").unwrap(), b"hello world");
+    assert_eq!(fs::metadata("/files/hello.txt").unwrap().size, 11);
+    assert!(fs::metadata("/files").unwrap().is_dir());
+    assert_eq!(fs::read("/files/missing"), Err(FsError::NotFound));
+    assert_eq!(fs::read("/files"), Err(FsError::IsADirectory));
+    assert_eq!(fs::read("/files/hello.txt/x"), Err(FsError::NotADirectory));
+    assert_eq!(fs::create_dir("/files"), Err(FsError::AlreadyExists));
+}
+
+#[test_case]
+fn directories_are_listed() {
+    fs::create_dir("/list").unwrap();
+    fs::write("/list/b", b"").unwrap();
+    fs::create_dir("/list/a").unwrap();
+    let entries: Vec<_> = fs::read_dir("/list")
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.name, entry.file_type))
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            (String::from("a"), FileType::Directory),
+            (String::from("b"), FileType::File)
+        ]
+    );
+}
+
+#[test_case]
+fn relative_paths_use_current_dir() {
+    fs::create_dir("/work").unwrap();
+    fs::create_dir("/work/sub").unwrap();
+    fs::set_current_dir("/work/sub").unwrap();
+    assert_eq!(fs::current_dir(), "/work/sub");
+    fs::write("../note", b"note").unwrap();
+    assert_eq!(fs::read("/work/note").unwrap(), b"note");
+    fs::set_current_dir("..").unwrap();
+    assert_eq!(fs::current_dir(), "/work");
+    assert_eq!(fs::lookup("./sub/.").unwrap().path(), "/work/sub");
+    assert_eq!(fs::set_current_dir("note"), Err(FsError::NotADirectory));
+    fs::set_current_dir("/").unwrap();
+}
+
+#[test_case]
+fn handles_keep_offsets() {
+    fs::write("/seek", b"0123456789").unwrap();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/seek")
+        .unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(file.read(&mut buf), Ok(4));
+    assert_eq!(&buf, b"0123");
+    assert_eq!(file.read(&mut buf), Ok(4));
+    assert_eq!(&buf, b"4567");
+    assert_eq!(file.seek(SeekFrom::End(-2)), Ok(8));
+    assert_eq!(file.read(&mut buf), Ok(2));
+    assert_eq!(file.read(&mut buf), Ok(0));
+    assert_eq!(file.seek(SeekFrom::Current(-20)), Err(FsError::InvalidSeek));
+    file.seek(SeekFrom::Start(1)).unwrap();
+    file.write(b"ab").unwrap();
+    assert_eq!(fs::read("/seek").unwrap(), b"0ab3456789");
+
+    let mut appender = OpenOptions::new().append(true).open("/seek").unwrap();
+    appender.write(b"!").unwrap();
+    assert_eq!(fs::read("/seek").unwrap(), b"0ab3456789!");
+
+    let mut reader = OpenOptions::new().read(true).open("/seek").unwrap();
+    assert_eq!(reader.write(b"x"), Err(FsError::PermissionDenied));
+    assert_eq!(
+        OpenOptions::new().read(true).open("/").err(),
+        Some(FsError::IsADirectory)
+    );
+}
+
+#[test_case]
+fn removal_checks_directories() {
+    fs::create_dir("/full").unwrap();
+    fs::write("/full/file", b"x").unwrap();
+    assert_eq!(fs::remove("/full"), Err(FsError::DirectoryNotEmpty));
+    fs::remove("/full/file").unwrap();
+    fs::remove("/full").unwrap();
+    assert_eq!(fs::metadata("/full"), Err(FsError::NotFound));
+    assert_eq!(fs::remove("/"), Err(FsError::InvalidPath));
+}
+
+#[test_case]
+fn mounts_shadow_directories() {
+    fs::create_dir("/mnt").unwrap();
+    fs::write("/mnt/hidden", b"").unwrap();
+    fs::mount("/mnt", Arc::new(TestFs::new("otherfs"))).unwrap();
+    assert!(fs::read_dir("/mnt").unwrap().is_empty());
+    fs::write("/mnt/inner", b"inner").unwrap();
+    assert_eq!(fs::read("/mnt/../mnt/inner").unwrap(), b"inner");
+    assert_eq!(
+        fs::mounts(),
+        [
+            (String::from("/"), "testfs"),
+            (String::from("/mnt"), "otherfs")
+        ]
+    );
+    assert_eq!(fs::remove("/mnt"), Err(FsError::Busy));
+    assert_eq!(
+        fs::mount("/mnt", Arc::new(TestFs::new("otherfs"))),
+        Err(FsError::Busy)
+    );
+    assert_eq!(fs::unmount("/"), Err(FsError::Busy));
+
+    fs::unmount("/mnt").unwrap();
+    assert!(fs::metadata("/mnt/hidden").is_ok());
+    assert_eq!(fs::metadata("/mnt/inner"), Err(FsError::NotFound));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use blog_os::{
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    drivers::virtio,
+    random,
+    task::simple_executor::block_on,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    assert_eq!(virtio::init(), 3);
+
+    test_main();
+    loop {}
+}
+
+/// The test runner attaches a modern-only block device backed by an empty 8 MiB image, with
+/// writes discarded when QEMU exits.
+fn disk() -> Arc<dyn BlockDevice + Send + Sync> {
+    block::device("vda").expect("VirtIO block device not detected")
+}
+
+#[test_case]
+fn block_device_is_detected() {
+    let disk = disk();
+    assert_eq!(disk.capacity(), 8 * 1024 * 1024);
+}
+
+// The second write leaves its pattern in the bounce frames, so reading the first pattern
+// back shows that the data was transferred by the device both ways.
+#[test_case]
+fn block_read_after_write_transfers_data() {
+    let disk = disk();
+    let pattern: Vec<u8> = (0..4 * SECTOR_SIZE).map(|i| (i % 251) as u8).collect();
+    block_on(disk.write(4, &pattern)).expect("writing sectors failed");
+    block_on(disk.write(8, &[0x5a; 4 * SECTOR_SIZE])).expect("writing sectors failed");
+    block_on(disk.flush()).expect("flushing failed");
+
+    let mut read_back = vec![0xff; 4 * SECTOR_SIZE];
+    block_on(disk.read(4, &mut read_back)).expect("reading sectors failed");
+    assert_eq!(read_back, pattern);
+}
+
+#[test_case]
+fn block_large_read_spans_several_requests() {
+    let disk = disk();
+    let mut buf = vec![0xff; 300 * SECTOR_SIZE];
+    block_on(disk.read(disk.sector_count() - 300, &mut buf)).expect("reading sectors failed");
+    assert!(buf.iter().all(|&byte| byte == 0));
+    assert_eq!(
+        block_on(disk.read(disk.sector_count(), &mut buf[..SECTOR_SIZE])),
+        Err(BlockError::OutOfRange)
+    );
+}
+
+#[test_case]
+fn block_dropped_request_releases_the_device() {
+    use core::task::Context;
+    use futures_util::task::noop_waker;
+
+    let disk = disk();
+    let mut buf = vec![0; 64 * SECTOR_SIZE];
+    let waker = noop_waker();
+    let mut context = Context::from_waker(&waker);
+    let mut read = disk.read(64, &mut buf);
+    // the request may complete before the first poll returns
+    let _ = read.as_mut().poll(&mut context);
+    drop(read);
+
+    let mut sector = [0xff; SECTOR_SIZE];
+    block_on(disk.read(disk.sector_count() - 1, &mut sector)).expect("reading failed");
+    assert!(sector.iter().all(|&byte| byte == 0));
+}
+
+/// The entropy device is legacy-only, so both transports are tested.
+#[test_case]
+fn rng_returns_random_bytes() {
+    let rng = virtio::rng::get().expect("VirtIO entropy device not detected");
+    let mut first = [0; 32];
+    let mut second = [0; 32];
+    let len = block_on(rng.read(&mut first)).expect("reading random bytes failed");
+    assert!(len > 0);
+    block_on(rng.read(&mut second)).expect("reading random bytes failed");
+    assert_ne!(first, second);
+
+    random::add_entropy(&first[..len], len * 8);
+    assert!(random::entropy() >= len * 8);
+}
+
+#[test_case]
+fn console_write_completes() {
+    let console = virtio::console::get().expect("VirtIO console not detected");
+    block_on(console.write(b"hello from the kernel\r\n")).expect("writing to the console failed");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}